@@ -0,0 +1,175 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use ka::{
+    actions::{
+        bucketed_log, diff, repository_log, shift, show, status, ActionOptions, ShiftMode,
+        UntrackedFiles,
+    },
+    diff::TextGranularity,
+    filesystem::Fs,
+};
+
+use crate::{print_diff, print_log_bucket};
+
+const HELP: &str = "n/p: newer/older  g <change>: select  s: shift here  x <file> <target>: extract
+t [m/h/d]: group by minute/hour/day  t: ungroup  q: quit";
+
+// A full-screen browser over the history of the repository. It redraws after every
+// command, which keeps it working in any terminal without raw mode.
+pub fn browse(options: ActionOptions, filesystem: &impl Fs) -> Result<()> {
+    let mut selected = None;
+    // Shows the changes grouped into spans of this many seconds instead of one by one.
+    let mut timeline = None;
+    let mut message = String::new();
+    let stdin = io::stdin();
+
+    loop {
+        let log = repository_log(options.clone(), filesystem).context("Failed loading history.")?;
+        let cursor = status(options.clone(), filesystem)
+            .context("Failed loading status.")?
+            .cursor;
+        let selected_index = selected.unwrap_or(cursor).min(log.len());
+        selected = Some(selected_index);
+
+        // Clears the screen and moves to its top.
+        print!("\x1B[2J\x1B[H");
+        if let Some(bucket_seconds) = timeline {
+            let buckets = bucketed_log(options.clone(), filesystem, bucket_seconds)
+                .context("Failed loading history.")?;
+            for bucket in buckets {
+                let contains = |change_index| {
+                    (bucket.first_change..=bucket.last_change).contains(&change_index)
+                };
+                let marker = match (contains(selected_index), contains(cursor)) {
+                    (true, _) => '>',
+                    (false, true) => '*',
+                    (false, false) => ' ',
+                };
+                print!("{} ", marker);
+                print_log_bucket(&bucket);
+            }
+        } else {
+            for entry in log.iter() {
+                let marker = match (
+                    entry.change_index == selected_index,
+                    entry.change_index == cursor,
+                ) {
+                    (true, _) => '>',
+                    (false, true) => '*',
+                    (false, false) => ' ',
+                };
+                println!(
+                    "{} {:>4}  {}  {} files  {}",
+                    marker,
+                    entry.change_index,
+                    entry.timestamp,
+                    entry.affected_files.len(),
+                    entry.message.as_deref().unwrap_or("")
+                );
+            }
+        }
+        println!();
+
+        if let Some(entry) = log.get(selected_index.wrapping_sub(1)) {
+            for path in entry.affected_files.iter() {
+                println!("--- {}", path.display());
+                match diff(
+                    options.clone(),
+                    filesystem,
+                    path,
+                    selected_index - 1,
+                    Some(selected_index),
+                    TextGranularity::Line,
+                ) {
+                    Ok(segments) => print_diff(segments, TextGranularity::Line),
+                    Err(error) => println!("{:#}", error),
+                }
+            }
+            println!();
+        }
+
+        println!("{}", message);
+        print!("{}\n> ", HELP);
+        io::stdout().flush().context("Failed writing to output.")?;
+
+        let mut line = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .context("Failed reading input.")?
+            == 0
+        {
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        message = match words.as_slice() {
+            ["q"] => return Ok(()),
+            ["n"] => {
+                selected = Some((selected_index + 1).min(log.len()));
+                String::new()
+            }
+            ["p"] => {
+                selected = Some(selected_index.saturating_sub(1).max(1));
+                String::new()
+            }
+            ["g", change_index] => match change_index.parse() {
+                Ok(change_index) => {
+                    selected = Some(change_index);
+                    String::new()
+                }
+                Err(_) => format!("'{}' isn't a change.", change_index),
+            },
+            ["t"] => {
+                timeline = None;
+                String::new()
+            }
+            ["t", span] => match *span {
+                "m" => Some(60),
+                "h" => Some(60 * 60),
+                "d" => Some(60 * 60 * 24),
+                _ => None,
+            }
+            .map_or_else(
+                || format!("'{}' isn't one of m, h or d.", span),
+                |bucket_seconds| {
+                    timeline = Some(bucket_seconds);
+                    String::new()
+                },
+            ),
+            ["s"] => match shift(
+                options.clone(),
+                filesystem,
+                selected_index,
+                ShiftMode::Safe,
+                UntrackedFiles::Keep,
+            ) {
+                Ok(_) => format!("Shifted to {}.", selected_index),
+                Err(error) => format!("{:#}", error),
+            },
+            ["x", path, target_path] => {
+                match extract_file(&options, filesystem, path, selected_index, target_path) {
+                    Ok(()) => format!("Extracted '{}' to '{}'.", path, target_path),
+                    Err(error) => format!("{:#}", error),
+                }
+            }
+            _ => "Unknown command.".to_string(),
+        };
+    }
+}
+
+fn extract_file(
+    options: &ActionOptions,
+    filesystem: &impl Fs,
+    path: &str,
+    cursor: usize,
+    target_path: &str,
+) -> Result<()> {
+    let content = show(options.clone(), filesystem, Path::new(path), cursor)?;
+    let mut target_file = filesystem.create_file(Path::new(target_path))?;
+    filesystem.write_to_file(&mut target_file, content)
+}
@@ -0,0 +1,612 @@
+// What the CLI takes, which the completions for shells and the man page are generated from. The
+// arguments are still parsed by `run` in `main.rs`, so a command or flag added there has to be
+// added here as well, which the test at the end checks by going through its source.
+
+use ka::templates::BUILT_IN_TEMPLATES;
+
+pub struct Command {
+    pub name: &'static str,
+    pub synopsis: &'static str,
+    pub description: &'static str,
+    // What each positional argument completes to, in order. The last one also applies to any
+    // further arguments.
+    pub arguments: Vec<Completion>,
+    pub flags: Vec<Flag>,
+}
+
+pub struct Flag {
+    pub name: &'static str,
+    // The placeholder of its value, for flags which take one.
+    pub value: Option<&'static str>,
+    pub description: &'static str,
+    pub completion: Completion,
+}
+
+#[derive(Clone, Copy)]
+pub enum Completion {
+    Nothing,
+    Paths,
+    // The IDs of the changes of the repository, which `ka complete changes` lists.
+    Changes,
+    // The names of the sessions of the repository, which `ka complete sessions` lists.
+    Sessions,
+    Choices(&'static [&'static str]),
+}
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+// `--verbose`, which every command takes.
+pub const VERBOSE_FLAG: Flag = Flag {
+    name: "--verbose",
+    value: None,
+    description: "Report what is being done on stderr, twice for every file.",
+    completion: Completion::Nothing,
+};
+
+fn command(
+    name: &'static str,
+    synopsis: &'static str,
+    description: &'static str,
+    arguments: &[Completion],
+    flags: Vec<Flag>,
+) -> Command {
+    Command {
+        name,
+        synopsis,
+        description,
+        arguments: arguments.to_vec(),
+        flags,
+    }
+}
+
+fn switch(name: &'static str, description: &'static str) -> Flag {
+    Flag {
+        name,
+        value: None,
+        description,
+        completion: Completion::Nothing,
+    }
+}
+
+fn option(
+    name: &'static str,
+    value: &'static str,
+    description: &'static str,
+    completion: Completion,
+) -> Flag {
+    Flag {
+        name,
+        value: Some(value),
+        description,
+        completion,
+    }
+}
+
+pub fn get_commands() -> Vec<Command> {
+    use Completion::*;
+
+    let mut commands = vec![
+        command(
+            "create",
+            "",
+            "Create a repository in the current directory and record its files.",
+            &[],
+            vec![
+                option(
+                    "--template",
+                    "name",
+                    "Start with the config and ignore patterns of a template.",
+                    Choices(BUILT_IN_TEMPLATES),
+                ),
+                switch(
+                    "--import",
+                    "Record the files in chunks, resumable if interrupted.",
+                ),
+                switch(
+                    "--baseline",
+                    "Don't check for files changing while they're recorded.",
+                ),
+                switch("--encrypt", "Encrypt the repository with KA_PASSPHRASE."),
+            ],
+        ),
+        command(
+            "update",
+            "",
+            "Record the changed files as a new change.",
+            &[],
+            vec![
+                switch("--interactive", "Ask which hunks to record."),
+                option(
+                    "--annotate",
+                    "key=value",
+                    "Keep the annotation with the change, can be repeated.",
+                    Nothing,
+                ),
+            ],
+        ),
+        command(
+            "shift",
+            "<change>",
+            "Move the working files to how they were at the change.",
+            &[Changes],
+            vec![
+                option(
+                    "--session",
+                    "name",
+                    "Shift to the end of the session.",
+                    Sessions,
+                ),
+                option(
+                    "--session-start",
+                    "name",
+                    "Shift to the start of the session.",
+                    Sessions,
+                ),
+                switch("--preview", "Only list what would change."),
+                switch("--force", "Overwrite unrecorded changes."),
+                switch("--auto-update", "Record unrecorded changes first."),
+                switch("--clean", "Remove untracked files."),
+                switch("--undo", "Go back to before the last shift."),
+            ],
+        ),
+        command(
+            "revert",
+            "<change>",
+            "Record a change undoing the change.",
+            &[Changes],
+            vec![],
+        ),
+        command(
+            "adopt",
+            "",
+            "Record files copied in from other repositories along with their history.",
+            &[],
+            vec![],
+        ),
+        command(
+            "squash",
+            "<from> <to>",
+            "Combine the changes from one cursor to another into one.",
+            &[Nothing],
+            vec![option(
+                "--message",
+                "message",
+                "The message of the combined change.",
+                Nothing,
+            )],
+        ),
+        command(
+            "prune",
+            "",
+            "Remove changes older than the retention policy.",
+            &[],
+            vec![option(
+                "--since",
+                "timestamp",
+                "Remove the changes before the timestamp instead.",
+                Nothing,
+            )],
+        ),
+        command(
+            "status",
+            "",
+            "List the files with unrecorded changes.",
+            &[],
+            vec![switch(
+                "--porcelain",
+                "Print them in a format for scripts, which stays the same across versions.",
+            )],
+        ),
+        command(
+            "summary",
+            "",
+            "Print the cursor, the count of changed files and when the latest change was recorded.",
+            &[],
+            vec![
+                switch(
+                    "--prompt",
+                    "Print only the cursor, followed by * and the count if there are changed files.",
+                ),
+                switch("--porcelain", "Print in a stable format, for scripts."),
+            ],
+        ),
+        command(
+            "show",
+            "<file>@<change>",
+            "Print the file as it was at the change.",
+            &[Paths],
+            vec![option(
+                "--output",
+                "file",
+                "Write it into the file instead.",
+                Paths,
+            )],
+        ),
+        command(
+            "backup",
+            "<directory>",
+            "Copy the repository into the directory, only what changed since the last backup.",
+            &[Paths],
+            vec![],
+        ),
+        command(
+            "restore-backup",
+            "<backup> [<directory>]",
+            "Restore the repository and its working files from a backup.",
+            &[Paths],
+            vec![],
+        ),
+        command(
+            "extract",
+            "<cursor> <directory>",
+            "Write the files as they were at the cursor into the directory.",
+            &[Nothing, Paths],
+            vec![],
+        ),
+    ];
+
+    #[cfg(feature = "archive")]
+    commands.push(command(
+        "archive",
+        "<change> <archive>",
+        "Write the files as they were at the change into a zip or tar.gz archive.",
+        &[Changes, Paths],
+        vec![option(
+            "--format",
+            "format",
+            "The format, instead of telling it by the extension.",
+            Choices(&["zip", "tar.gz"]),
+        )],
+    ));
+    #[cfg(feature = "sqlite")]
+    commands.push(command(
+        "export-sqlite",
+        "<database>",
+        "Write the history into a new SQLite database.",
+        &[Paths],
+        vec![],
+    ));
+
+    commands.extend(vec![
+        command(
+            "diff",
+            "<file> <from> [<to>]",
+            "Show how the file changed between two cursors, or since one.",
+            &[Paths, Nothing],
+            vec![
+                switch("--word", "Mark changed words."),
+                switch("--char", "Mark changed characters."),
+            ],
+        ),
+        command(
+            "grep",
+            "<pattern>",
+            "Search the files as they are at the cursor.",
+            &[Nothing],
+            vec![option(
+                "--at",
+                "change",
+                "Search them as they were at the change.",
+                Changes,
+            )],
+        ),
+        command(
+            "timeline",
+            "",
+            "Print the history as JSON, for drawing it.",
+            &[],
+            vec![option(
+                "--sparkline",
+                "file",
+                "Draw the size of the file over time as an SVG.",
+                Paths,
+            )],
+        ),
+        command(
+            "log",
+            "[<path>]",
+            "List the changes of a file or directory, or all of them.",
+            &[Paths],
+            vec![
+                switch("--by-session", "List all changes by their session."),
+                switch("--by-minute", "Sum up the changes of each minute."),
+                switch("--by-hour", "Sum up the changes of each hour."),
+                switch("--by-day", "Sum up the changes of each day."),
+                option(
+                    "--annotated",
+                    "key[=value]",
+                    "List the changes with the annotation.",
+                    Nothing,
+                ),
+                option(
+                    "--since",
+                    "timestamp",
+                    "List the changes since the timestamp.",
+                    Nothing,
+                ),
+                switch(
+                    "--porcelain",
+                    "List them in a format for scripts, which stays the same across versions.",
+                ),
+            ],
+        ),
+        command(
+            "stats",
+            "",
+            "Print how large the history is and how it grew.",
+            &[],
+            vec![option(
+                "--bucket",
+                "duration",
+                "How long each span of growth is, like 1d.",
+                Nothing,
+            )],
+        ),
+        command(
+            "doctor",
+            "",
+            "Check whether ka works as it should here.",
+            &[],
+            vec![],
+        ),
+        command(
+            "migrate",
+            "",
+            "Convert histories in older formats.",
+            &[],
+            vec![],
+        ),
+        command(
+            "redelta",
+            "",
+            "Replace changes which were stored whole with diffs.",
+            &[],
+            vec![],
+        ),
+    ]);
+
+    #[cfg(unix)]
+    commands.push(command(
+        "watch",
+        "[status|stop|metrics]",
+        "Record changes whenever the files have been quiet for a while.",
+        &[Choices(if cfg!(feature = "metrics") {
+            &["status", "stop", "metrics"]
+        } else {
+            &["status", "stop"]
+        })],
+        vec![
+            option(
+                "--quiet",
+                "duration",
+                "How long the files have to be quiet, 10s by default.",
+                Nothing,
+            ),
+            option(
+                "--on-save-of",
+                "pattern",
+                "Only record saves of matching files, or not of them with a leading !.",
+                Nothing,
+            ),
+            option(
+                "--on-save-by",
+                "process",
+                "Only record saves by matching processes, or not by them with a leading !.",
+                Nothing,
+            ),
+        ],
+    ));
+    #[cfg(feature = "tui")]
+    commands.push(command(
+        "browse",
+        "",
+        "Browse the history interactively.",
+        &[],
+        vec![],
+    ));
+
+    commands.extend(vec![
+        command(
+            "recover",
+            "<file>",
+            "Restore a deleted file.",
+            &[Paths],
+            vec![option(
+                "--cursor",
+                "cursor",
+                "Restore it as it was at the cursor.",
+                Nothing,
+            )],
+        ),
+        command(
+            "deleted",
+            "",
+            "List the deleted files which can be restored.",
+            &[],
+            vec![],
+        ),
+        command(
+            "resurrect",
+            "<file>",
+            "Restore a file listed by deleted.",
+            &[Paths],
+            vec![option(
+                "--at",
+                "change",
+                "Restore it as it was at the change.",
+                Changes,
+            )],
+        ),
+        command(
+            "forget",
+            "<file>",
+            "Stop recording the file.",
+            &[Paths],
+            vec![switch("--purge", "Remove its history as well.")],
+        ),
+        command(
+            "redact",
+            "<change-index> <file>",
+            "Remove what the change recorded for the file.",
+            &[Nothing, Paths],
+            vec![],
+        ),
+        command(
+            "sync",
+            "",
+            "Bring files at their own cursor up to date.",
+            &[],
+            vec![switch("--force", "Overwrite unrecorded changes.")],
+        ),
+        command(
+            "session",
+            "start <name> | end",
+            "Start or end a named session of changes.",
+            &[Choices(&["start", "end"]), Nothing],
+            vec![],
+        ),
+        command(
+            "pin",
+            "<file>",
+            "Keep the history of the file from being pruned.",
+            &[Paths],
+            vec![],
+        ),
+        command(
+            "unpin",
+            "<file>",
+            "Let the history of the file be pruned again.",
+            &[Paths],
+            vec![],
+        ),
+        command(
+            "lock",
+            "[<file>]",
+            "Lock the file for others, or list the locked files.",
+            &[Paths],
+            vec![],
+        ),
+        command("unlock", "<file>", "Release a lock.", &[Paths], vec![]),
+        command(
+            "track",
+            "<directory>",
+            "Add the directory to the tracked ones.",
+            &[Paths],
+            vec![],
+        ),
+        command(
+            "untrack",
+            "<directory>",
+            "Remove the directory from the tracked ones.",
+            &[Paths],
+            vec![],
+        ),
+        command(
+            "workspace",
+            "update|status",
+            "Update or list the repositories of a workspace.",
+            &[Choices(&["update", "status"])],
+            vec![option(
+                "--workspace",
+                "file",
+                "The workspace, ./workspace by default.",
+                Paths,
+            )],
+        ),
+        command(
+            "completions",
+            "<shell>",
+            "Print the completions for bash, zsh or fish.",
+            &[Choices(SHELLS)],
+            vec![],
+        ),
+        command("man", "", "Print this manual page.", &[], vec![]),
+    ]);
+
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_commands;
+
+    // The function's source, from its signature up to its closing brace.
+    fn get_function<'a>(source: &'a str, name: &str) -> &'a str {
+        let start = source
+            .find(&format!("\nfn {}(", name))
+            .unwrap_or_else(|| panic!("There's no function {}.", name));
+        let length = source[start..].find("\n}\n").unwrap();
+        &source[start..start + length]
+    }
+
+    // The flags the source checks for, like `"--force"`.
+    fn get_flags(source: &str) -> Vec<&str> {
+        source
+            .split('"')
+            .skip(1)
+            .step_by(2)
+            .filter(|literal| literal.starts_with("--") && literal.len() > 2)
+            .collect()
+    }
+
+    // The commands `try_main` and `run` match on, each with the source of its arm. Arms only
+    // built on some targets or with some features follow a `#[cfg(..)]`.
+    fn get_arms(source: &str) -> Vec<(Vec<&str>, bool, String)> {
+        let mut arms: Vec<(Vec<&str>, bool, String)> = Vec::new();
+        let mut is_conditional = false;
+        for line in source.lines() {
+            let is_arm = line.starts_with("        \"") && line.contains("\" => ");
+            if is_arm {
+                let patterns = &line[..line.find(" => ").unwrap()];
+                let names = patterns
+                    .split(" | ")
+                    .map(|pattern| pattern.trim().trim_matches('"'))
+                    .collect();
+                arms.push((names, is_conditional, String::new()));
+            }
+            is_conditional = line.trim_start().starts_with("#[cfg(");
+            if let Some((_, _, arm_source)) = arms.last_mut() {
+                arm_source.push_str(line);
+                arm_source.push('\n');
+            }
+        }
+        arms
+    }
+
+    #[test]
+    fn list_every_command_and_flag() {
+        let main_source = include_str!("main.rs");
+        let mut arms = get_arms(get_function(main_source, "try_main"));
+        arms.extend(get_arms(get_function(main_source, "run")));
+        // Both take their further arguments elsewhere.
+        arms.push((
+            vec!["workspace"],
+            false,
+            get_function(main_source, "run_workspace").to_string(),
+        ));
+        arms.push((vec!["watch"], true, include_str!("watch.rs").to_string()));
+
+        let commands = get_commands();
+        for (names, is_conditional, arm_source) in arms {
+            for name in names {
+                // Only the completions call this one.
+                if name == "complete" {
+                    continue;
+                }
+                let command = match commands.iter().find(|command| command.name == name) {
+                    Some(command) => command,
+                    None if is_conditional => continue,
+                    None => panic!("The command '{}' isn't listed.", name),
+                };
+                for flag in get_flags(&arm_source) {
+                    assert!(
+                        command.flags.iter().any(|listed| listed.name == flag),
+                        "The flag '{}' of '{}' isn't listed.",
+                        flag,
+                        name
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,473 @@
+// Completions for shells and the man page, both generated from the commands in `commands.rs`.
+// Change IDs and session names are completed by the shells calling `ka complete changes` and
+// `ka complete sessions`, which list those of the repository they're run in.
+
+use std::{collections::BTreeSet, env, fmt::Write};
+
+use anyhow::{bail, Result};
+
+use ka::{
+    actions::{repository_log, session_log, ActionOptions},
+    config::Config,
+    filesystem::FsImpl,
+};
+
+use crate::{
+    commands::{get_commands, Command, Completion, Flag, SHELLS, VERBOSE_FLAG},
+    SHORT_ID_LENGTH,
+};
+
+pub fn render_completions(shell: &str, program: &str) -> Result<String> {
+    let completions = match shell {
+        "bash" => render_bash(program),
+        // zsh runs the completions for bash just fine, once it's been told to.
+        "zsh" => format!(
+            "autoload -U +X bashcompinit && bashcompinit\n\n{}",
+            render_bash(program)
+        ),
+        "fish" => render_fish(program),
+        shell => bail!(
+            "Unknown shell '{}', expected one of {}.",
+            shell,
+            SHELLS.join(", ")
+        ),
+    };
+    Ok(completions)
+}
+
+// Prints the change IDs or session names of the repository, one per line. Outside of a
+// repository, or in an encrypted one, it quietly prints nothing, since shells show whatever
+// goes wrong while completing in the middle of the command line.
+pub fn print_candidates(kind: &str, filesystem: &FsImpl) {
+    let options = match env::current_dir()
+        .ok()
+        .and_then(|current_directory| ActionOptions::discover(filesystem, &current_directory).ok())
+    {
+        Some(options) => options,
+        None => return,
+    };
+    let is_encrypted =
+        Config::load_from(filesystem, &options).map_or(true, |config| config.encryption.is_some());
+    if is_encrypted {
+        return;
+    }
+
+    let candidates = match kind {
+        "changes" => repository_log(options, filesystem)
+            .unwrap_or_default()
+            .into_iter()
+            .rev()
+            .filter_map(|entry| entry.change_id)
+            .map(|change_id| change_id[..SHORT_ID_LENGTH].to_string())
+            .collect::<Vec<_>>(),
+        "sessions" => session_log(options, filesystem)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|group| group.name)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+    for candidate in candidates {
+        println!("{}", candidate);
+    }
+}
+
+fn render_bash(program: &str) -> String {
+    let commands = get_commands();
+    let function = format!("_{}", program.replace('-', "_"));
+    let mut script = String::new();
+
+    writeln!(script, "{}() {{", function).unwrap();
+    script.push_str(
+        r#"    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local command="" position=0 value=0 index word
+    for ((index = 1; index < COMP_CWORD; index++)); do
+        word="${COMP_WORDS[index]}"
+        if ((value)); then
+            value=0
+            continue
+        fi
+        case "$word" in
+            --verbose | -v) continue ;;
+        esac
+        if [[ -z "$command" ]]; then
+            command="$word"
+            continue
+        fi
+        case "$command $word" in
+"#,
+    );
+    let value_flags = commands
+        .iter()
+        .flat_map(|command| {
+            command
+                .flags
+                .iter()
+                .filter(|flag| flag.value.is_some())
+                .map(move |flag| format!("\"{} {}\"", command.name, flag.name))
+        })
+        .collect::<Vec<_>>();
+    if !value_flags.is_empty() {
+        writeln!(
+            script,
+            "            {}) value=1 ;;",
+            value_flags.join(" | ")
+        )
+        .unwrap();
+    }
+    script.push_str(
+        r#"            *" -"*) ;;
+            *) ((position++)) ;;
+        esac
+    done
+
+    if [[ -z "$command" ]]; then
+"#,
+    );
+    let names = commands
+        .iter()
+        .map(|command| command.name)
+        .collect::<Vec<_>>();
+    writeln!(script, "        {}", render_bash_words(&names)).unwrap();
+    script.push_str(
+        r#"        return
+    fi
+
+    if ((value)); then
+        case "$command ${COMP_WORDS[COMP_CWORD - 1]}" in
+"#,
+    );
+    for command in &commands {
+        for flag in command.flags.iter().filter(|flag| flag.value.is_some()) {
+            writeln!(
+                script,
+                "            \"{} {}\") {} ;;",
+                command.name,
+                flag.name,
+                render_bash_completion(program, flag.completion)
+            )
+            .unwrap();
+        }
+    }
+    script.push_str(
+        r#"            *) COMPREPLY=() ;;
+        esac
+        return
+    fi
+
+    if [[ "$cur" == -* ]]; then
+        case "$command" in
+"#,
+    );
+    for command in &commands {
+        let flags = command
+            .flags
+            .iter()
+            .chain(Some(&VERBOSE_FLAG))
+            .map(|flag| flag.name)
+            .collect::<Vec<_>>();
+        writeln!(
+            script,
+            "            {}) {} ;;",
+            command.name,
+            render_bash_words(&flags)
+        )
+        .unwrap();
+    }
+    script.push_str(
+        r#"            *) COMPREPLY=() ;;
+        esac
+        return
+    fi
+
+    case "$command $position" in
+"#,
+    );
+    for command in &commands {
+        for (index, completion) in command.arguments.iter().enumerate() {
+            // The last argument also completes any further ones.
+            let position = if index + 1 == command.arguments.len() {
+                "\"*".to_string()
+            } else {
+                format!("{}\"", index)
+            };
+            writeln!(
+                script,
+                "        \"{} {}) {} ;;",
+                command.name,
+                position,
+                render_bash_completion(program, *completion)
+            )
+            .unwrap();
+        }
+    }
+    script.push_str(
+        r#"        *) COMPREPLY=() ;;
+    esac
+}
+
+"#,
+    );
+    writeln!(script, "complete -F {} {}", function, program).unwrap();
+
+    script
+}
+
+fn render_bash_completion(program: &str, completion: Completion) -> String {
+    match completion {
+        Completion::Nothing => "COMPREPLY=()".to_string(),
+        Completion::Paths => {
+            "compopt -o filenames 2>/dev/null; COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+        }
+        Completion::Changes => format!(
+            "COMPREPLY=($(compgen -W \"$({} complete changes 2>/dev/null)\" -- \"$cur\"))",
+            program
+        ),
+        Completion::Sessions => format!(
+            "COMPREPLY=($(compgen -W \"$({} complete sessions 2>/dev/null)\" -- \"$cur\"))",
+            program
+        ),
+        Completion::Choices(choices) => render_bash_words(choices),
+    }
+}
+
+fn render_bash_words(words: &[&str]) -> String {
+    format!(
+        "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+        words.join(" ")
+    )
+}
+
+fn render_fish(program: &str) -> String {
+    let commands = get_commands();
+    let function = format!("__{}_argument", program.replace('-', "_"));
+    let mut script = String::new();
+
+    // Whether the command line is at the positional argument of the command, or past it with
+    // `last`. The flags after those take a value, which isn't counted.
+    writeln!(script, "function {} -a command position mode", function).unwrap();
+    script.push_str(
+        r#"    set -l value_flags $argv[4..-1]
+    set -l words (commandline -opc)
+    set -e words[1]
+    set -l found 0
+    set -l count 0
+    set -l value 0
+    for word in $words
+        if test $value = 1
+            set value 0
+            continue
+        end
+        if contains -- $word --verbose -v
+            continue
+        end
+        if test $found = 0
+            test $word = $command; or return 1
+            set found 1
+        else if contains -- $word $value_flags
+            set value 1
+        else if not string match -q -- '-*' $word
+            set count (math $count + 1)
+        end
+    end
+    test $found = 1; and test $value = 0; or return 1
+    if test $mode = last
+        test $count -ge $position
+    else
+        test $count -eq $position
+    end
+end
+
+"#,
+    );
+
+    writeln!(script, "complete -c {} -f", program).unwrap();
+    writeln!(
+        script,
+        "complete -c {} -s v -l verbose -d {}",
+        program,
+        quote_fish(VERBOSE_FLAG.description)
+    )
+    .unwrap();
+    for command in &commands {
+        writeln!(
+            script,
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}",
+            program,
+            command.name,
+            quote_fish(command.description)
+        )
+        .unwrap();
+    }
+
+    for command in &commands {
+        let condition = format!("__fish_seen_subcommand_from {}", command.name);
+        for flag in &command.flags {
+            writeln!(
+                script,
+                "complete -c {} -n '{}' {} -d {}",
+                program,
+                condition,
+                render_fish_flag(program, flag),
+                quote_fish(flag.description)
+            )
+            .unwrap();
+        }
+
+        let value_flags = command
+            .flags
+            .iter()
+            .filter(|flag| flag.value.is_some())
+            .map(|flag| format!(" {}", flag.name))
+            .collect::<String>();
+        for (index, completion) in command.arguments.iter().enumerate() {
+            let mode = if index + 1 == command.arguments.len() {
+                "last"
+            } else {
+                "exact"
+            };
+            let arguments = match render_fish_completion(program, *completion) {
+                Some(arguments) => arguments,
+                None => continue,
+            };
+            writeln!(
+                script,
+                "complete -c {} -n '{} {} {} {}{}' {}",
+                program, function, command.name, index, mode, value_flags, arguments
+            )
+            .unwrap();
+        }
+    }
+
+    script
+}
+
+fn render_fish_flag(program: &str, flag: &Flag) -> String {
+    let name = format!("-l {}", flag.name.trim_start_matches("--"));
+    if flag.value.is_none() {
+        return name;
+    }
+    match render_fish_completion(program, flag.completion) {
+        Some(arguments) => format!("{} -r {}", name, arguments),
+        None => format!("{} -r", name),
+    }
+}
+
+fn render_fish_completion(program: &str, completion: Completion) -> Option<String> {
+    match completion {
+        Completion::Nothing => None,
+        Completion::Paths => Some("-F".to_string()),
+        Completion::Changes => Some(format!("-a '({} complete changes)'", program)),
+        Completion::Sessions => Some(format!("-a '({} complete sessions)'", program)),
+        Completion::Choices(choices) => Some(format!("-a '{}'", choices.join(" "))),
+    }
+}
+
+fn quote_fish(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+pub fn render_man_page(program: &str) -> String {
+    let mut page = String::new();
+
+    writeln!(page, ".TH {} 1", escape_roff(&program.to_uppercase())).unwrap();
+    page.push_str(".SH NAME\n");
+    writeln!(
+        page,
+        "{} \\- keeps the entire history of every file in a directory",
+        escape_roff(program)
+    )
+    .unwrap();
+    page.push_str(".SH SYNOPSIS\n");
+    writeln!(
+        page,
+        "\\fB{}\\fR [\\fB\\-\\-verbose\\fR] \\fIcommand\\fR [\\fIarguments\\fR]",
+        escape_roff(program)
+    )
+    .unwrap();
+    page.push_str(".SH DESCRIPTION\n");
+    page.push_str(
+        "Records every change of the files in a directory, in a \\fB.ka\\fR directory inside \
+         of it. Every command but \\fBcreate\\fR and \\fBrestore\\-backup\\fR works from \
+         anywhere inside of the repository.\n",
+    );
+    page.push_str(
+        ".PP\nChanges are referred to by their index, counting from 1, and most commands also \
+         take the ID of a change, or the start of one.\n",
+    );
+
+    page.push_str(".SH OPTIONS\n");
+    render_man_flag(&mut page, &VERBOSE_FLAG);
+
+    page.push_str(".SH COMMANDS\n");
+    for command in get_commands() {
+        render_man_command(&mut page, &command);
+    }
+
+    page.push_str(".SH EXIT STATUS\n");
+    for (code, description) in [
+        (0, "Success."),
+        (
+            1,
+            "Any other error, e.g. a missing argument or a file outside of the repository.",
+        ),
+        (
+            2,
+            "The index in \\fB.ka\\fR is missing or unreadable, or something in it is truncated or corrupted, \\fBdoctor\\fR tells more.",
+        ),
+        (
+            3,
+            "A file is locked by someone else, or a watcher is running for the repository already.",
+        ),
+    ] {
+        writeln!(page, ".TP\n\\fB{}\\fR\n{}", code, description).unwrap();
+    }
+
+    page.push_str(".SH ENVIRONMENT\n");
+    page.push_str(".TP\n\\fBKA_PASSPHRASE\\fR\nThe passphrase of an encrypted repository.\n");
+    page.push_str(
+        ".TP\n\\fBRUST_LOG\\fR\nThe level of what is reported on stderr, like \\fBdebug\\fR.\n",
+    );
+
+    page
+}
+
+fn render_man_command(page: &mut String, command: &Command) {
+    page.push_str(".TP\n");
+    write!(page, "\\fB{}\\fR", escape_roff(command.name)).unwrap();
+    if !command.synopsis.is_empty() {
+        write!(page, " \\fI{}\\fR", escape_roff(command.synopsis)).unwrap();
+    }
+    page.push('\n');
+    writeln!(page, "{}", escape_roff(command.description)).unwrap();
+
+    if !command.flags.is_empty() {
+        page.push_str(".RS\n");
+        for flag in &command.flags {
+            render_man_flag(page, flag);
+        }
+        page.push_str(".RE\n");
+    }
+}
+
+fn render_man_flag(page: &mut String, flag: &Flag) {
+    page.push_str(".TP\n");
+    write!(page, "\\fB{}\\fR", escape_roff(flag.name)).unwrap();
+    if let Some(value) = flag.value {
+        write!(page, " \\fI{}\\fR", escape_roff(value)).unwrap();
+    }
+    page.push('\n');
+    writeln!(page, "{}", escape_roff(flag.description)).unwrap();
+}
+
+fn escape_roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    // Lines starting with these would be taken as requests.
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
@@ -1,36 +1,1271 @@
-use std::{env, time::SystemTime};
+use std::{
+    env, fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{bail, Context, Error, Result};
 
+#[cfg(feature = "sqlite")]
+use ka::actions::export_sqlite;
+#[cfg(feature = "archive")]
+use ka::actions::{archive, ArchiveFormat};
 use ka::{
-    actions::{create, shift, update, ActionOptions},
-    filesystem::FsImpl,
+    actions::{
+        adopt, apply_retention, create, create_baseline, create_from_template, doctor, end_session,
+        extract, find_session, forget, import, migrate, pin, preview_shift, prune, recover, redact,
+        redelta, render_sparkline, resolve_change, revert, session_log, squash, start_session,
+        stats, sync, track, unpin, untrack, update_interactive, ActionOptions, CheckOutcome,
+        ShiftPreviewEntry, ShiftPreviewKind,
+    },
+    config::{parse_duration, Config},
+    consistency::Inconsistency,
+    diff::{ApplyError, Hunk, SegmentKind, TextGranularity, TextSegment},
+    encryption::{encrypt_repository, EncryptedFs},
+    files::{normalize_path, Locations},
+    filesystem::{Fs, FsImpl},
+    images::{ImageChange, ImageInfo},
+    policy::{ContentType, SkipReason},
+    templates::get_templates_path,
+    trace::{set_subscriber, Fields as TraceFields, Level, Subscriber},
+    workspace::Workspace,
+    Annotations, CorruptionError, FileLogKind, FileStatus, LockedError, LogBucket, LogEntry,
+    Progress, Repository, ShiftMode, Status, Summary, Template, UntrackedFiles, UpdateSummary,
 };
 
+#[cfg(feature = "tui")]
+mod browse;
+mod commands;
+mod completions;
+mod porcelain;
+#[cfg(unix)]
+mod watch;
+
+// How many characters of a change ID are shown, which is usually enough to tell them apart.
+const SHORT_ID_LENGTH: usize = 12;
+
+// The exit codes scripts can tell failures apart by, which are listed in the man page. Anything
+// else that goes wrong exits with 1, and bugs still panic, with 101.
+const EXIT_FAILURE: i32 = 1;
+// The index is missing or unreadable, or something in `.ka` can't be read, `ka doctor` tells more.
+const EXIT_CORRUPTED: i32 = 2;
+// A file is locked by someone else, or a watcher is running already.
+const EXIT_LOCKED: i32 = 3;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let command = args[1].as_str();
+    if let Err(error) = try_main() {
+        eprintln!("Error: {:?}", error);
+        process::exit(get_exit_code(&error));
+    }
+}
+
+fn get_exit_code(error: &Error) -> i32 {
+    if error.downcast_ref::<CorruptionError>().is_some()
+        || error.downcast_ref::<ApplyError>().is_some()
+        || error.downcast_ref::<UnreadableIndexError>().is_some()
+    {
+        EXIT_CORRUPTED
+    } else if error.downcast_ref::<LockedError>().is_some() {
+        EXIT_LOCKED
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+// The index can't be opened, which like a corrupted one leaves the repository unusable until it's
+// repaired or restored.
+#[derive(Debug)]
+struct UnreadableIndexError(PathBuf);
+
+impl fmt::Display for UnreadableIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The index '{}' is missing or can't be read, `ka doctor` tells more.",
+            self.0.display()
+        )
+    }
+}
+
+impl std::error::Error for UnreadableIndexError {}
 
-    let options = ActionOptions::from_path("./repo");
-    //let options = ActionOptions::from_pwd().expect("Could not get current path.");
+fn try_main() -> Result<()> {
+    let (verbose_args, args): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| arg == "--verbose" || arg == "-v");
+    install_trace_subscriber(verbose_args.len())?;
+    let command = get_argument(&args, 1, "a command, see the man page")?;
 
     let filesystem = FsImpl {};
 
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("Could not get current system time.")
-        .as_secs();
+    // These don't need a repository. Completions and the man page are for whatever name the
+    // binary was installed under.
+    let program = Path::new(&args[0])
+        .file_name()
+        .map_or("ka".into(), |name| name.to_string_lossy());
+    match command {
+        "completions" => {
+            let shell = get_argument(&args, 2, "a shell")?;
+            print!("{}", completions::render_completions(shell, &program)?);
+            return Ok(());
+        }
+        "man" => {
+            print!("{}", completions::render_man_page(&program));
+            return Ok(());
+        }
+        // Used by the completions, for the change IDs and session names.
+        "complete" => {
+            completions::print_candidates(args.get(2).map_or("", String::as_str), &filesystem);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Every command but `create` and `restore-backup` works from anywhere inside of the
+    // repository.
+    let options = if command == "create" || command == "restore-backup" {
+        ActionOptions::from_pwd().context("Could not get current path.")?
+    } else {
+        let current_directory = env::current_dir().context("Could not get current path.")?;
+        ActionOptions::discover(&filesystem, &current_directory)
+            .context("Failed finding the repository.")?
+    };
+
+    let timestamp = options.now();
+
+    if command == "workspace" {
+        return run_workspace(&args, &filesystem, timestamp);
+    }
+
+    // Nearly every command reads the index, so one which is missing or can't be read is told
+    // apart up front, rather than failing like any other file. `doctor` reports it itself.
+    if !matches!(command, "create" | "restore-backup" | "doctor") {
+        let index_path = Locations::from(&options).get_repository_index_path();
+        if let Err(error) = filesystem.open_readable_file(&index_path) {
+            return Err(error.context(UnreadableIndexError(index_path)));
+        }
+    }
+
+    let is_encrypted = command != "create"
+        && command != "restore-backup"
+        && Config::load_from(&filesystem, &options)
+            .context("Failed loading config.")?
+            .encryption
+            .is_some();
+
+    if is_encrypted {
+        let encrypted_filesystem = EncryptedFs::open(&filesystem, &options, &get_passphrase()?)
+            .context("Failed opening encrypted repository.")?;
+        run(command, &args, options, &encrypted_filesystem, timestamp)
+    } else {
+        run(command, &args, options, &filesystem, timestamp)
+    }
+}
+
+// What the actions are doing goes to stderr, with `--verbose` (twice for every file) or a
+// level like `debug` in `RUST_LOG`.
+struct StderrSubscriber {
+    max_level: Level,
+}
+
+impl Subscriber for StderrSubscriber {
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.max_level
+    }
+
+    fn event(&self, level: Level, spans: &[&'static str], message: &str, fields: TraceFields) {
+        let mut line = format!("{:>5} ", level);
+        if !spans.is_empty() {
+            line.push_str(&format!("{}: ", spans.join(":")));
+        }
+        line.push_str(message);
+        for (name, value) in fields {
+            line.push_str(&format!(" {}={}", name, value));
+        }
+        eprintln!("{}", line);
+    }
+}
+
+// Keeps rewriting a single line on stderr.
+struct StderrProgress;
+
+impl Progress for StderrProgress {
+    fn file_done(&mut self, _working_path: &Path, done: usize, total: usize) {
+        eprint!("\rImported {}/{} files.", done, total);
+        if done == total {
+            eprintln!();
+        }
+    }
+}
+
+fn install_trace_subscriber(verbosity: usize) -> Result<()> {
+    let max_level = match verbosity {
+        0 => env::var("RUST_LOG")
+            .ok()
+            .and_then(|level| Level::parse(level.rsplit('=').next().unwrap_or_default())),
+        1 => Some(Level::Debug),
+        _ => Some(Level::Trace),
+    };
 
+    if let Some(max_level) = max_level {
+        let subscriber: &'static StderrSubscriber =
+            Box::leak(Box::new(StderrSubscriber { max_level }));
+        set_subscriber(subscriber).context("Failed installing the trace subscriber.")?;
+    }
+    Ok(())
+}
+
+fn run(
+    command: &str,
+    args: &[String],
+    options: ActionOptions,
+    filesystem: &impl Fs,
+    timestamp: u64,
+) -> Result<()> {
     match command {
         "create" => {
-            create(options, &filesystem, timestamp).expect("Failed executing Create action.");
+            // Large directories are better imported, which can be resumed by running it again.
+            let template = get_flag_value(args, "--template");
+            if template.is_some()
+                && args
+                    .iter()
+                    .any(|arg| arg == "--import" || arg == "--baseline")
+            {
+                bail!("Templates can't be combined with --import or --baseline.");
+            }
+            let summary = if let Some(name) = template {
+                let template = Template::load(filesystem, name, get_templates_path().as_deref())
+                    .context("Failed loading template.")?;
+                create_from_template(options.clone(), filesystem, timestamp, &template)
+                    .context("Failed executing Create action.")?
+            } else if args.iter().any(|arg| arg == "--import") {
+                import(options.clone(), filesystem, timestamp, &mut StderrProgress)
+                    .context("Failed executing Import action.")?
+            } else if args.iter().any(|arg| arg == "--baseline") {
+                // Quicker, as long as nothing writes to the files meanwhile.
+                create_baseline(options.clone(), filesystem, timestamp)
+                    .context("Failed executing Create action.")?
+            } else {
+                create(options.clone(), filesystem, timestamp)
+                    .context("Failed executing Create action.")?
+            };
+            print_update_summary(&options, summary);
+
+            if args.iter().any(|arg| arg == "--encrypt") {
+                encrypt_repository(&options, filesystem, &get_passphrase()?)
+                    .context("Failed encrypting repository.")?;
+            }
         }
         "update" => {
-            update(options, &filesystem, timestamp).expect("Failed executing Update action.");
+            // Each `--annotate key=value` is kept with the change.
+            let annotations: Annotations = args
+                .windows(2)
+                .filter(|pair| pair[0] == "--annotate")
+                .map(|pair| parse_annotation(&pair[1]))
+                .collect::<Result<_>>()?;
+            let summary = if args.iter().any(|arg| arg == "--interactive") {
+                if !annotations.is_empty() {
+                    bail!("Interactive updates can't be annotated.");
+                }
+                update_interactive(
+                    options.clone(),
+                    filesystem,
+                    timestamp,
+                    &mut |path, old_content, hunk| {
+                        ask_for_hunk(options.display_path(path), old_content, hunk)
+                    },
+                )
+            } else {
+                open_repository(&options, filesystem)?.update_annotated(timestamp, annotations)
+            }
+            .context("Failed executing Update action.")?;
+            print_update_summary(&options, summary);
+            apply_retention(options, filesystem, timestamp)
+                .context("Failed applying retention policy.")?;
         }
         "shift" => {
-            let new_cursor: usize = args[2].as_str().parse().expect("Invalid cursor.");
+            if args.iter().any(|arg| arg == "--undo") {
+                let force = args.iter().any(|arg| arg == "--force");
+                open_repository(&options, filesystem)?
+                    .undo_shift(force)
+                    .context("Failed undoing the shift.")?;
+                return Ok(());
+            }
+
+            // Sessions are shifted to by their name, jumping to their end or start.
+            let new_cursor = if let Some(name) = get_flag_value(args, "--session") {
+                find_session(options.clone(), filesystem, name)
+                    .context("Failed finding session.")?
+                    .1
+            } else if let Some(name) = get_flag_value(args, "--session-start") {
+                find_session(options.clone(), filesystem, name)
+                    .context("Failed finding session.")?
+                    .0
+            } else {
+                let new_cursor = args[2..]
+                    .iter()
+                    .find(|arg| !arg.starts_with("--"))
+                    .context("Expected a cursor.")?;
+                resolve_change(options.clone(), filesystem, new_cursor)
+                    .context("Invalid cursor.")?
+            };
+
+            if args.iter().any(|arg| arg == "--preview") {
+                let preview = preview_shift(options.clone(), filesystem, new_cursor)
+                    .context("Failed executing Shift preview.")?;
+                print_shift_preview(&options, preview);
+                return Ok(());
+            }
+
+            let mode = if args.iter().any(|arg| arg == "--force") {
+                ShiftMode::Force
+            } else if args.iter().any(|arg| arg == "--auto-update") {
+                ShiftMode::AutoUpdate(timestamp)
+            } else {
+                ShiftMode::Safe
+            };
+
+            let untracked_files = if args.iter().any(|arg| arg == "--clean") {
+                UntrackedFiles::Clean
+            } else {
+                UntrackedFiles::Keep
+            };
+
+            let untracked_paths = open_repository(&options, filesystem)?
+                .shift(new_cursor, mode, untracked_files)
+                .context("Failed executing Shift actions.")?;
+
+            for path in untracked_paths {
+                println!(
+                    "Left untracked '{}' in place.",
+                    options.display_path(&path).display()
+                );
+            }
+        }
+        "revert" => {
+            let change_index = resolve_change(
+                options.clone(),
+                filesystem,
+                get_argument(args, 2, "a change")?,
+            )
+            .context("Invalid change index.")?;
+
+            revert(options, filesystem, change_index, timestamp)
+                .context("Failed executing Revert action.")?;
+        }
+        "adopt" => {
+            let adopted_files = adopt(options.clone(), filesystem, timestamp)
+                .context("Failed executing Adopt action.")?;
+
+            for path in adopted_files {
+                println!("Adopted '{}'.", options.display_path(&path).display());
+            }
+        }
+        "squash" => {
+            let from: usize = get_argument(args, 2, "a cursor")?
+                .parse()
+                .context("Invalid cursor.")?;
+            let to: usize = get_argument(args, 3, "a cursor")?
+                .parse()
+                .context("Invalid cursor.")?;
+            let message = get_flag_value(args, "--message").map(String::from);
+
+            squash(options, filesystem, from, to, message)
+                .context("Failed executing Squash action.")?;
+        }
+        "prune" => {
+            let pruned = match get_flag_value(args, "--since") {
+                Some(since) => {
+                    let since = since.parse().context("Invalid timestamp.")?;
+                    prune(options, filesystem, since)
+                }
+                None => apply_retention(options, filesystem, timestamp),
+            }
+            .context("Failed executing Prune action.")?;
+
+            println!("Pruned {} changes.", pruned);
+        }
+        "status" => {
+            let status = open_repository(&options, filesystem)?
+                .status()
+                .context("Failed executing Status action.")?;
+
+            if args.iter().any(|arg| arg == "--porcelain") {
+                porcelain::print_status(&options, &status);
+            } else {
+                print_status(&options, status);
+            }
+        }
+        "summary" => {
+            let summary = open_repository(&options, filesystem)?
+                .summary()
+                .context("Failed executing Summary action.")?;
+
+            if args.iter().any(|arg| arg == "--porcelain") {
+                porcelain::print_summary(&summary);
+            } else if args.iter().any(|arg| arg == "--prompt") {
+                print_prompt_summary(&summary);
+            } else {
+                print_summary(&summary);
+            }
+        }
+        "show" => {
+            let (path, cursor) = get_argument(args, 2, "a file as <file>@<cursor>")?
+                .rsplit_once('@')
+                .context("Expected the file as <file>@<cursor>.")?;
+            let cursor =
+                resolve_change(options.clone(), filesystem, cursor).context("Invalid cursor.")?;
+
+            let path = resolve_path(&options, path)?;
+            let content = open_repository(&options, filesystem)?
+                .show(&path, cursor)
+                .context("Failed executing Show action.")?;
+
+            match get_flag_value(args, "--output") {
+                Some(output_path) => {
+                    let mut output_file = filesystem
+                        .create_file(Path::new(output_path))
+                        .context("Failed creating output file.")?;
+                    filesystem
+                        .write_to_file(&mut output_file, content)
+                        .context("Failed writing output file.")?;
+                }
+                None => io::stdout()
+                    .write_all(&content)
+                    .context("Failed writing to output.")?,
+            }
+        }
+        "backup" => {
+            let target_path = Path::new(get_argument(args, 2, "a target path")?);
+            let summary = open_repository(&options, filesystem)?
+                .backup(target_path, timestamp)
+                .context("Failed executing Backup action.")?;
+
+            println!(
+                "Copied {} files, {} unchanged, removed {}.",
+                summary.copied, summary.unchanged, summary.removed
+            );
+        }
+        "restore-backup" => {
+            let backup_path = Path::new(get_argument(args, 2, "a backup")?);
+            // Absolute like the paths of discovered repositories, which the restored one is
+            // opened with later.
+            let target_path = args.get(3).map_or(options.repository_path.clone(), |path| {
+                normalize_path(&options.repository_path.join(path))
+            });
+            let (_, restored_paths) =
+                Repository::restore_from_backup(filesystem, backup_path, &target_path)
+                    .context("Failed executing Restore Backup action.")?;
+
+            println!(
+                "Restored {} files into '{}'.",
+                restored_paths.len(),
+                target_path.display()
+            );
+        }
+        "extract" => {
+            let cursor: usize = get_argument(args, 2, "a cursor")?
+                .parse()
+                .context("Invalid cursor.")?;
+            let target_path = Path::new(get_argument(args, 3, "a target path")?);
+
+            let extracted_paths = extract(options, filesystem, cursor, target_path)
+                .context("Failed executing Extract action.")?;
+
+            println!(
+                "Extracted {} files into '{}'.",
+                extracted_paths.len(),
+                target_path.display()
+            );
+        }
+        #[cfg(feature = "archive")]
+        "archive" => {
+            let cursor = resolve_change(
+                options.clone(),
+                filesystem,
+                get_argument(args, 2, "a change")?,
+            )
+            .context("Invalid cursor.")?;
+            let target_path = Path::new(get_argument(args, 3, "a target path")?);
+            let format = match get_flag_value(args, "--format") {
+                Some("zip") => ArchiveFormat::Zip,
+                Some("tar.gz") => ArchiveFormat::TarGz,
+                Some(format) => bail!("Unknown archive format: {}", format),
+                None => ArchiveFormat::from_path(target_path)
+                    .context("Expected a .zip or .tar.gz archive, or a --format.")?,
+            };
+
+            let archived_paths = archive(options, filesystem, cursor, format, target_path)
+                .context("Failed executing Archive action.")?;
+
+            println!(
+                "Archived {} files into '{}'.",
+                archived_paths.len(),
+                target_path.display()
+            );
+        }
+        #[cfg(feature = "sqlite")]
+        "export-sqlite" => {
+            let target_path = Path::new(get_argument(args, 2, "a target path")?);
+
+            let summary = export_sqlite(options, filesystem, target_path)
+                .context("Failed executing Export SQLite action.")?;
+
+            println!(
+                "Exported {} changes of {} files into '{}'.",
+                summary.change_count,
+                summary.file_count,
+                target_path.display()
+            );
+        }
+        "diff" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+            let from: usize = get_argument(args, 3, "a cursor")?
+                .parse()
+                .context("Invalid cursor.")?;
+            let to = args
+                .get(4)
+                .filter(|arg| !arg.starts_with("--"))
+                .map(|to| to.parse().context("Invalid cursor."))
+                .transpose()?;
+
+            let granularity = if args.iter().any(|arg| arg == "--word") {
+                TextGranularity::Word
+            } else if args.iter().any(|arg| arg == "--char") {
+                TextGranularity::Character
+            } else {
+                TextGranularity::Line
+            };
+
+            let segments = open_repository(&options, filesystem)?
+                .diff(&path, from, to, granularity)
+                .context("Failed executing Diff action.")?;
+
+            print_diff(segments, granularity);
+        }
+        // Searches the files as they were at `--at`, or at the cursor.
+        "grep" => {
+            let repository = open_repository(&options, filesystem)?;
+            let cursor = match get_flag_value(args, "--at") {
+                Some(cursor) => resolve_change(options.clone(), filesystem, cursor)
+                    .context("Invalid cursor.")?,
+                None => {
+                    repository
+                        .status()
+                        .context("Failed executing Status action.")?
+                        .cursor
+                }
+            };
+
+            let matches = repository
+                .grep(get_argument(args, 2, "a pattern")?, cursor)
+                .context("Failed executing Grep action.")?;
+            for grep_match in matches {
+                println!(
+                    "{}:{}: {}",
+                    grep_match.path.display(),
+                    grep_match.line_number,
+                    grep_match.line
+                );
+            }
+        }
+        "timeline" => {
+            let timeline = open_repository(&options, filesystem)?
+                .timeline()
+                .context("Failed executing Timeline action.")?;
+
+            // A single file can also be drawn right away, as an SVG of its size over time.
+            let output = match get_flag_value(args, "--sparkline") {
+                Some(path) => {
+                    let path = resolve_path(&options, path)?;
+                    let file = timeline
+                        .files
+                        .iter()
+                        .find(|file| file.path == path)
+                        .context("The file has no history.")?;
+                    render_sparkline(file, timeline.changes.len(), 120, 24).into_bytes()
+                }
+                None => timeline.encode().context("Failed encoding timeline.")?,
+            };
+            io::stdout()
+                .write_all(&output)
+                .context("Failed writing to output.")?;
+        }
+        "log" => {
+            // Only single changes are listed for scripts.
+            let porcelain = args.iter().any(|arg| arg == "--porcelain");
+            let is_grouped = args.iter().any(|arg| {
+                ["--by-session", "--by-minute", "--by-hour", "--by-day"].contains(&arg.as_str())
+            });
+            if porcelain && is_grouped {
+                bail!("Grouped changes can't be listed with --porcelain.");
+            }
+
+            if args.iter().any(|arg| arg == "--by-session") {
+                let groups =
+                    session_log(options, filesystem).context("Failed executing Log action.")?;
+                for group in groups {
+                    match group.name {
+                        Some(name) => println!("Session '{}':", name),
+                        None => println!("Outside of sessions:"),
+                    }
+                    for entry in group.entries {
+                        println!(
+                            "  {} at {}: {} files",
+                            entry.change_index,
+                            entry.timestamp,
+                            entry.affected_files.len()
+                        );
+                    }
+                }
+                return Ok(());
+            }
+
+            let bucket_seconds = [
+                ("--by-minute", 60),
+                ("--by-hour", 60 * 60),
+                ("--by-day", 60 * 60 * 24),
+            ]
+            .iter()
+            .find(|(flag, _)| args.iter().any(|arg| arg == flag))
+            .map(|(_, bucket_seconds)| *bucket_seconds);
+            if let Some(bucket_seconds) = bucket_seconds {
+                let buckets = open_repository(&options, filesystem)?
+                    .change_buckets(bucket_seconds)
+                    .context("Failed executing Log action.")?;
+                for bucket in buckets {
+                    print_log_bucket(&bucket);
+                }
+                return Ok(());
+            }
+
+            let print_entry = |entry: LogEntry| {
+                if porcelain {
+                    porcelain::print_log_entry(&options, &entry);
+                } else {
+                    print_log_entry(entry);
+                }
+            };
+
+            if let Some(annotation) = get_flag_value(args, "--annotated") {
+                // Either `key`, for any value, or `key=value`.
+                let (key, value) = match annotation.split_once('=') {
+                    Some((key, value)) => (key, Some(value)),
+                    None => (annotation, None),
+                };
+                let log = open_repository(&options, filesystem)?
+                    .annotated_changes(key, value)
+                    .context("Failed executing Log action.")?;
+                for entry in log {
+                    print_entry(entry);
+                }
+                return Ok(());
+            }
+
+            if let Some(since) = get_flag_value(args, "--since") {
+                let since = since.parse().context("Invalid timestamp.")?;
+                let log = open_repository(&options, filesystem)?
+                    .changes_since(since)
+                    .context("Failed executing Log action.")?;
+                for entry in log {
+                    print_entry(entry);
+                }
+                return Ok(());
+            }
+
+            // Without a path, all changes are listed.
+            let path = match args[2..].iter().find(|arg| !arg.starts_with("--")) {
+                Some(path) => resolve_path(&options, path)?,
+                None => {
+                    let log = open_repository(&options, filesystem)?
+                        .changes()
+                        .context("Failed executing Log action.")?;
+                    for entry in log {
+                        print_entry(entry);
+                    }
+                    return Ok(());
+                }
+            };
+            // Directories only have histories of the files in them.
+            let history_path = Locations::from(&options).ka_files_path.join(&path);
+            if filesystem.read_directory(&history_path).is_ok() {
+                let log = open_repository(&options, filesystem)?
+                    .directory_changes(&path)
+                    .context("Failed executing Log action.")?;
+                for entry in log {
+                    print_entry(entry);
+                }
+                return Ok(());
+            }
+
+            let log = open_repository(&options, filesystem)?
+                .file_changes(&path)
+                .context("Failed executing Log action.")?;
+
+            for entry in log {
+                if porcelain {
+                    porcelain::print_file_log_entry(&options, &path, &entry);
+                    continue;
+                }
+
+                let kind = match entry.kind {
+                    FileLogKind::Created => "created",
+                    FileLogKind::Modified => "modified",
+                    FileLogKind::Deleted => "deleted",
+                };
+                let content_type = match entry.content_type {
+                    Some(ContentType::Text) => "text, ",
+                    Some(ContentType::Binary) => "binary, ",
+                    None => "",
+                };
+                if let Some(change_id) = entry.change_id {
+                    print!("{} ", &change_id[..SHORT_ID_LENGTH]);
+                }
+                print!(
+                    "{} at {}: {} ({}+{} -{} bytes)",
+                    entry.change_index,
+                    entry.timestamp,
+                    kind,
+                    content_type,
+                    entry.bytes_added,
+                    entry.bytes_removed
+                );
+                if let Some(author) = entry.author {
+                    print!(" by {}", author);
+                }
+                print_annotations(&entry.annotations);
+                match entry.message {
+                    Some(message) => println!(" {}", message),
+                    None => println!(),
+                }
+            }
+        }
+        "stats" => {
+            let bucket_seconds = parse_duration(get_flag_value(args, "--bucket").unwrap_or("1d"))
+                .context("Invalid bucket duration.")?;
 
-            shift(options, &filesystem, new_cursor).expect("Failed executing Shift actions.");
+            let stats = stats(options.clone(), filesystem, bucket_seconds)
+                .context("Failed executing Stats action.")?;
+
+            println!("Changes: {}", stats.change_count);
+            println!("Tracked files: {}", stats.tracked_file_count);
+            println!("History size: {} bytes", stats.history_size);
+            println!("Working size: {} bytes", stats.working_size);
+            println!("Largest histories:");
+            for (path, size) in stats.largest_histories {
+                println!(
+                    "  {} bytes: {}",
+                    size,
+                    options.display_path(&path).display()
+                );
+            }
+            println!("Largest insertions:");
+            for insertion in stats.largest_insertions {
+                println!(
+                    "  {} bytes: {} in change {}",
+                    insertion.bytes_added,
+                    options.display_path(&insertion.path).display(),
+                    insertion.change_index
+                );
+            }
+            println!("Growth:");
+            for bucket in stats.growth {
+                println!(
+                    "  from {}: {} changes, +{} -{} bytes",
+                    bucket.start, bucket.change_count, bucket.bytes_added, bucket.bytes_removed
+                );
+            }
+        }
+        "doctor" => {
+            let checks = doctor(options, filesystem, timestamp);
+
+            for check in checks.iter() {
+                let outcome = match check.outcome {
+                    CheckOutcome::Passed => "ok",
+                    CheckOutcome::Warning => "warning",
+                    CheckOutcome::Failed => "failed",
+                };
+                println!("{:<8} {}: {}", outcome, check.name, check.detail);
+            }
+            if checks
+                .iter()
+                .any(|check| check.outcome == CheckOutcome::Failed)
+            {
+                println!("Some checks failed, ka won't work as it should here.");
+            }
+        }
+        "migrate" => {
+            let migrated =
+                migrate(options, filesystem).context("Failed executing Migrate action.")?;
+
+            println!("Migrated {} files.", migrated);
+        }
+        "redelta" => {
+            let replaced =
+                redelta(options, filesystem).context("Failed executing Redelta action.")?;
+
+            println!("Replaced {} degraded changes.", replaced);
+        }
+        #[cfg(unix)]
+        "watch" => watch::run_watch(args, options, filesystem)?,
+        #[cfg(feature = "tui")]
+        "browse" => browse::browse(options, filesystem)?,
+        "recover" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+            let cursor = get_flag_value(args, "--cursor")
+                .map(|cursor| cursor.parse().context("Invalid cursor."))
+                .transpose()?;
+
+            recover(options, filesystem, &path, cursor, timestamp)
+                .context("Failed executing Recover action.")?;
+        }
+        "deleted" => {
+            let deleted_files = open_repository(&options, filesystem)?
+                .deleted()
+                .context("Failed listing deleted files.")?;
+            for deleted_file in deleted_files {
+                let path = options.display_path(&deleted_file.path).display();
+                match deleted_file.deleted_at {
+                    Some(deleted_at) => println!("{} (deleted at {})", path, deleted_at),
+                    None => println!("{} (deleted, not recorded yet)", path),
+                }
+            }
+        }
+        // Like `recover`, named for bringing back files listed by `deleted`.
+        "resurrect" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+            let cursor = get_flag_value(args, "--at")
+                .map(|cursor| resolve_change(options.clone(), filesystem, cursor))
+                .transpose()
+                .context("Invalid cursor.")?;
+
+            recover(options, filesystem, &path, cursor, timestamp)
+                .context("Failed executing Resurrect action.")?;
+        }
+        "forget" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+            let purge = args.iter().any(|arg| arg == "--purge");
+
+            forget(options, filesystem, &path, purge).context("Failed executing Forget action.")?;
+        }
+        "redact" => {
+            let change_index: usize = get_argument(args, 2, "a change index")?
+                .parse()
+                .context("Invalid change index.")?;
+            let path = resolve_path(&options, get_argument(args, 3, "a path")?)?;
+
+            redact(options, filesystem, change_index, &path)
+                .context("Failed executing Redact action.")?;
+        }
+        "sync" => {
+            let force = args.iter().any(|arg| arg == "--force");
+
+            let synced_paths = sync(options.clone(), filesystem, force)
+                .context("Failed executing Sync action.")?;
+
+            for path in synced_paths {
+                println!("Synced '{}'.", options.display_path(&path).display());
+            }
+        }
+        "session" => match get_argument(args, 2, "start or end")? {
+            "start" => {
+                let name = args.get(3).context("Expected the name of the session.")?;
+                start_session(options, filesystem, name).context("Failed starting session.")?;
+            }
+            "end" => {
+                let name = end_session(options, filesystem).context("Failed ending session.")?;
+                println!("Ended session '{}'.", name);
+            }
+            command => bail!("Unknown session command: {}", command),
+        },
+        "pin" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+
+            if !pin(options, filesystem, &path).context("Failed executing Pin action.")? {
+                println!("'{}' is already pinned.", path.display());
+            }
+        }
+        "unpin" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+
+            if !unpin(options, filesystem, &path).context("Failed executing Unpin action.")? {
+                println!("'{}' wasn't pinned.", path.display());
+            }
+        }
+        "lock" => {
+            let repository = open_repository(&options, filesystem)?;
+            if args.len() < 3 {
+                let locks = repository
+                    .locked_files()
+                    .context("Failed executing Lock action.")?;
+                for (path, lock) in locks {
+                    println!(
+                        "{} locked by {} at {}",
+                        options.display_path(&path).display(),
+                        lock.owner,
+                        lock.since
+                    );
+                }
+                return Ok(());
+            }
+
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+            if !repository
+                .lock_file(&path)
+                .context("Failed executing Lock action.")?
+            {
+                println!("'{}' is already locked.", path.display());
+            }
+        }
+        "unlock" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+
+            if !open_repository(&options, filesystem)?
+                .unlock_file(&path)
+                .context("Failed executing Unlock action.")?
+            {
+                println!("'{}' wasn't locked.", path.display());
+            }
+        }
+        "track" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+
+            if !track(options, filesystem, &path).context("Failed executing Track action.")? {
+                println!("'{}' is already tracked.", path.display());
+            }
+        }
+        "untrack" => {
+            let path = resolve_path(&options, get_argument(args, 2, "a path")?)?;
+
+            if !untrack(options, filesystem, &path).context("Failed executing Untrack action.")? {
+                println!("'{}' wasn't tracked.", path.display());
+            }
+        }
+        _ => bail!("Unknown command: {}", command),
+    }
+    Ok(())
+}
+
+fn run_workspace(args: &[String], filesystem: &FsImpl, timestamp: u64) -> Result<()> {
+    let workspace_path = Path::new(get_flag_value(args, "--workspace").unwrap_or("./workspace"));
+    let workspace =
+        Workspace::load(filesystem, workspace_path).context("Failed loading workspace.")?;
+
+    // The other repositories are still gone through if one of them fails.
+    let mut failed_count = 0;
+    match get_argument(args, 2, "update or status")? {
+        "update" => {
+            for (root, result) in workspace.update(filesystem, timestamp) {
+                match result {
+                    Ok(summary) => {
+                        println!("Updated '{}'.", root.display());
+                        let options = ActionOptions::new(root);
+                        print_update_summary(&options, summary);
+                    }
+                    Err(error) => {
+                        eprintln!("{:?}", error);
+                        failed_count += 1;
+                    }
+                }
+            }
+        }
+        "status" => {
+            for (root, result) in workspace.status(filesystem) {
+                println!("Repository '{}':", root.display());
+                let options = ActionOptions::new(root);
+                match result {
+                    Ok(status) => print_status(&options, status),
+                    Err(error) => {
+                        eprintln!("{:?}", error);
+                        failed_count += 1;
+                    }
+                }
+            }
+        }
+        command => bail!("Unknown workspace command: {}", command),
+    }
+
+    if failed_count > 0 {
+        bail!("Failed for {} of the repositories.", failed_count);
+    }
+    Ok(())
+}
+
+fn ask_for_hunk(path: &Path, old_content: &[u8], hunk: &Hunk) -> bool {
+    let line_number = old_content[..hunk.at]
+        .iter()
+        .filter(|byte| **byte == b'\n')
+        .count()
+        + 1;
+    println!("--- {} at line {}", path.display(), line_number);
+    for line in String::from_utf8_lossy(&old_content[hunk.at..hunk.upto]).lines() {
+        println!("-{}", line);
+    }
+    for line in String::from_utf8_lossy(&hunk.new_content).lines() {
+        println!("+{}", line);
+    }
+
+    // Hunks aren't recorded if no one can be asked about them.
+    loop {
+        print!("Record this hunk? [y/n] ");
+        if io::stdout().flush().is_err() {
+            return false;
+        }
+
+        let mut answer = String::new();
+        if !matches!(io::stdin().read_line(&mut answer), Ok(length) if length > 0) {
+            return false;
+        }
+        match answer.trim() {
+            "y" => return true,
+            "n" => return false,
+            _ => {}
         }
-        _ => panic!("Unknown command: {}", command),
     }
 }
+
+fn print_log_entry(entry: LogEntry) {
+    if let Some(change_id) = entry.change_id {
+        print!("{} ", &change_id[..SHORT_ID_LENGTH]);
+    }
+    print!(
+        "{} at {}: {} files",
+        entry.change_index,
+        entry.timestamp,
+        entry.affected_files.len()
+    );
+    if let Some(author) = entry.author {
+        print!(" by {}", author);
+    }
+    print_annotations(&entry.annotations);
+    match entry.message {
+        Some(message) => println!(" {}", message),
+        None => println!(),
+    }
+}
+
+fn print_annotations(annotations: &Annotations) {
+    if !annotations.is_empty() {
+        let pairs: Vec<String> = annotations
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        print!(" [{}]", pairs.join(", "));
+    }
+}
+
+fn print_update_summary(options: &ActionOptions, summary: UpdateSummary) {
+    for (path, error) in summary.skipped {
+        eprintln!(
+            "Skipped '{}': {:#}",
+            options.display_path(&path).display(),
+            error
+        );
+    }
+    for path in summary.racy {
+        eprintln!(
+            "'{}' kept changing while it was recorded, update again once it settles.",
+            options.display_path(&path).display()
+        );
+    }
+    for path in summary.reserved {
+        eprintln!(
+            "'{}' has a name Windows reserves, it can't be restored there.",
+            options.display_path(&path).display()
+        );
+    }
+    for path in summary.over_budget {
+        eprintln!(
+            "'{}' was too large to diff within the memory budget, only its changed part was.",
+            options.display_path(&path).display()
+        );
+    }
+    for (path, lock) in summary.locked {
+        eprintln!(
+            "'{}' is locked by {}, recorded it anyway.",
+            options.display_path(&path).display(),
+            lock.owner
+        );
+    }
+    for path in summary.deferred {
+        eprintln!(
+            "'{}' was recorded too recently, its changes wait for its minimum change interval.",
+            options.display_path(&path).display()
+        );
+    }
+}
+
+fn print_diff(segments: Vec<TextSegment>, granularity: TextGranularity) {
+    for segment in segments {
+        match granularity {
+            TextGranularity::Line => {
+                let prefix = match segment.kind {
+                    SegmentKind::Equal => ' ',
+                    SegmentKind::Inserted => '+',
+                    SegmentKind::Deleted => '-',
+                };
+                for line in segment.text.lines() {
+                    println!("{}{}", prefix, line);
+                }
+            }
+            // Finer changes are marked inline, like `git diff --word-diff` does.
+            _ => match segment.kind {
+                SegmentKind::Equal => print!("{}", segment.text),
+                SegmentKind::Inserted => print!("{{+{}+}}", segment.text),
+                SegmentKind::Deleted => print!("[-{}-]", segment.text),
+            },
+        }
+    }
+}
+
+fn print_shift_preview(options: &ActionOptions, preview: Vec<ShiftPreviewEntry>) {
+    for entry in preview {
+        let kind = match entry.kind {
+            ShiftPreviewKind::Created => "created",
+            ShiftPreviewKind::Modified => "modified",
+            ShiftPreviewKind::Deleted => "deleted",
+        };
+        println!(
+            "{}: {} ({} -> {} bytes, +{} -{})",
+            kind,
+            options.display_path(&entry.path).display(),
+            entry.old_size,
+            entry.new_size,
+            entry.bytes_added,
+            entry.bytes_removed
+        );
+    }
+}
+
+fn print_log_bucket(bucket: &LogBucket) {
+    println!(
+        "from {}: changes {}..={} ({}), {} files, +{} -{} bytes",
+        bucket.start,
+        bucket.first_change,
+        bucket.last_change,
+        bucket.change_count,
+        bucket.file_count,
+        bucket.bytes_added,
+        bucket.bytes_removed
+    );
+}
+
+fn print_summary(summary: &Summary) {
+    println!("At cursor {}.", summary.cursor);
+    match summary.dirty_count {
+        0 => println!("No unrecorded changes."),
+        1 => println!("1 file with unrecorded changes."),
+        dirty_count => println!("{} files with unrecorded changes.", dirty_count),
+    }
+    if let Some(last_update) = summary.last_update {
+        println!("Latest change recorded at {}.", last_update);
+    }
+}
+
+// Short enough for a shell prompt, like `12*3` for the cursor and the count of dirty files.
+fn print_prompt_summary(summary: &Summary) {
+    if summary.dirty_count > 0 {
+        println!("{}*{}", summary.cursor, summary.dirty_count);
+    } else {
+        println!("{}", summary.cursor);
+    }
+}
+
+fn print_status(options: &ActionOptions, status: Status) {
+    println!("At cursor {}.", status.cursor);
+    for (path, file_status) in status.files {
+        let binary = match (status.images.get(&path), status.content_types.get(&path)) {
+            (Some(image_change), _) => describe_image_change(image_change),
+            (None, Some(ContentType::Binary)) => " (binary)".to_string(),
+            _ => String::new(),
+        };
+        let description = match file_status {
+            FileStatus::Added => format!("added{}", binary),
+            FileStatus::Modified => format!("modified{}", binary),
+            FileStatus::Deleted => "deleted".to_string(),
+            FileStatus::Skipped(SkipReason::TooLarge {
+                size,
+                max_file_size,
+            }) => format!("skipped ({} > {} bytes)", size, max_file_size),
+            FileStatus::Skipped(SkipReason::Binary) => "skipped (binary)".to_string(),
+            FileStatus::Skipped(SkipReason::Excluded) => "skipped (excluded)".to_string(),
+            FileStatus::Skipped(SkipReason::OutsideScope) => "outside of scope".to_string(),
+        };
+        println!("{}: {}", description, options.display_path(&path).display());
+    }
+    for inconsistency in status.inconsistencies {
+        match inconsistency {
+            Inconsistency::MissingFileChange { change_index, path } => eprintln!(
+                "The index lists '{}' for change {}, but its history has no such change.",
+                options.display_path(&path).display(),
+                change_index
+            ),
+            Inconsistency::MissingIndexEntry { change_index, path } => eprintln!(
+                "The history of '{}' has change {}, but the index doesn't list it.",
+                options.display_path(&path).display(),
+                change_index
+            ),
+        }
+    }
+}
+
+// Like ` (PNG 640x480 -> PNG 800x600)`, or only the new side for added images.
+fn describe_image_change(image_change: &ImageChange) -> String {
+    let describe = |info: Option<ImageInfo>| match info {
+        Some(info) => info.to_string(),
+        None => "not an image".to_string(),
+    };
+    let mut description = match image_change.old_size {
+        0 => describe(image_change.new),
+        _ => format!(
+            "{} -> {}",
+            describe(image_change.old),
+            describe(image_change.new)
+        ),
+    };
+    if let Some(hash_distance) = image_change.hash_distance {
+        description.push_str(&format!(", {} of 64 bits apart", hash_distance));
+    }
+    format!(" ({})", description)
+}
+
+fn open_repository<'a, F: Fs>(
+    options: &ActionOptions,
+    filesystem: &'a F,
+) -> Result<Repository<'a, F>> {
+    Repository::open(filesystem, &options.repository_path).context("Failed opening repository.")
+}
+
+// Paths on the command line are relative to where ka is run from, actions take them
+// relative to the repository.
+fn resolve_path(options: &ActionOptions, path: &str) -> Result<PathBuf> {
+    let current_directory = env::current_dir().context("Could not get current path.")?;
+    options
+        .resolve_path(Path::new(path), &current_directory)
+        .context("Invalid path.")
+}
+
+fn parse_annotation(annotation: &str) -> Result<(String, String)> {
+    match annotation.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => bail!("Invalid annotation '{}', expected key=value.", annotation),
+    }
+}
+
+// The positional argument at `index`, or an error saying what was expected there.
+fn get_argument<'a>(args: &'a [String], index: usize, expected: &str) -> Result<&'a str> {
+    args.get(index)
+        .map(|arg| arg.as_str())
+        .with_context(|| format!("Expected {}.", expected))
+}
+
+fn get_flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
+}
+
+fn get_passphrase() -> Result<String> {
+    env::var("KA_PASSPHRASE").context("The repository is encrypted, set KA_PASSPHRASE to open it.")
+}
@@ -0,0 +1,165 @@
+// Output for scripts and prompts rather than people, with `--porcelain`. It stays the same
+// across versions while the usual output is free to change, except that new kinds of lines may
+// be added, which scripts should skip.
+//
+// Every line starts with a keyword, followed by fields separated by single spaces. Text like
+// paths and messages is always the last field, with backslashes, tabs and line breaks written
+// as `\\`, `\t`, `\n` and `\r`. Paths are relative to the repository and separated by `/`.
+//
+// `status` prints
+//
+//     cursor <cursor>
+//     file <A|M|D|S> <path>
+//
+// with a `file` line for each file an update would add, modify or delete, or skips.
+//
+// `summary` prints
+//
+//     cursor <cursor>
+//     dirty <count of files an update would add, modify or delete>
+//     updated <timestamp of the latest change>
+//
+// where `updated` is left out if there are no changes.
+//
+// `log` prints for each change, oldest first,
+//
+//     change <index> <ID, or - if it has none> <timestamp>
+//     author <author>
+//     message <message>
+//     annotation <key>=<value>
+//     file <path>
+//
+// where only `change` is always there, and there's a `file` line for each file the change
+// affected, sorted by path. In the log of a single file, these are followed by
+//
+//     kind <created|modified|deleted>
+//     bytes <added> <removed>
+//     type <text|binary>
+//
+// where `type` is left out if it isn't known.
+
+use std::path::{Component, Path};
+
+use ka::{
+    actions::ActionOptions, policy::ContentType, Annotations, FileLogEntry, FileLogKind,
+    FileStatus, LogEntry, Status, Summary,
+};
+
+pub fn print_status(options: &ActionOptions, status: &Status) {
+    println!("cursor {}", status.cursor);
+    for (path, file_status) in &status.files {
+        let code = match file_status {
+            FileStatus::Added => 'A',
+            FileStatus::Modified => 'M',
+            FileStatus::Deleted => 'D',
+            FileStatus::Skipped(_) => 'S',
+        };
+        println!("file {} {}", code, format_path(options, path));
+    }
+}
+
+pub fn print_summary(summary: &Summary) {
+    println!("cursor {}", summary.cursor);
+    println!("dirty {}", summary.dirty_count);
+    if let Some(last_update) = summary.last_update {
+        println!("updated {}", last_update);
+    }
+}
+
+pub fn print_log_entry(options: &ActionOptions, entry: &LogEntry) {
+    print_change(
+        entry.change_index,
+        &entry.change_id,
+        entry.timestamp,
+        &entry.author,
+        &entry.message,
+        &entry.annotations,
+    );
+    let mut paths = entry
+        .affected_files
+        .iter()
+        .map(|path| format_path(options, path))
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        println!("file {}", path);
+    }
+}
+
+pub fn print_file_log_entry(options: &ActionOptions, path: &Path, entry: &FileLogEntry) {
+    print_change(
+        entry.change_index,
+        &entry.change_id,
+        entry.timestamp,
+        &entry.author,
+        &entry.message,
+        &entry.annotations,
+    );
+    println!("file {}", format_path(options, path));
+
+    let kind = match entry.kind {
+        FileLogKind::Created => "created",
+        FileLogKind::Modified => "modified",
+        FileLogKind::Deleted => "deleted",
+    };
+    println!("kind {}", kind);
+    println!("bytes {} {}", entry.bytes_added, entry.bytes_removed);
+    match entry.content_type {
+        Some(ContentType::Text) => println!("type text"),
+        Some(ContentType::Binary) => println!("type binary"),
+        None => {}
+    }
+}
+
+fn print_change(
+    change_index: usize,
+    change_id: &Option<String>,
+    timestamp: u64,
+    author: &Option<String>,
+    message: &Option<String>,
+    annotations: &Annotations,
+) {
+    println!(
+        "change {} {} {}",
+        change_index,
+        change_id.as_deref().unwrap_or("-"),
+        timestamp
+    );
+    if let Some(author) = author {
+        println!("author {}", escape(author));
+    }
+    if let Some(message) = message {
+        println!("message {}", escape(message));
+    }
+    for (key, value) in annotations {
+        println!("annotation {}={}", escape(key), escape(value));
+    }
+}
+
+// Working paths and those relative to the repository both end up like `notes/today`.
+fn format_path(options: &ActionOptions, path: &Path) -> String {
+    let path = options
+        .display_path(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    escape(&path)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
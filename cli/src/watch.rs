@@ -0,0 +1,252 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use ka::{
+    actions::ActionOptions,
+    config::{parse_duration, Config},
+    files::Locations,
+    filesystem::Fs,
+    notifications::{is_disk_full, notify_all, Notification, SkippedFile},
+    watch::{get_socket_path, WatchFilters, WatchState},
+    LockedError,
+};
+
+#[cfg(feature = "metrics")]
+use ka::metrics::{set_recorder, Registry};
+
+use crate::{get_flag_value, print_update_summary};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How long the watcher waits before trying again once the disk is full, as freeing up space
+// usually takes somebody doing it.
+const DISK_FULL_PAUSE_SECONDS: u64 = 60;
+
+pub fn run_watch(args: &[String], options: ActionOptions, filesystem: &impl Fs) -> Result<()> {
+    let socket_path = get_socket_path(&options);
+
+    match args.get(2).map(String::as_str) {
+        Some("status") => print!("{}", send_command(&socket_path, "status")?),
+        Some("stop") => print!("{}", send_command(&socket_path, "stop")?),
+        #[cfg(feature = "metrics")]
+        Some("metrics") => print!("{}", send_command(&socket_path, "metrics")?),
+        _ => {
+            let quiet_seconds = parse_duration(get_flag_value(args, "--quiet").unwrap_or("10s"))
+                .context("Invalid quiet period.")?;
+            // Each `--on-save-of pattern` and `--on-save-by process` adds to the filters of the
+            // config, where `!` excludes.
+            let get_flag_values = |flag: &str| {
+                args.windows(2)
+                    .filter(|pair| pair[0] == flag)
+                    .map(|pair| pair[1].clone())
+                    .collect()
+            };
+            let filters = WatchFilters {
+                paths: get_flag_values("--on-save-of"),
+                processes: get_flag_values("--on-save-by"),
+            };
+            run_daemon(&socket_path, options, filesystem, quiet_seconds, filters)?;
+        }
+    }
+    Ok(())
+}
+
+fn send_command(socket_path: &Path, command: &str) -> Result<String> {
+    let mut stream =
+        UnixStream::connect(socket_path).context("No watcher is running for this repository.")?;
+    writeln!(stream, "{}", command).context("Failed sending command to the watcher.")?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .context("Failed reading response of the watcher.")?;
+    Ok(response)
+}
+
+// Records changes once the working files have been quiet for a while, until it's stopped
+// through its socket. Everything it needs to resume is in the watch state, so after a
+// crash it can simply be started again.
+fn run_daemon(
+    socket_path: &Path,
+    options: ActionOptions,
+    filesystem: &impl Fs,
+    quiet_seconds: u64,
+    flag_filters: WatchFilters,
+) -> Result<()> {
+    if socket_path.exists() {
+        if UnixStream::connect(socket_path).is_ok() {
+            bail!(LockedError(
+                "A watcher is already running for this repository.".to_string()
+            ));
+        }
+        // Left behind by a watcher which didn't get to clean up.
+        fs::remove_file(socket_path).context("Failed removing stale watcher socket.")?;
+    }
+
+    let listener = UnixListener::bind(socket_path).context("Failed creating watcher socket.")?;
+    listener
+        .set_nonblocking(true)
+        .context("Failed creating watcher socket.")?;
+
+    let mut state =
+        WatchState::load(filesystem, &options).context("Failed loading watch state.")?;
+    let config = Config::load_from(filesystem, &options).context("Failed loading config.")?;
+    let sinks = config.notifications;
+    let mut config_filters = config.watch;
+    let mut reload_error = None;
+    let locations = Locations::from(&options);
+    // The same error keeps coming back with every poll, but is only worth one notification.
+    let mut last_error = None;
+    let mut paused_until = None;
+
+    #[cfg(feature = "metrics")]
+    let registry: &'static Registry = {
+        let registry: &'static Registry = Box::leak(Box::default());
+        set_recorder(registry).context("Failed installing the metrics recorder.")?;
+        registry
+    };
+
+    loop {
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    #[cfg(feature = "metrics")]
+                    let metrics = registry.render();
+                    #[cfg(not(feature = "metrics"))]
+                    let metrics = String::new();
+                    if !handle_command(stream, &options, &state, paused_until, &metrics) {
+                        fs::remove_file(socket_path).context("Failed removing watcher socket.")?;
+                        return Ok(());
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error).context("Failed accepting watcher command."),
+            }
+        }
+
+        let timestamp = options.now();
+        if paused_until.is_some_and(|paused_until| timestamp < paused_until) {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+        paused_until = None;
+
+        // The filters are taken from the config again with every poll, so changing them doesn't
+        // need a restart. A config which can't be loaded keeps the previous ones.
+        match Config::load_from(filesystem, &options) {
+            Ok(config) => {
+                config_filters = config.watch;
+                reload_error = None;
+            }
+            Err(error) => {
+                let message = format!("{:#}", error);
+                if reload_error.as_ref() != Some(&message) {
+                    eprintln!("Failed reloading the watch filters: {}", message);
+                    reload_error = Some(message);
+                }
+            }
+        }
+        let mut filters = config_filters.clone();
+        filters.paths.extend(flag_filters.paths.iter().cloned());
+        filters
+            .processes
+            .extend(flag_filters.processes.iter().cloned());
+
+        match state.poll(filesystem, &options, timestamp, quiet_seconds, &filters) {
+            Ok(Some(summary)) => {
+                last_error = None;
+                // Pending files may still have been left out, e.g. because they couldn't be
+                // read, so only what the change has in it is reported.
+                if !summary.recorded.is_empty() {
+                    let recorded = Notification::Recorded {
+                        timestamp,
+                        files: summary
+                            .recorded
+                            .iter()
+                            .map(|path| options.display_path(path).to_path_buf())
+                            .collect(),
+                    };
+                    notify_all(&sinks, &locations, &recorded);
+                    println!("Recorded a change.");
+                }
+                if !summary.skipped.is_empty() {
+                    let files = summary
+                        .skipped
+                        .iter()
+                        .map(|(path, error)| SkippedFile {
+                            path: options.display_path(path).to_path_buf(),
+                            error: format!("{:#}", error),
+                        })
+                        .collect();
+                    notify_all(&sinks, &locations, &Notification::Skipped { files });
+                }
+
+                print_update_summary(&options, summary);
+            }
+            Ok(None) => last_error = None,
+            Err(error) => {
+                eprintln!("{:?}", error);
+                if is_disk_full(&error) {
+                    paused_until = Some(timestamp + DISK_FULL_PAUSE_SECONDS);
+                }
+                let message = format!("{:#}", error);
+                if last_error.as_ref() != Some(&message) {
+                    notify_all(&sinks, &locations, &Notification::failed(&error));
+                    last_error = Some(message);
+                }
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Returns whether the watcher should keep running.
+fn handle_command(
+    stream: UnixStream,
+    options: &ActionOptions,
+    state: &WatchState,
+    paused_until: Option<u64>,
+    metrics: &str,
+) -> bool {
+    let mut command = String::new();
+    let mut reader = BufReader::new(&stream);
+    if stream.set_nonblocking(false).is_err() || reader.read_line(&mut command).is_err() {
+        return true;
+    }
+
+    let mut stream = &stream;
+    match command.trim() {
+        "status" => {
+            let _ = writeln!(stream, "{} files pending.", state.pending.len());
+            for path in state.pending.keys() {
+                let _ = writeln!(stream, "  {}", options.display_path(path).display());
+            }
+            if let Some(last_change) = state.last_change {
+                let _ = writeln!(stream, "Last change seen at {}.", last_change);
+            }
+            if let Some(paused_until) = paused_until {
+                let _ = writeln!(stream, "The disk is full, paused until {}.", paused_until);
+            }
+            true
+        }
+        "stop" => {
+            let _ = writeln!(stream, "Stopped watching.");
+            false
+        }
+        "metrics" => {
+            let _ = write!(stream, "{}", metrics);
+            true
+        }
+        command => {
+            let _ = writeln!(stream, "Unknown command: {}", command);
+            true
+        }
+    }
+}
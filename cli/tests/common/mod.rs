@@ -0,0 +1,22 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+// A directory of its own for each test, emptied first, as the binary works on the real filesystem.
+pub fn get_test_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("ka-cli-{}", name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+pub fn run(directory: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ka-cli"))
+        .current_dir(directory)
+        .args(args)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap()
+}
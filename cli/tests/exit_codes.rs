@@ -0,0 +1,50 @@
+use std::{fs, path::Path};
+
+mod common;
+
+use common::{get_test_directory, run};
+
+fn get_exit_code(directory: &Path, args: &[&str]) -> Option<i32> {
+    run(directory, args).status.code()
+}
+
+#[test]
+fn exit_with_generic_failure() {
+    let directory = get_test_directory("generic-failure");
+    assert_eq!(get_exit_code(&directory, &[]), Some(1));
+    assert_eq!(get_exit_code(&directory, &["status"]), Some(1));
+
+    fs::write(directory.join("test"), "first").unwrap();
+    assert_eq!(get_exit_code(&directory, &["create"]), Some(0));
+    assert_eq!(get_exit_code(&directory, &["status"]), Some(0));
+    assert_eq!(get_exit_code(&directory, &["show", "missing@1"]), Some(1));
+}
+
+#[test]
+fn exit_with_repository_error() {
+    let directory = get_test_directory("repository-error");
+    fs::write(directory.join("test"), "first").unwrap();
+    assert_eq!(get_exit_code(&directory, &["create"]), Some(0));
+
+    // A corrupted index and a missing one both leave the repository to be repaired.
+    let index_path = directory.join(".ka").join("index");
+    fs::write(&index_path, "garbage").unwrap();
+    assert_eq!(get_exit_code(&directory, &["status"]), Some(2));
+    fs::remove_file(&index_path).unwrap();
+    assert_eq!(get_exit_code(&directory, &["status"]), Some(2));
+    assert_eq!(get_exit_code(&directory, &["log"]), Some(2));
+}
+
+#[test]
+fn exit_with_locked_file() {
+    let directory = get_test_directory("locked-file");
+    fs::write(directory.join("test"), "first").unwrap();
+    assert_eq!(get_exit_code(&directory, &["create"]), Some(0));
+
+    let locks = format!(
+        r#"{{"files":{{"{}":{{"owner":"someone else","since":0}}}}}}"#,
+        directory.join("test").display()
+    );
+    fs::write(directory.join(".ka").join("locks"), locks).unwrap();
+    assert_eq!(get_exit_code(&directory, &["lock", "test"]), Some(3));
+}
@@ -0,0 +1,101 @@
+use std::{fs, path::Path};
+
+mod common;
+
+use common::{get_test_directory, run};
+
+fn run_porcelain(directory: &Path, args: &[&str]) -> String {
+    let output = run(directory, args);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Change IDs and timestamps differ from run to run, so only their form is checked.
+fn mask_changes(output: &str) -> String {
+    let mut masked = String::new();
+    for line in output.lines() {
+        let fields = line.split(' ').collect::<Vec<_>>();
+        if fields[0] == "change" {
+            assert_eq!(fields.len(), 4, "{}", line);
+            assert!(fields[2] == "-" || fields[2].len() == 64, "{}", line);
+            assert!(fields[3].parse::<u64>().is_ok(), "{}", line);
+            masked.push_str(&format!("change {} <id> <timestamp>\n", fields[1]));
+        } else {
+            masked.push_str(line);
+            masked.push('\n');
+        }
+    }
+    masked
+}
+
+#[test]
+fn print_status() {
+    let directory = get_test_directory("porcelain-status");
+    fs::create_dir(directory.join("notes")).unwrap();
+    fs::write(directory.join("notes").join("with space"), "first").unwrap();
+    fs::write(directory.join("todo"), "first").unwrap();
+    run_porcelain(&directory, &["create"]);
+    assert_eq!(
+        run_porcelain(&directory, &["status", "--porcelain"]),
+        "cursor 1\n"
+    );
+
+    fs::remove_file(directory.join("notes").join("with space")).unwrap();
+    fs::write(directory.join("todo"), "second").unwrap();
+    fs::write(directory.join("back\\slash"), "first").unwrap();
+    assert_eq!(
+        run_porcelain(&directory, &["status", "--porcelain"]),
+        "cursor 1\n\
+         file A back\\\\slash\n\
+         file D notes/with space\n\
+         file M todo\n"
+    );
+}
+
+#[test]
+fn print_log() {
+    let directory = get_test_directory("porcelain-log");
+    fs::create_dir(directory.join("notes")).unwrap();
+    fs::write(directory.join("notes").join("today"), "first").unwrap();
+    fs::write(directory.join("todo"), "first").unwrap();
+    run_porcelain(&directory, &["create"]);
+    fs::write(directory.join("todo"), "first second").unwrap();
+    fs::write(directory.join("new"), "first").unwrap();
+    run_porcelain(
+        &directory,
+        &[
+            "update",
+            "--annotate",
+            "note=two\nlines",
+            "--annotate",
+            "tab=a\tb",
+        ],
+    );
+
+    assert_eq!(
+        mask_changes(&run_porcelain(&directory, &["log", "--porcelain"])),
+        "change 1 <id> <timestamp>\n\
+         file notes/today\n\
+         file todo\n\
+         change 2 <id> <timestamp>\n\
+         annotation note=two\\nlines\n\
+         annotation tab=a\\tb\n\
+         file new\n\
+         file todo\n"
+    );
+    assert_eq!(
+        mask_changes(&run_porcelain(&directory, &["log", "todo", "--porcelain"])),
+        "change 1 <id> <timestamp>\n\
+         file todo\n\
+         kind created\n\
+         bytes 5 0\n\
+         type text\n\
+         change 2 <id> <timestamp>\n\
+         annotation note=two\\nlines\n\
+         annotation tab=a\\tb\n\
+         file todo\n\
+         kind modified\n\
+         bytes 7 0\n\
+         type text\n"
+    );
+}
@@ -0,0 +1,33 @@
+use std::{fs, path::Path};
+
+mod common;
+
+use common::{get_test_directory, run};
+
+fn run_summary(directory: &Path, args: &[&str]) -> String {
+    let output = run(directory, args);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn summarize_in_full_or_for_prompts() {
+    let directory = get_test_directory("summary");
+    fs::write(directory.join("today"), "Water plants").unwrap();
+    assert!(run(&directory, &["create"]).status.success());
+    assert_eq!(run_summary(&directory, &["summary", "--prompt"]), "1\n");
+
+    fs::write(directory.join("today"), "Feed cat").unwrap();
+    fs::write(directory.join("later"), "Walk dog").unwrap();
+    let summary = run_summary(&directory, &["summary"]);
+    let lines = summary.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines[..2],
+        ["At cursor 1.", "2 files with unrecorded changes."]
+    );
+    let last_update = lines[2]
+        .strip_prefix("Latest change recorded at ")
+        .and_then(|line| line.strip_suffix('.'));
+    assert!(last_update.unwrap().parse::<u64>().is_ok(), "{}", summary);
+    assert_eq!(run_summary(&directory, &["summary", "--prompt"]), "1*2\n");
+}
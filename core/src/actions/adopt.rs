@@ -0,0 +1,181 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::Config,
+    diff::ContentChange,
+    files::{FileState, Locations},
+    filesystem::Fs,
+    history::{
+        Annotations, FileChange, FileChangeVariant, FileHistory, RepositoryChange,
+        RepositoryHistory,
+    },
+    journal,
+    policy::get_content_type,
+};
+
+use super::ActionOptions;
+
+// Starts a fresh history for every working file which was tracked before, but lost it's
+// history file, without recording any other changes done to the working directory.
+pub fn adopt(command_options: ActionOptions, fs: &impl Fs, timestamp: u64) -> Result<Vec<PathBuf>> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let entries = locations
+        .get_repository_files(fs)
+        .context("Could not traverse files.")?;
+
+    let config = Config::load(fs, &locations)?;
+    let cursor = repository_history.cursor;
+    let tracked_paths = repository_history.get_tracked_paths();
+
+    let mut adopted_histories = Vec::new();
+
+    for state in entries {
+        if !is_file_orphaned(&state, &tracked_paths) {
+            continue;
+        }
+
+        if let FileState::Untracked(untracked) = state {
+            let mut file = untracked.load_file(fs)?;
+            let file_content = fs.read_from_file(&mut file)?;
+
+            let mut new_history = FileHistory::default();
+            new_history.add_change(FileChange {
+                change_index: cursor + 1,
+                variant: FileChangeVariant::Updated(ContentChange::diff(&[], &file_content)),
+                degraded: false,
+                content_hash: Some(FileChange::hash_content(&file_content)),
+                change_id: None,
+                content_type: Some(get_content_type(&config, &untracked.path, &file_content)),
+            });
+            new_history.provenance = Some(get_adopted_provenance(cursor + 1));
+
+            adopted_histories.push((untracked, new_history));
+        }
+    }
+
+    let file_changes: Vec<_> = adopted_histories
+        .iter()
+        .flat_map(|(untracked, new_history)| {
+            let change = new_history.get_changes().last()?;
+            Some((untracked.path.as_path(), change))
+        })
+        .collect();
+    let change_id = RepositoryChange::compute_id(
+        repository_history.get_change_id(cursor),
+        timestamp,
+        &file_changes,
+    );
+
+    let mut adopted_files = Vec::new();
+    for (untracked, mut new_history) in adopted_histories {
+        for change in new_history.get_changes_mut() {
+            change.change_id = Some(change_id.clone());
+        }
+        let mut history_file = untracked.create_history_file(fs, &locations)?;
+        new_history.write_to_file(fs, &locations, &mut history_file)?;
+
+        adopted_files.push(untracked.path);
+    }
+
+    if !adopted_files.is_empty() {
+        repository_history.add_change(RepositoryChange {
+            affected_files: adopted_files.clone(),
+            timestamp,
+            message: None,
+            id: Some(change_id),
+            author: config.identity.get_author(),
+            annotations: Annotations::new(),
+        });
+        repository_history.cursor += 1;
+
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+    }
+
+    Ok(adopted_files)
+}
+
+pub(super) fn is_file_orphaned(file_state: &FileState, tracked_paths: &HashSet<&PathBuf>) -> bool {
+    match file_state {
+        FileState::Untracked(untracked) => tracked_paths.contains(&untracked.path),
+        _ => false,
+    }
+}
+
+pub(super) fn get_adopted_provenance(change_index: usize) -> String {
+    format!(
+        "Adopted from the working directory at change {} after the previous history was lost.",
+        change_index
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::{FileHistory, RepositoryHistory},
+    };
+
+    use super::{adopt, get_adopted_provenance};
+
+    #[test]
+    fn adopt_orphaned_file() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./orphan", b"orphan");
+        write_file(&fs_mock, "./other", b"other");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        fs_mock
+            .delete_file(Path::new("./.ka/files/orphan"))
+            .unwrap();
+        write_file(&fs_mock, "./other", b"changed");
+        write_file(&fs_mock, "./new", b"new");
+
+        let adopted = adopt(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+        assert_eq!(adopted, vec![Path::new("./orphan").to_path_buf()]);
+
+        let history = FileHistory::decode(&read_file(&fs_mock, "./.ka/files/orphan")).unwrap();
+        assert_eq!(history.get_content(2).unwrap(), b"orphan");
+        assert_eq!(history.provenance, Some(get_adopted_provenance(2)));
+
+        // Other changes are left for the next update.
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/new")));
+        let index = read_file(&fs_mock, "./.ka/index");
+        assert_eq!(RepositoryHistory::decode(&index).unwrap().cursor, 2);
+    }
+
+    #[test]
+    fn update_adopts_orphaned_file() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./orphan", b"orphan");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        fs_mock
+            .delete_file(Path::new("./.ka/files/orphan"))
+            .unwrap();
+
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let history = FileHistory::decode(&read_file(&fs_mock, "./.ka/files/orphan")).unwrap();
+        assert_eq!(history.provenance, Some(get_adopted_provenance(2)));
+    }
+}
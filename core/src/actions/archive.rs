@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    archive::{write_gzip, write_tar, write_zip, ArchiveEntry},
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+};
+
+use super::ActionOptions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    // Guesses the format from the extension of the archive's path.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+// Like `extract`, but writes the tree at the cursor into a single archive, which can be handed
+// to someone without the rest of the history. Returns the archived paths, relative to the
+// repository.
+pub fn archive(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    cursor: usize,
+    format: ArchiveFormat,
+    target_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let change_count = repository_history.get_changes().len();
+    if cursor > change_count {
+        bail!(
+            "The cursor {} is out of range, as the repository only has the changes 1..={}.",
+            cursor,
+            change_count
+        );
+    }
+
+    // The archive would otherwise be tracked by the next update.
+    if target_path.starts_with(&locations.repository_path) {
+        bail!(
+            "The archive '{}' has to be outside of the repository.",
+            target_path.display()
+        );
+    }
+
+    if fs.path_exists(target_path) {
+        bail!("The archive '{}' already exists.", target_path.display());
+    }
+
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+    history_paths.sort();
+
+    let mut entries = Vec::new();
+    let mut archived_paths = Vec::new();
+
+    for history_path in history_paths {
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+
+        if !file_history.does_file_exist(cursor) {
+            continue;
+        }
+
+        let relative_path = history_path.strip_prefix(&locations.ka_files_path)?;
+        let name = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        entries.push(ArchiveEntry {
+            name,
+            content: file_history.get_content(cursor)?,
+        });
+        archived_paths.push(relative_path.to_path_buf());
+    }
+
+    // Files get the time of the change they are archived at.
+    let mtime = cursor
+        .checked_sub(1)
+        .and_then(|index| repository_history.get_changes().get(index))
+        .map_or(0, |change| change.timestamp);
+
+    let archive = match format {
+        ArchiveFormat::Zip => write_zip(&entries, mtime)?,
+        ArchiveFormat::TarGz => write_gzip(&write_tar(&entries, mtime)?, mtime),
+    };
+
+    let mut archive_file = fs.create_file(target_path)?;
+    fs.write_to_file(&mut archive_file, archive)?;
+
+    Ok(archived_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{archive, ArchiveFormat};
+
+    #[test]
+    fn archive_old_tree() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        fs_mock.create_directory(Path::new("/notes")).unwrap();
+        fs_mock
+            .create_directory(Path::new("/notes/drafts"))
+            .unwrap();
+        write_file(&fs_mock, "/notes/drafts/today", b"Water plants");
+        create(ActionOptions::from_path("/notes"), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "/notes/later", b"Feed cat");
+        update(ActionOptions::from_path("/notes"), &fs_mock, now + 1).unwrap();
+
+        assert!(archive(
+            ActionOptions::from_path("/notes"),
+            &fs_mock,
+            1,
+            ArchiveFormat::Zip,
+            Path::new("/notes/old.zip"),
+        )
+        .is_err());
+
+        let archived_paths = archive(
+            ActionOptions::from_path("/notes"),
+            &fs_mock,
+            1,
+            ArchiveFormat::Zip,
+            Path::new("/old.zip"),
+        )
+        .expect("Action failed.");
+        assert_eq!(archived_paths, vec![Path::new("drafts/today")]);
+
+        let zip = read_file(&fs_mock, "/old.zip");
+        assert_eq!(&zip[..4], b"PK\x03\x04");
+        assert_eq!(&zip[30..42], b"drafts/today");
+        assert_eq!(&zip[42..54], b"Water plants");
+
+        archive(
+            ActionOptions::from_path("/notes"),
+            &fs_mock,
+            2,
+            ArchiveFormat::TarGz,
+            Path::new("/new.tar.gz"),
+        )
+        .expect("Action failed.");
+        assert_eq!(&read_file(&fs_mock, "/new.tar.gz")[..3], &[0x1F, 0x8B, 8]);
+
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("old.tgz")),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::from_path(Path::new("old.rar")), None);
+    }
+}
@@ -0,0 +1,448 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    crypto,
+    files::{collect_files, rebase_path, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+    journal, line_endings,
+    locks::Locks,
+    metrics::ActionTimer,
+    trace::{self, Level},
+};
+
+use super::ActionOptions;
+
+pub const BACKUP_MANIFEST_NAME: &str = "manifest";
+
+// What a backup directory holds, written once all of its files are copied and verified. The
+// files are the ones of `.ka`, by their path relative to it, with the SHA-256 of their content.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct BackupManifest {
+    pub timestamp: u64,
+    pub change_count: usize,
+    pub files: BTreeMap<PathBuf, String>,
+    // Where the repository was, which the working paths stored in `.ka` start with. Empty for
+    // backups from before it was kept.
+    #[serde(default)]
+    pub repository_path: PathBuf,
+}
+
+impl BackupManifest {
+    pub fn load<FS: Fs>(fs: &FS, target_path: &Path) -> Result<Option<Self>> {
+        let manifest_path = target_path.join(BACKUP_MANIFEST_NAME);
+        if !fs.path_exists(&manifest_path) {
+            return Ok(None);
+        }
+
+        let mut manifest_file = fs.open_readable_file(&manifest_path)?;
+        let buffer = fs
+            .read_from_file(&mut manifest_file)
+            .context("Failed reading backup manifest.")?;
+        serde_json::from_slice(&buffer)
+            .map(Some)
+            .context("Failed decoding backup manifest.")
+    }
+
+    fn write<FS: Fs>(&self, fs: &FS, target_path: &Path) -> Result<()> {
+        let encoded = serde_json::to_vec(self).context("Failed encoding backup manifest.")?;
+        let mut manifest_file = fs.create_file(&target_path.join(BACKUP_MANIFEST_NAME))?;
+        fs.write_to_file(&mut manifest_file, encoded)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BackupSummary {
+    pub copied: usize,
+    pub unchanged: usize,
+    // Files of an earlier backup which the repository doesn't have anymore, e.g. after pruning.
+    pub removed: usize,
+}
+
+// Copies the `.ka` directory into `target_path`, below `store`, along with a manifest. Backing
+// up into the same target again only copies the files which changed since, and removes the
+// ones which are gone, so the target always mirrors the latest backup. Every copied file is
+// read back and compared by its hash. The cache isn't backed up, as it's rebuilt when needed.
+// There's no hard-linking through `Fs`, so the files are always copied.
+pub fn backup(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    target_path: &Path,
+    timestamp: u64,
+) -> Result<BackupSummary> {
+    let _timer = ActionTimer::start("backup");
+    let _span = trace::span(Level::Info, "backup", &[("target", &target_path.display())]);
+    let locations = Locations::from(&command_options);
+
+    if target_path.starts_with(&locations.repository_path) {
+        bail!(
+            "The backup target '{}' has to be outside of the repository.",
+            target_path.display()
+        );
+    }
+
+    // Pending writes are completed first, so the backup never has half a change.
+    journal::complete_pending(fs, &locations)?;
+
+    let previous_manifest = BackupManifest::load(fs, target_path)?.unwrap_or_default();
+    let store_path = get_store_path(target_path);
+
+    let mut ka_paths = Vec::new();
+    collect_files(fs, &locations.ka_path, &mut ka_paths)?;
+    ka_paths.sort();
+
+    let mut summary = BackupSummary::default();
+    let mut manifest = BackupManifest {
+        timestamp,
+        repository_path: locations.repository_path.clone(),
+        ..BackupManifest::default()
+    };
+    for path in ka_paths {
+        if path.starts_with(locations.get_cache_path()) || path == locations.get_watch_socket_path()
+        {
+            continue;
+        }
+        let relative_path = path.strip_prefix(&locations.ka_path)?.to_path_buf();
+
+        let mut file = fs.open_readable_file(&path)?;
+        let content = fs
+            .read_from_file(&mut file)
+            .with_context(|| format!("Failed reading '{}'.", path.display()))?;
+        let hash = crypto::to_hex(&crypto::sha256(&content));
+
+        let backup_path = store_path.join(&relative_path);
+        if previous_manifest.files.get(&relative_path) == Some(&hash)
+            && fs.path_exists(&backup_path)
+        {
+            summary.unchanged += 1;
+        } else {
+            copy_verified(fs, &backup_path, content, &hash)?;
+            summary.copied += 1;
+        }
+        manifest.files.insert(relative_path, hash);
+    }
+
+    for relative_path in previous_manifest.files.keys() {
+        let backup_path = store_path.join(relative_path);
+        if !manifest.files.contains_key(relative_path) && fs.path_exists(&backup_path) {
+            fs.delete_file(&backup_path)?;
+            summary.removed += 1;
+        }
+    }
+
+    let mut repository_index_file =
+        fs.open_readable_file(&locations.get_repository_index_path())?;
+    manifest.change_count =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?
+            .get_changes()
+            .len();
+    manifest.write(fs, target_path)?;
+
+    trace::event(
+        Level::Info,
+        "Backed up the repository.",
+        &[
+            ("copied", &summary.copied),
+            ("unchanged", &summary.unchanged),
+            ("removed", &summary.removed),
+        ],
+    );
+    Ok(summary)
+}
+
+// Creates the repository at `target_path` from a backup, with the working files as they were at
+// the cursor when it was backed up. Every file of the backup is checked against the manifest
+// before anything is written, and nothing is written over existing files. Restoring somewhere
+// else than the repository was moves the working paths stored in `.ka` along, and leaves out
+// the undo snapshot, the state of the watcher and of an interrupted import, which are about the
+// working files where it was. Returns the restored working files.
+pub fn restore_from_backup(
+    fs: &impl Fs,
+    backup_path: &Path,
+    target_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let _timer = ActionTimer::start("restore_from_backup");
+    let _span = trace::span(
+        Level::Info,
+        "restore_from_backup",
+        &[("backup", &backup_path.display())],
+    );
+    let manifest = match BackupManifest::load(fs, backup_path)? {
+        Some(manifest) => manifest,
+        None => bail!(
+            "'{}' isn't a backup, it has no manifest.",
+            backup_path.display()
+        ),
+    };
+
+    let store_path = get_store_path(backup_path);
+    let mut files = Vec::new();
+    for (relative_path, hash) in manifest.files.iter() {
+        let stored_path = store_path.join(relative_path);
+        let mut stored_file = fs
+            .open_readable_file(&stored_path)
+            .with_context(|| format!("The backup is missing '{}'.", relative_path.display()))?;
+        let content = fs.read_from_file(&mut stored_file)?;
+        if crypto::to_hex(&crypto::sha256(&content)) != *hash {
+            bail!(
+                "The backup of '{}' doesn't match its manifest.",
+                relative_path.display()
+            );
+        }
+        files.push((relative_path, content));
+    }
+
+    let locations = Locations::from(&ActionOptions::new(target_path.to_path_buf()));
+    if fs.path_exists(&locations.ka_path) {
+        bail!(
+            "There already is a repository at '{}'.",
+            target_path.display()
+        );
+    }
+    let source_path = &manifest.repository_path;
+    let is_moved = !source_path.as_os_str().is_empty() && source_path != target_path;
+    let left_out_paths = [
+        locations.get_undo_path(),
+        locations.get_watch_state_path(),
+        locations.get_import_state_path(),
+    ];
+    for (relative_path, content) in files {
+        let path = locations.ka_path.join(relative_path);
+        if is_moved
+            && left_out_paths
+                .iter()
+                .any(|left_out| path.starts_with(left_out))
+        {
+            continue;
+        }
+        let mut file = fs.create_file(&path)?;
+        fs.write_to_file(&mut file, content)?;
+    }
+
+    let mut repository_index_file =
+        fs.open_writable_file(&locations.get_repository_index_path())?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    if is_moved {
+        repository_history.rebase_paths(source_path, target_path);
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+
+        let mut locks = Locks::load(fs, &locations)?;
+        locks.files = locks
+            .files
+            .into_iter()
+            .map(|(path, lock)| (rebase_path(&path, source_path, target_path), lock))
+            .collect();
+        locks.write(fs, &locations)?;
+    }
+    let config = Config::load(fs, &locations)?;
+
+    let mut history_paths = Vec::new();
+    if fs.path_exists(&locations.ka_files_path) {
+        collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+    }
+    history_paths.sort();
+
+    // Everything is replayed before anything is written, like shifting does.
+    let mut restored_files = Vec::new();
+    for history_path in history_paths {
+        let working_path = locations.working_from_history(&history_path)?;
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        let file_cursor = repository_history.get_file_cursor(&working_path);
+        if file_history.forgotten || !file_history.does_file_exist(file_cursor) {
+            continue;
+        }
+        let relative_path = working_path.strip_prefix(&locations.repository_path)?;
+        if fs.path_exists(&working_path) {
+            bail!(
+                "Restoring would overwrite '{}', restore into an empty directory instead.",
+                relative_path.display()
+            );
+        }
+        let content = file_history
+            .get_content(file_cursor)
+            .with_context(|| format!("Failed restoring '{}'.", relative_path.display()))?;
+        restored_files.push((working_path, content));
+    }
+
+    let mut restored_paths = Vec::new();
+    for (working_path, content) in restored_files {
+        let content = line_endings::restore(&config, &working_path, content);
+        let mut working_file = fs.create_file(&working_path)?;
+        fs.write_to_file(&mut working_file, content)?;
+        restored_paths.push(working_path);
+    }
+
+    trace::event(
+        Level::Info,
+        "Restored the repository.",
+        &[
+            ("changes", &manifest.change_count),
+            ("files", &restored_paths.len()),
+        ],
+    );
+    Ok(restored_paths)
+}
+
+fn get_store_path(target_path: &Path) -> PathBuf {
+    target_path.join("store")
+}
+
+fn copy_verified(fs: &impl Fs, backup_path: &Path, content: Vec<u8>, hash: &str) -> Result<()> {
+    let mut backup_file = fs.create_file(backup_path)?;
+    fs.write_to_file(&mut backup_file, content)?;
+
+    let mut backup_file = fs.open_readable_file(backup_path)?;
+    let copied_content = fs.read_from_file(&mut backup_file)?;
+    if crypto::to_hex(&crypto::sha256(&copied_content)) != hash {
+        bail!(
+            "The backup of '{}' doesn't match the original.",
+            backup_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{
+            create, lock_file, locked_files, pin, shift, status, update, ActionOptions, ShiftMode,
+            UntrackedFiles,
+        },
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::RepositoryHistory,
+    };
+
+    use super::{backup, restore_from_backup, BackupManifest, BackupSummary};
+
+    #[test]
+    fn incremental_backup() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path("./notes");
+        let target_path = Path::new("./backup");
+
+        write_file(&fs_mock, "./notes/todo", b"first");
+        write_file(&fs_mock, "./notes/done", b"nothing");
+        create(options.clone(), &fs_mock, now).unwrap();
+
+        let summary = backup(options.clone(), &fs_mock, target_path, now).unwrap();
+        assert_eq!(summary.unchanged, 0);
+        assert_eq!(summary.removed, 0);
+        assert!(fs_mock.path_exists(Path::new("./backup/store/files/todo")));
+
+        // Only the history of the changed file and the index are copied again.
+        write_file(&fs_mock, "./notes/todo", b"first second");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        let copied = summary.copied;
+        let summary = backup(options.clone(), &fs_mock, target_path, now + 2).unwrap();
+        assert_eq!(
+            summary,
+            BackupSummary {
+                copied: 2,
+                unchanged: copied - 2,
+                removed: 0,
+            }
+        );
+
+        let manifest = BackupManifest::load(&fs_mock, target_path)
+            .unwrap()
+            .unwrap();
+        assert_eq!(manifest.change_count, 2);
+        assert_eq!(manifest.timestamp, now + 2);
+        assert!(manifest.files.contains_key(Path::new("files/todo")));
+
+        assert!(backup(options, &fs_mock, Path::new("./notes/backup"), now).is_err());
+    }
+
+    #[test]
+    fn restore_backup() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path("./notes");
+        let target_path = Path::new("./backup");
+
+        write_file(&fs_mock, "./notes/todo", b"first");
+        write_file(&fs_mock, "./notes/old", b"gone");
+        create(options.clone(), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./notes/todo", b"first second");
+        fs_mock.delete_file(Path::new("./notes/old")).unwrap();
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        backup(options, &fs_mock, target_path, now + 2).unwrap();
+
+        let restored_paths =
+            restore_from_backup(&fs_mock, target_path, Path::new("./restored")).unwrap();
+        assert_eq!(restored_paths, [Path::new("./restored/todo")]);
+        assert_eq!(read_file(&fs_mock, "./restored/todo"), b"first second");
+        let status = status(ActionOptions::from_path("./restored"), &fs_mock).unwrap();
+        assert_eq!(status.cursor, 2);
+        assert!(status.files.is_empty());
+
+        // Neither an existing repository nor a damaged backup is restored.
+        assert!(restore_from_backup(&fs_mock, target_path, Path::new("./restored")).is_err());
+        write_file(&fs_mock, "./backup/store/files/todo", b"damaged");
+        assert!(restore_from_backup(&fs_mock, target_path, Path::new("./other")).is_err());
+        assert!(!fs_mock.path_exists(Path::new("./other")));
+    }
+
+    #[test]
+    fn restore_backup_elsewhere() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path("./notes");
+        let target_path = Path::new("./backup");
+
+        write_file(&fs_mock, "./notes/todo", b"first");
+        write_file(&fs_mock, "./notes/done", b"nothing");
+        create(options.clone(), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./notes/todo", b"first second");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        pin(options.clone(), &fs_mock, Path::new("done")).unwrap();
+        lock_file(options.clone(), &fs_mock, Path::new("todo")).unwrap();
+        backup(options, &fs_mock, target_path, now + 2).unwrap();
+
+        let restored_path = Path::new("./elsewhere");
+        restore_from_backup(&fs_mock, target_path, restored_path).unwrap();
+        let restored_options = ActionOptions::from_path("./elsewhere");
+
+        let mut index_file = fs_mock
+            .open_readable_file(Path::new("./elsewhere/.ka/index"))
+            .unwrap();
+        let buffer = fs_mock.read_from_file(&mut index_file).unwrap();
+        let repository_history = RepositoryHistory::decode(&buffer).unwrap();
+        for change in repository_history.get_changes() {
+            assert!(change
+                .affected_files
+                .iter()
+                .all(|path| path.starts_with(restored_path)));
+        }
+        assert!(repository_history.is_pinned(Path::new("./elsewhere/done")));
+        let locks = locked_files(restored_options.clone(), &fs_mock).unwrap();
+        assert!(locks.contains_key(Path::new("./elsewhere/todo")));
+
+        // Shifting relies on the paths of the changes.
+        shift(
+            restored_options,
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert_eq!(read_file(&fs_mock, "./elsewhere/todo"), b"first");
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{
+    actions::{update, UpdateSummary},
+    files::Locations,
+    filesystem::Fs,
+    history::RepositoryHistory,
+    metrics::ActionTimer,
+    templates::Template,
+    trace::{self, Level},
+};
+use anyhow::Result;
+
+use super::{update::update_baseline, ActionOptions};
+
+pub fn create(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+) -> Result<UpdateSummary> {
+    let _timer = ActionTimer::start("create");
+    let _span = trace::span(
+        Level::Info,
+        "create",
+        &[("repository", &command_options.repository_path.display())],
+    );
+    let locations = Locations::from(&command_options);
+    initialize(fs, &locations)?;
+
+    update(command_options, fs, timestamp)
+}
+
+// Like `create`, but faster for large directories, since it doesn't check whether files
+// changed while they were recorded. Such a file might be recorded in a state it never was in,
+// so it's meant for directories nothing else writes to meanwhile.
+pub fn create_baseline(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+) -> Result<UpdateSummary> {
+    let _timer = ActionTimer::start("create");
+    let _span = trace::span(
+        Level::Info,
+        "create_baseline",
+        &[("repository", &command_options.repository_path.display())],
+    );
+    let locations = Locations::from(&command_options);
+    initialize(fs, &locations)?;
+
+    update_baseline(command_options, fs, timestamp)
+}
+
+// Like `create`, but with the config and ignore patterns of the template in place before the
+// files are first recorded.
+pub fn create_from_template(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+    template: &Template,
+) -> Result<UpdateSummary> {
+    let _timer = ActionTimer::start("create");
+    let _span = trace::span(
+        Level::Info,
+        "create_from_template",
+        &[("repository", &command_options.repository_path.display())],
+    );
+    let locations = Locations::from(&command_options);
+    initialize(fs, &locations)?;
+    template.apply(fs, &locations)?;
+
+    update(command_options, fs, timestamp)
+}
+
+// Replaces whatever is in `.ka` with an empty repository.
+pub(super) fn initialize(fs: &impl Fs, locations: &Locations) -> Result<()> {
+    if fs.path_exists(&locations.ka_path) {
+        fs.delete_directory(&locations.ka_path)?;
+    }
+
+    fs.create_directory(&locations.ka_path)?;
+    fs.create_directory(&locations.ka_files_path)?;
+
+    let mut index_file = fs.create_file(&locations.get_repository_index_path())?;
+    let empty_history = RepositoryHistory::default();
+    empty_history.write_to_file(fs, locations, &mut index_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{repository_log, show, ActionOptions},
+        config::Config,
+        diff::ContentChange,
+        files::Locations,
+        filesystem::mock::{EntryMock, FsMock, FsState},
+        history::{
+            Annotations, FileChange, FileChangeVariant, FileHistory, RepositoryChange,
+            RepositoryHistory,
+        },
+        policy::ContentType,
+        templates::Template,
+    };
+
+    use super::{create, create_baseline, create_from_template};
+
+    #[test]
+    fn create_empty() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        let expected_index = RepositoryHistory::default().encode().unwrap();
+
+        create(options, &fs_mock, now).expect("Action failed.");
+
+        fs_mock.assert_match(FsState::new(vec![
+            EntryMock::dir("./.ka"),
+            EntryMock::file("./.ka/index", &expected_index),
+            EntryMock::dir("./.ka/files"),
+        ]));
+    }
+
+    #[test]
+    fn create_basic() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        let mut file_change = FileChange {
+            change_index: 1,
+            variant: FileChangeVariant::Updated(vec![ContentChange::Inserted {
+                at: 0,
+                new_content: vec![1, 2, 3],
+            }]),
+            degraded: false,
+            content_hash: Some(FileChange::hash_content(&[1, 2, 3])),
+            change_id: None,
+            content_type: Some(ContentType::Text),
+        };
+        let change_id =
+            RepositoryChange::compute_id(None, now, &[(Path::new("./test"), &file_change)]);
+        file_change.change_id = Some(change_id.clone());
+
+        let expected_index = {
+            let mut history = RepositoryHistory::default();
+            history.add_change(RepositoryChange {
+                affected_files: vec![Path::new("./test").into()],
+                timestamp: now,
+                message: None,
+                id: Some(change_id),
+                author: None,
+                annotations: Annotations::new(),
+            });
+            history.cursor = 1;
+
+            // The change is appended to the empty index written first.
+            let mut index = RepositoryHistory::default().encode().unwrap();
+            index.extend(history.encode_latest_change().unwrap());
+            index
+        };
+
+        let expected_file_history = {
+            let mut history = FileHistory::default();
+            history.add_change(file_change);
+            history.encode().unwrap()
+        };
+
+        fs_mock.set_state(FsState::new(vec![EntryMock::file("./test", &[1, 2, 3])]));
+
+        create(options, &fs_mock, now).expect("Action failed.");
+
+        fs_mock.assert_match(FsState::new(vec![
+            EntryMock::file("./test", &[1, 2, 3]),
+            EntryMock::dir("./.ka"),
+            EntryMock::file("./.ka/index", &expected_index),
+            EntryMock::dir("./.ka/files"),
+            EntryMock::file("./.ka/files/test", &expected_file_history),
+        ]))
+    }
+
+    #[test]
+    fn create_baseline_like_create() {
+        let now = 0xC0FFEE;
+        let state = || {
+            FsState::new(vec![
+                EntryMock::file("./test", b"first"),
+                EntryMock::dir("./notes"),
+                EntryMock::file("./notes/todo", b"Water plants"),
+                EntryMock::file("./empty", b""),
+            ])
+        };
+
+        let mut created = FsMock::new();
+        created.set_state(state());
+        create(ActionOptions::from_path("."), &created, now).expect("Action failed.");
+        let mut baseline = FsMock::new();
+        baseline.set_state(state());
+        let summary =
+            create_baseline(ActionOptions::from_path("."), &baseline, now).expect("Action failed.");
+        assert!(summary.racy.is_empty());
+
+        // The files may be in another order, which also changes the ID of the change.
+        let get_affected_files = |fs: &FsMock| {
+            let mut affected_files = repository_log(ActionOptions::from_path("."), fs).unwrap()[0]
+                .affected_files
+                .clone();
+            affected_files.sort();
+            affected_files
+        };
+        assert_eq!(get_affected_files(&baseline), get_affected_files(&created));
+        for path in ["test", "notes/todo", "empty"] {
+            let show_file =
+                |fs: &FsMock| show(ActionOptions::from_path("."), fs, Path::new(path), 1).unwrap();
+            assert_eq!(show_file(&baseline), show_file(&created));
+        }
+    }
+
+    #[test]
+    fn create_from_templates() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+        fs_mock.set_state(FsState::new(vec![
+            EntryMock::dir("./repository"),
+            EntryMock::file("./repository/.kaignore", b"*.bak"),
+            EntryMock::file("./repository/todo.md", b"Water plants"),
+            EntryMock::file("./repository/todo.tmp", b"Water"),
+            EntryMock::dir("./other"),
+            EntryMock::dir("./templates"),
+            EntryMock::file(
+                "./templates/notes.json",
+                br#"{"config": {"retention": "7d"}, "ignore": ["*.bak", "*.old"]}"#,
+            ),
+        ]));
+        let options = ActionOptions::from_path("./repository");
+        let locations = Locations::from(&options);
+
+        let template = Template::load(&fs_mock, "notes", None).unwrap();
+        create_from_template(options.clone(), &fs_mock, now, &template).expect("Action failed.");
+        let config = Config::load(&fs_mock, &locations).unwrap();
+        assert_eq!(config.min_change_interval.as_deref(), Some("1m"));
+        let mut affected_files = repository_log(options.clone(), &fs_mock).unwrap()[0]
+            .affected_files
+            .clone();
+        affected_files.sort();
+        assert_eq!(
+            affected_files,
+            [Path::new(".kaignore"), Path::new("todo.md")]
+        );
+        let ignore_patterns = show(options.clone(), &fs_mock, Path::new(".kaignore"), 1).unwrap();
+        assert_eq!(
+            ignore_patterns,
+            b"*.bak\n.obsidian/workspace*\n.trash/\n*.tmp\n"
+        );
+
+        // The templates of the user replace the built-in ones.
+        let template = Template::load(&fs_mock, "notes", Some(Path::new("./templates"))).unwrap();
+        let options = ActionOptions::from_path("./other");
+        let locations = Locations::from(&options);
+        create_from_template(options, &fs_mock, now, &template).expect("Action failed.");
+        let config = Config::load(&fs_mock, &locations).unwrap();
+        assert_eq!(config.retention.as_deref(), Some("7d"));
+        assert_eq!(config.min_change_interval, None);
+
+        assert!(Template::load(&fs_mock, "music", None).is_err());
+        assert!(Template::load(&fs_mock, "../notes", Some(Path::new("./templates"))).is_err());
+    }
+}
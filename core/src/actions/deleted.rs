@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    files::{FileState, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+};
+
+use super::{recover::get_last_existing_cursor, ActionOptions};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeletedFile {
+    pub path: PathBuf,
+    // The change which recorded the deletion, or `None` if it isn't recorded yet.
+    pub deleted_at: Option<usize>,
+    // The latest cursor the file existed at, which `recover` restores it from by default.
+    pub last_existing_cursor: usize,
+}
+
+// The files which only exist in their history, but did exist up to the cursor. Forgotten
+// files and ones which are only created after the cursor aren't listed.
+pub fn deleted(command_options: ActionOptions, fs: &impl Fs) -> Result<Vec<DeletedFile>> {
+    let locations = Locations::from(&command_options);
+
+    let mut repository_index_file =
+        fs.open_readable_file(&locations.get_repository_index_path())?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let entries = locations
+        .get_repository_files(fs)
+        .context("Could not traverse files.")?;
+
+    let mut deleted_files = Vec::new();
+    for state in entries {
+        let deleted = match state {
+            FileState::Deleted(deleted) => deleted,
+            FileState::Untracked(_) | FileState::Tracked(_) => continue,
+        };
+        let working_path = locations.working_from_history(&deleted.history_path)?;
+        let cursor = repository_history.get_file_cursor(&working_path);
+
+        let mut history_file = fs.open_readable_file(&deleted.history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        if file_history.forgotten {
+            continue;
+        }
+
+        if let Some(last_existing_cursor) = get_last_existing_cursor(&file_history, cursor) {
+            deleted_files.push(DeletedFile {
+                path: working_path,
+                deleted_at: (last_existing_cursor != cursor).then_some(last_existing_cursor + 1),
+                last_existing_cursor,
+            });
+        }
+    }
+
+    deleted_files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(deleted_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{
+        actions::{create, recover, shift, update, ActionOptions, ShiftMode, UntrackedFiles},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{deleted, DeletedFile};
+
+    #[test]
+    fn list_deleted_files() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        write_file(&fs_mock, "./gone", b"gone");
+        write_file(&fs_mock, "./missing", b"missing");
+        create(options.clone(), &fs_mock, now).unwrap();
+
+        fs_mock.delete_file(Path::new("./gone")).unwrap();
+        write_file(&fs_mock, "./later", b"later");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        fs_mock.delete_file(Path::new("./missing")).unwrap();
+
+        assert_eq!(
+            deleted(options.clone(), &fs_mock).unwrap(),
+            vec![
+                DeletedFile {
+                    path: PathBuf::from("./gone"),
+                    deleted_at: Some(2),
+                    last_existing_cursor: 1,
+                },
+                DeletedFile {
+                    path: PathBuf::from("./missing"),
+                    deleted_at: None,
+                    last_existing_cursor: 2,
+                },
+            ]
+        );
+
+        // Files created after the cursor weren't deleted, they just don't exist yet.
+        write_file(&fs_mock, "./missing", b"missing");
+        shift(
+            options.clone(),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert!(deleted(options.clone(), &fs_mock).unwrap().is_empty());
+
+        shift(
+            options.clone(),
+            &fs_mock,
+            2,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        recover(options.clone(), &fs_mock, Path::new("gone"), None, now + 2).unwrap();
+        assert!(deleted(options, &fs_mock).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+use std::{iter, path::Path};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::Config,
+    diff::{diff_text, SegmentKind, TextGranularity, TextSegment},
+    files::Locations,
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+    images::ImageChange,
+    policy::{get_content_type, ContentType},
+    tabular::diff_table,
+};
+
+use super::ActionOptions;
+
+// Compares a file at the cursor `from` with the file at the cursor `to`, or with the working
+// file if there is no `to`. A file which doesn't exist on one side is compared as empty.
+// Binary content isn't diffed, its sides are only summarized by their length, or for images
+// by their dimensions as well.
+pub fn diff(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    path: &Path,
+    from: usize,
+    to: Option<usize>,
+    granularity: TextGranularity,
+) -> Result<Vec<TextSegment>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let change_count = repository_history.get_changes().len();
+    for cursor in iter::once(from).chain(to) {
+        if cursor > change_count {
+            bail!(
+                "The cursor {} is out of range, as the repository only has the changes 1..={}.",
+                cursor,
+                change_count
+            );
+        }
+    }
+
+    let working_path = locations.get_working_path(path)?;
+    let history_path = locations.history_from_working(&working_path)?;
+    let file_history = if fs.path_exists(&history_path) {
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        FileHistory::from_file(fs, &locations, &mut history_file)?
+    } else {
+        FileHistory::default()
+    };
+
+    let old_content = file_history.get_content(from)?;
+    let new_content = match to {
+        Some(to) => file_history.get_content(to)?,
+        None if fs.path_exists(&working_path) => {
+            let mut working_file = fs.open_readable_file(&working_path)?;
+            fs.read_from_file(&mut working_file)?
+        }
+        None => Vec::new(),
+    };
+
+    let config = Config::load(fs, &locations)?;
+    let is_binary = [&old_content, &new_content]
+        .iter()
+        .any(|content| get_content_type(&config, &working_path, content) == ContentType::Binary);
+    if is_binary {
+        return Ok(summarize_binary(&config, &old_content, &new_content));
+    }
+    if let Some(segments) = diff_table(
+        &config,
+        &working_path,
+        &old_content,
+        &new_content,
+        granularity,
+    ) {
+        return Ok(segments);
+    }
+
+    Ok(diff_text(
+        &String::from_utf8_lossy(&old_content),
+        &String::from_utf8_lossy(&new_content),
+        granularity,
+    ))
+}
+
+fn summarize_binary(config: &Config, old_content: &[u8], new_content: &[u8]) -> Vec<TextSegment> {
+    if old_content == new_content {
+        return Vec::new();
+    }
+    if let Some(image_change) =
+        ImageChange::compare(old_content, new_content, config.perceptual_hashes)
+    {
+        return summarize_image(&image_change, old_content, new_content);
+    }
+
+    [
+        (SegmentKind::Deleted, old_content),
+        (SegmentKind::Inserted, new_content),
+    ]
+    .iter()
+    .filter(|(_, content)| !content.is_empty())
+    .map(|(kind, content)| TextSegment {
+        kind: *kind,
+        text: format!("Binary content, {} bytes\n", content.len()),
+    })
+    .collect()
+}
+
+fn summarize_image(
+    image_change: &ImageChange,
+    old_content: &[u8],
+    new_content: &[u8],
+) -> Vec<TextSegment> {
+    let mut segments: Vec<_> = [
+        (
+            SegmentKind::Deleted,
+            old_content,
+            image_change.describe_old(),
+        ),
+        (
+            SegmentKind::Inserted,
+            new_content,
+            image_change.describe_new(),
+        ),
+    ]
+    .iter()
+    .filter(|(_, content, _)| !content.is_empty())
+    .map(|(kind, _, description)| TextSegment {
+        kind: *kind,
+        text: format!("{}\n", description),
+    })
+    .collect();
+    if let Some(hash_distance) = image_change.hash_distance {
+        segments.push(TextSegment {
+            kind: SegmentKind::Equal,
+            text: format!(
+                "The perceptual hashes differ in {} of 64 bits.\n",
+                hash_distance
+            ),
+        });
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        diff::{SegmentKind, TextGranularity, TextSegment},
+        filesystem::mock::{write_file, FsMock},
+    };
+
+    use super::diff;
+
+    fn segment(kind: SegmentKind, text: &str) -> TextSegment {
+        TextSegment {
+            kind,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn diff_cursors_and_working_file() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./notes", b"first line\nsecond line\n");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./notes", b"first line\nsecond row\n");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+        write_file(&fs_mock, "./notes", b"first line\n");
+
+        let segments = diff(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("notes"),
+            1,
+            Some(2),
+            TextGranularity::Word,
+        )
+        .expect("Action failed.");
+        assert_eq!(
+            segments,
+            [
+                segment(SegmentKind::Equal, "first line\nsecond "),
+                segment(SegmentKind::Deleted, "line"),
+                segment(SegmentKind::Inserted, "row"),
+                segment(SegmentKind::Equal, "\n"),
+            ]
+        );
+
+        let segments = diff(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("notes"),
+            2,
+            None,
+            TextGranularity::Line,
+        )
+        .expect("Action failed.");
+        assert_eq!(
+            segments,
+            [
+                segment(SegmentKind::Equal, "first line\n"),
+                segment(SegmentKind::Deleted, "second row\n"),
+            ]
+        );
+
+        assert!(diff(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("notes"),
+            3,
+            None,
+            TextGranularity::Line,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn summarize_binary_diffs() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./image", b"\x89PNG\0\0");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./image", b"\x89PNG\0\0\0");
+
+        let segments = diff(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("image"),
+            1,
+            None,
+            TextGranularity::Line,
+        )
+        .expect("Action failed.");
+        assert_eq!(
+            segments,
+            [
+                segment(SegmentKind::Deleted, "Binary content, 6 bytes\n"),
+                segment(SegmentKind::Inserted, "Binary content, 7 bytes\n"),
+            ]
+        );
+
+        // Images are summarized by their dimensions as well.
+        write_file(&fs_mock, "./image", b"GIF89a\x40\x01\xf0\x00\0");
+        let segments = diff(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("image"),
+            1,
+            None,
+            TextGranularity::Line,
+        )
+        .expect("Action failed.");
+        assert_eq!(
+            segments,
+            [
+                segment(SegmentKind::Deleted, "Not an image, 6 bytes\n"),
+                segment(SegmentKind::Inserted, "GIF 320x240, 11 bytes\n"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    consistency::check_consistency,
+    crypto,
+    files::Locations,
+    filesystem::Fs,
+    history::RepositoryHistory,
+    trace::{self, Level},
+};
+
+use super::{create, shift, update, ActionOptions, ShiftMode, UntrackedFiles};
+
+// Below this much free space, the next large update might not fit anymore.
+const LOW_SPACE_BYTES: u64 = 100 * 1024 * 1024;
+// Writing, reading and deleting a small file taking longer than this makes every action slow.
+const SLOW_IO: Duration = Duration::from_millis(50);
+const IO_ROUNDS: u32 = 8;
+const IO_BLOCK_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed,
+    // Works, but might explain odd behavior.
+    Warning,
+    Failed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DoctorCheck {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+    pub detail: String,
+}
+
+impl DoctorCheck {
+    fn from_result(name: &'static str, result: Result<(CheckOutcome, String)>) -> Self {
+        let (outcome, detail) =
+            result.unwrap_or_else(|error| (CheckOutcome::Failed, format!("{:#}", error)));
+        DoctorCheck {
+            name,
+            outcome,
+            detail,
+        }
+    }
+}
+
+// Checks whether ka works as it should in this repository and on its filesystem. Besides
+// looking at the store, it records and shifts a scratch file in a repository of its own in
+// `.ka/doctor`, so the history of the repository isn't touched. The scratch repository is
+// removed again afterwards.
+pub fn doctor(command_options: ActionOptions, fs: &impl Fs, timestamp: u64) -> Vec<DoctorCheck> {
+    let locations = Locations::from(&command_options);
+    let scratch_path = locations.get_doctor_path();
+    // Left behind by a doctor which was interrupted.
+    if fs.path_exists(&scratch_path) {
+        let _ = fs.delete_directory(&scratch_path);
+    }
+
+    let checks = vec![
+        DoctorCheck::from_result("store", check_store(fs, &locations)),
+        DoctorCheck::from_result("journal", check_journal(fs, &locations)),
+        DoctorCheck::from_result("round trip", check_round_trip(fs, &scratch_path, timestamp)),
+        DoctorCheck::from_result("io latency", check_latency(fs, &scratch_path)),
+        DoctorCheck::from_result("space", check_space(fs, &locations)),
+    ];
+
+    if fs.path_exists(&scratch_path) {
+        if let Err(error) = fs.delete_directory(&scratch_path) {
+            trace::event(
+                Level::Warn,
+                "Failed removing the scratch repository.",
+                &[("error", &error)],
+            );
+        }
+    }
+    checks
+}
+
+// The index loads and agrees with the file histories.
+fn check_store(fs: &impl Fs, locations: &Locations) -> Result<(CheckOutcome, String)> {
+    let mut repository_index_file =
+        fs.open_readable_file(&locations.get_repository_index_path())?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, locations, &mut repository_index_file)?;
+    let inconsistencies = check_consistency(fs, locations, &repository_history)?;
+
+    let change_count = repository_history.get_changes().len();
+    if !inconsistencies.is_empty() {
+        return Ok((
+            CheckOutcome::Warning,
+            format!(
+                "{} changes, but the index and the file histories disagree in {} places. Set the consistency policy to heal to fix them.",
+                change_count,
+                inconsistencies.len()
+            ),
+        ));
+    }
+    Ok((
+        CheckOutcome::Passed,
+        format!(
+            "{} changes, at {}.",
+            change_count, repository_history.cursor
+        ),
+    ))
+}
+
+// An interrupted action leaves its journal behind, which the next action completes.
+fn check_journal(fs: &impl Fs, locations: &Locations) -> Result<(CheckOutcome, String)> {
+    if fs.path_exists(&locations.get_journal_path()) {
+        return Ok((
+            CheckOutcome::Warning,
+            "An interrupted action is waiting to be completed by the next one.".to_string(),
+        ));
+    }
+    Ok((CheckOutcome::Passed, "Nothing interrupted.".to_string()))
+}
+
+// Records two versions of a scratch file, and shifts back and forth between them.
+fn check_round_trip(
+    fs: &impl Fs,
+    scratch_path: &Path,
+    timestamp: u64,
+) -> Result<(CheckOutcome, String)> {
+    let options = ActionOptions::new(scratch_path.to_path_buf());
+    let scratch_file_path = scratch_path.join("scratch");
+    let first = b"The first version of the scratch file.\n".to_vec();
+    let second = b"The first version of the scratch file.\nAnd the second.\n".to_vec();
+
+    write_file(fs, &scratch_file_path, first.clone())?;
+    create(options.clone(), fs, timestamp)?;
+    write_file(fs, &scratch_file_path, second.clone())?;
+    update(options.clone(), fs, timestamp)?;
+
+    for (cursor, content) in [(1, &first), (2, &second)] {
+        shift(
+            options.clone(),
+            fs,
+            cursor,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )?;
+        let mut scratch_file = fs.open_readable_file(&scratch_file_path)?;
+        let restored = fs.read_from_file(&mut scratch_file)?;
+        if crypto::sha256(&restored) != crypto::sha256(content) {
+            bail!(
+                "Shifting to {} restored different content than was recorded.",
+                cursor
+            );
+        }
+    }
+    Ok((
+        CheckOutcome::Passed,
+        "Recorded, shifted and restored a scratch file.".to_string(),
+    ))
+}
+
+fn check_latency(fs: &impl Fs, scratch_path: &Path) -> Result<(CheckOutcome, String)> {
+    let latency_path = scratch_path.join("latency");
+    let block = vec![0xA5; IO_BLOCK_LENGTH];
+
+    let elapsed = measure(|| {
+        for _ in 0..IO_ROUNDS {
+            write_file(fs, &latency_path, block.clone())?;
+            let mut latency_file = fs.open_readable_file(&latency_path)?;
+            if fs.read_from_file(&mut latency_file)? != block {
+                bail!("Read back different content than was written.");
+            }
+            drop(latency_file);
+            fs.delete_file(&latency_path)?;
+        }
+        Ok(())
+    })?;
+    let elapsed = match elapsed {
+        Some(elapsed) => elapsed / IO_ROUNDS,
+        None => return Ok((CheckOutcome::Passed, "Can't be measured here.".to_string())),
+    };
+
+    let detail = format!(
+        "Writing, reading and deleting {} KiB took {} ms.",
+        IO_BLOCK_LENGTH / 1024,
+        elapsed.as_millis()
+    );
+    if elapsed > SLOW_IO {
+        return Ok((CheckOutcome::Warning, detail));
+    }
+    Ok((CheckOutcome::Passed, detail))
+}
+
+fn check_space(fs: &impl Fs, locations: &Locations) -> Result<(CheckOutcome, String)> {
+    let available_bytes = match fs.available_space(&locations.ka_path)? {
+        Some(available_bytes) => available_bytes,
+        None => return Ok((CheckOutcome::Passed, "Can't be told here.".to_string())),
+    };
+    let detail = format!("{} bytes are left.", available_bytes);
+    if available_bytes < LOW_SPACE_BYTES {
+        return Ok((CheckOutcome::Warning, detail));
+    }
+    Ok((CheckOutcome::Passed, detail))
+}
+
+fn write_file(fs: &impl Fs, path: &Path, content: Vec<u8>) -> Result<()> {
+    let mut file = fs.create_file(path)?;
+    fs.write_to_file(&mut file, content)
+}
+
+// There's no clock to measure with on `wasm32`.
+#[cfg(not(target_arch = "wasm32"))]
+fn measure(run: impl FnOnce() -> Result<()>) -> Result<Option<Duration>> {
+    let start = std::time::Instant::now();
+    run()?;
+    Ok(Some(start.elapsed()))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn measure(run: impl FnOnce() -> Result<()>) -> Result<Option<Duration>> {
+    run()?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, status, ActionOptions},
+        files::Locations,
+        filesystem::{mock::FsMock, Fs},
+    };
+
+    use super::{doctor, CheckOutcome};
+
+    #[test]
+    fn healthy_repository() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        let mut file = fs_mock.create_file(Path::new("./test")).unwrap();
+        fs_mock.write_to_file(&mut file, b"first".to_vec()).unwrap();
+        create(options.clone(), &fs_mock, now).unwrap();
+        fs_mock.set_available_space(Some(1024 * 1024));
+
+        let checks = doctor(options.clone(), &fs_mock, now + 1);
+        let outcomes: Vec<_> = checks
+            .iter()
+            .map(|check| (check.name, check.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("store", CheckOutcome::Passed),
+                ("journal", CheckOutcome::Passed),
+                ("round trip", CheckOutcome::Passed),
+                ("io latency", CheckOutcome::Passed),
+                ("space", CheckOutcome::Warning),
+            ]
+        );
+
+        // Nothing of the scratch repository is left, nor recorded in the repository.
+        assert!(!fs_mock.path_exists(&Locations::from(&options).get_doctor_path()));
+        let status = status(options, &fs_mock).unwrap();
+        assert_eq!(status.cursor, 1);
+        assert!(status.files.is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+use std::{convert::TryFrom, path::Path};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+    policy::ContentType,
+    sqlite::{write_database, Table, Value},
+};
+
+use super::{
+    log::{count_change_bytes, get_file_log_kind},
+    ActionOptions, FileLogKind,
+};
+
+const CHANGES_SQL: &str = "CREATE TABLE changes (change_index INTEGER PRIMARY KEY, id TEXT, \
+                           timestamp INTEGER, author TEXT, message TEXT, annotations TEXT)";
+const FILES_SQL: &str = "CREATE TABLE files (change_index INTEGER, path TEXT, kind TEXT, \
+                         bytes_added INTEGER, bytes_removed INTEGER, content_type TEXT)";
+const CHANGE_STATS_SQL: &str = "CREATE TABLE change_stats (change_index INTEGER PRIMARY KEY, \
+                                file_count INTEGER, created_count INTEGER, \
+                                modified_count INTEGER, deleted_count INTEGER, \
+                                bytes_added INTEGER, bytes_removed INTEGER)";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SqliteExport {
+    pub change_count: usize,
+    // How many different files were changed.
+    pub file_count: usize,
+    // How often any file was changed, the rows of the `files` table.
+    pub file_change_count: usize,
+}
+
+#[derive(Default)]
+struct ChangeStats {
+    file_count: usize,
+    created_count: usize,
+    modified_count: usize,
+    deleted_count: usize,
+    bytes_added: usize,
+    bytes_removed: usize,
+}
+
+// Writes the history into a new SQLite database, for whatever questions the log and stats
+// don't answer. It has three tables, joined by `change_index`: `changes` with a row for each
+// change, `files` with a row for each file each change affected, and `change_stats` summing
+// those up for each change. Annotations are kept as a JSON object.
+pub fn export_sqlite(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    target_path: &Path,
+) -> Result<SqliteExport> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    let changes = repository_history.get_changes();
+
+    // The database would otherwise be tracked by the next update.
+    if target_path.starts_with(&locations.repository_path) {
+        bail!(
+            "The database '{}' has to be outside of the repository.",
+            target_path.display()
+        );
+    }
+
+    if fs.path_exists(target_path) {
+        bail!("The database '{}' already exists.", target_path.display());
+    }
+
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+    history_paths.sort();
+
+    let mut file_rows = Vec::new();
+    let mut file_count = 0;
+    let mut stats = changes
+        .iter()
+        .map(|_| ChangeStats::default())
+        .collect::<Vec<_>>();
+
+    for history_path in history_paths {
+        let relative_path = history_path.strip_prefix(&locations.ka_files_path)?;
+        let path = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        if !file_history.get_changes().is_empty() {
+            file_count += 1;
+        }
+
+        for file_change in file_history.get_changes() {
+            let kind = get_file_log_kind(&file_history, file_change);
+            let (bytes_added, bytes_removed) = count_change_bytes(&file_history, file_change)?;
+
+            let change_stats = &mut stats[file_change.change_index - 1];
+            change_stats.file_count += 1;
+            match kind {
+                FileLogKind::Created => change_stats.created_count += 1,
+                FileLogKind::Modified => change_stats.modified_count += 1,
+                FileLogKind::Deleted => change_stats.deleted_count += 1,
+            }
+            change_stats.bytes_added += bytes_added;
+            change_stats.bytes_removed += bytes_removed;
+
+            let kind = match kind {
+                FileLogKind::Created => "created",
+                FileLogKind::Modified => "modified",
+                FileLogKind::Deleted => "deleted",
+            };
+            let content_type = match file_change.content_type {
+                Some(ContentType::Text) => Value::Text("text".to_string()),
+                Some(ContentType::Binary) => Value::Text("binary".to_string()),
+                None => Value::Null,
+            };
+            file_rows.push((
+                file_change.change_index,
+                vec![
+                    Value::Integer(i64::try_from(file_change.change_index)?),
+                    Value::Text(path.clone()),
+                    Value::Text(kind.to_string()),
+                    Value::Integer(i64::try_from(bytes_added)?),
+                    Value::Integer(i64::try_from(bytes_removed)?),
+                    content_type,
+                ],
+            ));
+        }
+    }
+
+    // In the order of the changes, and by path within each.
+    file_rows.sort_by_key(|(change_index, _)| *change_index);
+
+    let text_or_null = |text: &Option<String>| match text {
+        Some(text) => Value::Text(text.clone()),
+        None => Value::Null,
+    };
+
+    let mut change_rows = Vec::new();
+    let mut change_stats_rows = Vec::new();
+    for (index, (change, change_stats)) in changes.iter().zip(stats).enumerate() {
+        let change_index = i64::try_from(index + 1)?;
+        let annotations = if change.annotations.is_empty() {
+            Value::Null
+        } else {
+            Value::Text(serde_json::to_string(&change.annotations)?)
+        };
+
+        change_rows.push((
+            change_index,
+            vec![
+                Value::Null,
+                text_or_null(&change.id),
+                Value::Integer(i64::try_from(change.timestamp)?),
+                text_or_null(&change.author),
+                text_or_null(&change.message),
+                annotations,
+            ],
+        ));
+        change_stats_rows.push((
+            change_index,
+            vec![
+                Value::Null,
+                Value::Integer(i64::try_from(change_stats.file_count)?),
+                Value::Integer(i64::try_from(change_stats.created_count)?),
+                Value::Integer(i64::try_from(change_stats.modified_count)?),
+                Value::Integer(i64::try_from(change_stats.deleted_count)?),
+                Value::Integer(i64::try_from(change_stats.bytes_added)?),
+                Value::Integer(i64::try_from(change_stats.bytes_removed)?),
+            ],
+        ));
+    }
+
+    let summary = SqliteExport {
+        change_count: change_rows.len(),
+        file_count,
+        file_change_count: file_rows.len(),
+    };
+
+    let database = write_database(&[
+        Table {
+            name: "changes".to_string(),
+            sql: CHANGES_SQL.to_string(),
+            rows: change_rows,
+        },
+        Table {
+            name: "files".to_string(),
+            sql: FILES_SQL.to_string(),
+            rows: (1..)
+                .zip(file_rows.into_iter().map(|(_, row)| row))
+                .collect(),
+        },
+        Table {
+            name: "change_stats".to_string(),
+            sql: CHANGE_STATS_SQL.to_string(),
+            rows: change_stats_rows,
+        },
+    ])?;
+
+    let mut database_file = fs.create_file(target_path)?;
+    fs.write_to_file(&mut database_file, database)?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, update_annotated, ActionOptions},
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::Annotations,
+    };
+
+    use super::{export_sqlite, SqliteExport};
+
+    #[test]
+    fn export_changes_to_sqlite() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        fs_mock.create_directory(Path::new("/notes")).unwrap();
+        write_file(&fs_mock, "/notes/today", b"Water plants");
+        write_file(&fs_mock, "/notes/later", b"Feed cat");
+        create(ActionOptions::from_path("/notes"), &fs_mock, now).unwrap();
+
+        // Enough changes for the tables to span several pages.
+        for index in 0..200 {
+            let content = format!("Water plants {}", "again ".repeat(index));
+            write_file(&fs_mock, "/notes/today", content.as_bytes());
+            update(ActionOptions::from_path("/notes"), &fs_mock, now + 1).unwrap();
+        }
+
+        fs_mock.delete_file(Path::new("/notes/later")).unwrap();
+        let mut annotations = Annotations::new();
+        annotations.insert("app".to_string(), "notes".to_string());
+        update_annotated(
+            ActionOptions::from_path("/notes"),
+            &fs_mock,
+            now + 2,
+            annotations,
+        )
+        .unwrap();
+
+        assert!(export_sqlite(
+            ActionOptions::from_path("/notes"),
+            &fs_mock,
+            Path::new("/notes/history.db"),
+        )
+        .is_err());
+
+        let summary = export_sqlite(
+            ActionOptions::from_path("/notes"),
+            &fs_mock,
+            Path::new("/history.db"),
+        )
+        .expect("Action failed.");
+        assert_eq!(
+            summary,
+            SqliteExport {
+                change_count: 202,
+                file_count: 2,
+                file_change_count: 203,
+            }
+        );
+
+        let database = read_file(&fs_mock, "/history.db");
+        assert_eq!(&database[..16], b"SQLite format 3\0");
+        assert_eq!(database.len() % 4096, 0);
+        assert_eq!(
+            u32::from_be_bytes([database[28], database[29], database[30], database[31]]) as usize,
+            database.len() / 4096
+        );
+
+        assert!(export_sqlite(
+            ActionOptions::from_path("/notes"),
+            &fs_mock,
+            Path::new("/history.db"),
+        )
+        .is_err());
+    }
+}
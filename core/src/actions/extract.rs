@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    collisions::{get_suffixed_path, CaseCollisionPolicy, CasePaths},
+    config::Config,
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+};
+
+use super::ActionOptions;
+
+pub fn extract(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    cursor: usize,
+    target_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let change_count = repository_history.get_changes().len();
+    if cursor > change_count {
+        bail!(
+            "The cursor {} is out of range, as the repository only has the changes 1..={}.",
+            cursor,
+            change_count
+        );
+    }
+
+    // Extracting into the working directory would mix the old tree with the current one.
+    if target_path.starts_with(&locations.repository_path) {
+        bail!(
+            "The target '{}' has to be outside of the repository.",
+            target_path.display()
+        );
+    }
+
+    if fs.path_exists(target_path) {
+        bail!(
+            "The target '{}' already exists, extract into a new directory instead.",
+            target_path.display()
+        );
+    }
+
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+    history_paths.sort();
+
+    let mut file_histories = Vec::new();
+    for history_path in history_paths {
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        if file_history.does_file_exist(cursor) {
+            let relative_path = history_path.strip_prefix(&locations.ka_files_path)?;
+            file_histories.push((target_path.join(relative_path), file_history));
+        }
+    }
+
+    // Paths only differing by case would overwrite each other on case-insensitive filesystems.
+    let config = Config::load(fs, &locations)?;
+    let mut case_paths = CasePaths::default();
+    let mut case_collisions = Vec::new();
+    if config.case_collisions != CaseCollisionPolicy::Allow {
+        for (extracted_path, _) in file_histories.iter() {
+            if let Some(other_path) = case_paths.insert(extracted_path) {
+                case_collisions.push((extracted_path.clone(), other_path));
+            }
+        }
+    }
+    if config.case_collisions == CaseCollisionPolicy::Error && !case_collisions.is_empty() {
+        bail!(
+            "The change {} has files whose paths only differ by case from others:\n{}\nSet `case_collisions` in the config to `Suffix` or `Skip` to extract them anyway.",
+            cursor,
+            case_collisions
+                .iter()
+                .map(|(path, other_path)| format!("{} and {}", path.display(), other_path.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    fs.create_directory(target_path)?;
+
+    let mut extracted_paths = Vec::new();
+
+    for (mut extracted_path, file_history) in file_histories {
+        if case_collisions
+            .iter()
+            .any(|(path, _)| *path == extracted_path)
+        {
+            match config.case_collisions {
+                CaseCollisionPolicy::Skip => continue,
+                CaseCollisionPolicy::Suffix => {
+                    extracted_path = get_suffixed_path(&extracted_path, |path| {
+                        fs.path_exists(path) || case_paths.contains(path)
+                    });
+                    case_paths.insert(&extracted_path);
+                }
+                CaseCollisionPolicy::Allow | CaseCollisionPolicy::Error => {}
+            }
+        }
+
+        let mut extracted_file = fs.create_file(&extracted_path)?;
+        fs.write_to_file(&mut extracted_file, file_history.get_content(cursor)?)?;
+
+        extracted_paths.push(extracted_path);
+    }
+
+    Ok(extracted_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        collisions::CaseCollisionPolicy,
+        config::Config,
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::extract;
+
+    #[test]
+    fn extract_past_tree() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./repo/notes", b"first");
+        write_file(&fs_mock, "./repo/nested/todo", b"todo");
+        create(ActionOptions::from_path("./repo"), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./repo/notes", b"first second");
+        write_file(&fs_mock, "./repo/new", b"new");
+        fs_mock
+            .delete_file(Path::new("./repo/nested/todo"))
+            .unwrap();
+        update(ActionOptions::from_path("./repo"), &fs_mock, now + 1).unwrap();
+
+        let extracted_paths = extract(
+            ActionOptions::from_path("./repo"),
+            &fs_mock,
+            1,
+            Path::new("./old"),
+        )
+        .expect("Action failed.");
+
+        assert_eq!(
+            extracted_paths,
+            vec![Path::new("./old/nested/todo"), Path::new("./old/notes")]
+        );
+        assert_eq!(read_file(&fs_mock, "./old/notes"), b"first");
+        assert_eq!(read_file(&fs_mock, "./old/nested/todo"), b"todo");
+        assert!(!fs_mock.path_exists(Path::new("./old/new")));
+
+        // The repository stays as it was.
+        assert_eq!(read_file(&fs_mock, "./repo/notes"), b"first second");
+        assert!(!fs_mock.path_exists(Path::new("./repo/nested/todo")));
+
+        let result = extract(
+            ActionOptions::from_path("./repo"),
+            &fs_mock,
+            1,
+            Path::new("./old"),
+        );
+        assert!(result.is_err());
+
+        let result = extract(
+            ActionOptions::from_path("./repo"),
+            &fs_mock,
+            1,
+            Path::new("./repo/old"),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn extract_with_case_collisions() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path("./repo");
+
+        write_file(&fs_mock, "./repo/README", b"upper");
+        write_file(&fs_mock, "./repo/Readme", b"mixed");
+        create(options.clone(), &fs_mock, now).unwrap();
+
+        let mut extracted = Vec::new();
+        for (case_collisions, target_path) in [
+            (CaseCollisionPolicy::Error, "./error"),
+            (CaseCollisionPolicy::Skip, "./skip"),
+            (CaseCollisionPolicy::Suffix, "./suffix"),
+        ] {
+            let config = Config {
+                case_collisions,
+                ..Config::default()
+            };
+            config.write(&fs_mock, &Locations::from(&options)).unwrap();
+            extracted.push(extract(
+                options.clone(),
+                &fs_mock,
+                1,
+                Path::new(target_path),
+            ));
+        }
+
+        assert!(extracted[0].is_err());
+        assert!(!fs_mock.path_exists(Path::new("./error")));
+        assert_eq!(
+            extracted[1].as_ref().unwrap(),
+            &vec![Path::new("./skip/README")]
+        );
+        assert_eq!(
+            extracted[2].as_ref().unwrap(),
+            &vec![
+                Path::new("./suffix/README"),
+                Path::new("./suffix/Readme (2)")
+            ]
+        );
+        assert_eq!(read_file(&fs_mock, "./suffix/Readme (2)"), b"mixed");
+    }
+}
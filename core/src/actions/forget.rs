@@ -0,0 +1,146 @@
+use std::{iter, path::Path};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    files::{normalize_path, Locations},
+    filesystem::Fs,
+    history::{materialize_copies, FileHistory, RepositoryHistory},
+    journal,
+};
+
+use super::ActionOptions;
+
+// Stops tracking a file, leaving the working file where it is. Its history is kept around
+// for looking at, unless it's purged, in which case the file is gone from all changes and
+// would be recorded as a new file by the next update.
+pub fn forget(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    path: &Path,
+    purge: bool,
+) -> Result<()> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let working_path = locations.get_working_path(path)?;
+    let history_path = locations.history_from_working(&working_path)?;
+    if !fs.path_exists(&history_path) {
+        bail!(
+            "The file '{}' can't be forgotten, because it has no history.",
+            command_options.display_path(&working_path).display()
+        );
+    }
+
+    if !purge {
+        let mut history_file = fs.open_writable_file(&history_path)?;
+        let mut file_history = FileHistory::read_stored(fs, &locations, &mut history_file)?;
+        file_history.forgotten = true;
+        file_history.write_to_file(fs, &locations, &mut history_file)?;
+        return Ok(());
+    }
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    // The changes themselves stay, even if they're left empty, so the cursors don't move.
+    for change in repository_history.get_changes_mut() {
+        change
+            .affected_files
+            .retain(|affected_path| *affected_path != working_path);
+    }
+
+    materialize_copies(fs, &locations, &iter::once(normalize_path(path)).collect())?;
+    repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+    FileHistory::delete_file(fs, &locations, &history_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, shift, status, update, ActionOptions, ShiftMode, UntrackedFiles},
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::RepositoryHistory,
+    };
+
+    use super::forget;
+
+    #[test]
+    fn forget_and_purge_files() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./kept", b"kept");
+        write_file(&fs_mock, "./forgotten", b"forgotten");
+        write_file(&fs_mock, "./purged", b"purged");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./kept", b"kept changed");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        assert!(forget(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("untracked"),
+            false
+        )
+        .is_err());
+        forget(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("forgotten"),
+            false,
+        )
+        .unwrap();
+        forget(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("purged"),
+            true,
+        )
+        .unwrap();
+
+        // Neither a changed nor a deleted forgotten file is picked up.
+        write_file(&fs_mock, "./forgotten", b"forgotten changed");
+        fs_mock.delete_file(Path::new("./purged")).unwrap();
+        assert!(status(ActionOptions::from_path("."), &fs_mock)
+            .unwrap()
+            .files
+            .is_empty());
+
+        // Shifting back doesn't resurrect the forgotten or the purged file.
+        fs_mock.delete_file(Path::new("./forgotten")).unwrap();
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert_eq!(read_file(&fs_mock, "./kept"), b"kept");
+        assert!(!fs_mock.path_exists(Path::new("./forgotten")));
+        assert!(!fs_mock.path_exists(Path::new("./purged")));
+
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/forgotten")));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/purged")));
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let mut index_file = fs_mock
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        let index = RepositoryHistory::from_file(&fs_mock, &locations, &mut index_file).unwrap();
+        assert!(!index
+            .get_tracked_paths()
+            .contains(&Path::new("./purged").to_path_buf()));
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::Config,
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+    policy::{get_content_type, ContentType},
+    regex::Regex,
+};
+
+use super::ActionOptions;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct GrepMatch {
+    // Relative to the repository.
+    pub path: PathBuf,
+    // Starting at 1, like editors count them.
+    pub line_number: usize,
+    pub line: String,
+}
+
+// Searches the files as they were at the cursor for lines matching the pattern, see `regex`
+// for what it supports. Each file is reconstructed from its history and searched on its own,
+// without writing anything. Binary files are left out.
+pub fn grep(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    pattern: &str,
+    cursor: usize,
+) -> Result<Vec<GrepMatch>> {
+    let regex = Regex::new(pattern)?;
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let change_count = repository_history.get_changes().len();
+    if cursor > change_count {
+        bail!(
+            "The cursor {} is out of range, as the repository only has the changes 1..={}.",
+            cursor,
+            change_count
+        );
+    }
+
+    let config = Config::load(fs, &locations)?;
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+    history_paths.sort();
+
+    let mut matches = Vec::new();
+    for history_path in history_paths {
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        if !file_history.does_file_exist(cursor) {
+            continue;
+        }
+
+        let relative_path = history_path.strip_prefix(&locations.ka_files_path)?;
+        let content = file_history.get_content(cursor)?;
+        if get_content_type(&config, relative_path, &content) == ContentType::Binary {
+            continue;
+        }
+
+        for (index, line) in String::from_utf8_lossy(&content).lines().enumerate() {
+            if regex.is_match(line) {
+                matches.push(GrepMatch {
+                    path: relative_path.to_path_buf(),
+                    line_number: index + 1,
+                    line: line.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::mock::{write_file, FsMock},
+    };
+
+    use super::{grep, GrepMatch};
+
+    #[test]
+    fn grep_at_cursor() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./notes/todo", b"buy milk\ncall ada\n");
+        write_file(&fs_mock, "./image", b"\0milk");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./notes/todo", b"call ada\n");
+        write_file(&fs_mock, "./list", b"oat milk\n");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let matches = grep(ActionOptions::from_path("."), &fs_mock, "m[a-z]+k$", 1).unwrap();
+        assert_eq!(
+            matches,
+            [GrepMatch {
+                path: PathBuf::from("notes/todo"),
+                line_number: 1,
+                line: "buy milk".into(),
+            }]
+        );
+
+        let matches = grep(ActionOptions::from_path("."), &fs_mock, "milk", 2).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].path, PathBuf::from("list"));
+
+        assert!(grep(ActionOptions::from_path("."), &fs_mock, "milk", 3).is_err());
+    }
+}
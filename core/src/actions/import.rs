@@ -0,0 +1,221 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::UpdateSummary,
+    files::{FileState, Locations},
+    filesystem::Fs,
+    journal::{self, Journal},
+    metrics::ActionTimer,
+    progress::Progress,
+    trace::{self, Level},
+};
+
+use super::{create::initialize, update_paths, ActionOptions};
+
+// How many files are recorded per change. Every chunk is written completely before the next
+// one is read, so an interrupted import only has to redo the chunk it was in.
+pub const IMPORT_CHUNK_LENGTH: usize = 512;
+
+// The files an import has yet to record, kept in `.ka/import` until it's done. Repositories
+// with an import left aren't opened for anything else.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ImportState {
+    pending: Vec<PathBuf>,
+    total: usize,
+}
+
+impl ImportState {
+    fn load<FS: Fs>(fs: &FS, locations: &Locations) -> Result<Option<Self>> {
+        let state_path = locations.get_import_state_path();
+        if !fs.path_exists(&state_path) {
+            return Ok(None);
+        }
+
+        let mut state_file = fs.open_readable_file(&state_path)?;
+        let buffer = fs
+            .read_from_file(&mut state_file)
+            .context("Failed reading import state.")?;
+        serde_json::from_slice(&buffer)
+            .map(Some)
+            .context("Failed decoding import state.")
+    }
+
+    // Through the journal, so the state is never left half written.
+    fn write<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<()> {
+        let encoded = serde_json::to_vec(self).context("Failed encoding import state.")?;
+        let mut journal = Journal::default();
+        journal.add_write(locations.get_import_state_path(), encoded);
+        journal.commit(fs, locations)
+    }
+}
+
+// Like `create`, but for directories too large to record in one go. The files are recorded
+// in chunks of `IMPORT_CHUNK_LENGTH`, each as a change of its own, and every recorded file is
+// reported to `progress`. Importing into a repository whose import was interrupted goes on
+// with the files it had left, rather than starting over.
+pub fn import(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+    progress: &mut dyn Progress,
+) -> Result<UpdateSummary> {
+    let _timer = ActionTimer::start("import");
+    let _span = trace::span(
+        Level::Info,
+        "import",
+        &[("repository", &command_options.repository_path.display())],
+    );
+    let locations = Locations::from(&command_options);
+
+    let mut state = match ImportState::load(fs, &locations)? {
+        Some(state) => {
+            journal::complete_pending(fs, &locations)?;
+            trace::event(
+                Level::Info,
+                "Resuming the import.",
+                &[("pending", &state.pending.len()), ("total", &state.total)],
+            );
+            state
+        }
+        None => {
+            // Until the state is written, this is just an empty repository.
+            initialize(fs, &locations)?;
+            let mut pending = locations
+                .get_repository_files(fs)
+                .context("Could not traverse files.")?
+                .into_iter()
+                .filter_map(|state| match state {
+                    FileState::Untracked(untracked) => Some(untracked.path),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            pending.sort();
+
+            let state = ImportState {
+                total: pending.len(),
+                pending,
+            };
+            state.write(fs, &locations)?;
+            state
+        }
+    };
+
+    let mut summary = UpdateSummary::default();
+    while !state.pending.is_empty() {
+        let chunk_length = state.pending.len().min(IMPORT_CHUNK_LENGTH);
+        let chunk = state.pending[..chunk_length]
+            .iter()
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        let chunk_summary = update_paths(command_options.clone(), fs, timestamp, &chunk)?;
+        summary.skipped.extend(chunk_summary.skipped);
+        summary.racy.extend(chunk_summary.racy);
+        summary.reserved.extend(chunk_summary.reserved);
+        summary.over_budget.extend(chunk_summary.over_budget);
+        summary.locked.extend(chunk_summary.locked);
+        summary.deferred.extend(chunk_summary.deferred);
+
+        let done = state.total - state.pending.len();
+        for (offset, working_path) in state.pending.drain(..chunk_length).enumerate() {
+            progress.file_done(&working_path, done + offset + 1, state.total);
+        }
+        // Files of a chunk which was recorded before this is written are just unchanged
+        // when the chunk is recorded again.
+        state.write(fs, &locations)?;
+    }
+
+    fs.delete_file(&locations.get_import_state_path())?;
+    trace::event(
+        Level::Info,
+        "Imported the files.",
+        &[("files", &state.total), ("skipped", &summary.skipped.len())],
+    );
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        path::Path,
+    };
+
+    use crate::{
+        actions::ActionOptions,
+        files::Locations,
+        filesystem::{mock::FsMock, Fs},
+        history::RepositoryHistory,
+        progress::Progress,
+    };
+
+    use super::{import, IMPORT_CHUNK_LENGTH};
+
+    struct Interrupting {
+        interrupt_at: Option<usize>,
+        reports: Vec<(usize, usize)>,
+    }
+
+    impl Progress for Interrupting {
+        fn file_done(&mut self, _working_path: &Path, done: usize, total: usize) {
+            if self.interrupt_at == Some(done) {
+                panic!("Interrupted.");
+            }
+            self.reports.push((done, total));
+        }
+    }
+
+    #[test]
+    fn resume_interrupted_import() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path("/home/notes");
+        let locations = Locations::from(&options);
+        let file_count = IMPORT_CHUNK_LENGTH + 10;
+        for number in 0..file_count {
+            let path = format!("/home/notes/{:04}", number);
+            let mut file = fs_mock.create_file(Path::new(&path)).unwrap();
+            fs_mock
+                .write_to_file(&mut file, number.to_string().into_bytes())
+                .unwrap();
+        }
+
+        // The first chunk is recorded, but the state doesn't know yet.
+        let mut progress = Interrupting {
+            interrupt_at: Some(IMPORT_CHUNK_LENGTH),
+            reports: Vec::new(),
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            import(options.clone(), &fs_mock, now, &mut progress)
+        }));
+        assert!(result.is_err());
+        assert!(fs_mock.path_exists(&locations.get_import_state_path()));
+        assert!(ActionOptions::discover(&fs_mock, Path::new("/home/notes")).is_err());
+
+        let mut progress = Interrupting {
+            interrupt_at: None,
+            reports: Vec::new(),
+        };
+        let summary = import(options.clone(), &fs_mock, now + 1, &mut progress).unwrap();
+        assert!(summary.skipped.is_empty());
+        assert_eq!(progress.reports.len(), file_count);
+        assert_eq!(progress.reports.last(), Some(&(file_count, file_count)));
+        assert!(!fs_mock.path_exists(&locations.get_import_state_path()));
+        assert!(ActionOptions::discover(&fs_mock, Path::new("/home/notes")).is_ok());
+
+        let mut index_file = fs_mock
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        let history = RepositoryHistory::from_file(&fs_mock, &locations, &mut index_file).unwrap();
+        let changes = history.get_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].affected_files.len(), IMPORT_CHUNK_LENGTH);
+        assert_eq!(changes[1].affected_files.len(), 10);
+        assert!(fs_mock.path_exists(Path::new("/home/notes/.ka/files/0000")));
+        assert!(fs_mock.path_exists(Path::new("/home/notes/.ka/files/0521")));
+    }
+}
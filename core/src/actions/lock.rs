@@ -0,0 +1,130 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::Config,
+    files::Locations,
+    filesystem::Fs,
+    locks::{self, Lock, LockedError, Locks},
+};
+
+use super::ActionOptions;
+
+// Tells others the file is being worked on. Locking a file again which is already locked by the
+// same owner keeps the lock as it is. Returns whether the file wasn't locked yet.
+pub fn lock_file(command_options: ActionOptions, fs: &impl Fs, path: &Path) -> Result<bool> {
+    let locations = Locations::from(&command_options);
+    let config = Config::load(fs, &locations)?;
+    let owner = locks::get_owner(&command_options, &config);
+
+    let working_path = locations.get_working_path(path)?;
+    let mut locks = Locks::load(fs, &locations)?;
+    if let Some(lock) = locks.files.get(&working_path) {
+        if lock.owner != owner {
+            bail!(LockedError(format!(
+                "The file '{}' is locked by {} already.",
+                command_options.display_path(&working_path).display(),
+                lock.owner
+            )));
+        }
+        return Ok(false);
+    }
+
+    locks.files.insert(
+        working_path,
+        Lock {
+            owner,
+            since: command_options.now(),
+        },
+    );
+    locks.write(fs, &locations)?;
+    Ok(true)
+}
+
+// Releases a lock of the same owner. Returns whether the file was locked.
+pub fn unlock_file(command_options: ActionOptions, fs: &impl Fs, path: &Path) -> Result<bool> {
+    let locations = Locations::from(&command_options);
+    let config = Config::load(fs, &locations)?;
+    let owner = locks::get_owner(&command_options, &config);
+
+    let working_path = locations.get_working_path(path)?;
+    let mut locks = Locks::load(fs, &locations)?;
+    match locks.files.get(&working_path) {
+        None => return Ok(false),
+        Some(lock) if lock.owner != owner => bail!(LockedError(format!(
+            "The file '{}' is locked by {}, only they can unlock it.",
+            command_options.display_path(&working_path).display(),
+            lock.owner
+        ))),
+        Some(_) => {}
+    }
+
+    locks.files.remove(&working_path);
+    locks.write(fs, &locations)?;
+    Ok(true)
+}
+
+// The locked files by their working paths.
+pub fn locked_files(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+) -> Result<BTreeMap<PathBuf, Lock>> {
+    let locations = Locations::from(&command_options);
+    Ok(Locks::load(fs, &locations)?.files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        locks::LockedError,
+    };
+
+    use super::{lock_file, locked_files, unlock_file};
+
+    #[test]
+    fn lock_files_by_owner() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let first_editor = || ActionOptions::from_path(".").with_lock_owner("first editor");
+        let second_editor = || ActionOptions::from_path(".").with_lock_owner("second editor");
+
+        write_file(&fs_mock, "./notes", b"first");
+        write_file(&fs_mock, "./todo", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        assert!(lock_file(first_editor(), &fs_mock, Path::new("notes")).unwrap());
+        assert!(!lock_file(first_editor(), &fs_mock, Path::new("notes")).unwrap());
+        let error = lock_file(second_editor(), &fs_mock, Path::new("notes")).unwrap_err();
+        assert!(error.downcast_ref::<LockedError>().is_some());
+        let error = unlock_file(second_editor(), &fs_mock, Path::new("notes")).unwrap_err();
+        assert!(error.downcast_ref::<LockedError>().is_some());
+        let locks = locked_files(ActionOptions::from_path("."), &fs_mock).unwrap();
+        assert_eq!(locks[Path::new("./notes")].owner, "first editor");
+
+        write_file(&fs_mock, "./notes", b"second");
+        write_file(&fs_mock, "./todo", b"second");
+        let summary = update(second_editor(), &fs_mock, now + 1).unwrap();
+        assert_eq!(summary.locked.len(), 1);
+        assert_eq!(summary.locked[0].0, Path::new("./notes"));
+        assert_eq!(summary.locked[0].1.owner, "first editor");
+
+        write_file(&fs_mock, "./notes", b"third");
+        let summary = update(first_editor(), &fs_mock, now + 2).unwrap();
+        assert!(summary.locked.is_empty());
+
+        assert!(unlock_file(first_editor(), &fs_mock, Path::new("notes")).unwrap());
+        assert!(!unlock_file(first_editor(), &fs_mock, Path::new("notes")).unwrap());
+        assert!(!fs_mock.path_exists(Path::new("./.ka/locks")));
+    }
+}
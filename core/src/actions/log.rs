@@ -0,0 +1,587 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    diff::ContentChange,
+    files::Locations,
+    filesystem::Fs,
+    history::{
+        Annotations, FileChange, FileChangeVariant, FileHistory, RepositoryChange,
+        RepositoryHistory,
+    },
+    policy::ContentType,
+};
+
+use super::ActionOptions;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileLogKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileLogEntry {
+    pub change_index: usize,
+    pub change_id: Option<String>,
+    pub timestamp: u64,
+    pub message: Option<String>,
+    pub author: Option<String>,
+    pub annotations: Annotations,
+    pub kind: FileLogKind,
+    pub bytes_added: usize,
+    pub bytes_removed: usize,
+    // Not known for deletions and changes recorded before content types were.
+    pub content_type: Option<ContentType>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogBucket {
+    // The start of the span of time the changes were made in.
+    pub start: u64,
+    pub first_change: usize,
+    pub last_change: usize,
+    pub change_count: usize,
+    // Files changed by any of the changes, each counted once.
+    pub file_count: usize,
+    pub bytes_added: usize,
+    pub bytes_removed: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub change_index: usize,
+    pub change_id: Option<String>,
+    pub timestamp: u64,
+    pub message: Option<String>,
+    pub author: Option<String>,
+    pub annotations: Annotations,
+    // Relative to the repository.
+    pub affected_files: Vec<PathBuf>,
+}
+
+pub fn repository_log(command_options: ActionOptions, fs: &impl Fs) -> Result<Vec<LogEntry>> {
+    repository_log_since(command_options, fs, 0)
+}
+
+// Only the changes recorded at or after `since`. Older segments of the index aren't read.
+pub fn repository_log_since(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    since: u64,
+) -> Result<Vec<LogEntry>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let (skipped_count, changes) =
+        RepositoryHistory::load_changes_since(fs, &locations, &mut repository_index_file, since)?;
+
+    Ok(changes
+        .into_iter()
+        .enumerate()
+        .filter(|(_, change)| change.timestamp >= since)
+        .map(|(index, change)| get_log_entry(&locations, skipped_count + index + 1, change))
+        .collect())
+}
+
+// Only the changes annotated with `key`, and with `value` for it if one is given.
+pub fn annotated_log(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    key: &str,
+    value: Option<&str>,
+) -> Result<Vec<LogEntry>> {
+    Ok(repository_log(command_options, fs)?
+        .into_iter()
+        .filter(|entry| match (entry.annotations.get(key), value) {
+            (Some(annotated_value), Some(value)) => annotated_value == value,
+            (annotated_value, _) => annotated_value.is_some(),
+        })
+        .collect())
+}
+
+// The changes which affected any file in the directory at `path`, or below it.
+pub fn directory_log(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    path: &Path,
+) -> Result<Vec<LogEntry>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let working_path = locations.get_working_path(path)?;
+    Ok(repository_history
+        .changes_for(&working_path)
+        .into_iter()
+        .map(|change_index| {
+            let change = repository_history.get_changes()[change_index - 1].clone();
+            get_log_entry(&locations, change_index, change)
+        })
+        .collect())
+}
+
+fn get_log_entry(locations: &Locations, change_index: usize, change: RepositoryChange) -> LogEntry {
+    LogEntry {
+        change_index,
+        change_id: change.id,
+        timestamp: change.timestamp,
+        message: change.message,
+        author: change.author,
+        annotations: change.annotations,
+        affected_files: change
+            .affected_files
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&locations.repository_path)
+                    .unwrap_or(path)
+                    .to_path_buf()
+            })
+            .collect(),
+    }
+}
+
+pub fn file_log(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    path: &Path,
+) -> Result<Vec<FileLogEntry>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let working_path = locations.get_working_path(path)?;
+    let history_path = locations.history_from_working(&working_path)?;
+    if !fs.path_exists(&history_path) {
+        bail!(
+            "The file '{}' has no history.",
+            command_options.display_path(&working_path).display()
+        );
+    }
+
+    let mut history_file = fs.open_readable_file(&history_path)?;
+    let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+
+    file_history
+        .get_changes()
+        .iter()
+        .map(|change| {
+            let repository_change = repository_history
+                .get_changes()
+                .get(change.change_index - 1)
+                .with_context(|| {
+                    format!(
+                        "The history of '{}' refers to the missing change {}.",
+                        command_options.display_path(&working_path).display(),
+                        change.change_index
+                    )
+                })?;
+
+            let kind = get_file_log_kind(&file_history, change);
+            let (bytes_added, bytes_removed) = count_change_bytes(&file_history, change)?;
+
+            Ok(FileLogEntry {
+                change_index: change.change_index,
+                change_id: repository_change.id.clone(),
+                timestamp: repository_change.timestamp,
+                message: repository_change.message.clone(),
+                author: repository_change.author.clone(),
+                annotations: repository_change.annotations.clone(),
+                kind,
+                bytes_added,
+                bytes_removed,
+                content_type: change.content_type,
+            })
+        })
+        .collect()
+}
+
+// Groups consecutive changes which happened within the same span of `bucket_seconds`, which
+// keeps histories with lots of tiny changes, like those recorded by watching, readable.
+pub fn bucketed_log(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    bucket_seconds: u64,
+) -> Result<Vec<LogBucket>> {
+    if bucket_seconds == 0 {
+        bail!("The log buckets have to span at least a second.");
+    }
+
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    let changes = repository_history.get_changes();
+
+    let mut change_bytes = vec![(0, 0); changes.len()];
+    for working_path in repository_history.get_tracked_paths() {
+        let history_path = locations.history_from_working(working_path)?;
+        // Purged files are still listed by the changes they were part of.
+        if !fs.path_exists(&history_path) {
+            continue;
+        }
+
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        for file_change in file_history.get_changes() {
+            let (added, removed) = count_change_bytes(&file_history, file_change)?;
+            if let Some(bytes) = change_bytes.get_mut(file_change.change_index - 1) {
+                bytes.0 += added;
+                bytes.1 += removed;
+            }
+        }
+    }
+
+    let mut buckets: Vec<LogBucket> = Vec::new();
+    let mut bucket_files = HashSet::new();
+    for (index, (change, (added, removed))) in changes.iter().zip(change_bytes).enumerate() {
+        let start = change.timestamp - change.timestamp % bucket_seconds;
+        match buckets.last_mut() {
+            Some(bucket) if bucket.start == start => {
+                bucket.last_change = index + 1;
+                bucket.change_count += 1;
+                bucket.bytes_added += added;
+                bucket.bytes_removed += removed;
+            }
+            _ => {
+                bucket_files.clear();
+                buckets.push(LogBucket {
+                    start,
+                    first_change: index + 1,
+                    last_change: index + 1,
+                    change_count: 1,
+                    file_count: 0,
+                    bytes_added: added,
+                    bytes_removed: removed,
+                });
+            }
+        }
+        bucket_files.extend(change.affected_files.iter());
+        if let Some(bucket) = buckets.last_mut() {
+            bucket.file_count = bucket_files.len();
+        }
+    }
+
+    Ok(buckets)
+}
+
+pub(super) fn get_file_log_kind(
+    file_history: &FileHistory,
+    file_change: &FileChange,
+) -> FileLogKind {
+    match file_change.variant {
+        FileChangeVariant::Updated(_)
+            if file_history.does_file_exist(file_change.change_index - 1) =>
+        {
+            FileLogKind::Modified
+        }
+        FileChangeVariant::Updated(_) => FileLogKind::Created,
+        FileChangeVariant::Deleted => FileLogKind::Deleted,
+        FileChangeVariant::CopiedFrom { .. } | FileChangeVariant::Structured(_) => unreachable!(),
+    }
+}
+
+// The bytes a change added to and removed from the file.
+pub(super) fn count_change_bytes(
+    file_history: &FileHistory,
+    file_change: &FileChange,
+) -> Result<(usize, usize)> {
+    match &file_change.variant {
+        FileChangeVariant::Updated(content_changes) => Ok(count_bytes(content_changes)),
+        FileChangeVariant::Deleted => {
+            let old_content = file_history.get_content(file_change.change_index - 1)?;
+            Ok((0, old_content.len()))
+        }
+        FileChangeVariant::CopiedFrom { .. } | FileChangeVariant::Structured(_) => {
+            unreachable!()
+        }
+    }
+}
+
+pub(super) fn count_bytes(content_changes: &[ContentChange]) -> (usize, usize) {
+    content_changes.iter().fold(
+        (0, 0),
+        |(added, removed), content_change| match content_change {
+            ContentChange::Inserted { new_content, .. } => (added + new_content.len(), removed),
+            ContentChange::Deleted { at, upto, .. } => (added, removed + upto - at),
+            ContentChange::InsertedObject { .. } => unreachable!(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, squash, update, update_annotated, ActionOptions},
+        config::{Config, Identity},
+        files::Locations,
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        history::Annotations,
+    };
+
+    use super::{
+        annotated_log, bucketed_log, directory_log, file_log, repository_log, FileLogKind,
+        LogBucket,
+    };
+
+    #[test]
+    fn log_file_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        write_file(&fs_mock, "./other", b"other");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./other", b"other changed");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        write_file(&fs_mock, "./test", b"first");
+        update(ActionOptions::from_path("."), &fs_mock, now + 3).unwrap();
+        squash(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            4,
+            4,
+            Some("Shorten".into()),
+        )
+        .unwrap();
+
+        fs_mock.delete_file(Path::new("./test")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + 4).unwrap();
+
+        let log = file_log(ActionOptions::from_path("."), &fs_mock, Path::new("test"))
+            .expect("Action failed.");
+
+        let summary: Vec<_> = log
+            .iter()
+            .map(|entry| {
+                (
+                    entry.change_index,
+                    entry.timestamp,
+                    &entry.kind,
+                    entry.bytes_added,
+                    entry.bytes_removed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, now, &FileLogKind::Created, 5, 0),
+                (3, now + 2, &FileLogKind::Modified, 7, 0),
+                (4, now + 3, &FileLogKind::Modified, 0, 7),
+                (5, now + 4, &FileLogKind::Deleted, 0, 5),
+            ]
+        );
+        assert_eq!(log[2].message, Some("Shorten".into()));
+
+        assert!(file_log(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("missing")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn log_repository_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        write_file(&fs_mock, "./other", b"other");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let log = repository_log(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].change_index, 1);
+        assert_eq!(log[0].affected_files.len(), 2);
+        assert_eq!(log[1].timestamp, now + 1);
+        assert_eq!(log[1].affected_files, vec![Path::new("test")]);
+    }
+
+    #[test]
+    fn log_change_authors() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        let config = Config {
+            identity: Identity {
+                name: Some("Ada".into()),
+                email: None,
+                machine: Some("laptop".into()),
+            },
+            ..Config::default()
+        };
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        config.write(&fs_mock, &locations).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let log = repository_log(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+        assert_eq!(log[0].author, None);
+        assert_eq!(log[1].author, Some("Ada on laptop".into()));
+
+        let log = file_log(ActionOptions::from_path("."), &fs_mock, Path::new("test"))
+            .expect("Action failed.");
+        assert_eq!(log[1].author, Some("Ada on laptop".into()));
+    }
+
+    #[test]
+    fn log_annotated_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        let annotate = |content: &[u8], pairs: &[(&str, &str)], timestamp| {
+            write_file(&fs_mock, "./test", content);
+            let annotations: Annotations = pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            update_annotated(
+                ActionOptions::from_path("."),
+                &fs_mock,
+                timestamp,
+                annotations,
+            )
+            .unwrap();
+        };
+        annotate(
+            b"second",
+            &[("app", "obsidian"), ("battery", "low")],
+            now + 1,
+        );
+        annotate(b"third", &[("app", "vim")], now + 2);
+
+        let log = repository_log(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+        assert!(log[0].annotations.is_empty());
+        assert_eq!(log[1].annotations.len(), 2);
+        assert_eq!(log[2].annotations["app"], "vim");
+
+        let get_indices = |key, value| {
+            annotated_log(ActionOptions::from_path("."), &fs_mock, key, value)
+                .expect("Action failed.")
+                .iter()
+                .map(|entry| entry.change_index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(get_indices("app", None), vec![2, 3]);
+        assert_eq!(get_indices("app", Some("obsidian")), vec![2]);
+        assert_eq!(get_indices("battery", Some("full")), Vec::<usize>::new());
+
+        let log = file_log(ActionOptions::from_path("."), &fs_mock, Path::new("test"))
+            .expect("Action failed.");
+        assert_eq!(log[1].annotations["battery"], "low");
+    }
+
+    #[test]
+    fn log_directory_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        write_file(&fs_mock, "./notes/today", b"first");
+        write_file(&fs_mock, "./notes-old", b"old");
+        create(options.clone(), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./notes-old", b"older");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./notes/deep/tomorrow", b"second");
+        update(options.clone(), &fs_mock, now + 2).unwrap();
+
+        let log = directory_log(options.clone(), &fs_mock, Path::new("notes")).unwrap();
+        let change_indices = log
+            .iter()
+            .map(|entry| entry.change_index)
+            .collect::<Vec<_>>();
+        assert_eq!(change_indices, vec![1, 3]);
+        assert_eq!(
+            log[1].affected_files,
+            vec![Path::new("notes/deep/tomorrow")]
+        );
+
+        let log = directory_log(options, &fs_mock, Path::new("notes/deep")).unwrap();
+        assert_eq!(log.len(), 1);
+    }
+
+    #[test]
+    fn log_changes_by_hour() {
+        let hour = 60 * 60;
+        let now = 10 * hour;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        write_file(&fs_mock, "./other", b"other");
+        update(ActionOptions::from_path("."), &fs_mock, now + 60).unwrap();
+
+        fs_mock.delete_file(Path::new("./other")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + hour).unwrap();
+
+        let buckets =
+            bucketed_log(ActionOptions::from_path("."), &fs_mock, hour).expect("Action failed.");
+
+        assert_eq!(
+            buckets,
+            vec![
+                LogBucket {
+                    start: now,
+                    first_change: 1,
+                    last_change: 2,
+                    change_count: 2,
+                    file_count: 2,
+                    bytes_added: 17,
+                    bytes_removed: 0,
+                },
+                LogBucket {
+                    start: now + hour,
+                    first_change: 3,
+                    last_change: 3,
+                    change_count: 1,
+                    file_count: 1,
+                    bytes_added: 0,
+                    bytes_removed: 5,
+                },
+            ]
+        );
+
+        assert!(bucketed_log(ActionOptions::from_path("."), &fs_mock, 0).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+    journal::{self, Journal},
+    migrations::{get_encoded_version, FILE_HISTORY_VERSION, REPOSITORY_HISTORY_VERSION},
+};
+
+use super::ActionOptions;
+
+// Older formats can always be read, but are only upgraded on disk by this action. Returns
+// how many files were upgraded.
+pub fn migrate(command_options: ActionOptions, fs: &impl Fs) -> Result<usize> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let mut journal = Journal::default();
+
+    let repository_index_path = locations.get_repository_index_path();
+    let buffer = read_file(fs, &repository_index_path)?;
+    if get_encoded_version(&buffer)? < REPOSITORY_HISTORY_VERSION {
+        let repository_history = RepositoryHistory::decode(&buffer)?;
+        journal.add_write(repository_index_path, repository_history.encode()?);
+    }
+
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+
+    for history_path in history_paths {
+        let buffer = read_file(fs, &history_path)?;
+        if get_encoded_version(&buffer)? < FILE_HISTORY_VERSION {
+            let file_history = FileHistory::decode(&buffer)
+                .with_context(|| format!("Failed migrating '{}'.", history_path.display()))?;
+            journal.add_write(history_path, file_history.encode()?);
+        }
+    }
+
+    let migrated_count = journal.len();
+    journal.commit(fs, &locations)?;
+
+    Ok(migrated_count)
+}
+
+fn read_file<FS: Fs>(fs: &FS, path: &Path) -> Result<Vec<u8>> {
+    let mut file = fs.open_readable_file(path)?;
+    fs.read_from_file(&mut file)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        actions::{shift, ActionOptions, ShiftMode, UntrackedFiles},
+        filesystem::mock::{read_file, EntryMock, FsMock, FsState},
+        history::{FileHistory, RepositoryHistory},
+        migrations::{FILE_HISTORY_VERSION, REPOSITORY_HISTORY_VERSION},
+    };
+
+    use super::migrate;
+
+    #[test]
+    fn migrate_unversioned_repository() {
+        let mut fs_mock = FsMock::new();
+
+        // As written before histories had a version.
+        let legacy_index =
+            br#"{"cursor":2,"changes":[{"affected_files":["./test"],"timestamp":1},{"affected_files":["./test"],"timestamp":2}]}"#;
+        let legacy_history = br#"{"changes":[{"change_index":1,"variant":{"Updated":[{"Inserted":{"at":0,"new_content":[97]}}]}},{"change_index":2,"variant":{"Updated":[{"Inserted":{"at":1,"new_content":[98]}}]}}]}"#;
+
+        fs_mock.set_state(FsState::new(vec![
+            EntryMock::file("./test", b"ab"),
+            EntryMock::dir("./.ka"),
+            EntryMock::file("./.ka/index", legacy_index),
+            EntryMock::dir("./.ka/files"),
+            EntryMock::file("./.ka/files/test", legacy_history),
+        ]));
+
+        // Legacy repositories keep working before they are migrated.
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect("Shifting failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"a");
+
+        // Shifting already rewrote the index, so only the file history is left.
+        let migrated = migrate(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+        assert_eq!(migrated, 1);
+
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.version, REPOSITORY_HISTORY_VERSION);
+        assert_eq!(index.cursor, 1);
+        let history = FileHistory::decode(&read_file(&fs_mock, "./.ka/files/test")).unwrap();
+        assert_eq!(history.version, FILE_HISTORY_VERSION);
+        assert_eq!(history.get_content(2).unwrap(), b"ab");
+
+        let migrated = migrate(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+        assert_eq!(migrated, 0);
+    }
+}
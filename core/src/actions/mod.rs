@@ -0,0 +1,228 @@
+mod adopt;
+#[cfg(feature = "archive")]
+mod archive;
+mod backup;
+mod create;
+mod deleted;
+mod diff;
+mod doctor;
+#[cfg(feature = "sqlite")]
+mod export_sqlite;
+mod extract;
+mod forget;
+mod grep;
+mod import;
+mod lock;
+mod log;
+mod migrate;
+mod pin;
+mod prune;
+mod recover;
+mod redact;
+mod redelta;
+mod resolve;
+mod revert;
+mod session;
+mod shift;
+mod show;
+mod squash;
+mod stats;
+mod status;
+mod summary;
+mod sync;
+mod timeline;
+mod track;
+mod transfer;
+mod update;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub use adopt::adopt;
+use anyhow::{bail, Result};
+#[cfg(feature = "archive")]
+pub use archive::{archive, ArchiveFormat};
+pub use backup::{backup, restore_from_backup, BackupSummary};
+pub use create::{create, create_baseline, create_from_template};
+pub use deleted::{deleted, DeletedFile};
+pub use diff::diff;
+pub use doctor::{doctor, CheckOutcome, DoctorCheck};
+#[cfg(feature = "sqlite")]
+pub use export_sqlite::{export_sqlite, SqliteExport};
+pub use extract::extract;
+pub use forget::forget;
+pub use grep::{grep, GrepMatch};
+pub use import::{import, IMPORT_CHUNK_LENGTH};
+pub use lock::{lock_file, locked_files, unlock_file};
+pub use log::{
+    annotated_log, bucketed_log, directory_log, file_log, repository_log, repository_log_since,
+    FileLogEntry, FileLogKind, LogBucket, LogEntry,
+};
+pub use migrate::migrate;
+pub use pin::{pin, unpin};
+pub use prune::{apply_retention, prune};
+pub use recover::recover;
+pub use redact::redact;
+pub use redelta::redelta;
+pub use resolve::resolve_change;
+pub use revert::revert;
+pub use session::{end_session, find_session, session_log, start_session, SessionLog};
+pub use shift::{
+    preview_shift, shift, undo_shift, ShiftMode, ShiftPreviewEntry, ShiftPreviewKind,
+    UntrackedFiles,
+};
+pub use show::show;
+pub use squash::squash;
+pub use stats::{stats, GrowthBucket, Insertion, Stats};
+pub use status::{status, FileStatus, Status};
+pub use summary::{summary, Summary};
+pub use sync::sync;
+pub use timeline::{
+    export_timeline, render_sparkline, FileActivity, FileTimeline, Timeline, TimelineChange,
+};
+pub use track::{track, untrack};
+pub use transfer::{pull, pull_with_drivers, push, PullConflict, PullSummary};
+pub use update::{
+    update, update_annotated, update_interactive, update_paths, HunkSelector, UpdateSummary,
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    files::{normalize_path, Locations},
+    filesystem::Fs,
+};
+
+#[derive(Clone)]
+pub struct ActionOptions {
+    pub repository_path: PathBuf,
+    // What the current time is taken from, the system clock unless one is given.
+    pub clock: Arc<dyn Clock>,
+    // Whom the files locked with these options belong to, the identity in the config unless
+    // one is given.
+    pub lock_owner: Option<String>,
+}
+
+impl ActionOptions {
+    pub fn new(repository_path: PathBuf) -> Self {
+        ActionOptions {
+            repository_path,
+            clock: Arc::new(SystemClock),
+            lock_owner: None,
+        }
+    }
+
+    pub fn from_path(path: &str) -> Self {
+        ActionOptions::new(Path::new(path).to_path_buf())
+    }
+
+    pub fn from_pwd() -> Result<Self> {
+        let repository_path = std::env::current_dir()?;
+        Ok(ActionOptions::new(repository_path))
+    }
+
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        ActionOptions { clock, ..self }
+    }
+
+    pub fn with_lock_owner(self, lock_owner: &str) -> Self {
+        ActionOptions {
+            lock_owner: Some(lock_owner.to_string()),
+            ..self
+        }
+    }
+
+    // The current time in seconds since the Unix epoch, according to the clock.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    // Finds the repository `path` is in, by looking for a `.ka` in it and all of its parents.
+    pub fn discover(fs: &impl Fs, path: &Path) -> Result<Self> {
+        let path = normalize_path(path);
+        for directory in path.ancestors() {
+            if fs.path_exists(&directory.join(".ka")) {
+                let options = ActionOptions::new(directory.to_path_buf());
+                if fs.path_exists(&Locations::from(&options).get_import_state_path()) {
+                    bail!(
+                        "The import into '{}' was interrupted, import again there to finish it.",
+                        directory.display()
+                    );
+                }
+                return Ok(options);
+            }
+        }
+        bail!("'{}' is not inside of a repository.", path.display())
+    }
+
+    // Maps a path the user gave, relative to `current_directory` or absolute, to one relative
+    // to the repository, which is what actions take. Paths outside of it are rejected.
+    pub fn resolve_path(&self, path: &Path, current_directory: &Path) -> Result<PathBuf> {
+        let repository_path = normalize_path(&current_directory.join(&self.repository_path));
+        let path = normalize_path(&current_directory.join(path));
+        match path.strip_prefix(&repository_path) {
+            Ok(relative_path) if !relative_path.starts_with(".ka") => {
+                Ok(relative_path.to_path_buf())
+            }
+            _ => bail!(
+                "'{}' is outside of the repository at '{}'.",
+                path.display(),
+                repository_path.display()
+            ),
+        }
+    }
+
+    // How a working path is shown to the user, relative to the repository.
+    pub fn display_path<'a>(&self, working_path: &'a Path) -> &'a Path {
+        working_path
+            .strip_prefix(&self.repository_path)
+            .unwrap_or(working_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::filesystem::{mock::FsMock, Fs};
+
+    use super::ActionOptions;
+
+    #[test]
+    fn resolve_paths_relative_to_repository() {
+        let fs_mock = FsMock::new();
+        fs_mock
+            .create_directory(Path::new("/home/notes/.ka"))
+            .unwrap();
+
+        let options = ActionOptions::discover(&fs_mock, Path::new("/home/notes/drafts")).unwrap();
+        assert_eq!(options.repository_path, Path::new("/home/notes"));
+        assert!(ActionOptions::discover(&fs_mock, Path::new("/home/other")).is_err());
+
+        let current_directory = Path::new("/home/notes/drafts");
+        assert_eq!(
+            options
+                .resolve_path(Path::new("./today"), current_directory)
+                .unwrap(),
+            Path::new("drafts/today")
+        );
+        assert_eq!(
+            options
+                .resolve_path(Path::new("../todo"), current_directory)
+                .unwrap(),
+            Path::new("todo")
+        );
+        assert!(options
+            .resolve_path(Path::new("../../secret"), current_directory)
+            .is_err());
+        assert!(options
+            .resolve_path(Path::new("/home/notes/.ka/index"), current_directory)
+            .is_err());
+
+        assert_eq!(
+            options.display_path(Path::new("/home/notes/drafts/today")),
+            Path::new("drafts/today")
+        );
+    }
+}
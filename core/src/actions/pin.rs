@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::{files::Locations, filesystem::Fs, history::RepositoryHistory, journal};
+
+use super::ActionOptions;
+
+// Keeps shifts from touching the file, e.g. for generated files which should stay at their
+// latest version while looking around older changes. Returns whether the file wasn't pinned yet.
+pub fn pin(command_options: ActionOptions, fs: &impl Fs, path: &Path) -> Result<bool> {
+    set_pinned(command_options, fs, path, true)
+}
+
+// Lets shifts touch the file again. It's left as it is until the next shift.
+pub fn unpin(command_options: ActionOptions, fs: &impl Fs, path: &Path) -> Result<bool> {
+    set_pinned(command_options, fs, path, false)
+}
+
+fn set_pinned(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    path: &Path,
+    pinned: bool,
+) -> Result<bool> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let working_path = locations.get_working_path(path)?;
+    if pinned && !fs.path_exists(&locations.history_from_working(&working_path)?) {
+        bail!(
+            "The file '{}' can't be pinned, because it has no history.",
+            command_options.display_path(&working_path).display()
+        );
+    }
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    if repository_history.is_pinned(&working_path) == pinned {
+        return Ok(false);
+    }
+
+    if pinned {
+        repository_history.pinned_files.push(working_path);
+    } else {
+        repository_history
+            .pinned_files
+            .retain(|pinned_path| *pinned_path != working_path);
+    }
+
+    if repository_history.get_stored_length().is_some() {
+        fs.append_to_file(
+            &mut repository_index_file,
+            repository_history.encode_pins()?,
+        )?;
+    } else {
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, shift, update, ActionOptions, ShiftMode, UntrackedFiles},
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::RepositoryHistory,
+    };
+
+    use super::{pin, unpin};
+
+    #[test]
+    fn shift_around_pinned_files() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./source", b"first");
+        write_file(&fs_mock, "./generated", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./source", b"second");
+        write_file(&fs_mock, "./generated", b"second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        assert!(pin(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("missing")
+        )
+        .is_err());
+        assert!(pin(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("generated")
+        )
+        .unwrap());
+        assert!(!pin(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("generated")
+        )
+        .unwrap());
+
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let mut index_file = fs_mock
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        let index = RepositoryHistory::from_file(&fs_mock, &locations, &mut index_file).unwrap();
+        assert!(index.is_pinned(Path::new("./generated")));
+
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert_eq!(read_file(&fs_mock, "./source"), b"first");
+        assert_eq!(read_file(&fs_mock, "./generated"), b"second");
+
+        assert!(unpin(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("generated")
+        )
+        .unwrap());
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            0,
+            ShiftMode::Force,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert!(!fs_mock.path_exists(Path::new("./generated")));
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+
+use crate::{
+    config::Config, files::Locations, filesystem::Fs, history::RepositoryHistory, journal,
+    metrics::ActionTimer,
+};
+
+use super::{squash::squash_range, ActionOptions};
+
+// Folds every change recorded before the given timestamp into a single baseline change,
+// returning how many changes were removed from the history.
+pub fn prune(command_options: ActionOptions, fs: &impl Fs, keep_since: u64) -> Result<usize> {
+    let _timer = ActionTimer::start("prune");
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let old_change_count = repository_history
+        .get_changes()
+        .iter()
+        .take_while(|change| change.timestamp < keep_since)
+        .count();
+
+    // We can't fold changes past the cursor, as the working directory wouldn't be
+    // reproducible from the history anymore.
+    let pruned_upto = old_change_count.min(repository_history.cursor);
+
+    if pruned_upto < 2 {
+        return Ok(0);
+    }
+
+    squash_range(
+        fs,
+        &locations,
+        &mut repository_history,
+        1,
+        pruned_upto,
+        Some(format!("Baseline of {} pruned changes.", pruned_upto)),
+    )?;
+
+    repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+
+    Ok(pruned_upto - 1)
+}
+
+// Prunes the history according to the retention policy of the repository, if it has one.
+pub fn apply_retention(command_options: ActionOptions, fs: &impl Fs, now: u64) -> Result<usize> {
+    let locations = Locations::from(&command_options);
+    let config = Config::load(fs, &locations)?;
+
+    match config.get_retention_seconds()? {
+        Some(retention) => prune(command_options, fs, now.saturating_sub(retention)),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        config::Config,
+        files::Locations,
+        filesystem::mock::{read_file, write_file, FsMock},
+        history::{FileHistory, RepositoryHistory},
+    };
+
+    use super::{apply_retention, prune};
+
+    fn create_history(fs_mock: &FsMock) {
+        write_file(fs_mock, "./test", b"one");
+        create(ActionOptions::from_path("."), fs_mock, 100).unwrap();
+
+        write_file(fs_mock, "./test", b"one two");
+        update(ActionOptions::from_path("."), fs_mock, 200).unwrap();
+
+        write_file(fs_mock, "./test", b"one two three");
+        update(ActionOptions::from_path("."), fs_mock, 300).unwrap();
+
+        write_file(fs_mock, "./test", b"one two three four");
+        update(ActionOptions::from_path("."), fs_mock, 400).unwrap();
+    }
+
+    #[test]
+    fn prune_old_changes() {
+        let fs_mock = FsMock::new();
+        create_history(&fs_mock);
+
+        let pruned = prune(ActionOptions::from_path("."), &fs_mock, 350).expect("Action failed.");
+        assert_eq!(pruned, 2);
+
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.cursor, 2);
+        assert_eq!(index.get_changes()[0].timestamp, 300);
+
+        let history = FileHistory::decode(&read_file(&fs_mock, "./.ka/files/test")).unwrap();
+        assert_eq!(history.get_content(1).unwrap(), b"one two three");
+        assert_eq!(history.get_content(2).unwrap(), b"one two three four");
+    }
+
+    #[test]
+    fn retention_policy() {
+        let fs_mock = FsMock::new();
+        create_history(&fs_mock);
+
+        let pruned = apply_retention(ActionOptions::from_path("."), &fs_mock, 400).unwrap();
+        assert_eq!(pruned, 0);
+
+        let config = Config {
+            retention: Some("2m".into()),
+            ..Config::default()
+        };
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        config.write(&fs_mock, &locations).unwrap();
+
+        let pruned = apply_retention(ActionOptions::from_path("."), &fs_mock, 400).unwrap();
+        assert_eq!(pruned, 1);
+    }
+}
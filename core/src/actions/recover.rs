@@ -0,0 +1,227 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    config::Config,
+    diff::ContentChange,
+    files::Locations,
+    filesystem::Fs,
+    history::{
+        Annotations, FileChange, FileChangeVariant, FileHistory, RepositoryChange,
+        RepositoryHistory,
+    },
+    journal,
+    policy::get_content_type,
+};
+
+use super::ActionOptions;
+
+pub fn recover(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    path: &Path,
+    from_cursor: Option<usize>,
+    timestamp: u64,
+) -> Result<()> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+    let config = Config::load(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let cursor = repository_history.cursor;
+
+    if cursor != repository_history.get_changes().len() {
+        bail!("Files can only be recovered while the cursor is at the latest change.");
+    }
+
+    let working_path = locations.get_working_path(path)?;
+    if fs.path_exists(&working_path) {
+        bail!(
+            "The file '{}' can't be recovered, because it exists in the working directory.",
+            command_options.display_path(&working_path).display()
+        );
+    }
+
+    let history_path = locations.history_from_working(&working_path)?;
+    if !fs.path_exists(&history_path) {
+        bail!(
+            "The file '{}' can't be recovered, because it has no history.",
+            command_options.display_path(&working_path).display()
+        );
+    }
+
+    let mut history_file = fs.open_writable_file(&history_path)?;
+    let mut file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+
+    let source_cursor = match from_cursor {
+        Some(from_cursor) => from_cursor,
+        None => get_last_existing_cursor(&file_history, cursor).with_context(|| {
+            format!(
+                "The file '{}' never existed up to the cursor.",
+                command_options.display_path(&working_path).display()
+            )
+        })?,
+    };
+
+    if source_cursor > cursor || !file_history.does_file_exist(source_cursor) {
+        bail!(
+            "The file '{}' didn't exist at the cursor {}.",
+            command_options.display_path(&working_path).display(),
+            source_cursor
+        );
+    }
+
+    let recovered_content = file_history.get_content(source_cursor)?;
+    // If the deletion wasn't recorded yet, the history still holds the last content.
+    let tip_content = if file_history.does_file_exist(cursor) {
+        file_history.get_content(cursor)?
+    } else {
+        Vec::new()
+    };
+
+    let mut working_file = fs.create_file(&working_path)?;
+    fs.write_to_file(&mut working_file, recovered_content.clone())?;
+
+    let changes = ContentChange::diff(&tip_content, &recovered_content);
+    if !changes.is_empty() || !file_history.does_file_exist(cursor) {
+        let mut change = FileChange {
+            change_index: cursor + 1,
+            variant: FileChangeVariant::Updated(changes),
+            degraded: false,
+            content_hash: Some(FileChange::hash_content(&recovered_content)),
+            change_id: None,
+            content_type: Some(get_content_type(&config, &working_path, &recovered_content)),
+        };
+        let change_id = RepositoryChange::compute_id(
+            repository_history.get_change_id(cursor),
+            timestamp,
+            &[(&working_path, &change)],
+        );
+        change.change_id = Some(change_id.clone());
+        file_history.add_change(change);
+        file_history.write_to_file(fs, &locations, &mut history_file)?;
+
+        repository_history.add_change(RepositoryChange {
+            affected_files: vec![working_path],
+            timestamp,
+            message: None,
+            id: Some(change_id),
+            author: config.identity.get_author(),
+            annotations: Annotations::new(),
+        });
+        repository_history.cursor += 1;
+
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+    }
+
+    Ok(())
+}
+
+pub(super) fn get_last_existing_cursor(file_history: &FileHistory, cursor: usize) -> Option<usize> {
+    if file_history.does_file_exist(cursor) {
+        return Some(cursor);
+    }
+
+    // The file was deleted, so we want the state right before it's latest deletion.
+    file_history
+        .get_changes()
+        .iter()
+        .rev()
+        .filter(|change| change.change_index <= cursor)
+        .find(|change| matches!(change.variant, FileChangeVariant::Deleted))
+        .map(|deletion| deletion.change_index - 1)
+        .filter(|&before_deletion| file_history.does_file_exist(before_deletion))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::recover;
+
+    #[test]
+    fn recover_deleted_file() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        fs_mock.delete_file(Path::new("./test")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        recover(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("test"),
+            None,
+            now + 3,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"second");
+
+        // The recovery is recorded, so there is nothing left to update.
+        let state = fs_mock.get_state();
+        update(ActionOptions::from_path("."), &fs_mock, now + 4).unwrap();
+        fs_mock.assert_match(state);
+    }
+
+    #[test]
+    fn recover_from_cursor() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        fs_mock.delete_file(Path::new("./test")).unwrap();
+
+        recover(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("test"),
+            Some(1),
+            now + 2,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"first");
+    }
+
+    #[test]
+    fn recover_existing_file() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        let result = recover(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("test"),
+            None,
+            now + 1,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{iter, path::Path};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    diff::ContentChange,
+    files::{collect_files, normalize_path, Locations},
+    filesystem::Fs,
+    history::{materialize_copies, FileChange, FileChangeVariant, FileHistory},
+    journal,
+    objects::{get_object_id, ObjectStore, OBJECT_THRESHOLD},
+};
+
+use super::ActionOptions;
+
+const PLACEHOLDER: &[u8] = b"[redacted]";
+
+// Replaces everything the change inserted into the file with a placeholder, wherever it
+// survives in the history, e.g. after a secret was recorded by accident. The later changes
+// are diffed again, so every later state still has the rest of its content. Content which a
+// later change inserted again, e.g. by replacing the whole file, isn't touched. The working
+// file is left alone, if it still has the content, the next update records it again.
+pub fn redact(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    change_index: usize,
+    path: &Path,
+) -> Result<()> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let working_path = locations.get_working_path(path)?;
+    let history_path = locations.history_from_working(&working_path)?;
+    if !fs.path_exists(&history_path) {
+        bail!(
+            "The file '{}' can't be redacted, because it has no history.",
+            command_options.display_path(&working_path).display()
+        );
+    }
+
+    let source_path = normalize_path(path);
+    materialize_copies(fs, &locations, &iter::once(source_path).collect())?;
+
+    let mut history_file = fs.open_writable_file(&history_path)?;
+    let mut file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+
+    let redacted_position = file_history
+        .get_changes()
+        .iter()
+        .position(|change| change.change_index == change_index);
+    let redacted_position = match redacted_position {
+        Some(position) if !get_insertions(&file_history.get_changes()[position]).is_empty() => {
+            position
+        }
+        _ => bail!(
+            "The change {} didn't add anything to '{}' which could be redacted.",
+            change_index,
+            command_options.display_path(&working_path).display()
+        ),
+    };
+
+    let object_ids: Vec<_> = get_insertions(&file_history.get_changes()[redacted_position])
+        .into_iter()
+        .filter(|new_content| new_content.len() >= OBJECT_THRESHOLD)
+        .map(get_object_id)
+        .collect();
+    let redacted_states = get_redacted_states(file_history.get_changes(), redacted_position);
+
+    let changes = file_history.get_changes_mut();
+    let mut previous_state = match redacted_position {
+        0 => Vec::new(),
+        position => redacted_states[position - 1].clone().unwrap_or_default(),
+    };
+    for (change, state) in changes
+        .iter_mut()
+        .zip(redacted_states)
+        .skip(redacted_position)
+    {
+        if let Some(state) = state {
+            change.variant =
+                FileChangeVariant::Updated(ContentChange::diff(&previous_state, &state));
+            change.content_hash = Some(FileChange::hash_content(&state));
+            change.degraded = false;
+            previous_state = state;
+        } else {
+            previous_state = Vec::new();
+        }
+    }
+
+    file_history.write_to_file(fs, &locations, &mut history_file)?;
+
+    // Large insertions live in the object store, which is only cleaned up if no other
+    // history still refers to them.
+    let store = ObjectStore::new(fs, &locations);
+    for object_id in object_ids {
+        if !is_object_referenced(fs, &locations, &object_id)? {
+            store.remove(&object_id)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn get_insertions(file_change: &FileChange) -> Vec<&[u8]> {
+    let content_changes = match &file_change.variant {
+        FileChangeVariant::Updated(content_changes) => content_changes,
+        FileChangeVariant::Deleted => return Vec::new(),
+        FileChangeVariant::CopiedFrom { .. } | FileChangeVariant::Structured(_) => {
+            unreachable!()
+        }
+    };
+
+    content_changes
+        .iter()
+        .filter_map(|content_change| match content_change {
+            ContentChange::Inserted { new_content, .. } if !new_content.is_empty() => {
+                Some(new_content.as_slice())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// The content after every change, with the bytes inserted by the redacted change replaced.
+// Deletions are `None`. Which bytes are redacted is tracked alongside the original content,
+// as the positions of the changes refer to it.
+fn get_redacted_states(changes: &[FileChange], redacted_position: usize) -> Vec<Option<Vec<u8>>> {
+    let mut content: Vec<u8> = Vec::new();
+    let mut redacted: Vec<bool> = Vec::new();
+    let mut states = Vec::new();
+
+    for (position, file_change) in changes.iter().enumerate() {
+        match &file_change.variant {
+            FileChangeVariant::Updated(content_changes) => {
+                for content_change in content_changes {
+                    match content_change {
+                        ContentChange::Inserted { at, new_content } => {
+                            let is_redacted = position == redacted_position;
+                            let inserted = iter::repeat_n(is_redacted, new_content.len());
+                            content.splice(at..at, new_content.iter().copied());
+                            redacted.splice(at..at, inserted);
+                        }
+                        ContentChange::Deleted { at, upto, .. } => {
+                            content.drain(at..upto);
+                            redacted.drain(at..upto);
+                        }
+                        ContentChange::InsertedObject { .. } => unreachable!(),
+                    }
+                }
+                states.push(Some(replace_redacted(&content, &redacted)));
+            }
+            FileChangeVariant::Deleted => {
+                content.clear();
+                redacted.clear();
+                states.push(None);
+            }
+            FileChangeVariant::CopiedFrom { .. } | FileChangeVariant::Structured(_) => {
+                unreachable!()
+            }
+        }
+    }
+
+    states
+}
+
+// Every run of redacted bytes becomes a single placeholder.
+fn replace_redacted(content: &[u8], redacted: &[bool]) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(content.len());
+    for (index, (&byte, &is_redacted)) in content.iter().zip(redacted).enumerate() {
+        if !is_redacted {
+            replaced.push(byte);
+        } else if index == 0 || !redacted[index - 1] {
+            replaced.extend_from_slice(PLACEHOLDER);
+        }
+    }
+    replaced
+}
+
+fn is_object_referenced<FS: Fs>(fs: &FS, locations: &Locations, object_id: &str) -> Result<bool> {
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+
+    for history_path in history_paths {
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::read_stored(fs, locations, &mut history_file)?;
+        for file_change in file_history
+            .get_changes()
+            .iter()
+            .chain(file_history.get_base())
+        {
+            if let FileChangeVariant::Updated(content_changes) = &file_change.variant {
+                let is_referenced =
+                    content_changes
+                        .iter()
+                        .any(|content_change| match content_change {
+                            ContentChange::InsertedObject { object, .. } => object == object_id,
+                            _ => false,
+                        });
+                if is_referenced {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, show, update, ActionOptions},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        objects::{get_object_id, OBJECT_THRESHOLD},
+    };
+
+    use super::redact;
+
+    fn show_at(fs: &FsMock, path: &str, cursor: usize) -> Vec<u8> {
+        show(ActionOptions::from_path("."), fs, Path::new(path), cursor).unwrap()
+    }
+
+    #[test]
+    fn redact_recorded_secret() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let large_secret = vec![b'x'; OBJECT_THRESHOLD];
+
+        write_file(&fs_mock, "./config", b"user = me\n");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./config", b"user = me\npassword = hunter2\n");
+        write_file(&fs_mock, "./key", &large_secret);
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./config", b"user = you\npassword = hunter2\n");
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        let object_id = get_object_id(&large_secret);
+        let object_path = Path::new("./.ka/objects")
+            .join(&object_id[..2])
+            .join(&object_id[2..]);
+        assert!(fs_mock.path_exists(&object_path));
+
+        assert!(redact(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            Path::new("missing")
+        )
+        .is_err());
+        assert!(redact(ActionOptions::from_path("."), &fs_mock, 3, Path::new("key")).is_err());
+        redact(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            2,
+            Path::new("config"),
+        )
+        .unwrap();
+        redact(ActionOptions::from_path("."), &fs_mock, 2, Path::new("key")).unwrap();
+
+        // The secret is gone from every later state too, but everything else is kept.
+        assert_eq!(show_at(&fs_mock, "config", 1), b"user = me\n");
+        assert_eq!(show_at(&fs_mock, "config", 2), b"user = me\n[redacted]");
+        assert_eq!(show_at(&fs_mock, "config", 3), b"user = you\n[redacted]");
+        assert_eq!(show_at(&fs_mock, "key", 2), b"[redacted]");
+        assert!(!fs_mock.path_exists(&object_path));
+
+        let mut history_file = fs_mock
+            .open_readable_file(Path::new("./.ka/files/config"))
+            .unwrap();
+        let history = fs_mock.read_from_file(&mut history_file).unwrap();
+        assert!(!history.windows(7).any(|window| window == b"hunter2"));
+    }
+}
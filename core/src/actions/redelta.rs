@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    config::Config,
+    diff::{ContentChange, DiffOptions},
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileChangeVariant, FileHistory},
+    journal::{self, Journal},
+};
+
+use super::{log::count_bytes, ActionOptions};
+
+// Diffs every change which hit the diff deadline again, this time without one, and keeps
+// the new changes if they are smaller. Returns how many changes were replaced.
+pub fn redelta(command_options: ActionOptions, fs: &impl Fs) -> Result<usize> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let config = Config::load(fs, &locations)?;
+    let options = DiffOptions {
+        deadline_ms: None,
+        ..config.diff
+    };
+
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+
+    let mut journal = Journal::default();
+    let mut replaced_count = 0;
+
+    for history_path in history_paths {
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let mut file_history = FileHistory::from_file(fs, &locations, &mut history_file)
+            .with_context(|| format!("Failed loading '{}'.", history_path.display()))?;
+
+        if !file_history
+            .get_changes()
+            .iter()
+            .any(|change| change.degraded)
+        {
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        for change in file_history.get_changes().iter() {
+            if change.degraded {
+                let old_content = file_history.get_content(change.change_index - 1)?;
+                let new_content = file_history.get_content(change.change_index)?;
+                contents.push((change.change_index, old_content, new_content));
+            }
+        }
+
+        for (change_index, old_content, new_content) in contents {
+            let delta = ContentChange::diff_with(&old_content, &new_content, &options);
+            let file_change = file_history
+                .get_changes_mut()
+                .iter_mut()
+                .find(|change| change.change_index == change_index)
+                .unwrap();
+
+            if let FileChangeVariant::Updated(ref old_changes) = file_change.variant {
+                if get_size(&delta.changes) < get_size(old_changes) {
+                    file_change.variant = FileChangeVariant::Updated(delta.changes);
+                    replaced_count += 1;
+                }
+            }
+            file_change.degraded = false;
+        }
+
+        journal.add_write(
+            history_path,
+            file_history.encode_for_storage(fs, &locations)?,
+        );
+    }
+
+    journal.commit(fs, &locations)?;
+
+    Ok(replaced_count)
+}
+
+fn get_size(changes: &[ContentChange]) -> usize {
+    let (bytes_added, bytes_removed) = count_bytes(changes);
+    bytes_added + bytes_removed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        config::Config,
+        diff::DiffOptions,
+        files::Locations,
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        history::{FileChangeVariant, FileHistory},
+    };
+
+    use super::redelta;
+
+    fn read_history(fs: &FsMock, path: &str) -> FileHistory {
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let mut file = fs.open_readable_file(Path::new(path)).unwrap();
+        FileHistory::from_file(fs, &locations, &mut file).unwrap()
+    }
+
+    #[test]
+    fn redelta_degraded_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+
+        write_file(&fs_mock, "./test", b"first line\nsecond line\nthird line\n");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        // A deadline which is always exceeded marks every diff as degraded.
+        let config = Config {
+            diff: DiffOptions {
+                deadline_ms: Some(0),
+                ..DiffOptions::default()
+            },
+            ..Config::default()
+        };
+        config.write(&fs_mock, &locations).unwrap();
+
+        write_file(&fs_mock, "./test", b"first line\nthird line\nfourth line\n");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let history = read_history(&fs_mock, "./.ka/files/test");
+        assert!(history.get_changes()[1].degraded);
+
+        redelta(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+
+        let history = read_history(&fs_mock, "./.ka/files/test");
+        assert!(!history.get_changes()[1].degraded);
+        assert!(matches!(
+            history.get_changes()[1].variant,
+            FileChangeVariant::Updated(_)
+        ));
+        assert_eq!(
+            history.get_content(2).unwrap(),
+            b"first line\nthird line\nfourth line\n"
+        );
+
+        // Nothing is left to do afterwards.
+        let replaced = redelta(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+        assert_eq!(replaced, 0);
+    }
+}
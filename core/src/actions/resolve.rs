@@ -0,0 +1,22 @@
+use anyhow::Result;
+
+use crate::{files::Locations, filesystem::Fs, history::RepositoryHistory};
+
+use super::ActionOptions;
+
+// Maps a change given as a cursor or as a prefix of its ID to its cursor, which is what the
+// other actions take.
+pub fn resolve_change(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    reference: &str,
+) -> Result<usize> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    repository_history.resolve_change(reference)
+}
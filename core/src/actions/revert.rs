@@ -0,0 +1,433 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    config::Config,
+    diff::{ContentChange, Hunk},
+    files::Locations,
+    filesystem::Fs,
+    history::{
+        Annotations, FileChange, FileChangeVariant, FileHistory, RepositoryChange,
+        RepositoryHistory,
+    },
+    journal::{self, Journal},
+    policy::get_content_type,
+};
+
+use super::ActionOptions;
+
+pub fn revert(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    change_index: usize,
+    timestamp: u64,
+) -> Result<()> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+    let config = Config::load(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let cursor = repository_history.cursor;
+
+    if change_index == 0 || change_index > cursor {
+        bail!(
+            "The change {} can't be reverted, as it's not part of the history up to the cursor at {}.",
+            change_index,
+            cursor
+        );
+    }
+
+    if cursor != repository_history.get_changes().len() {
+        bail!("Changes can only be reverted while the cursor is at the latest change.");
+    }
+
+    let reverted_change = &repository_history.get_changes()[change_index - 1];
+
+    // We first compute the inverse for every file, so that nothing is written
+    // if any of the files can't be reverted.
+    let mut reverted_files = Vec::new();
+
+    for working_path in reverted_change.affected_files.iter() {
+        let history_path = locations.history_from_working(working_path)?;
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+
+        if is_file_dirty(fs, working_path, &file_history, cursor)? {
+            bail!(
+                "The file '{}' has changes which weren't recorded yet, update the repository before reverting.",
+                command_options.display_path(working_path).display()
+            );
+        }
+
+        let change =
+            get_reverted_change(&config, working_path, &file_history, change_index, cursor)
+                .with_context(|| {
+                    format!(
+                        "Failed reverting '{}'.",
+                        command_options.display_path(working_path).display()
+                    )
+                })?;
+
+        if let Some(change) = change {
+            reverted_files.push((working_path.clone(), history_path, file_history, change));
+        }
+    }
+
+    let file_changes: Vec<_> = reverted_files
+        .iter()
+        .map(|(working_path, _, _, change)| (working_path.as_path(), change))
+        .collect();
+    let change_id = RepositoryChange::compute_id(
+        repository_history.get_change_id(cursor),
+        timestamp,
+        &file_changes,
+    );
+
+    // The histories, working files and index are written through the journal, so they can't
+    // be left disagreeing with each other. Deletions aren't journaled, and happen afterwards.
+    let mut journal = Journal::default();
+    let mut deleted_files = Vec::new();
+    let mut affected_files = Vec::new();
+
+    for (working_path, history_path, mut file_history, mut change) in reverted_files {
+        change.change_id = Some(change_id.clone());
+        file_history.add_change(change);
+        for (segment_path, encoded_segment) in file_history.seal(fs, &locations, &history_path)? {
+            journal.add_write(segment_path, encoded_segment);
+        }
+        journal.add_write(
+            history_path,
+            file_history.encode_for_storage(fs, &locations)?,
+        );
+
+        if file_history.is_file_deleted(cursor + 1) {
+            deleted_files.push(working_path.clone());
+        } else {
+            journal.add_write(working_path.clone(), file_history.get_content(cursor + 1)?);
+        }
+
+        affected_files.push(working_path);
+    }
+
+    if !affected_files.is_empty() {
+        repository_history.add_change(RepositoryChange {
+            affected_files,
+            timestamp,
+            message: None,
+            id: Some(change_id),
+            author: config.identity.get_author(),
+            annotations: Annotations::new(),
+        });
+        repository_history.cursor += 1;
+
+        match repository_history.get_stored_length() {
+            Some(stored_length) if !repository_history.needs_sealing() => journal.add_append(
+                repository_index_path,
+                stored_length,
+                repository_history.encode_latest_change()?,
+            ),
+            _ => {
+                for (segment_path, encoded_segment) in
+                    repository_history.encode_segments(&locations)?
+                {
+                    journal.add_write(segment_path, encoded_segment);
+                }
+                journal.add_write(repository_index_path, repository_history.encode()?);
+            }
+        }
+    }
+
+    journal.commit(fs, &locations)?;
+
+    for working_path in deleted_files {
+        fs.delete_file(&working_path)?;
+    }
+
+    Ok(())
+}
+
+fn get_reverted_change(
+    config: &Config,
+    working_path: &Path,
+    file_history: &FileHistory,
+    change_index: usize,
+    cursor: usize,
+) -> Result<Option<FileChange>> {
+    let existed_before = file_history.does_file_exist(change_index - 1);
+    let existed_after = file_history.does_file_exist(change_index);
+
+    let later_changes: Vec<&FileChange> = file_history
+        .get_changes()
+        .iter()
+        .filter(|c| c.change_index > change_index && c.change_index <= cursor)
+        .collect();
+
+    match (existed_before, existed_after) {
+        (false, true) => {
+            if !later_changes.is_empty() {
+                bail!("The file was changed after it was created.");
+            }
+            Ok(Some(FileChange {
+                change_index: cursor + 1,
+                variant: FileChangeVariant::Deleted,
+                degraded: false,
+                content_hash: None,
+                change_id: None,
+                content_type: None,
+            }))
+        }
+        (true, false) => {
+            if file_history.does_file_exist(cursor) {
+                bail!("The file was recreated after it was deleted.");
+            }
+            let old_content = file_history.get_content(change_index - 1)?;
+            Ok(Some(FileChange {
+                change_index: cursor + 1,
+                variant: FileChangeVariant::Updated(ContentChange::diff(&[], &old_content)),
+                degraded: false,
+                content_hash: Some(FileChange::hash_content(&old_content)),
+                change_id: None,
+                content_type: Some(get_content_type(config, working_path, &old_content)),
+            }))
+        }
+        (true, true) => {
+            let before = file_history.get_content(change_index - 1)?;
+            let after = file_history.get_content(change_index)?;
+
+            let mut hunks = Hunk::diff(&after, &before);
+
+            for later_change in later_changes {
+                let content_changes = match later_change.variant {
+                    FileChangeVariant::Updated(ref content_changes) => content_changes,
+                    FileChangeVariant::Deleted => {
+                        bail!("The file was deleted after the change.")
+                    }
+                    FileChangeVariant::CopiedFrom { .. } | FileChangeVariant::Structured(_) => {
+                        unreachable!()
+                    }
+                };
+
+                for content_change in content_changes {
+                    hunks = hunks
+                        .iter()
+                        .map(|hunk| hunk.rebase(content_change))
+                        .collect::<Option<_>>()
+                        .context("The change conflicts with a later change to the file.")?;
+                }
+            }
+
+            let tip_content = file_history.get_content(cursor)?;
+            let mut reverted_content = tip_content.clone();
+            Hunk::apply_all(&hunks, &mut reverted_content);
+
+            let changes = ContentChange::diff(&tip_content, &reverted_content);
+            Ok(if changes.is_empty() {
+                None
+            } else {
+                Some(FileChange {
+                    change_index: cursor + 1,
+                    variant: FileChangeVariant::Updated(changes),
+                    degraded: false,
+                    content_hash: Some(FileChange::hash_content(&reverted_content)),
+                    change_id: None,
+                    content_type: Some(get_content_type(config, working_path, &reverted_content)),
+                })
+            })
+        }
+        (false, false) => Ok(None),
+    }
+}
+
+fn is_file_dirty(
+    fs: &impl Fs,
+    working_path: &Path,
+    file_history: &FileHistory,
+    cursor: usize,
+) -> Result<bool> {
+    let exists_in_history = file_history.does_file_exist(cursor);
+
+    if fs.path_exists(working_path) != exists_in_history {
+        return Ok(true);
+    }
+
+    if exists_in_history {
+        let mut working_file = fs.open_readable_file(working_path)?;
+        let working_content = fs.read_from_file(&mut working_file)?;
+        Ok(working_content != file_history.get_content(cursor)?)
+    } else {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::{bail, Result};
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, EntryMock, FileMock, FsMock},
+            Fs,
+        },
+        history::RepositoryHistory,
+        journal,
+    };
+
+    use super::revert;
+
+    // Fails writing the working file once the journal is on the disk, like an interruption.
+    struct InterruptedFs {
+        inner: FsMock,
+    }
+
+    impl Fs for InterruptedFs {
+        type File = FileMock;
+        type Entry = EntryMock;
+
+        fn create_file(&self, path: &Path) -> Result<Self::File> {
+            if path == Path::new("./test") && self.inner.path_exists(Path::new("./.ka/journal")) {
+                bail!("Interrupted.");
+            }
+            self.inner.create_file(path)
+        }
+
+        fn delete_file(&self, path: &Path) -> Result<()> {
+            self.inner.delete_file(path)
+        }
+
+        fn open_readable_file(&self, path: &Path) -> Result<Self::File> {
+            self.inner.open_readable_file(path)
+        }
+
+        fn open_writable_file(&self, path: &Path) -> Result<Self::File> {
+            self.inner.open_writable_file(path)
+        }
+
+        fn create_directory(&self, path: &Path) -> Result<()> {
+            self.inner.create_directory(path)
+        }
+
+        fn read_directory(&self, path: &Path) -> Result<Vec<Self::Entry>> {
+            self.inner.read_directory(path)
+        }
+
+        fn delete_directory(&self, path: &Path) -> Result<()> {
+            self.inner.delete_directory(path)
+        }
+
+        fn write_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            self.inner.write_to_file(file, buffer)
+        }
+
+        fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            self.inner.append_to_file(file, buffer)
+        }
+
+        fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>> {
+            self.inner.read_from_file(file)
+        }
+
+        fn path_exists(&self, path: &Path) -> bool {
+            self.inner.path_exists(path)
+        }
+    }
+
+    #[test]
+    fn revert_earlier_change() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first\nsecond\nthird\n");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first\nchanged\nthird\n");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./test", b"first\nchanged\nthird\nfourth\n");
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        revert(ActionOptions::from_path("."), &fs_mock, 2, now + 3).expect("Action failed.");
+
+        assert_eq!(
+            read_file(&fs_mock, "./test"),
+            b"first\nsecond\nthird\nfourth\n"
+        );
+
+        // Reverting must leave the working tree matching the recorded state.
+        let state = fs_mock.get_state();
+        update(ActionOptions::from_path("."), &fs_mock, now + 4).unwrap();
+        fs_mock.assert_match(state);
+    }
+
+    #[test]
+    fn revert_creation() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./kept", &[1, 2, 3]);
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./created", &[4, 5, 6]);
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        revert(ActionOptions::from_path("."), &fs_mock, 2, now + 2).expect("Action failed.");
+
+        assert!(!fs_mock.path_exists(Path::new("./created")));
+        assert!(fs_mock.path_exists(Path::new("./kept")));
+    }
+
+    #[test]
+    fn revert_conflicting_change() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"one two three");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"one 2 three");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./test", b"one II three");
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        let state = fs_mock.get_state();
+        assert!(revert(ActionOptions::from_path("."), &fs_mock, 2, now + 3).is_err());
+        fs_mock.assert_match(state);
+    }
+
+    #[test]
+    fn complete_interrupted_revert() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+
+        write_file(&fs_mock, "./test", b"first\nsecond\n");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first\nchanged\n");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let fs = InterruptedFs { inner: fs_mock };
+        assert!(revert(ActionOptions::from_path("."), &fs, 2, now + 2).is_err());
+
+        // The next action completes the revert, instead of finding only its history written.
+        let fs_mock = fs.inner;
+        journal::complete_pending(&fs_mock, &locations).unwrap();
+        assert_eq!(read_file(&fs_mock, "./test"), b"first\nsecond\n");
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.cursor, 3);
+
+        let state = fs_mock.get_state();
+        update(ActionOptions::from_path("."), &fs_mock, now + 3).unwrap();
+        fs_mock.assert_match(state);
+    }
+}
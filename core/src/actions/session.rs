@@ -0,0 +1,194 @@
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    files::Locations,
+    filesystem::Fs,
+    history::{RepositoryHistory, Session},
+    journal,
+};
+
+use super::{repository_log, ActionOptions, LogEntry};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SessionLog {
+    // Changes recorded outside of any session aren't named.
+    pub name: Option<String>,
+    pub entries: Vec<LogEntry>,
+}
+
+// Every change recorded from now on is part of the session, until it's ended.
+pub fn start_session(command_options: ActionOptions, fs: &impl Fs, name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("Sessions need a name.");
+    }
+
+    set_sessions(command_options, fs, |repository_history| {
+        if let Some(session) = repository_history.get_open_session() {
+            bail!(
+                "The session '{}' is still going, end it before starting another one.",
+                session.name
+            );
+        }
+
+        let start = repository_history.cursor;
+        repository_history.sessions.push(Session {
+            name: name.to_string(),
+            start,
+            end: None,
+        });
+        Ok(())
+    })
+}
+
+// Ends the session which is going on, returning its name.
+pub fn end_session(command_options: ActionOptions, fs: &impl Fs) -> Result<String> {
+    let mut name = String::new();
+    set_sessions(command_options, fs, |repository_history| {
+        let cursor = repository_history.cursor;
+        let session = repository_history
+            .sessions
+            .last_mut()
+            .filter(|session| session.end.is_none())
+            .context("There is no session going on.")?;
+
+        session.end = Some(cursor.max(session.start));
+        name = session.name.clone();
+        Ok(())
+    })?;
+    Ok(name)
+}
+
+// The cursors the latest session with the name started and ended at, e.g. to shift to them.
+pub fn find_session(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    name: &str,
+) -> Result<(usize, usize)> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    repository_history
+        .get_session_bounds(name)
+        .with_context(|| format!("There is no session named '{}'.", name))
+}
+
+// The log of the repository, split into the sessions the changes were recorded in.
+pub fn session_log(command_options: ActionOptions, fs: &impl Fs) -> Result<Vec<SessionLog>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let mut groups: Vec<SessionLog> = Vec::new();
+    for entry in repository_log(command_options, fs)? {
+        let name = repository_history
+            .sessions
+            .iter()
+            .find(|session| {
+                let end = session
+                    .end
+                    .unwrap_or(repository_history.get_changes().len());
+                entry.change_index > session.start && entry.change_index <= end
+            })
+            .map(|session| session.name.clone());
+
+        match groups.last_mut() {
+            Some(group) if group.name == name => group.entries.push(entry),
+            _ => groups.push(SessionLog {
+                name,
+                entries: vec![entry],
+            }),
+        }
+    }
+
+    Ok(groups)
+}
+
+fn set_sessions<FS: Fs>(
+    command_options: ActionOptions,
+    fs: &FS,
+    change: impl FnOnce(&mut RepositoryHistory) -> Result<()>,
+) -> Result<()> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    change(&mut repository_history)?;
+
+    if repository_history.get_stored_length().is_some() {
+        fs.append_to_file(
+            &mut repository_index_file,
+            repository_history.encode_sessions()?,
+        )
+    } else {
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        actions::{create, squash, update, ActionOptions},
+        filesystem::mock::{write_file, FsMock},
+    };
+
+    use super::{end_session, find_session, session_log, start_session};
+
+    #[test]
+    fn group_changes_by_session() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"one");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        start_session(ActionOptions::from_path("."), &fs_mock, "refactor").unwrap();
+        assert!(start_session(ActionOptions::from_path("."), &fs_mock, "other").is_err());
+        for (timestamp, content) in [(now + 1, "two"), (now + 2, "three"), (now + 3, "four")] {
+            write_file(&fs_mock, "./test", content.as_bytes());
+            update(ActionOptions::from_path("."), &fs_mock, timestamp).unwrap();
+        }
+        assert_eq!(
+            end_session(ActionOptions::from_path("."), &fs_mock).unwrap(),
+            "refactor"
+        );
+        assert!(end_session(ActionOptions::from_path("."), &fs_mock).is_err());
+
+        write_file(&fs_mock, "./test", b"five");
+        update(ActionOptions::from_path("."), &fs_mock, now + 4).unwrap();
+
+        assert_eq!(
+            find_session(ActionOptions::from_path("."), &fs_mock, "refactor").unwrap(),
+            (1, 4)
+        );
+
+        let groups: Vec<_> = session_log(ActionOptions::from_path("."), &fs_mock)
+            .unwrap()
+            .into_iter()
+            .map(|group| (group.name, group.entries.len()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![(None, 1), (Some("refactor".into()), 3), (None, 1)]
+        );
+
+        // Squashing inside of the session keeps its bounds around the squashed change.
+        squash(ActionOptions::from_path("."), &fs_mock, 3, 4, None).unwrap();
+        assert_eq!(
+            find_session(ActionOptions::from_path("."), &fs_mock, "refactor").unwrap(),
+            (1, 3)
+        );
+    }
+}
@@ -0,0 +1,1098 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    actions::{status, update, FileStatus},
+    collisions::{get_suffixed_path, CaseCollisionPolicy, CasePaths},
+    config::Config,
+    diff::ContentChange,
+    files::{FileState, Locations},
+    filesystem::{ensure_available_space, Fs},
+    history::{FileHistory, RepositoryHistory},
+    hooks::{self, ShiftEvent},
+    journal, line_endings,
+    metrics::ActionTimer,
+    trace::{self, Level},
+    trash::remove_file,
+    undo::UndoSnapshot,
+};
+
+use super::{log::count_bytes, ActionOptions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftMode {
+    // Refuse to shift while there are unrecorded changes to tracked files.
+    Safe,
+    // Overwrite unrecorded changes.
+    Force,
+    // Record unrecorded changes as a new change first, with the given timestamp.
+    AutoUpdate(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UntrackedFiles {
+    // Leave untracked files in place, refusing to shift if one is in the way of a restored file.
+    Keep,
+    // Delete untracked files which are in the way of restored files.
+    Clean,
+}
+
+pub fn shift(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    new_cursor: usize,
+    mode: ShiftMode,
+    untracked_files: UntrackedFiles,
+) -> Result<Vec<PathBuf>> {
+    let _timer = ActionTimer::start("shift");
+    let _span = trace::span(Level::Info, "shift", &[("cursor", &new_cursor)]);
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    match mode {
+        ShiftMode::Safe => {
+            let dirty_files = get_dirty_files(command_options.clone(), fs)?;
+            if !dirty_files.is_empty() {
+                bail!(
+                    "Shifting would overwrite unrecorded changes to:\n{}\nUpdate first, or shift with force.",
+                    dirty_files.join("\n")
+                );
+            }
+        }
+        ShiftMode::Force => (),
+        ShiftMode::AutoUpdate(timestamp) => {
+            let dirty_files = get_dirty_files(command_options.clone(), fs)?;
+            if !dirty_files.is_empty() {
+                let mut repository_index_file =
+                    fs.open_readable_file(&locations.get_repository_index_path())?;
+                let repository_history =
+                    RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+                if repository_history.cursor != repository_history.get_changes().len() {
+                    bail!(
+                        "Unrecorded changes can only be recorded at the latest change {}, but the cursor is at {}.",
+                        repository_history.get_changes().len(),
+                        repository_history.cursor
+                    );
+                }
+
+                update(command_options.clone(), fs, timestamp)?;
+            }
+        }
+    }
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let mut removed_files = Vec::new();
+    let mut restored_files = Vec::new();
+    for (state, file_history, file_cursor) in
+        get_shifted_files(fs, &locations, &repository_history, new_cursor)?
+    {
+        if file_history.does_file_exist(new_cursor) {
+            restored_files.push((state, file_history, file_cursor));
+        } else if let FileState::Tracked(tracked) = state {
+            // The file might be gone already, if it was also deleted at the current cursor.
+            if fs.path_exists(&tracked.working_path) {
+                removed_files.push(tracked.working_path);
+            }
+        }
+    }
+
+    let config = Config::load(fs, &locations)?;
+    let suffixed_paths = match config.case_collisions {
+        CaseCollisionPolicy::Allow => HashMap::new(),
+        policy => {
+            resolve_case_collisions(fs, &locations, policy, &mut restored_files, &removed_files)?
+        }
+    };
+
+    let mut conflicting_paths = Vec::new();
+    for (state, _, _) in restored_files.iter() {
+        let working_path = state.get_working_path(&locations)?;
+        for path in get_conflicting_paths(fs, &locations, &working_path)? {
+            if !conflicting_paths.contains(&path) {
+                conflicting_paths.push(path);
+            }
+        }
+    }
+
+    trace::event(
+        Level::Debug,
+        "Found the files to shift.",
+        &[
+            ("restored", &restored_files.len()),
+            ("removed", &removed_files.len()),
+            ("conflicting", &conflicting_paths.len()),
+        ],
+    );
+
+    if !conflicting_paths.is_empty() && untracked_files == UntrackedFiles::Keep {
+        bail!(
+            "Shifting would overwrite untracked files at:\n{}\nMove them, or shift with clean.",
+            conflicting_paths
+                .iter()
+                .map(|path| command_options.display_path(path).display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    // Everything is replayed before anything is written, so a corrupted history leaves the
+    // repository and its working files as they were.
+    let mut restored_contents = Vec::new();
+    for (state, file_history, file_cursor) in restored_files {
+        let working_path = state.get_working_path(&locations)?;
+        let new_content = get_restored_content(
+            fs,
+            &config,
+            &state,
+            &file_history,
+            file_cursor,
+            new_cursor,
+            &conflicting_paths,
+        )
+        .with_context(|| {
+            format!(
+                "Failed restoring '{}'.",
+                command_options.display_path(&working_path).display()
+            )
+        })?;
+        restored_contents.push((state, working_path, new_content));
+    }
+
+    let restored_length: usize = restored_contents
+        .iter()
+        .map(|(_, _, content)| content.len())
+        .sum();
+    ensure_available_space(fs, &locations.repository_path, restored_length as u64)?;
+
+    // Everything the shift writes or removes is kept first, so it can be undone.
+    let mut touched_paths = conflicting_paths.clone();
+    touched_paths.extend(removed_files.iter().cloned());
+    for (_, working_path, _) in restored_contents.iter() {
+        touched_paths.push(
+            suffixed_paths
+                .get(working_path)
+                .unwrap_or(working_path)
+                .clone(),
+        );
+    }
+    touched_paths.sort();
+    touched_paths.dedup();
+    UndoSnapshot::take(
+        fs,
+        &locations,
+        &repository_history,
+        new_cursor,
+        &touched_paths,
+    )?;
+
+    let mut event = ShiftEvent {
+        old_cursor: repository_history.cursor,
+        new_cursor,
+        restored: Vec::new(),
+        removed: Vec::new(),
+    };
+
+    let file_cursors = get_shifted_file_cursors(&repository_history, new_cursor);
+    let has_new_file_cursors = file_cursors != repository_history.file_cursors;
+
+    repository_history.cursor = new_cursor;
+    repository_history.file_cursors = file_cursors;
+    if repository_history.get_stored_length().is_some() {
+        let mut records = repository_history.encode_cursor()?;
+        if has_new_file_cursors {
+            records.extend(repository_history.encode_file_cursors()?);
+        }
+        fs.append_to_file(&mut repository_index_file, records)?;
+    } else {
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+    }
+
+    for path in conflicting_paths {
+        if fs.read_directory(&path).is_ok() {
+            fs.delete_directory(&path)?;
+        } else {
+            fs.delete_file(&path)?;
+        }
+    }
+
+    // Removing files first frees up paths which restored files might need.
+    for working_path in removed_files {
+        remove_file(fs, &locations, config.removed_files, &working_path)?;
+        event.removed.push(
+            working_path
+                .strip_prefix(&locations.repository_path)?
+                .to_path_buf(),
+        );
+    }
+
+    for (state, working_path, new_content) in restored_contents {
+        let new_content = line_endings::restore(&config, &working_path, new_content);
+        // Files restored under another path aren't tracked there, they're left to be renamed.
+        if let Some(suffixed_path) = suffixed_paths.get(&working_path) {
+            trace_restored(suffixed_path, &new_content);
+            let mut suffixed_file = fs.create_file(suffixed_path)?;
+            fs.write_to_file(&mut suffixed_file, new_content)?;
+            event.restored.push(
+                suffixed_path
+                    .strip_prefix(&locations.repository_path)?
+                    .to_path_buf(),
+            );
+            continue;
+        }
+
+        let mut working_file = match state {
+            FileState::Tracked(tracked) => tracked.create_working_file(fs)?,
+            FileState::Deleted(deleted) => deleted.create_working_file(fs, &locations)?,
+            FileState::Untracked(_) => unreachable!(),
+        };
+        trace_restored(&working_path, &new_content);
+        fs.write_to_file(&mut working_file, new_content)?;
+        event.restored.push(
+            working_path
+                .strip_prefix(&locations.repository_path)?
+                .to_path_buf(),
+        );
+    }
+    hooks::run_hook(fs, &locations, hooks::POST_SHIFT, &event);
+
+    // Files outside of the tracked directories are never recorded, so they aren't worth a mention.
+    let mut untracked_paths = Vec::new();
+    for state in locations.get_repository_files(fs)? {
+        if let FileState::Untracked(untracked) = state {
+            if config.is_in_scope(&locations, &untracked.path) {
+                untracked_paths.push(untracked.path);
+            }
+        }
+    }
+    untracked_paths.sort();
+
+    Ok(untracked_paths)
+}
+
+// Puts the working files and cursor back to how they were before the last shift, as long as
+// nothing was recorded or shifted since. Changes made to the working files after the shift
+// are refused to be overwritten, unless forced.
+pub fn undo_shift(command_options: ActionOptions, fs: &impl Fs, force: bool) -> Result<()> {
+    let _timer = ActionTimer::start("undo_shift");
+    let _span = trace::span(Level::Info, "undo_shift", &[]);
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let snapshot = UndoSnapshot::load(fs, &locations)?.context("There is no shift to undo.")?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    if repository_history.cursor != snapshot.shifted_to
+        || repository_history.get_changes().len() != snapshot.change_count
+    {
+        bail!("The repository changed since the last shift, which can't be undone anymore.");
+    }
+
+    if !force {
+        let dirty_files = get_dirty_files(command_options, fs)?;
+        if !dirty_files.is_empty() {
+            bail!(
+                "Undoing the shift would overwrite unrecorded changes to:\n{}\nUpdate first, or undo with force.",
+                dirty_files.join("\n")
+            );
+        }
+    }
+
+    // Restoring the files again is harmless, so an interrupted undo can just be repeated.
+    snapshot.restore(fs, &locations)?;
+
+    let has_new_file_cursors = snapshot.file_cursors != repository_history.file_cursors;
+    repository_history.cursor = snapshot.cursor;
+    repository_history.file_cursors = snapshot.file_cursors;
+    if repository_history.get_stored_length().is_some() {
+        let mut records = repository_history.encode_cursor()?;
+        if has_new_file_cursors {
+            records.extend(repository_history.encode_file_cursors()?);
+        }
+        fs.append_to_file(&mut repository_index_file, records)?;
+    } else {
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+    }
+
+    trace::event(
+        Level::Info,
+        "Undid the shift.",
+        &[("cursor", &repository_history.cursor)],
+    );
+    UndoSnapshot::delete(fs, &locations)
+}
+
+// Applies the policy to restored files colliding with others by case, and returns the paths
+// the ones which get a suffix are restored to instead.
+fn resolve_case_collisions(
+    fs: &impl Fs,
+    locations: &Locations,
+    policy: CaseCollisionPolicy,
+    restored_files: &mut Vec<(FileState, FileHistory, usize)>,
+    removed_files: &[PathBuf],
+) -> Result<HashMap<PathBuf, PathBuf>> {
+    let case_collisions = get_case_collisions(fs, locations, restored_files, removed_files)?;
+    let mut suffixed_paths = HashMap::new();
+    if case_collisions.is_empty() {
+        return Ok(suffixed_paths);
+    }
+
+    let display_path = |path: &Path| {
+        let path = path
+            .strip_prefix(&locations.repository_path)
+            .unwrap_or(path);
+        path.display().to_string()
+    };
+    match policy {
+        CaseCollisionPolicy::Allow => {}
+        CaseCollisionPolicy::Error => bail!(
+            "Shifting would restore files whose paths only differ by case from others:\n{}\nSet `case_collisions` in the config to `Suffix` or `Skip` to restore them anyway.",
+            case_collisions
+                .iter()
+                .map(|(path, other_path)| format!("{} and {}", display_path(path), display_path(other_path)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+        CaseCollisionPolicy::Skip => {
+            for (path, other_path) in case_collisions.iter() {
+                trace::event(
+                    Level::Warn,
+                    "Skipped restoring a file whose path only differs by case.",
+                    &[("path", &path.display()), ("other", &other_path.display())],
+                );
+            }
+            restored_files.retain(|(state, _, _)| {
+                state
+                    .get_working_path(locations)
+                    .map_or(true, |working_path| {
+                        !case_collisions.iter().any(|(path, _)| *path == working_path)
+                    })
+            });
+        }
+        CaseCollisionPolicy::Suffix => {
+            for (path, other_path) in case_collisions {
+                let suffixed_path = get_suffixed_path(&path, |suffixed_path| {
+                    fs.path_exists(suffixed_path)
+                        || suffixed_paths.values().any(|taken| taken == suffixed_path)
+                });
+                trace::event(
+                    Level::Warn,
+                    "Restored a file whose path only differs by case under another path.",
+                    &[
+                        ("path", &suffixed_path.display()),
+                        ("other", &other_path.display()),
+                    ],
+                );
+                suffixed_paths.insert(path, suffixed_path);
+            }
+        }
+    }
+    Ok(suffixed_paths)
+}
+
+// Restored files whose paths only differ by case from another file which is there after the
+// shift, along with that file. Files already next to each other are left alone.
+fn get_case_collisions(
+    fs: &impl Fs,
+    locations: &Locations,
+    restored_files: &[(FileState, FileHistory, usize)],
+    removed_files: &[PathBuf],
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut restored_paths = restored_files
+        .iter()
+        .map(|(state, _, _)| state.get_working_path(locations))
+        .collect::<Result<Vec<_>>>()?;
+    restored_paths.sort();
+
+    let mut case_paths = CasePaths::default();
+    for state in locations.get_repository_files(fs)? {
+        let working_path = match state {
+            FileState::Tracked(tracked) => tracked.working_path,
+            FileState::Untracked(untracked) => untracked.path,
+            FileState::Deleted(_) => continue,
+        };
+        if !removed_files.contains(&working_path) && !restored_paths.contains(&working_path) {
+            case_paths.insert(&working_path);
+        }
+    }
+
+    let mut case_collisions = Vec::new();
+    for path in restored_paths {
+        if let Some(other_path) = case_paths.insert(&path) {
+            case_collisions.push((path, other_path));
+        }
+    }
+    Ok(case_collisions)
+}
+
+fn get_restored_content(
+    fs: &impl Fs,
+    config: &Config,
+    state: &FileState,
+    file_history: &FileHistory,
+    file_cursor: usize,
+    new_cursor: usize,
+    conflicting_paths: &[PathBuf],
+) -> Result<Vec<u8>> {
+    if let FileState::Tracked(tracked) = state {
+        // Untracked files in the way of the working file are of no use to start from.
+        if new_cursor < file_cursor
+            && fs.path_exists(&tracked.working_path)
+            && !conflicting_paths.contains(&tracked.working_path)
+        {
+            let mut working_file = tracked.load_working_file(fs)?;
+            let old_content = line_endings::normalize(
+                config,
+                &tracked.working_path,
+                fs.read_from_file(&mut working_file)?,
+            );
+
+            // Undoing the changes is a lot cheaper than replaying the entire history, but
+            // only possible if we know all of the changes in between.
+            if let Some(new_content) =
+                file_history.get_content_backwards(old_content, file_cursor, new_cursor)
+            {
+                return Ok(new_content);
+            }
+        }
+    }
+    file_history.get_content(new_cursor)
+}
+
+fn trace_restored(working_path: &Path, content: &[u8]) {
+    trace::event(
+        Level::Trace,
+        "Restored the file.",
+        &[("path", &working_path.display()), ("bytes", &content.len())],
+    );
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShiftPreviewKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ShiftPreviewEntry {
+    pub path: PathBuf,
+    pub kind: ShiftPreviewKind,
+    pub old_size: usize,
+    pub new_size: usize,
+    pub bytes_added: usize,
+    pub bytes_removed: usize,
+}
+
+// What shifting to the cursor would do to the working files, without touching any of them.
+// Unrecorded changes count as part of the working files, just like a forced shift would
+// overwrite them.
+pub fn preview_shift(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    new_cursor: usize,
+) -> Result<Vec<ShiftPreviewEntry>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    let config = Config::load(fs, &locations)?;
+
+    let mut entries = Vec::new();
+    for (state, file_history, _) in
+        get_shifted_files(fs, &locations, &repository_history, new_cursor)?
+    {
+        let path = state.get_working_path(&locations)?;
+
+        let old_content = match &state {
+            FileState::Tracked(tracked) if fs.path_exists(&tracked.working_path) => {
+                let mut working_file = tracked.load_working_file(fs)?;
+                let old_content = fs.read_from_file(&mut working_file)?;
+                Some(line_endings::normalize(&config, &path, old_content))
+            }
+            _ => None,
+        };
+        let new_content = if file_history.does_file_exist(new_cursor) {
+            Some(file_history.get_content(new_cursor)?)
+        } else {
+            None
+        };
+
+        let kind = match (&old_content, &new_content) {
+            (None, None) => continue,
+            (Some(old_content), Some(new_content)) if old_content == new_content => continue,
+            (None, Some(_)) => ShiftPreviewKind::Created,
+            (Some(_), None) => ShiftPreviewKind::Deleted,
+            (Some(_), Some(_)) => ShiftPreviewKind::Modified,
+        };
+
+        let old_content = old_content.unwrap_or_default();
+        let new_content = new_content.unwrap_or_default();
+        let (bytes_added, bytes_removed) =
+            count_bytes(&ContentChange::diff(&old_content, &new_content));
+
+        entries.push(ShiftPreviewEntry {
+            path,
+            kind,
+            old_size: old_content.len(),
+            new_size: new_content.len(),
+            bytes_added,
+            bytes_removed,
+        });
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(entries)
+}
+
+// The recorded files which have to change to get to the new cursor, with their histories and
+// the cursor they are at now.
+fn get_shifted_files<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    repository_history: &RepositoryHistory,
+    new_cursor: usize,
+) -> Result<Vec<(FileState, FileHistory, usize)>> {
+    let change_count = repository_history.get_changes().len();
+    if new_cursor > change_count {
+        bail!(
+            "The cursor {} is out of range, as the repository only has the changes 1..={}.",
+            new_cursor,
+            change_count
+        );
+    }
+
+    // Files at their own cursor have to be shifted from there, whether they changed in
+    // between the cursors or not.
+    let mut candidates = get_changed_paths(repository_history, new_cursor);
+    candidates.extend(repository_history.file_cursors.keys());
+
+    // Files without a history were never recorded, so they are left alone, just like pinned ones.
+    let mut shifted_files = Vec::new();
+    for path in candidates {
+        let file_cursor = repository_history.get_file_cursor(path);
+        if repository_history.is_pinned(path) || file_cursor == new_cursor {
+            continue;
+        }
+
+        let state = FileState::from_working(fs, locations, path)?;
+        let history_path = match &state {
+            FileState::Tracked(tracked) => &tracked.history_path,
+            FileState::Deleted(deleted) => &deleted.history_path,
+            FileState::Untracked(_) => continue,
+        };
+        let mut history_file = fs.open_readable_file(history_path)?;
+        let file_history = FileHistory::from_file(fs, locations, &mut history_file)?;
+        if file_history.forgotten {
+            continue;
+        }
+
+        shifted_files.push((state, file_history, file_cursor));
+    }
+
+    Ok(shifted_files)
+}
+
+// Paths of the files changed in between the cursor and the new one.
+fn get_changed_paths(
+    repository_history: &RepositoryHistory,
+    new_cursor: usize,
+) -> HashSet<&PathBuf> {
+    let old_cursor = repository_history.cursor;
+    let changes_between_cursors = if old_cursor < new_cursor {
+        old_cursor..new_cursor
+    } else {
+        new_cursor..old_cursor
+    };
+
+    repository_history.get_changes()[changes_between_cursors]
+        .iter()
+        .flat_map(|change| change.affected_files.iter())
+        .collect()
+}
+
+// Pinned files stay where they are, so the ones which would have changed keep their own cursor.
+// All others end up at the new cursor.
+fn get_shifted_file_cursors(
+    repository_history: &RepositoryHistory,
+    new_cursor: usize,
+) -> BTreeMap<PathBuf, usize> {
+    let changed_paths = get_changed_paths(repository_history, new_cursor);
+
+    repository_history
+        .pinned_files
+        .iter()
+        .filter(|path| {
+            changed_paths.contains(path) || repository_history.file_cursors.contains_key(*path)
+        })
+        .map(|path| (path.clone(), repository_history.get_file_cursor(path)))
+        .filter(|(_, file_cursor)| *file_cursor != new_cursor)
+        .collect()
+}
+
+// Untracked files which occupy the path of a restored file, or one of its parent directories.
+fn get_conflicting_paths(
+    fs: &impl Fs,
+    locations: &Locations,
+    working_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut conflicting_paths = Vec::new();
+
+    if fs.read_directory(working_path).is_ok() {
+        conflicting_paths.push(working_path.to_path_buf());
+    }
+
+    for parent_path in working_path
+        .ancestors()
+        .skip(1)
+        .take_while(|path| *path != locations.repository_path)
+    {
+        let is_file = fs.path_exists(parent_path) && fs.read_directory(parent_path).is_err();
+        if is_file && !fs.path_exists(&locations.history_from_working(parent_path)?) {
+            conflicting_paths.push(parent_path.to_path_buf());
+        }
+    }
+
+    Ok(conflicting_paths)
+}
+
+// Files whose working content differs from the content at the cursor. Untracked files
+// aren't touched by a shift, so they don't count.
+// Pinned files aren't overwritten by shifts, so their changes don't get in the way.
+pub(super) fn get_dirty_files(command_options: ActionOptions, fs: &impl Fs) -> Result<Vec<String>> {
+    let locations = Locations::from(&command_options);
+    let mut repository_index_file =
+        fs.open_readable_file(&locations.get_repository_index_path())?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    Ok(status(command_options.clone(), fs)?
+        .files
+        .into_iter()
+        .filter(|(path, file_status)| {
+            matches!(file_status, FileStatus::Modified | FileStatus::Deleted)
+                && !repository_history.is_pinned(path)
+        })
+        .map(|(path, _)| command_options.display_path(&path).display().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{
+            create, preview_shift, shift, status, undo_shift, update, ActionOptions, ShiftMode,
+            ShiftPreviewEntry, ShiftPreviewKind, UntrackedFiles,
+        },
+        collisions::CaseCollisionPolicy,
+        config::Config,
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::RepositoryHistory,
+        notifications::is_disk_full,
+    };
+
+    #[test]
+    fn shift_back_and_forth() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        write_file(&fs_mock, "./new", b"new");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./test", b"second third");
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            2,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"first second");
+
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"first");
+        assert!(!fs_mock.path_exists(Path::new("./new")));
+
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            3,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"second third");
+        assert_eq!(read_file(&fs_mock, "./new"), b"new");
+    }
+
+    #[test]
+    fn refuse_writes_without_space() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        write_file(&fs_mock, "./test", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./test", &[b'x'; 64]);
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+
+        // Neither the update nor the shift to the larger content get to write anything.
+        fs_mock.set_available_space(Some(16));
+        write_file(&fs_mock, "./test", &[b'y'; 64]);
+        let error = update(options.clone(), &fs_mock, now + 2).unwrap_err();
+        assert!(is_disk_full(&error));
+        assert!(!fs_mock.path_exists(&Locations::from(&options).get_journal_path()));
+        assert_eq!(status(options.clone(), &fs_mock).unwrap().cursor, 2);
+
+        write_file(&fs_mock, "./test", &[b'x'; 64]);
+        shift(
+            options.clone(),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        let error = shift(
+            options.clone(),
+            &fs_mock,
+            2,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap_err();
+        assert!(is_disk_full(&error));
+        assert_eq!(read_file(&fs_mock, "./test"), b"first");
+
+        fs_mock.set_available_space(None);
+        shift(options, &fs_mock, 2, ShiftMode::Safe, UntrackedFiles::Keep).unwrap();
+        assert_eq!(read_file(&fs_mock, "./test"), [b'x'; 64]);
+    }
+
+    #[test]
+    fn shift_back_with_unrecorded_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        // The working content doesn't match the cursor anymore, so the history is replayed.
+        write_file(&fs_mock, "./test", b"x");
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Force,
+            UntrackedFiles::Keep,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"first");
+    }
+
+    #[test]
+    fn undo_forced_shift() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let get_cursor = || {
+            let locations = Locations::from(&options);
+            let mut index_file = fs_mock
+                .open_readable_file(&locations.get_repository_index_path())
+                .unwrap();
+            RepositoryHistory::from_file(&fs_mock, &locations, &mut index_file)
+                .unwrap()
+                .cursor
+        };
+
+        write_file(&fs_mock, "./test", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./test", b"first second");
+        write_file(&fs_mock, "./new", b"new");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./test", b"unrecorded");
+        shift(
+            options.clone(),
+            &fs_mock,
+            1,
+            ShiftMode::Force,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert_eq!(read_file(&fs_mock, "./test"), b"first");
+        assert!(!fs_mock.path_exists(Path::new("./new")));
+
+        // Changes made after the shift are just as worth keeping.
+        write_file(&fs_mock, "./test", b"after");
+        assert!(undo_shift(options.clone(), &fs_mock, false).is_err());
+        write_file(&fs_mock, "./test", b"first");
+
+        undo_shift(options.clone(), &fs_mock, false).unwrap();
+        assert_eq!(read_file(&fs_mock, "./test"), b"unrecorded");
+        assert_eq!(read_file(&fs_mock, "./new"), b"new");
+        assert_eq!(get_cursor(), 2);
+        assert!(undo_shift(options.clone(), &fs_mock, false).is_err());
+
+        // Nothing is undone once another change was recorded.
+        shift(
+            options.clone(),
+            &fs_mock,
+            1,
+            ShiftMode::Force,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        shift(
+            options.clone(),
+            &fs_mock,
+            2,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        write_file(&fs_mock, "./test", b"recorded");
+        update(options.clone(), &fs_mock, now + 2).unwrap();
+        assert!(undo_shift(options.clone(), &fs_mock, true).is_err());
+        assert_eq!(get_cursor(), 3);
+    }
+
+    #[test]
+    fn refuse_shift_with_unrecorded_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        write_file(&fs_mock, "./other", b"other");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./test", b"unrecorded");
+        fs_mock.delete_file(Path::new("./other")).unwrap();
+        write_file(&fs_mock, "./untracked", b"untracked");
+
+        let error = shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect_err("Action should have failed.");
+        let message = error.to_string();
+        // The files are named relative to the repository, like everywhere else.
+        assert!(message.contains("\nother\ntest\n"), "{}", message);
+        assert!(!message.contains("untracked"));
+        assert_eq!(read_file(&fs_mock, "./test"), b"unrecorded");
+
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::AutoUpdate(now + 2),
+            UntrackedFiles::Keep,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"first");
+        assert_eq!(read_file(&fs_mock, "./other"), b"other");
+
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.get_changes().len(), 3);
+
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            3,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect("Action failed.");
+        assert_eq!(read_file(&fs_mock, "./test"), b"unrecorded");
+        assert!(!fs_mock.path_exists(Path::new("./other")));
+    }
+
+    #[test]
+    fn shift_with_untracked_files() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./draft", b"draft");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        fs_mock.delete_file(Path::new("./draft")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        // An untracked directory now occupies the path of the deleted file.
+        write_file(&fs_mock, "./draft/notes", b"notes");
+        write_file(&fs_mock, "./scratch", b"scratch");
+
+        let error = shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect_err("Action should have failed.");
+        assert!(error.to_string().contains("\ndraft\n"));
+        assert_eq!(read_file(&fs_mock, "./draft/notes"), b"notes");
+
+        let untracked_paths = shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Clean,
+        )
+        .expect("Action failed.");
+        assert_eq!(untracked_paths, vec![Path::new("./scratch")]);
+        assert_eq!(read_file(&fs_mock, "./draft"), b"draft");
+        assert_eq!(read_file(&fs_mock, "./scratch"), b"scratch");
+    }
+
+    #[test]
+    fn preview_shift_without_touching_files() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        write_file(&fs_mock, "./gone", b"gone");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        write_file(&fs_mock, "./new", b"new");
+        fs_mock.delete_file(Path::new("./gone")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let preview = preview_shift(ActionOptions::from_path("."), &fs_mock, 1).unwrap();
+        assert_eq!(
+            preview,
+            vec![
+                ShiftPreviewEntry {
+                    path: Path::new("./gone").into(),
+                    kind: ShiftPreviewKind::Created,
+                    old_size: 0,
+                    new_size: 4,
+                    bytes_added: 4,
+                    bytes_removed: 0,
+                },
+                ShiftPreviewEntry {
+                    path: Path::new("./new").into(),
+                    kind: ShiftPreviewKind::Deleted,
+                    old_size: 3,
+                    new_size: 0,
+                    bytes_added: 0,
+                    bytes_removed: 3,
+                },
+                ShiftPreviewEntry {
+                    path: Path::new("./test").into(),
+                    kind: ShiftPreviewKind::Modified,
+                    old_size: 12,
+                    new_size: 5,
+                    bytes_added: 0,
+                    bytes_removed: 7,
+                },
+            ]
+        );
+
+        assert_eq!(read_file(&fs_mock, "./test"), b"first second");
+        assert!(!fs_mock.path_exists(Path::new("./gone")));
+        assert!(preview_shift(ActionOptions::from_path("."), &fs_mock, 3).is_err());
+    }
+
+    #[test]
+    fn shift_with_case_collisions() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let shift = |cursor: usize| {
+            shift(
+                ActionOptions::from_path("."),
+                &fs_mock,
+                cursor,
+                ShiftMode::Safe,
+                UntrackedFiles::Keep,
+            )
+        };
+        let set_policy = |case_collisions: CaseCollisionPolicy| {
+            let config = Config {
+                case_collisions,
+                ..Config::default()
+            };
+            config.write(&fs_mock, &Locations::from(&options)).unwrap();
+        };
+
+        write_file(&fs_mock, "./Readme", b"old readme");
+        create(options.clone(), &fs_mock, now).unwrap();
+        fs_mock.delete_file(Path::new("./Readme")).unwrap();
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+
+        // On a case-insensitive filesystem, restoring this would overwrite the untracked file.
+        write_file(&fs_mock, "./README", b"new readme");
+        set_policy(CaseCollisionPolicy::Error);
+        let error = shift(1).expect_err("Action should have failed.");
+        assert!(error.to_string().contains("\nReadme and README\n"));
+        assert!(!fs_mock.path_exists(Path::new("./Readme")));
+
+        set_policy(CaseCollisionPolicy::Skip);
+        assert_eq!(shift(1).unwrap(), vec![Path::new("./README")]);
+        assert!(!fs_mock.path_exists(Path::new("./Readme")));
+        // The skipped file counts as deleted, so shifting on has to be forced.
+        super::shift(
+            options.clone(),
+            &fs_mock,
+            2,
+            ShiftMode::Force,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+
+        set_policy(CaseCollisionPolicy::Suffix);
+        assert_eq!(
+            shift(1).unwrap(),
+            vec![Path::new("./README"), Path::new("./Readme (2)")]
+        );
+        assert_eq!(read_file(&fs_mock, "./Readme (2)"), b"old readme");
+        assert_eq!(read_file(&fs_mock, "./README"), b"new readme");
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    files::Locations,
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+};
+
+use super::ActionOptions;
+
+pub fn show(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    path: &Path,
+    cursor: usize,
+) -> Result<Vec<u8>> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let change_count = repository_history.get_changes().len();
+    if cursor > change_count {
+        bail!(
+            "The cursor {} is out of range, as the repository only has the changes 1..={}.",
+            cursor,
+            change_count
+        );
+    }
+
+    let working_path = locations.get_working_path(path)?;
+    let history_path = locations.history_from_working(&working_path)?;
+    if !fs.path_exists(&history_path) {
+        bail!(
+            "The file '{}' can't be shown, because it has no history.",
+            command_options.display_path(&working_path).display()
+        );
+    }
+
+    let mut history_file = fs.open_readable_file(&history_path)?;
+    let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+
+    if !file_history.does_file_exist(cursor) {
+        bail!(
+            "The file '{}' didn't exist at the cursor {}.",
+            command_options.display_path(&working_path).display(),
+            cursor
+        );
+    }
+
+    file_history.get_content(cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::show;
+
+    #[test]
+    fn show_past_content() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        fs_mock.delete_file(Path::new("./test")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        let content = show(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("test"),
+            1,
+        )
+        .expect("Action failed.");
+        assert_eq!(content, b"first");
+
+        let content = show(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("test"),
+            2,
+        )
+        .expect("Action failed.");
+        assert_eq!(content, b"first second");
+
+        // The working directory isn't touched.
+        assert!(!fs_mock.path_exists(Path::new("./test")));
+
+        assert!(show(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("test"),
+            3
+        )
+        .is_err());
+        assert!(show(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("test"),
+            4
+        )
+        .is_err());
+        assert!(show(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("missing"),
+            1
+        )
+        .is_err());
+    }
+}
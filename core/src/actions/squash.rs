@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    diff::ContentChange,
+    files::{normalize_path, Locations},
+    filesystem::Fs,
+    history::{
+        materialize_copies, FileChange, FileChangeVariant, FileHistory, RepositoryChange,
+        RepositoryHistory,
+    },
+    journal,
+    metrics::ActionTimer,
+};
+
+use super::ActionOptions;
+
+pub fn squash(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    from: usize,
+    to: usize,
+    message: Option<String>,
+) -> Result<()> {
+    let _timer = ActionTimer::start("squash");
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    let change_count = repository_history.get_changes().len();
+
+    if from == 0 || from > to || to > change_count {
+        bail!(
+            "The range {}..={} can't be squashed, as the repository only has the changes 1..={}.",
+            from,
+            to,
+            change_count
+        );
+    }
+
+    squash_range(fs, &locations, &mut repository_history, from, to, message)?;
+
+    repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+
+    Ok(())
+}
+
+pub(super) fn squash_range(
+    fs: &impl Fs,
+    locations: &Locations,
+    repository_history: &mut RepositoryHistory,
+    from: usize,
+    to: usize,
+    message: Option<String>,
+) -> Result<()> {
+    let cursor = repository_history.cursor;
+    if cursor >= from && cursor < to {
+        bail!(
+            "The range {}..={} can't be squashed, as the cursor at {} is placed inside of it.",
+            from,
+            to,
+            cursor
+        );
+    }
+    if let Some((path, _)) = repository_history
+        .file_cursors
+        .iter()
+        .find(|(_, file_cursor)| **file_cursor >= from && **file_cursor < to)
+    {
+        bail!(
+            "The range {}..={} can't be squashed, as '{}' is at a change inside of it. Sync it first.",
+            from,
+            to,
+            path.strip_prefix(&locations.repository_path)
+                .unwrap_or(path)
+                .display()
+        );
+    }
+
+    let removed_count = to - from;
+    // The squashed change ends up where the last change of the range did, so it keeps its ID.
+    let change_id = repository_history.get_change_id(to).map(String::from);
+
+    // Every file changed in or after the range has to be renumbered.
+    let files_to_rewrite: HashSet<_> = repository_history.get_changes()[from - 1..]
+        .iter()
+        .flat_map(|change| change.affected_files.iter().cloned())
+        .collect();
+    let source_paths = files_to_rewrite
+        .iter()
+        .filter_map(|working_path| working_path.strip_prefix(&locations.repository_path).ok())
+        .map(normalize_path)
+        .collect();
+    materialize_copies(fs, locations, &source_paths)?;
+
+    let mut squashed_files = Vec::new();
+
+    for working_path in files_to_rewrite {
+        let history_path = locations.history_from_working(&working_path)?;
+        if !fs.path_exists(&history_path) {
+            continue;
+        }
+
+        let mut history_file = fs.open_writable_file(&history_path)?;
+        let mut file_history = FileHistory::from_file(fs, locations, &mut history_file)?;
+
+        let squashed_change =
+            get_squashed_change(&file_history, from, to)?.map(|change| FileChange {
+                change_id: change_id.clone(),
+                ..change
+            });
+        let has_squashed_change = squashed_change.is_some();
+
+        let changes = file_history.get_changes_mut();
+        let later_changes = changes.split_off(
+            changes
+                .iter()
+                .position(|change| change.change_index >= from)
+                .unwrap_or(changes.len()),
+        );
+
+        changes.extend(squashed_change);
+        changes.extend(
+            later_changes
+                .into_iter()
+                .filter(|change| change.change_index > to)
+                .map(|change| FileChange {
+                    change_index: change.change_index - removed_count,
+                    variant: change.variant,
+                    degraded: false,
+                    content_hash: change.content_hash,
+                    change_id: change.change_id,
+                    content_type: change.content_type,
+                }),
+        );
+
+        // A file which only existed within the range doesn't need a history anymore.
+        if file_history.get_changes().is_empty() {
+            FileHistory::delete_file(fs, locations, &history_path)?;
+        } else {
+            file_history.write_to_file(fs, locations, &mut history_file)?;
+        }
+
+        if has_squashed_change {
+            squashed_files.push(working_path);
+        }
+    }
+
+    // Keep the order of the paths stable, as they were in the original changes.
+    let affected_files = repository_history.get_changes()[from - 1..to]
+        .iter()
+        .flat_map(|change| change.affected_files.iter())
+        .fold(Vec::new(), |mut acc, path| {
+            if squashed_files.contains(path) && !acc.contains(path) {
+                acc.push(path.clone());
+            }
+            acc
+        });
+
+    let timestamp = repository_history.get_changes()[to - 1].timestamp;
+    let author = repository_history.get_changes()[to - 1].author.clone();
+    // Where the squashed changes disagree on an annotation, the latest one wins.
+    let annotations = repository_history.get_changes()[from - 1..to]
+        .iter()
+        .flat_map(|change| change.annotations.clone())
+        .collect();
+
+    let changes = repository_history.get_changes_mut();
+    changes.splice(
+        from - 1..to,
+        std::iter::once(RepositoryChange {
+            affected_files,
+            timestamp,
+            message,
+            id: change_id,
+            author,
+            annotations,
+        }),
+    );
+
+    if cursor >= to {
+        repository_history.cursor -= removed_count;
+    }
+    for file_cursor in repository_history.file_cursors.values_mut() {
+        if *file_cursor >= to {
+            *file_cursor -= removed_count;
+        }
+    }
+    // Sessions which started or ended inside of the range now do so after the squashed change.
+    let renumber = |cursor: usize| {
+        if cursor >= to {
+            cursor - removed_count
+        } else {
+            cursor.min(from)
+        }
+    };
+    for session in repository_history.sessions.iter_mut() {
+        session.start = renumber(session.start);
+        session.end = session.end.map(renumber);
+    }
+
+    Ok(())
+}
+
+fn get_squashed_change(
+    file_history: &FileHistory,
+    from: usize,
+    to: usize,
+) -> Result<Option<FileChange>> {
+    let existed_before = file_history.does_file_exist(from - 1);
+    let exists_after = file_history.does_file_exist(to);
+
+    let (variant, content_hash) = match (existed_before, exists_after) {
+        (true, true) => {
+            let new_content = file_history.get_content(to)?;
+            let content_changes =
+                ContentChange::diff(&file_history.get_content(from - 1)?, &new_content);
+            if content_changes.is_empty() {
+                return Ok(None);
+            }
+            (
+                FileChangeVariant::Updated(content_changes),
+                Some(FileChange::hash_content(&new_content)),
+            )
+        }
+        (false, true) => {
+            let new_content = file_history.get_content(to)?;
+            (
+                FileChangeVariant::Updated(ContentChange::diff(&[], &new_content)),
+                Some(FileChange::hash_content(&new_content)),
+            )
+        }
+        (true, false) => (FileChangeVariant::Deleted, None),
+        (false, false) => return Ok(None),
+    };
+
+    Ok(Some(FileChange {
+        change_index: from,
+        variant,
+        degraded: false,
+        content_hash,
+        change_id: None,
+        content_type: exists_after
+            .then(|| file_history.get_content_type(to))
+            .flatten(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::{FileHistory, RepositoryHistory},
+    };
+
+    use super::squash;
+
+    #[test]
+    fn squash_middle_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"one");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"one two");
+        write_file(&fs_mock, "./temporary", b"temporary");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        write_file(&fs_mock, "./test", b"one two three");
+        fs_mock.delete_file(Path::new("./temporary")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        write_file(&fs_mock, "./test", b"one two three four");
+        update(ActionOptions::from_path("."), &fs_mock, now + 3).unwrap();
+
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        let latest_id = index.get_change_id(4).unwrap().to_string();
+        let squashed_id = index.get_change_id(3).unwrap().to_string();
+
+        squash(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            2,
+            3,
+            Some("Squashed".into()),
+        )
+        .expect("Action failed.");
+
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.cursor, 3);
+        assert_eq!(index.get_changes().len(), 3);
+
+        let squashed = &index.get_changes()[1];
+        assert_eq!(squashed.affected_files, vec![Path::new("./test")]);
+        assert_eq!(squashed.timestamp, now + 2);
+        assert_eq!(squashed.message, Some("Squashed".into()));
+
+        // The changes are renumbered, but keep their IDs.
+        assert_eq!(squashed.id.as_ref(), Some(&squashed_id));
+        assert_eq!(index.resolve_change(&latest_id[..8]).unwrap(), 3);
+
+        let history = FileHistory::decode(&read_file(&fs_mock, "./.ka/files/test")).unwrap();
+        assert_eq!(history.get_content(1).unwrap(), b"one");
+        assert_eq!(history.get_content(2).unwrap(), b"one two three");
+        assert_eq!(history.get_content(3).unwrap(), b"one two three four");
+
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/temporary")));
+    }
+
+    #[test]
+    fn squash_around_cursor() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"one");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"one two");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let result = squash(ActionOptions::from_path("."), &fs_mock, 1, 2, None);
+        assert!(result.is_ok());
+
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.cursor, 1);
+
+        let result = squash(ActionOptions::from_path("."), &fs_mock, 1, 3, None);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    diff::ContentChange,
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileChangeVariant, FileHistory, RepositoryHistory},
+    objects::ObjectStore,
+};
+
+use super::{log::count_change_bytes, ActionOptions};
+
+const LARGEST_HISTORIES_COUNT: usize = 10;
+const LARGEST_INSERTIONS_COUNT: usize = 10;
+
+#[derive(Debug)]
+pub struct Stats {
+    pub change_count: usize,
+    pub tracked_file_count: usize,
+    // Everything stored in the `.ka` directory, including the index and objects.
+    pub history_size: u64,
+    // The tracked files which currently exist in the working directory.
+    pub working_size: u64,
+    // What each tracked file takes up in `.ka`: its history, the segments its oldest changes
+    // are sealed in, and the objects its large insertions were moved to. Objects which several
+    // files share count for each of them.
+    pub largest_histories: Vec<(PathBuf, u64)>,
+    // The changes which inserted the most bytes into a single file, like a log file which
+    // was recorded while it grew.
+    pub largest_insertions: Vec<Insertion>,
+    pub growth: Vec<GrowthBucket>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Insertion {
+    pub path: PathBuf,
+    pub change_index: usize,
+    pub bytes_added: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct GrowthBucket {
+    pub start: u64,
+    pub change_count: usize,
+    pub bytes_added: usize,
+    pub bytes_removed: usize,
+}
+
+pub fn stats(command_options: ActionOptions, fs: &impl Fs, bucket_seconds: u64) -> Result<Stats> {
+    if bucket_seconds == 0 {
+        bail!("The growth buckets have to span at least a second.");
+    }
+
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    let changes = repository_history.get_changes();
+
+    let mut growth: BTreeMap<u64, GrowthBucket> = BTreeMap::new();
+    for change in changes {
+        let start = change.timestamp - change.timestamp % bucket_seconds;
+        growth
+            .entry(start)
+            .or_insert(GrowthBucket {
+                start,
+                change_count: 0,
+                bytes_added: 0,
+                bytes_removed: 0,
+            })
+            .change_count += 1;
+    }
+
+    let mut ka_paths = Vec::new();
+    collect_files(fs, &locations.ka_path, &mut ka_paths)?;
+
+    let mut sizes = HashMap::new();
+    for path in ka_paths {
+        let mut file = fs.open_readable_file(&path)?;
+        let size = fs.read_from_file(&mut file)?.len() as u64;
+        sizes.insert(path, size);
+    }
+    let history_size = sizes.values().sum();
+    let get_size = |path: &Path| sizes.get(path).copied().unwrap_or_default();
+
+    let mut history_paths = sizes
+        .keys()
+        .filter(|path| path.starts_with(&locations.ka_files_path))
+        .cloned()
+        .collect::<Vec<_>>();
+    history_paths.sort();
+
+    let store = ObjectStore::new(fs, &locations);
+    let mut tracked_file_count = 0;
+    let mut working_size = 0;
+    let mut history_sizes = Vec::new();
+    let mut insertions = Vec::new();
+
+    for history_path in history_paths {
+        let working_path = locations.working_from_history(&history_path)?;
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let stored_history = FileHistory::read_stored(fs, &locations, &mut history_file)?;
+
+        // Forgotten files aren't tracked anymore, though their histories still take up space.
+        if !stored_history.forgotten {
+            tracked_file_count += 1;
+            if fs.path_exists(&working_path) {
+                let mut working_file = fs.open_readable_file(&working_path)?;
+                working_size += fs.read_from_file(&mut working_file)?.len() as u64;
+            }
+        }
+
+        let segments_size = stored_history
+            .get_segments()
+            .iter()
+            .map(|segment| get_size(&locations.ka_path.join(&segment.path)))
+            .sum::<u64>();
+        let objects_size = get_object_ids(&stored_history)
+            .into_iter()
+            .map(|id| get_size(&store.get_object_path(id)))
+            .sum::<u64>();
+        history_sizes.push((
+            working_path.clone(),
+            get_size(&history_path) + segments_size + objects_size,
+        ));
+
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        for file_change in file_history.get_changes() {
+            let timestamp = changes[file_change.change_index - 1].timestamp;
+            let bucket = growth
+                .get_mut(&(timestamp - timestamp % bucket_seconds))
+                .expect("Every change has a bucket.");
+
+            let (added, removed) = count_change_bytes(&file_history, file_change)?;
+            bucket.bytes_added += added;
+            bucket.bytes_removed += removed;
+
+            if added > 0 {
+                insertions.push(Insertion {
+                    path: working_path.clone(),
+                    change_index: file_change.change_index,
+                    bytes_added: added,
+                });
+            }
+        }
+    }
+
+    history_sizes.sort_by(|(a_path, a_size), (b_path, b_size)| {
+        b_size.cmp(a_size).then_with(|| a_path.cmp(b_path))
+    });
+    history_sizes.truncate(LARGEST_HISTORIES_COUNT);
+
+    insertions.sort_by(|a, b| {
+        b.bytes_added
+            .cmp(&a.bytes_added)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.change_index.cmp(&b.change_index))
+    });
+    insertions.truncate(LARGEST_INSERTIONS_COUNT);
+
+    Ok(Stats {
+        change_count: changes.len(),
+        tracked_file_count,
+        history_size,
+        working_size,
+        largest_histories: history_sizes,
+        largest_insertions: insertions,
+        growth: growth.into_values().collect(),
+    })
+}
+
+// The objects the large insertions of the history were moved to, each once.
+fn get_object_ids(stored_history: &FileHistory) -> HashSet<&str> {
+    let mut object_ids = HashSet::new();
+    for file_change in stored_history
+        .get_changes()
+        .iter()
+        .chain(stored_history.get_base())
+    {
+        if let FileChangeVariant::Updated(content_changes) = &file_change.variant {
+            for content_change in content_changes {
+                if let ContentChange::InsertedObject { object, .. } = content_change {
+                    object_ids.insert(object.as_str());
+                }
+            }
+        }
+    }
+    object_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, forget, update, ActionOptions},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        objects::OBJECT_THRESHOLD,
+    };
+
+    use super::{stats, GrowthBucket, Insertion};
+
+    #[test]
+    fn repository_stats() {
+        let day = 60 * 60 * 24;
+        let now = 10 * day;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        write_file(&fs_mock, "./large", &[1; 100]);
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        fs_mock.delete_file(Path::new("./large")).unwrap();
+        update(ActionOptions::from_path("."), &fs_mock, now + day).unwrap();
+
+        let result = stats(ActionOptions::from_path("."), &fs_mock, day).expect("Action failed.");
+
+        assert_eq!(result.change_count, 3);
+        assert_eq!(result.tracked_file_count, 2);
+        assert_eq!(result.working_size, 12);
+        assert!(result.history_size > 100);
+
+        assert_eq!(result.largest_histories.len(), 2);
+        assert_eq!(result.largest_histories[0].0, Path::new("./large"));
+
+        assert_eq!(
+            result.largest_insertions,
+            vec![
+                Insertion {
+                    path: Path::new("./large").to_path_buf(),
+                    change_index: 1,
+                    bytes_added: 100,
+                },
+                Insertion {
+                    path: Path::new("./test").to_path_buf(),
+                    change_index: 2,
+                    bytes_added: 7,
+                },
+                Insertion {
+                    path: Path::new("./test").to_path_buf(),
+                    change_index: 1,
+                    bytes_added: 5,
+                },
+            ]
+        );
+
+        assert_eq!(
+            result.growth,
+            vec![
+                GrowthBucket {
+                    start: now,
+                    change_count: 2,
+                    bytes_added: 112,
+                    bytes_removed: 0,
+                },
+                GrowthBucket {
+                    start: now + day,
+                    change_count: 1,
+                    bytes_added: 0,
+                    bytes_removed: 100,
+                },
+            ]
+        );
+
+        assert!(stats(ActionOptions::from_path("."), &fs_mock, 0).is_err());
+    }
+
+    #[test]
+    fn leave_out_forgotten_files() {
+        let fs_mock = FsMock::new();
+        write_file(&fs_mock, "./test", b"first");
+        write_file(&fs_mock, "./forgotten", b"forgotten");
+        create(ActionOptions::from_path("."), &fs_mock, 0).unwrap();
+        forget(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("forgotten"),
+            false,
+        )
+        .unwrap();
+
+        let result = stats(ActionOptions::from_path("."), &fs_mock, 1).expect("Action failed.");
+        assert_eq!(result.tracked_file_count, 1);
+        assert_eq!(result.working_size, 5);
+        // Its history is still there to be looked at.
+        assert_eq!(result.largest_histories.len(), 2);
+    }
+
+    #[test]
+    fn attribute_objects_to_files() {
+        let fs_mock = FsMock::new();
+        let content = (0..OBJECT_THRESHOLD * 2)
+            .map(|index| (index % 251) as u8)
+            .collect::<Vec<_>>();
+        write_file(&fs_mock, "./huge", &content);
+        write_file(&fs_mock, "./copy", &content);
+        create(ActionOptions::from_path("."), &fs_mock, 0).unwrap();
+
+        let result = stats(ActionOptions::from_path("."), &fs_mock, 1).expect("Action failed.");
+
+        // The one object counts for both files, but only once for the repository.
+        for (_, size) in result.largest_histories.iter() {
+            assert!(*size > content.len() as u64);
+        }
+        assert!(result.history_size < 2 * content.len() as u64);
+    }
+}
@@ -0,0 +1,296 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    cache::StatCache,
+    config::Config,
+    consistency::Inconsistency,
+    files::{FileState, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+    images::ImageChange,
+    line_endings,
+    policy::{get_content_type, get_file_policy, ContentType, FilePolicy, SkipReason},
+};
+
+use super::ActionOptions;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileStatus {
+    Added,
+    Modified,
+    Deleted,
+    Skipped(SkipReason),
+}
+
+#[derive(Debug)]
+pub struct Status {
+    pub cursor: usize,
+    // Only files which would be affected by an update, or are skipped by it, are listed.
+    pub files: Vec<(PathBuf, FileStatus)>,
+    // The content types an update would record for the added and modified files.
+    pub content_types: BTreeMap<PathBuf, ContentType>,
+    // How the added and modified images differ from what was recorded.
+    pub images: BTreeMap<PathBuf, ImageChange>,
+    // Only checked if the config asks for it.
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+pub fn status(command_options: ActionOptions, fs: &impl Fs) -> Result<Status> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let config = Config::load(fs, &locations)?;
+
+    let (repository_history, inconsistencies) = RepositoryHistory::from_file_checked(
+        fs,
+        &locations,
+        &mut repository_index_file,
+        config.consistency,
+    )?;
+    let cursor = repository_history.cursor;
+
+    let entries = locations
+        .get_repository_files(fs)
+        .context("Could not traverse files.")?;
+
+    let mut files = Vec::new();
+    let mut content_types = BTreeMap::new();
+    let mut images = BTreeMap::new();
+    let mut working_paths = Vec::new();
+
+    for state in entries {
+        let working_path = state.get_working_path(&locations)?;
+        working_paths.push(working_path.clone());
+        // Files outside of the tracked directories aren't read, as there could be a lot of them.
+        let file_status = if !config.is_in_scope(&locations, &working_path) {
+            match state {
+                FileState::Untracked(_) => {
+                    Some((FileStatus::Skipped(SkipReason::OutsideScope), None, None))
+                }
+                FileState::Deleted(_) | FileState::Tracked(_) => None,
+            }
+        } else {
+            let file_cursor = repository_history.get_file_cursor(&working_path);
+            get_file_status(fs, &locations, &config, file_cursor, &state)?
+        };
+        if let Some((file_status, content_type, image_change)) = file_status {
+            if let Some(content_type) = content_type {
+                content_types.insert(working_path.clone(), content_type);
+            }
+            if let Some(image_change) = image_change {
+                images.insert(working_path.clone(), image_change);
+            }
+            files.push((working_path, file_status));
+        }
+    }
+
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    // Only for `summary`, so a repository which can't be written to still has a status.
+    if let Some(index_length) = repository_history.get_stored_length() {
+        let dirty_count = count_dirty_files(&files);
+        StatCache::new(fs, &locations)
+            .store(index_length, dirty_count, &working_paths)
+            .ok();
+    }
+
+    Ok(Status {
+        cursor,
+        files,
+        content_types,
+        images,
+        inconsistencies,
+    })
+}
+
+// The files an update would record changes of, leaving out those it skips.
+pub(super) fn count_dirty_files(files: &[(PathBuf, FileStatus)]) -> usize {
+    files
+        .iter()
+        .filter(|(_, file_status)| !matches!(file_status, FileStatus::Skipped(_)))
+        .count()
+}
+
+// What `get_file_status` found out about a file which would be affected by an update.
+type FileStatusEntry = (FileStatus, Option<ContentType>, Option<ImageChange>);
+
+fn get_file_status<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    config: &Config,
+    cursor: usize,
+    file_state: &FileState,
+) -> Result<Option<FileStatusEntry>> {
+    match file_state {
+        FileState::Deleted(deleted) => {
+            let mut history_file = fs.open_readable_file(&deleted.history_path)?;
+            let file_history = FileHistory::from_file(fs, locations, &mut history_file)?;
+            Ok(
+                if file_history.does_file_exist(cursor) && !file_history.forgotten {
+                    Some((FileStatus::Deleted, None, None))
+                } else {
+                    None
+                },
+            )
+        }
+        FileState::Untracked(untracked) => {
+            let mut file = untracked.load_file(fs)?;
+            let content = fs.read_from_file(&mut file)?;
+            Ok(Some(
+                match get_file_policy(config, &untracked.path, &content) {
+                    FilePolicy::Skip(reason) => (FileStatus::Skipped(reason), None, None),
+                    FilePolicy::Track(_) => {
+                        let content_type = get_content_type(config, &untracked.path, &content);
+                        (
+                            FileStatus::Added,
+                            Some(content_type),
+                            compare_images(config, content_type, &[], &content),
+                        )
+                    }
+                },
+            ))
+        }
+        FileState::Tracked(tracked) => {
+            let mut history_file = fs.open_readable_file(&tracked.history_path)?;
+            let file_history = FileHistory::from_file(fs, locations, &mut history_file)?;
+            if file_history.forgotten {
+                return Ok(None);
+            }
+
+            let mut working_file = tracked.load_working_file(fs)?;
+            let content = line_endings::normalize(
+                config,
+                &tracked.working_path,
+                fs.read_from_file(&mut working_file)?,
+            );
+
+            if let FilePolicy::Skip(reason) =
+                get_file_policy(config, &tracked.working_path, &content)
+            {
+                return Ok(Some((FileStatus::Skipped(reason), None, None)));
+            }
+
+            let (file_status, old_content) = if !file_history.does_file_exist(cursor) {
+                (FileStatus::Added, Vec::new())
+            } else {
+                let old_content = file_history.get_content(cursor)?;
+                if old_content == content {
+                    return Ok(None);
+                }
+                (FileStatus::Modified, old_content)
+            };
+            let content_type = get_content_type(config, &tracked.working_path, &content);
+            let image_change = compare_images(config, content_type, &old_content, &content);
+            Ok(Some((file_status, Some(content_type), image_change)))
+        }
+    }
+}
+
+// Only binary files can be images.
+fn compare_images(
+    config: &Config,
+    content_type: ContentType,
+    old_content: &[u8],
+    new_content: &[u8],
+) -> Option<ImageChange> {
+    if content_type != ContentType::Binary {
+        return None;
+    }
+    ImageChange::compare(old_content, new_content, config.perceptual_hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        config::Config,
+        files::Locations,
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        policy::SkipReason,
+    };
+
+    use super::{status, FileStatus};
+
+    #[test]
+    fn status_with_skipped_files() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./modified", b"one");
+        write_file(&fs_mock, "./deleted", b"two");
+        write_file(&fs_mock, "./unchanged", b"three");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        let config = Config {
+            max_file_size: Some(8),
+            ..Config::default()
+        };
+        config
+            .write(&fs_mock, &Locations::from(&ActionOptions::from_path(".")))
+            .unwrap();
+
+        write_file(&fs_mock, "./modified", b"one!");
+        write_file(&fs_mock, "./added", b"four");
+        write_file(&fs_mock, "./large", b"too large to track");
+        fs_mock.delete_file(Path::new("./deleted")).unwrap();
+
+        let result = status(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+        assert_eq!(result.cursor, 1);
+        assert_eq!(
+            result.files,
+            vec![
+                (Path::new("./added").into(), FileStatus::Added),
+                (Path::new("./deleted").into(), FileStatus::Deleted),
+                (
+                    Path::new("./large").into(),
+                    FileStatus::Skipped(SkipReason::TooLarge {
+                        size: 18,
+                        max_file_size: 8
+                    })
+                ),
+                (Path::new("./modified").into(), FileStatus::Modified),
+            ]
+        );
+
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        // After updating, only the skipped file is left.
+        let result = status(ActionOptions::from_path("."), &fs_mock).expect("Action failed.");
+        assert_eq!(result.files.len(), 1);
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/large")));
+    }
+
+    #[test]
+    fn stop_at_max_depth() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+
+        write_file(&fs_mock, "./one/two/three/deep", b"deep");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        let config = Config {
+            max_depth: Some(2),
+            ..Config::default()
+        };
+        config.write(&fs_mock, &locations).unwrap();
+
+        let error = status(ActionOptions::from_path("."), &fs_mock).unwrap_err();
+        assert!(format!("{:#}", error).contains("./one/two/three"));
+
+        let config = Config {
+            max_depth: Some(3),
+            ..Config::default()
+        };
+        config.write(&fs_mock, &locations).unwrap();
+        assert!(status(ActionOptions::from_path("."), &fs_mock).is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{Context, Result};
+
+use crate::{cache::StatCache, files::Locations, filesystem::Fs, history::RepositoryHistory};
+
+use super::{status, status::count_dirty_files, ActionOptions};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub cursor: usize,
+    // How many files an update would record changes of.
+    pub dirty_count: usize,
+    // When the latest change was recorded, if there is one.
+    pub last_update: Option<u64>,
+}
+
+// The gist of `status`, quick enough to run for every shell prompt. Only the index is read,
+// without the segments it lists, and the count of dirty files is taken from the stat cache
+// `status` keeps. Only if something changed since is the full status found again, which
+// brings the cache up to date for the next time.
+pub fn summary(command_options: ActionOptions, fs: &impl Fs) -> Result<Summary> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let buffer = fs
+        .read_from_file(&mut repository_index_file)
+        .context("Failed reading repository history.")?;
+    let repository_history = RepositoryHistory::decode(&buffer)?;
+
+    let cached_count = StatCache::new(fs, &locations).get_dirty_count(buffer.len());
+    let dirty_count = match cached_count {
+        Some(dirty_count) => dirty_count,
+        None => count_dirty_files(&status(command_options, fs)?.files),
+    };
+
+    Ok(Summary {
+        cursor: repository_history.cursor,
+        dirty_count,
+        last_update: repository_history.get_latest_timestamp(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        cache::StatCache,
+        files::Locations,
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{summary, Summary};
+
+    #[test]
+    fn summarize_from_stat_cache() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let locations = Locations::from(&options);
+        let get_cached_count = || {
+            let mut index_file = fs_mock
+                .open_readable_file(&locations.get_repository_index_path())
+                .unwrap();
+            let index_length = fs_mock.read_from_file(&mut index_file).unwrap().len();
+            StatCache::new(&fs_mock, &locations).get_dirty_count(index_length)
+        };
+
+        write_file(&fs_mock, "./test", b"a");
+        create(options.clone(), &fs_mock, now).unwrap();
+        assert_eq!(get_cached_count(), None);
+        assert_eq!(
+            summary(options.clone(), &fs_mock).unwrap(),
+            Summary {
+                cursor: 1,
+                dirty_count: 0,
+                last_update: Some(now),
+            }
+        );
+        assert_eq!(get_cached_count(), Some(0));
+
+        // Both changed files and new ones make the cache stale.
+        write_file(&fs_mock, "./test", b"ab");
+        assert_eq!(get_cached_count(), None);
+        assert_eq!(summary(options.clone(), &fs_mock).unwrap().dirty_count, 1);
+        write_file(&fs_mock, "./folder/new", b"c");
+        assert_eq!(get_cached_count(), None);
+        assert_eq!(summary(options.clone(), &fs_mock).unwrap().dirty_count, 2);
+        assert_eq!(get_cached_count(), Some(2));
+
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        assert_eq!(get_cached_count(), None);
+        assert_eq!(
+            summary(options, &fs_mock).unwrap(),
+            Summary {
+                cursor: 2,
+                dirty_count: 0,
+                last_update: Some(now + 1),
+            }
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    files::Locations,
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+    journal,
+};
+
+use super::ActionOptions;
+
+// Brings every file which is at its own cursor, like pinned files after a shift, back to the
+// cursor of the repository. Pinned files stay pinned. Returns the paths of the synced files.
+pub fn sync(command_options: ActionOptions, fs: &impl Fs, force: bool) -> Result<Vec<PathBuf>> {
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_writable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+
+    if repository_history.file_cursors.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let cursor = repository_history.cursor;
+    let mut synced_files = Vec::new();
+    let mut dirty_files = Vec::new();
+
+    for (working_path, &file_cursor) in repository_history.file_cursors.iter() {
+        let history_path = locations.history_from_working(working_path)?;
+        if !fs.path_exists(&history_path) {
+            continue;
+        }
+
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+
+        let working_content = if fs.path_exists(working_path) {
+            let mut working_file = fs.open_readable_file(working_path)?;
+            Some(fs.read_from_file(&mut working_file)?)
+        } else {
+            None
+        };
+        let file_content = if file_history.does_file_exist(file_cursor) {
+            Some(file_history.get_content(file_cursor)?)
+        } else {
+            None
+        };
+        if working_content != file_content {
+            dirty_files.push(
+                command_options
+                    .display_path(working_path)
+                    .display()
+                    .to_string(),
+            );
+        }
+
+        let new_content = if file_history.does_file_exist(cursor) {
+            Some(file_history.get_content(cursor)?)
+        } else {
+            None
+        };
+        synced_files.push((working_path.clone(), new_content));
+    }
+
+    if !dirty_files.is_empty() && !force {
+        bail!(
+            "Syncing would overwrite unrecorded changes to:\n{}\nShift to their change to record them, or sync with force.",
+            dirty_files.join("\n")
+        );
+    }
+
+    repository_history.file_cursors.clear();
+    if repository_history.get_stored_length().is_some() {
+        fs.append_to_file(
+            &mut repository_index_file,
+            repository_history.encode_file_cursors()?,
+        )?;
+    } else {
+        repository_history.write_to_file(fs, &locations, &mut repository_index_file)?;
+    }
+
+    let mut synced_paths = Vec::new();
+    for (working_path, new_content) in synced_files {
+        match new_content {
+            Some(new_content) => {
+                let mut working_file = fs.create_file(&working_path)?;
+                fs.write_to_file(&mut working_file, new_content)?;
+            }
+            None if fs.path_exists(&working_path) => fs.delete_file(&working_path)?,
+            None => (),
+        }
+        synced_paths.push(working_path);
+    }
+
+    Ok(synced_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{
+            create, pin, shift, status, unpin, update, ActionOptions, ShiftMode, UntrackedFiles,
+        },
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::RepositoryHistory,
+    };
+
+    use super::sync;
+
+    fn read_index(fs: &FsMock) -> RepositoryHistory {
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let mut file = fs
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        RepositoryHistory::from_file(fs, &locations, &mut file).unwrap()
+    }
+
+    fn shift_to(fs: &FsMock, cursor: usize) {
+        shift(
+            ActionOptions::from_path("."),
+            fs,
+            cursor,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn sync_files_at_their_own_cursor() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./source", b"first");
+        write_file(&fs_mock, "./generated", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./source", b"second");
+        write_file(&fs_mock, "./generated", b"second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        pin(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("generated"),
+        )
+        .unwrap();
+        shift_to(&fs_mock, 1);
+        assert_eq!(
+            read_index(&fs_mock).get_file_cursor(Path::new("./generated")),
+            2
+        );
+
+        // The pinned file is at its own cursor, so it isn't modified.
+        let result = status(ActionOptions::from_path("."), &fs_mock).unwrap();
+        assert!(result.files.is_empty());
+
+        // Its changes can't be recorded on top of an older change.
+        write_file(&fs_mock, "./generated", b"third");
+        let summary = update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+        assert_eq!(summary.skipped.len(), 1);
+        assert!(sync(ActionOptions::from_path("."), &fs_mock, false).is_err());
+
+        // Unpinned files at their own cursor follow the next shift from there.
+        write_file(&fs_mock, "./generated", b"second");
+        unpin(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("generated"),
+        )
+        .unwrap();
+        shift_to(&fs_mock, 0);
+        assert!(!fs_mock.path_exists(Path::new("./generated")));
+        assert!(read_index(&fs_mock).file_cursors.is_empty());
+
+        shift_to(&fs_mock, 2);
+        pin(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("generated"),
+        )
+        .unwrap();
+        shift_to(&fs_mock, 1);
+        assert_eq!(read_file(&fs_mock, "./generated"), b"second");
+
+        let synced = sync(ActionOptions::from_path("."), &fs_mock, false).unwrap();
+        assert_eq!(synced, vec![Path::new("./generated")]);
+        assert_eq!(read_file(&fs_mock, "./generated"), b"first");
+        assert!(read_index(&fs_mock).file_cursors.is_empty());
+        assert!(read_index(&fs_mock).is_pinned(Path::new("./generated")));
+    }
+}
@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    files::Locations,
+    filesystem::Fs,
+    history::{FileChangeVariant, FileHistory, RepositoryHistory},
+};
+
+use super::{log::count_change_bytes, ActionOptions};
+
+// The whole history in one document, for drawing timelines of it elsewhere. Sizes are in
+// bytes, and follow from the recorded changes without replaying any content.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Timeline {
+    pub changes: Vec<TimelineChange>,
+    pub files: Vec<FileTimeline>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct TimelineChange {
+    pub change_index: usize,
+    pub change_id: Option<String>,
+    pub timestamp: u64,
+    pub message: Option<String>,
+    pub author: Option<String>,
+    pub file_count: usize,
+    pub bytes_added: usize,
+    pub bytes_removed: usize,
+    // The size of all files which exist after the change.
+    pub total_size: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FileTimeline {
+    // Relative to the repository.
+    pub path: PathBuf,
+    pub activity: Vec<FileActivity>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FileActivity {
+    pub change_index: usize,
+    pub timestamp: u64,
+    pub bytes_added: usize,
+    pub bytes_removed: usize,
+    // What the file has after the change, nothing once it's deleted.
+    pub size: Option<usize>,
+}
+
+impl Timeline {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self).context("Failed encoding timeline.")
+    }
+}
+
+pub fn export_timeline(command_options: ActionOptions, fs: &impl Fs) -> Result<Timeline> {
+    let locations = Locations::from(&command_options);
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    let changes = repository_history.get_changes();
+
+    let mut timeline_changes: Vec<_> = changes
+        .iter()
+        .enumerate()
+        .map(|(index, change)| TimelineChange {
+            change_index: index + 1,
+            change_id: change.id.clone(),
+            timestamp: change.timestamp,
+            message: change.message.clone(),
+            author: change.author.clone(),
+            file_count: change.affected_files.len(),
+            bytes_added: 0,
+            bytes_removed: 0,
+            total_size: 0,
+        })
+        .collect();
+    // How much the size of all files changed with each change, summed up once all are known.
+    let mut size_deltas = vec![0isize; changes.len()];
+
+    let mut working_paths: Vec<_> = repository_history.get_tracked_paths().into_iter().collect();
+    working_paths.sort();
+
+    let mut files = Vec::new();
+    for working_path in working_paths {
+        let history_path = locations.history_from_working(working_path)?;
+        // Purged files are still listed by the changes they were part of.
+        if !fs.path_exists(&history_path) {
+            continue;
+        }
+
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        let mut size = 0;
+        let mut activity = Vec::new();
+        for file_change in file_history.get_changes() {
+            let (bytes_added, bytes_removed) = count_change_bytes(&file_history, file_change)?;
+            let new_size = match file_change.variant {
+                FileChangeVariant::Deleted => None,
+                _ => Some(size + bytes_added - bytes_removed),
+            };
+
+            let index = file_change.change_index - 1;
+            if let Some(timeline_change) = timeline_changes.get_mut(index) {
+                timeline_change.bytes_added += bytes_added;
+                timeline_change.bytes_removed += bytes_removed;
+                size_deltas[index] += new_size.unwrap_or_default() as isize - size as isize;
+            }
+            activity.push(FileActivity {
+                change_index: file_change.change_index,
+                timestamp: changes
+                    .get(index)
+                    .map(|change| change.timestamp)
+                    .unwrap_or_default(),
+                bytes_added,
+                bytes_removed,
+                size: new_size,
+            });
+            size = new_size.unwrap_or_default();
+        }
+
+        files.push(FileTimeline {
+            path: working_path
+                .strip_prefix(&locations.repository_path)?
+                .to_path_buf(),
+            activity,
+        });
+    }
+
+    let mut total_size = 0isize;
+    for (timeline_change, size_delta) in timeline_changes.iter_mut().zip(size_deltas) {
+        total_size += size_delta;
+        timeline_change.total_size = total_size as usize;
+    }
+
+    Ok(Timeline {
+        changes: timeline_changes,
+        files,
+    })
+}
+
+// A small SVG of how the size of the file developed over the `change_count` changes of the
+// repository, from the first to the last.
+pub fn render_sparkline(
+    file: &FileTimeline,
+    change_count: usize,
+    width: u32,
+    height: u32,
+) -> String {
+    let max_size = file
+        .activity
+        .iter()
+        .filter_map(|activity| activity.size)
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    let get_x = |change_index: usize| {
+        if change_count <= 1 {
+            return 0.0;
+        }
+        (change_index - 1) as f64 * f64::from(width) / (change_count - 1) as f64
+    };
+    let get_y = |size: usize| f64::from(height) * (1.0 - size as f64 / max_size as f64);
+
+    // The size only changes with the changes of the file, and holds in between.
+    let mut points = Vec::new();
+    let mut size = 0;
+    for activity in file.activity.iter() {
+        let x = get_x(activity.change_index);
+        if !points.is_empty() {
+            points.push((x, get_y(size)));
+        }
+        size = activity.size.unwrap_or_default();
+        points.push((x, get_y(size)));
+    }
+    if let Some(&(x, y)) = points.last() {
+        if x < f64::from(width) {
+            points.push((f64::from(width), y));
+        }
+    }
+    let points = points
+        .iter()
+        .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\"><polyline fill=\"none\" stroke=\"currentColor\" points=\"{points}\"/></svg>\n",
+        width = width,
+        height = height,
+        points = points
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{export_timeline, render_sparkline, FileActivity};
+
+    #[test]
+    fn export_sizes_over_time() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        write_file(&fs_mock, "./notes", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./notes", b"first second");
+        write_file(&fs_mock, "./todo", b"milk");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        fs_mock.delete_file(Path::new("./notes")).unwrap();
+        update(options.clone(), &fs_mock, now + 2).unwrap();
+
+        let timeline = export_timeline(options, &fs_mock).unwrap();
+        let total_sizes: Vec<_> = timeline
+            .changes
+            .iter()
+            .map(|change| (change.file_count, change.total_size))
+            .collect();
+        assert_eq!(total_sizes, [(1, 5), (2, 16), (1, 4)]);
+
+        assert_eq!(timeline.files.len(), 2);
+        assert_eq!(timeline.files[0].path, PathBuf::from("notes"));
+        assert_eq!(
+            timeline.files[0].activity[2],
+            FileActivity {
+                change_index: 3,
+                timestamp: now + 2,
+                bytes_added: 0,
+                bytes_removed: 12,
+                size: None,
+            }
+        );
+        let sizes: Vec<_> = timeline.files[0]
+            .activity
+            .iter()
+            .map(|activity| activity.size)
+            .collect();
+        assert_eq!(sizes, [Some(5), Some(12), None]);
+
+        let sparkline = render_sparkline(&timeline.files[0], 3, 100, 10);
+        assert!(sparkline.contains("points=\"0.0,5.8 50.0,5.8 50.0,0.0 100.0,0.0 100.0,10.0\""));
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    files::{normalize_path, Locations},
+    filesystem::Fs,
+};
+
+use super::ActionOptions;
+
+// Adds a directory to the ones which are recorded. Once there's one, everything outside of the
+// tracked directories is left alone by updates. Returns whether the config changed.
+pub fn track(command_options: ActionOptions, fs: &impl Fs, path: &Path) -> Result<bool> {
+    let locations = Locations::from(&command_options);
+    let tracked_path = get_tracked_path(&locations, path)?;
+
+    let mut config = Config::load(fs, &locations)?;
+    if config.tracked_paths.contains(&tracked_path) {
+        return Ok(false);
+    }
+
+    config.tracked_paths.push(tracked_path);
+    config.tracked_paths.sort();
+    config.write(fs, &locations)?;
+
+    Ok(true)
+}
+
+// Removes a directory from the tracked ones. Without any left, the whole repository is
+// recorded again. Returns whether the config changed.
+pub fn untrack(command_options: ActionOptions, fs: &impl Fs, path: &Path) -> Result<bool> {
+    let locations = Locations::from(&command_options);
+    let tracked_path = get_tracked_path(&locations, path)?;
+
+    let mut config = Config::load(fs, &locations)?;
+    let tracked_count = config.tracked_paths.len();
+    config
+        .tracked_paths
+        .retain(|existing_path| *existing_path != tracked_path);
+    if config.tracked_paths.len() == tracked_count {
+        return Ok(false);
+    }
+
+    config.write(fs, &locations)?;
+
+    Ok(true)
+}
+
+fn get_tracked_path(locations: &Locations, path: &Path) -> Result<PathBuf> {
+    // Checks that the path is inside of the repository, and not in `.ka`.
+    locations.get_working_path(path)?;
+    Ok(normalize_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, status, update, ActionOptions, FileStatus},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        policy::SkipReason,
+    };
+
+    use super::{track, untrack};
+
+    #[test]
+    fn only_record_tracked_directories() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        assert!(track(ActionOptions::from_path("."), &fs_mock, Path::new("docs/")).unwrap());
+        assert!(!track(ActionOptions::from_path("."), &fs_mock, Path::new("./docs")).unwrap());
+        assert!(track(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            Path::new("../docs")
+        )
+        .is_err());
+
+        write_file(&fs_mock, "./docs/guide", b"guide");
+        write_file(&fs_mock, "./build/output", b"output");
+
+        let result = status(ActionOptions::from_path("."), &fs_mock).unwrap();
+        assert_eq!(
+            result.files,
+            vec![
+                (
+                    Path::new("./build/output").into(),
+                    FileStatus::Skipped(SkipReason::OutsideScope)
+                ),
+                (Path::new("./docs/guide").into(), FileStatus::Added),
+            ]
+        );
+
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/docs/guide")));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/build/output")));
+
+        assert!(untrack(ActionOptions::from_path("."), &fs_mock, Path::new("docs")).unwrap());
+        assert!(!untrack(ActionOptions::from_path("."), &fs_mock, Path::new("docs")).unwrap());
+
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/build/output")));
+    }
+}
@@ -0,0 +1,753 @@
+use std::{
+    collections::{btree_map, BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    config::Config,
+    diff::ContentChange,
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileChange, FileChangeVariant, FileHistory, RepositoryChange, RepositoryHistory},
+    journal::{self, Journal},
+    merge::{MergeDrivers, MergeInput},
+    metrics::ActionTimer,
+    policy::get_content_type,
+    protocol::{negotiate_version, receive_message, send_message, Message, Transport},
+    trace::{self, Level},
+};
+
+use super::{shift, shift::get_dirty_files, ActionOptions, ShiftMode, UntrackedFiles};
+
+#[derive(Debug, Default)]
+pub struct PullSummary {
+    // How many changes were received from the other side.
+    pub received: usize,
+    // How many changes of this repository, which the other side didn't have, were recorded
+    // again on top of the received ones.
+    pub rebased: usize,
+    // Files whose rebased changes overlap with received ones.
+    pub conflicts: Vec<PullConflict>,
+}
+
+// Both sides edited the same part of a file. The rebased change keeps the content this side
+// recorded, while the other side's edit is still in the history, at the received change.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PullConflict {
+    pub working_path: PathBuf,
+    pub cursor: usize,
+}
+
+// The history of a file as this side recorded it, and as it's rebuilt on top of the
+// received changes.
+struct PulledFile {
+    original: FileHistory,
+    rebased: FileHistory,
+}
+
+// Sends the other side the changes it's missing, once it advertised the ones it has. Returns
+// how many changes were sent.
+pub fn push(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    transport: &mut impl Transport,
+) -> Result<usize> {
+    let _timer = ActionTimer::start("push");
+    let _span = trace::span(
+        Level::Info,
+        "push",
+        &[("repository", &command_options.repository_path.display())],
+    );
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+    let version = negotiate_version(transport)?;
+
+    let (change_count, latest_change_id, change_ids, files) = match receive_message(transport)? {
+        Message::Advertise {
+            change_count,
+            latest_change_id,
+            change_ids,
+            files,
+        } => (change_count, latest_change_id, change_ids, files),
+        message => bail!(
+            "Expected the other side to advertise its changes, got '{}'.",
+            message.get_name()
+        ),
+    };
+
+    let mut repository_index_file =
+        fs.open_readable_file(&locations.get_repository_index_path())?;
+    let repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    let changes = repository_history.get_changes();
+
+    // Before version 2, the other side can only take changes on top of all of its own.
+    let base = if version >= 2 {
+        changes
+            .iter()
+            .zip(change_ids.iter())
+            .take_while(|(change, change_id)| change.id == **change_id)
+            .count()
+    } else {
+        change_count
+    };
+    let refusal = if base > changes.len() {
+        Some(format!(
+            "The other side has {} changes, but this one only {}.",
+            change_count,
+            changes.len()
+        ))
+    } else if version < 2
+        && repository_history.get_change_id(change_count) != latest_change_id.as_deref()
+    {
+        Some(format!(
+            "The histories of both sides diverged at change {}.",
+            change_count
+        ))
+    } else {
+        None
+    };
+    if let Some(reason) = refusal {
+        send_message(
+            transport,
+            &Message::Refused {
+                reason: reason.clone(),
+            },
+        )?;
+        bail!("Refused sending changes: {}", reason);
+    }
+
+    let mut repository_changes = Vec::new();
+    let mut affected_paths = HashSet::new();
+    for change in changes[base..].iter() {
+        let mut change = change.clone();
+        for working_path in change.affected_files.iter_mut() {
+            *working_path = working_path
+                .strip_prefix(&locations.repository_path)?
+                .to_path_buf();
+            affected_paths.insert(working_path.clone());
+        }
+        repository_changes.push(change);
+    }
+
+    // Only the files affected by the missing changes can have changes the other side misses.
+    // Its changes after the common ones are its own, whatever their index.
+    let mut file_changes = BTreeMap::new();
+    for path in affected_paths {
+        let history_path = locations.ka_files_path.join(&path);
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::from_file(fs, &locations, &mut history_file)?;
+        let latest_change_index = files.get(&path).copied().unwrap_or(0).min(base);
+        let missing_changes: Vec<_> = file_history
+            .get_changes()
+            .iter()
+            .filter(|file_change| file_change.change_index > latest_change_index)
+            .cloned()
+            .collect();
+        if !missing_changes.is_empty() {
+            file_changes.insert(path, missing_changes);
+        }
+    }
+
+    let sent_count = repository_changes.len();
+    send_message(
+        transport,
+        &Message::Changes {
+            base: Some(base),
+            repository_changes,
+            file_changes,
+        },
+    )?;
+    trace::event(
+        Level::Info,
+        "Sent the changes.",
+        &[("changes", &sent_count)],
+    );
+
+    Ok(sent_count)
+}
+
+// Receives the changes this repository is missing from the other side. If the repository was
+// at its latest change, it's shifted to the new latest one.
+//
+// Changes of this repository which the other side doesn't have are recorded again on top of
+// the received ones, merged with the merge drivers of the config. Edits the driver can't
+// merge keep this side's content, and are reported as conflicts.
+pub fn pull(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    transport: &mut impl Transport,
+) -> Result<PullSummary> {
+    let config = Config::load_from(fs, &command_options)?;
+    pull_with_drivers(
+        command_options,
+        fs,
+        transport,
+        &MergeDrivers::from_config(&config),
+    )
+}
+
+// Like `pull`, but with merge drivers the caller might have registered drivers of its own with.
+pub fn pull_with_drivers(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    transport: &mut impl Transport,
+    merge_drivers: &MergeDrivers,
+) -> Result<PullSummary> {
+    let _timer = ActionTimer::start("pull");
+    let _span = trace::span(
+        Level::Info,
+        "pull",
+        &[("repository", &command_options.repository_path.display())],
+    );
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+    let version = negotiate_version(transport)?;
+    let config = Config::load(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let mut repository_history =
+        RepositoryHistory::from_file(fs, &locations, &mut repository_index_file)?;
+    let change_count = repository_history.get_changes().len();
+
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+    let mut files = BTreeMap::new();
+    for history_path in history_paths {
+        // Only the change indices are needed, so the content isn't resolved.
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let file_history = FileHistory::decode(&fs.read_from_file(&mut history_file)?)?;
+        if let Some(file_change) = file_history.get_changes().last() {
+            let path = history_path.strip_prefix(&locations.ka_files_path)?;
+            files.insert(path.to_path_buf(), file_change.change_index);
+        }
+    }
+
+    let change_ids = if version >= 2 {
+        repository_history
+            .get_changes()
+            .iter()
+            .map(|change| change.id.clone())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    send_message(
+        transport,
+        &Message::Advertise {
+            change_count,
+            latest_change_id: repository_history
+                .get_change_id(change_count)
+                .map(str::to_string),
+            change_ids,
+            files,
+        },
+    )?;
+
+    let (base, repository_changes, file_changes) = match receive_message(transport)? {
+        Message::Changes {
+            base,
+            repository_changes,
+            file_changes,
+        } => (
+            base.unwrap_or(change_count),
+            repository_changes,
+            file_changes,
+        ),
+        Message::Refused { reason } => bail!("The other side refused sending changes: {}", reason),
+        message => bail!(
+            "Expected the other side to send changes, got '{}'.",
+            message.get_name()
+        ),
+    };
+
+    let mut summary = PullSummary {
+        received: repository_changes.len(),
+        ..PullSummary::default()
+    };
+    if repository_changes.is_empty() {
+        return Ok(summary);
+    }
+    if base > change_count {
+        bail!(
+            "The other side sent the changes after change {}, but this repository only has {}.",
+            base,
+            change_count
+        );
+    }
+
+    let is_diverged = base < change_count;
+    if is_diverged {
+        if repository_history.cursor != change_count {
+            bail!(
+                "The histories of both sides diverged, which can only be pulled while the cursor is at the latest change {}.",
+                change_count
+            );
+        }
+        if !repository_history.file_cursors.is_empty() {
+            bail!("The histories of both sides diverged, sync the files which are at their own cursors before pulling.");
+        }
+        let dirty_files = get_dirty_files(command_options.clone(), fs)?;
+        if !dirty_files.is_empty() {
+            bail!(
+                "Pulling would overwrite unrecorded changes to:\n{}\nUpdate first.",
+                dirty_files.join("\n")
+            );
+        }
+    }
+
+    let mut pulled_files = BTreeMap::new();
+    let received_count = repository_changes.len();
+    for (path, missing_changes) in file_changes {
+        // The paths come from the other side, which mustn't reach outside of the repository.
+        let working_path = locations.get_working_path(&path)?;
+        let pulled_file = get_pulled_file(fs, &locations, &mut pulled_files, &working_path, base)?;
+        for file_change in missing_changes {
+            if file_change.change_index <= base || file_change.change_index > base + received_count
+            {
+                bail!(
+                    "The other side sent a change to '{}' which isn't part of the changes it sent.",
+                    path.display()
+                );
+            }
+            pulled_file.rebased.add_change(file_change);
+        }
+    }
+
+    let local_changes = repository_history.get_changes_mut().split_off(base);
+    for mut change in repository_changes {
+        for path in change.affected_files.iter_mut() {
+            *path = locations.get_working_path(path)?;
+        }
+        repository_history.add_change(change);
+    }
+
+    // The cursor each of the local changes ended up at, as some might be left out because
+    // the other side made the same change.
+    let mut rebased_cursors = Vec::new();
+    for (offset, local_change) in local_changes.into_iter().enumerate() {
+        let original_cursor = base + offset + 1;
+        let cursor = repository_history.get_changes().len() + 1;
+
+        let mut rebased_changes = Vec::new();
+        for working_path in local_change.affected_files.iter() {
+            let pulled_file =
+                get_pulled_file(fs, &locations, &mut pulled_files, working_path, base)?;
+            let before = get_file_content(&pulled_file.original, original_cursor - 1)?;
+            let after = get_file_content(&pulled_file.original, original_cursor)?;
+            let current = get_file_content(&pulled_file.rebased, cursor - 1)?;
+
+            // Either side might have left the file as it was, which needs no merging.
+            let merged = if current == before || current == after {
+                Some(after.clone())
+            } else {
+                let theirs_timestamp = pulled_file
+                    .rebased
+                    .get_changes()
+                    .last()
+                    .and_then(|file_change| {
+                        repository_history
+                            .get_changes()
+                            .get(file_change.change_index - 1)
+                    })
+                    .map_or(0, |change| change.timestamp);
+                merge_drivers.get_driver(working_path)?.merge(&MergeInput {
+                    path: working_path,
+                    base: before.as_deref(),
+                    ours: after.as_deref(),
+                    ours_timestamp: local_change.timestamp,
+                    theirs: current.as_deref(),
+                    theirs_timestamp,
+                })
+            };
+            let content = merged.unwrap_or_else(|| {
+                summary.conflicts.push(PullConflict {
+                    working_path: working_path.clone(),
+                    cursor,
+                });
+                after.clone()
+            });
+            if content == current {
+                continue;
+            }
+
+            let variant = match content {
+                Some(ref content) => FileChangeVariant::Updated(ContentChange::diff(
+                    current.as_deref().unwrap_or_default(),
+                    content,
+                )),
+                None => FileChangeVariant::Deleted,
+            };
+            let file_change = FileChange {
+                change_index: cursor,
+                variant,
+                degraded: false,
+                content_hash: content.as_deref().map(FileChange::hash_content),
+                change_id: None,
+                content_type: content
+                    .as_deref()
+                    .map(|content| get_content_type(&config, working_path, content)),
+            };
+            rebased_changes.push((working_path.clone(), file_change));
+        }
+
+        if !rebased_changes.is_empty() {
+            let file_changes: Vec<_> = rebased_changes
+                .iter()
+                .map(|(working_path, file_change)| (working_path.as_path(), file_change))
+                .collect();
+            let change_id = RepositoryChange::compute_id(
+                repository_history.get_change_id(cursor - 1),
+                local_change.timestamp,
+                &file_changes,
+            );
+
+            let mut affected_files = Vec::new();
+            for (working_path, mut file_change) in rebased_changes {
+                file_change.change_id = Some(change_id.clone());
+                let pulled_file =
+                    get_pulled_file(fs, &locations, &mut pulled_files, &working_path, base)?;
+                pulled_file.rebased.add_change(file_change);
+                affected_files.push(working_path);
+            }
+            repository_history.add_change(RepositoryChange {
+                affected_files,
+                timestamp: local_change.timestamp,
+                message: local_change.message,
+                id: Some(change_id),
+                author: local_change.author,
+                annotations: local_change.annotations,
+            });
+            summary.rebased += 1;
+        }
+        rebased_cursors.push(repository_history.get_changes().len());
+    }
+
+    let new_cursor = repository_history.get_changes().len();
+    let get_rebased_cursor = |cursor: usize| match cursor.checked_sub(base + 1) {
+        Some(offset) => rebased_cursors.get(offset).copied().unwrap_or(new_cursor),
+        None => cursor,
+    };
+    for session in repository_history.sessions.iter_mut() {
+        session.start = get_rebased_cursor(session.start);
+        session.end = session.end.map(get_rebased_cursor);
+    }
+
+    let mut working_files = Vec::new();
+    if is_diverged {
+        repository_history.cursor = new_cursor;
+        for (working_path, pulled_file) in pulled_files.iter() {
+            let was_tracked = pulled_file.original.does_file_exist(change_count);
+            let content = get_file_content(&pulled_file.rebased, new_cursor)?;
+            if !was_tracked && content.is_some() && fs.path_exists(working_path) {
+                bail!(
+                    "'{}' isn't tracked, but a received file would be restored there. Move it out of the way first.",
+                    command_options.display_path(working_path).display()
+                );
+            }
+            working_files.push((working_path.clone(), content));
+        }
+    }
+
+    let mut journal = Journal::default();
+    for (working_path, pulled_file) in pulled_files {
+        journal.add_write(
+            locations.history_from_working(&working_path)?,
+            pulled_file.rebased.encode_for_storage(fs, &locations)?,
+        );
+    }
+    for (segment_path, encoded_segment) in repository_history.encode_segments(&locations)? {
+        journal.add_write(segment_path, encoded_segment);
+    }
+    journal.add_write(repository_index_path, repository_history.encode()?);
+    journal.commit(fs, &locations)?;
+    trace::event(
+        Level::Info,
+        "Received the changes.",
+        &[
+            ("changes", &received_count),
+            ("rebased", &summary.rebased),
+            ("conflicts", &summary.conflicts.len()),
+        ],
+    );
+
+    if is_diverged {
+        // The working files were at the latest change, so they follow to the new one.
+        for (working_path, content) in working_files {
+            match content {
+                Some(content) => {
+                    let mut working_file = fs.create_file(&working_path)?;
+                    fs.write_to_file(&mut working_file, content)?;
+                }
+                None if fs.path_exists(&working_path) => fs.delete_file(&working_path)?,
+                None => {}
+            }
+        }
+    } else if repository_history.cursor == change_count {
+        // Working files behind the latest change stay where they are, like after a shift.
+        shift(
+            command_options,
+            fs,
+            new_cursor,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .with_context(|| {
+            format!(
+                "Received {} changes, but couldn't shift to the latest one.",
+                received_count
+            )
+        })?;
+    }
+
+    Ok(summary)
+}
+
+// Loads the history of the file once, with only the changes both sides have in common.
+fn get_pulled_file<'a, FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    pulled_files: &'a mut BTreeMap<PathBuf, PulledFile>,
+    working_path: &Path,
+    base: usize,
+) -> Result<&'a mut PulledFile> {
+    match pulled_files.entry(working_path.to_path_buf()) {
+        btree_map::Entry::Occupied(occupied) => Ok(occupied.into_mut()),
+        btree_map::Entry::Vacant(vacant) => {
+            let history_path = locations.history_from_working(working_path)?;
+            let original = if fs.path_exists(&history_path) {
+                let mut history_file = fs.open_readable_file(&history_path)?;
+                FileHistory::from_file(fs, locations, &mut history_file)?
+            } else {
+                FileHistory::default()
+            };
+            let mut rebased = original.clone();
+            rebased
+                .get_changes_mut()
+                .retain(|file_change| file_change.change_index <= base);
+            Ok(vacant.insert(PulledFile { original, rebased }))
+        }
+    }
+}
+
+fn get_file_content(file_history: &FileHistory, cursor: usize) -> Result<Option<Vec<u8>>> {
+    if file_history.does_file_exist(cursor) {
+        Ok(Some(file_history.get_content(cursor)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, thread};
+
+    use anyhow::Result;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        config::Config,
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::{FileHistory, RepositoryHistory},
+        merge::UNION,
+        protocol::MemoryTransport,
+    };
+
+    use super::{pull, push, PullConflict, PullSummary};
+
+    fn read_index(fs: &impl Fs, options: &ActionOptions) -> RepositoryHistory {
+        let locations = Locations::from(options);
+        let mut index_file = fs
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        RepositoryHistory::from_file(fs, &locations, &mut index_file).unwrap()
+    }
+
+    fn transfer(
+        fs: &FsMock,
+        from: &ActionOptions,
+        to: &ActionOptions,
+    ) -> (Result<usize>, Result<PullSummary>) {
+        let (mut pushing, mut pulling) = MemoryTransport::pair();
+        thread::scope(|scope| {
+            let pushed = scope.spawn(|| push(from.clone(), fs, &mut pushing));
+            let pulled = pull(to.clone(), fs, &mut pulling);
+            // Lets the pushing side stop waiting, if pulling failed early.
+            drop(pulling);
+            (pushed.join().unwrap(), pulled)
+        })
+    }
+
+    // Two repositories with the same first change, `todo` with the given content.
+    fn create_pair(fs: &FsMock, content: &[u8]) -> (ActionOptions, ActionOptions) {
+        let now = 0xC0FFEE;
+        let laptop = ActionOptions::from_path("./laptop");
+        let desktop = ActionOptions::from_path("./desktop");
+
+        write_file(fs, "./laptop/todo", content);
+        create(laptop.clone(), fs, now).unwrap();
+        fs.create_directory(Path::new("./desktop")).unwrap();
+        create(desktop.clone(), fs, now).unwrap();
+        let (pushed, pulled) = transfer(fs, &laptop, &desktop);
+        assert_eq!(pushed.unwrap(), 1);
+        assert_eq!(pulled.unwrap().received, 1);
+
+        (laptop, desktop)
+    }
+
+    #[test]
+    fn transfer_missing_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let (laptop, desktop) = create_pair(&fs_mock, b"Water plants");
+        assert_eq!(read_file(&fs_mock, "./desktop/todo"), b"Water plants");
+
+        write_file(&fs_mock, "./laptop/todo", b"Water plants\nFeed cat");
+        write_file(&fs_mock, "./laptop/groceries", b"Cat food");
+        update(laptop.clone(), &fs_mock, now + 1).unwrap();
+
+        let (pushed, pulled) = transfer(&fs_mock, &laptop, &desktop);
+        assert_eq!(pushed.unwrap(), 1);
+        let summary = pulled.unwrap();
+        assert_eq!((summary.received, summary.rebased), (1, 0));
+        assert_eq!(
+            read_file(&fs_mock, "./desktop/todo"),
+            b"Water plants\nFeed cat"
+        );
+        assert_eq!(read_file(&fs_mock, "./desktop/groceries"), b"Cat food");
+
+        let laptop_index = read_index(&fs_mock, &laptop);
+        let desktop_index = read_index(&fs_mock, &desktop);
+        assert_eq!(desktop_index.cursor, 2);
+        for (laptop_change, desktop_change) in laptop_index
+            .get_changes()
+            .iter()
+            .zip(desktop_index.get_changes())
+        {
+            assert_eq!(laptop_change.id, desktop_change.id);
+        }
+        let mut history_file = fs_mock
+            .open_readable_file(Path::new("./desktop/.ka/files/todo"))
+            .unwrap();
+        let file_history =
+            FileHistory::from_file(&fs_mock, &Locations::from(&desktop), &mut history_file)
+                .unwrap();
+        assert_eq!(file_history.get_changes().len(), 2);
+
+        let (pushed, pulled) = transfer(&fs_mock, &laptop, &desktop);
+        assert_eq!(pushed.unwrap(), 0);
+        assert_eq!(pulled.unwrap().received, 0);
+    }
+
+    #[test]
+    fn rebase_diverged_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let (laptop, desktop) = create_pair(&fs_mock, b"Water plants\nWash car\n");
+
+        write_file(&fs_mock, "./laptop/todo", b"Water all plants\nWash car\n");
+        update(laptop.clone(), &fs_mock, now + 1).unwrap();
+        write_file(&fs_mock, "./desktop/todo", b"Water plants\nWash the car\n");
+        write_file(&fs_mock, "./desktop/diary", b"Dear diary");
+        update(desktop.clone(), &fs_mock, now + 2).unwrap();
+
+        let (pushed, pulled) = transfer(&fs_mock, &laptop, &desktop);
+        assert_eq!(pushed.unwrap(), 1);
+        let summary = pulled.unwrap();
+        assert_eq!((summary.received, summary.rebased), (1, 1));
+        assert!(summary.conflicts.is_empty());
+        assert_eq!(
+            read_file(&fs_mock, "./desktop/todo"),
+            b"Water all plants\nWash the car\n"
+        );
+        assert_eq!(read_file(&fs_mock, "./desktop/diary"), b"Dear diary");
+
+        let desktop_index = read_index(&fs_mock, &desktop);
+        assert_eq!(desktop_index.cursor, 3);
+        assert_eq!(
+            desktop_index.get_changes()[1].id,
+            read_index(&fs_mock, &laptop).get_changes()[1].id
+        );
+
+        // The laptop now only misses the rebased change.
+        let (pushed, pulled) = transfer(&fs_mock, &desktop, &laptop);
+        assert_eq!(pushed.unwrap(), 1);
+        assert_eq!(pulled.unwrap().received, 1);
+        assert_eq!(
+            read_file(&fs_mock, "./laptop/todo"),
+            b"Water all plants\nWash the car\n"
+        );
+    }
+
+    #[test]
+    fn report_conflicting_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let (laptop, desktop) = create_pair(&fs_mock, b"Water plants");
+
+        write_file(&fs_mock, "./laptop/todo", b"Water cacti");
+        update(laptop.clone(), &fs_mock, now + 1).unwrap();
+        write_file(&fs_mock, "./desktop/todo", b"Water ferns");
+        update(desktop.clone(), &fs_mock, now + 2).unwrap();
+
+        let (_, pulled) = transfer(&fs_mock, &laptop, &desktop);
+        let summary = pulled.unwrap();
+        assert_eq!(
+            summary.conflicts,
+            vec![PullConflict {
+                working_path: Path::new("./desktop/todo").to_path_buf(),
+                cursor: 3,
+            }]
+        );
+        assert_eq!(read_file(&fs_mock, "./desktop/todo"), b"Water ferns");
+    }
+
+    #[test]
+    fn merge_with_configured_driver() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let (laptop, desktop) = create_pair(&fs_mock, b"Water plants\n");
+        let mut config = Config::default();
+        config.merge_drivers.insert("todo".into(), UNION.into());
+        config.write(&fs_mock, &Locations::from(&desktop)).unwrap();
+
+        write_file(&fs_mock, "./laptop/todo", b"Water cacti\n");
+        update(laptop.clone(), &fs_mock, now + 1).unwrap();
+        write_file(&fs_mock, "./desktop/todo", b"Water ferns\n");
+        update(desktop.clone(), &fs_mock, now + 2).unwrap();
+
+        let (_, pulled) = transfer(&fs_mock, &laptop, &desktop);
+        assert!(pulled.unwrap().conflicts.is_empty());
+        assert_eq!(
+            read_file(&fs_mock, "./desktop/todo"),
+            b"Water cacti\nWater ferns\n"
+        );
+    }
+
+    #[test]
+    fn refuse_pulling_over_unrecorded_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let (laptop, desktop) = create_pair(&fs_mock, b"Water plants");
+
+        write_file(&fs_mock, "./laptop/todo", b"Water cacti");
+        update(laptop.clone(), &fs_mock, now + 1).unwrap();
+        write_file(&fs_mock, "./desktop/todo", b"Water ferns");
+        update(desktop.clone(), &fs_mock, now + 2).unwrap();
+        write_file(&fs_mock, "./desktop/todo", b"Water ferns twice");
+
+        let (_, pulled) = transfer(&fs_mock, &laptop, &desktop);
+        assert!(pulled.is_err());
+        assert_eq!(read_index(&fs_mock, &desktop).get_changes().len(), 2);
+        assert_eq!(read_file(&fs_mock, "./desktop/todo"), b"Water ferns twice");
+    }
+}
@@ -0,0 +1,1544 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+
+use crate::{
+    cache::TipCache,
+    collisions::{CaseCollisionPolicy, CasePaths},
+    config::Config,
+    crypto,
+    diff::{ContentChange, Delta, Hunk},
+    files::{normalize_path, FileState, Locations},
+    filesystem::{is_reserved_on_windows, Fs},
+    history::{
+        Annotations, CopySource, FileChange, FileChangeVariant, FileHistory, FileTip,
+        RepositoryChange, RepositoryHistory, FILE_SEGMENT_LENGTH,
+    },
+    journal::{self, Journal},
+    line_endings,
+    locks::{self, Lock, Locks},
+    metrics::{self, ActionTimer},
+    policy::{get_content_type, get_file_policy, FilePolicy, TrackingMode},
+    structured, tabular,
+    trace::{self, Level},
+};
+
+use super::{
+    adopt::{get_adopted_provenance, is_file_orphaned},
+    ActionOptions,
+};
+
+#[derive(Debug, Default)]
+pub struct UpdateSummary {
+    // The working files the change recorded, none if nothing changed.
+    pub recorded: Vec<PathBuf>,
+    // Working files which couldn't be read or collide with others, and were left out of the
+    // change.
+    pub skipped: Vec<(PathBuf, Error)>,
+    // Working files which kept changing while they were recorded. What was recorded for
+    // them might not match any version of the file that was ever complete.
+    pub racy: Vec<PathBuf>,
+    // New working files with a name Windows reserves, like `CON` or one ending in a dot.
+    // They're recorded like any other, but Windows can't restore them by their plain path.
+    pub reserved: Vec<PathBuf>,
+    // Recorded working files which were too large to diff within the memory budget of the
+    // config, so only the part of them which changed was compared.
+    pub over_budget: Vec<PathBuf>,
+    // Recorded working files which someone else locked.
+    pub locked: Vec<(PathBuf, Lock)>,
+    // Changed working files which were recorded less than their minimum change interval ago.
+    // They're left for a later update.
+    pub deferred: Vec<PathBuf>,
+}
+
+// How often a file which changed while being read is read again.
+const RACY_RETRIES: usize = 3;
+
+// Decides whether a hunk is recorded, given the working path and the recorded content
+// the hunk applies to.
+pub type HunkSelector<'a> = &'a mut dyn FnMut(&Path, &[u8], &Hunk) -> bool;
+
+pub fn update(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+) -> Result<UpdateSummary> {
+    update_with(
+        command_options,
+        fs,
+        timestamp,
+        None,
+        None,
+        Annotations::new(),
+        false,
+    )
+}
+
+// Like `update`, but keeps `annotations` with the change, if there is one.
+pub fn update_annotated(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+    annotations: Annotations,
+) -> Result<UpdateSummary> {
+    update_with(
+        command_options,
+        fs,
+        timestamp,
+        None,
+        None,
+        annotations,
+        false,
+    )
+}
+
+// Like `update`, but only records the hunks which `select` accepts. The rejected ones stay
+// in the working files, so a later update can still record them.
+pub fn update_interactive(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+    select: HunkSelector,
+) -> Result<UpdateSummary> {
+    update_with(
+        command_options,
+        fs,
+        timestamp,
+        Some(select),
+        None,
+        Annotations::new(),
+        false,
+    )
+}
+
+// Like `update`, but only records the working files in `working_paths`, e.g. one chunk of
+// an import at a time.
+pub fn update_paths(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+    working_paths: &HashSet<PathBuf>,
+) -> Result<UpdateSummary> {
+    update_with(
+        command_options,
+        fs,
+        timestamp,
+        None,
+        Some(working_paths),
+        Annotations::new(),
+        false,
+    )
+}
+
+// Records the working files of a new repository as they are, trusting them not to change
+// while they're read. That saves reading and hashing every file twice more to find out.
+pub(super) fn update_baseline(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+) -> Result<UpdateSummary> {
+    update_with(
+        command_options,
+        fs,
+        timestamp,
+        None,
+        None,
+        Annotations::new(),
+        true,
+    )
+}
+
+fn update_with(
+    command_options: ActionOptions,
+    fs: &impl Fs,
+    timestamp: u64,
+    mut select: Option<HunkSelector>,
+    working_paths: Option<&HashSet<PathBuf>>,
+    annotations: Annotations,
+    is_baseline: bool,
+) -> Result<UpdateSummary> {
+    let _timer = ActionTimer::start("update");
+    let _span = trace::span(
+        Level::Info,
+        "update",
+        &[("repository", &command_options.repository_path.display())],
+    );
+    let locations = Locations::from(&command_options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let repository_index_path = locations.get_repository_index_path();
+    let mut repository_index_file = fs.open_readable_file(&repository_index_path)?;
+    let config = Config::load(fs, &locations)?;
+
+    let (mut repository_history, _) = RepositoryHistory::from_file_checked(
+        fs,
+        &locations,
+        &mut repository_index_file,
+        config.consistency,
+    )?;
+
+    let entries = locations
+        .get_repository_files(fs)
+        .context("Could not traverse files.")?;
+    trace::event(
+        Level::Debug,
+        "Found the files.",
+        &[("files", &entries.len())],
+    );
+
+    // Nothing is written until all files are handled, so an interrupted update can be completed.
+    let mut journal = Journal::default();
+    let mut affected_files = Vec::new();
+    let mut history_writes = Vec::new();
+    let mut summary = UpdateSummary::default();
+    let tracked_paths = repository_history.get_tracked_paths();
+
+    // Asking for the hunks of a file again would be confusing, so interactive updates
+    // only report files which changed while being recorded.
+    let retries = if select.is_some() { 0 } else { RACY_RETRIES };
+    let mut fingerprints = Vec::new();
+    // The recorded content of every file by its hash, which new files might be copies of.
+    // Only worth collecting if there are new files at all.
+    let mut copy_sources = entries
+        .iter()
+        .any(|state| matches!(state, FileState::Untracked(_)))
+        .then(HashMap::new);
+    // New files whose paths only differ by case from recorded ones would be restored on top
+    // of each other on case-insensitive filesystems.
+    let mut case_paths = (config.case_collisions != CaseCollisionPolicy::Allow).then(|| {
+        let mut case_paths = CasePaths::default();
+        for state in entries.iter() {
+            if let FileState::Tracked(tracked) = state {
+                case_paths.insert(&tracked.working_path);
+            }
+        }
+        case_paths
+    });
+
+    'files: for state in entries {
+        let working_path = state.get_working_path(&locations)?;
+        if !config.is_in_scope(&locations, &working_path)
+            || working_paths.is_some_and(|working_paths| !working_paths.contains(&working_path))
+        {
+            continue;
+        }
+        if let (FileState::Untracked(_), Some(case_paths)) = (&state, case_paths.as_mut()) {
+            if let Some(other_path) = case_paths.insert(&working_path) {
+                let error = anyhow!(
+                    "Its path only differs by case from '{}', which is recorded already.",
+                    command_options.display_path(&other_path).display()
+                );
+                summary.skipped.push((working_path, error));
+                continue;
+            }
+        }
+        if let FileState::Untracked(_) = &state {
+            let relative_path = working_path.strip_prefix(&locations.repository_path)?;
+            if is_reserved_on_windows(relative_path) {
+                summary.reserved.push(working_path.clone());
+            }
+        }
+        if let Some(&file_cursor) = repository_history.file_cursors.get(&working_path) {
+            match check_file_cursor(
+                fs,
+                &locations,
+                &config,
+                &working_path,
+                file_cursor,
+                repository_history.cursor,
+            ) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(error) => {
+                    summary.skipped.push((working_path, error));
+                    continue;
+                }
+            }
+        }
+        let mut attempt = 0;
+        let mut is_over_budget;
+
+        let (changed_file, fingerprint) = loop {
+            is_over_budget = false;
+            // A single unreadable file shouldn't keep all the others from being recorded.
+            let working_content = match &state {
+                FileState::Deleted(_) => Vec::new(),
+                FileState::Untracked(_) | FileState::Tracked(_) => {
+                    match read_working_file(fs, &locations, &working_path) {
+                        Ok(working_content) => working_content,
+                        Err(error) => {
+                            summary.skipped.push((working_path, error));
+                            continue 'files;
+                        }
+                    }
+                }
+            };
+            let fingerprint = match &state {
+                // Baselines don't check for files which changed while being recorded at all.
+                FileState::Untracked(_) | FileState::Tracked(_) if !is_baseline => {
+                    Some(crypto::sha256(&working_content))
+                }
+                _ => None,
+            };
+
+            let is_orphaned = is_file_orphaned(&state, &tracked_paths);
+            let changed_file = get_new_history_for_file(
+                fs,
+                repository_history.cursor,
+                &state,
+                line_endings::normalize(&config, &working_path, working_content),
+                &locations,
+                &config,
+                is_orphaned,
+                &mut select,
+                copy_sources.as_mut(),
+                &mut is_over_budget,
+            )
+            .with_context(|| {
+                format!(
+                    "Failed recording '{}'.",
+                    command_options.display_path(&working_path).display()
+                )
+            })?;
+
+            // The file could have been written to while we were reading it, in which case
+            // what we read might be a mix of its old and new content.
+            if changed_file.is_none()
+                || is_baseline
+                || is_unchanged(fs, &locations, &working_path, fingerprint)
+            {
+                break (changed_file, fingerprint);
+            }
+            if attempt == retries {
+                summary.racy.push(working_path.clone());
+                break (changed_file, fingerprint);
+            }
+            attempt += 1;
+        };
+
+        // Interactive updates already asked which hunks to record, so they aren't limited.
+        if changed_file.is_some()
+            && select.is_none()
+            && is_deferred(&config, &repository_history, &working_path, timestamp)?
+        {
+            summary.deferred.push(working_path);
+            continue;
+        }
+
+        if let Some((history_path, history_write)) = changed_file {
+            trace::event(
+                Level::Trace,
+                "Recorded the file.",
+                &[
+                    ("path", &working_path.display()),
+                    ("attempts", &(attempt + 1)),
+                ],
+            );
+            history_writes.push((history_path, history_write));
+            if is_over_budget {
+                summary.over_budget.push(working_path.clone());
+            }
+            if !is_baseline {
+                fingerprints.push((working_path.clone(), fingerprint));
+            }
+            affected_files.push(working_path);
+        }
+    }
+
+    if let Some(copy_sources) = copy_sources {
+        for (_, history_write) in history_writes.iter_mut() {
+            use_copy_source(history_write, &copy_sources, repository_history.cursor);
+        }
+    }
+
+    // The file changes can only be written once the ID of the change they are part of is known.
+    let file_changes = affected_files
+        .iter()
+        .zip(history_writes.iter())
+        .map(|(working_path, (_, history_write))| {
+            Ok((working_path.as_path(), history_write.get_change()?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let change_id = RepositoryChange::compute_id(
+        repository_history.get_change_id(repository_history.cursor),
+        timestamp,
+        &file_changes,
+    );
+    for (history_path, mut history_write) in history_writes {
+        history_write.get_change_mut()?.change_id = Some(change_id.clone());
+        match history_write {
+            HistoryWrite::Append {
+                stored_length,
+                change,
+            } => {
+                let record = FileHistory::encode_change_for_storage(&change, fs, &locations)?;
+                let history_bytes = stored_length + record.len();
+                metrics::record_histogram(metrics::HISTORY_BYTES, &[], history_bytes as f64);
+                journal.add_append(history_path, stored_length, record)
+            }
+            HistoryWrite::Replace(mut new_file_history) => {
+                for (segment_path, encoded_segment) in
+                    new_file_history.seal(fs, &locations, &history_path)?
+                {
+                    journal.add_write(segment_path, encoded_segment);
+                }
+                let encoded = new_file_history.encode_for_storage(fs, &locations)?;
+                metrics::record_histogram(metrics::HISTORY_BYTES, &[], encoded.len() as f64);
+                journal.add_write(history_path, encoded)
+            }
+        }
+    }
+
+    // Files can also change after they were handled, which is only checked once more.
+    for (working_path, fingerprint) in fingerprints {
+        if !summary.racy.contains(&working_path)
+            && !is_unchanged(fs, &locations, &working_path, fingerprint)
+        {
+            summary.racy.push(working_path);
+        }
+    }
+
+    // Locks are only advisory, the files are recorded all the same.
+    let locks = Locks::load(fs, &locations)?;
+    let owner = locks::get_owner(&command_options, &config);
+    for working_path in affected_files.iter() {
+        match locks.files.get(working_path) {
+            Some(lock) if lock.owner != owner => {
+                summary.locked.push((working_path.clone(), lock.clone()))
+            }
+            _ => {}
+        }
+    }
+
+    let has_recorded_change = !affected_files.is_empty();
+    if has_recorded_change {
+        // Recorded files are at the new change, just like the repository.
+        let file_cursor_count = repository_history.file_cursors.len();
+        for working_path in affected_files.iter() {
+            repository_history.file_cursors.remove(working_path);
+        }
+        let has_new_file_cursors = repository_history.file_cursors.len() != file_cursor_count;
+
+        summary.recorded = affected_files.clone();
+        repository_history.add_change(RepositoryChange {
+            affected_files,
+            timestamp,
+            message: None,
+            id: Some(change_id),
+            author: config.identity.get_author(),
+            annotations,
+        });
+        repository_history.cursor += 1;
+
+        // Histories still in the legacy format are converted by writing them completely, and
+        // so is the index once enough changes piled up in it to seal some of them.
+        match repository_history.get_stored_length() {
+            Some(stored_length) if !repository_history.needs_sealing() => {
+                let mut records = repository_history.encode_latest_change()?;
+                if has_new_file_cursors {
+                    records.extend(repository_history.encode_file_cursors()?);
+                }
+                journal.add_append(repository_index_path, stored_length, records)
+            }
+            _ => {
+                for (segment_path, encoded_segment) in
+                    repository_history.encode_segments(&locations)?
+                {
+                    journal.add_write(segment_path, encoded_segment);
+                }
+                journal.add_write(repository_index_path, repository_history.encode()?);
+            }
+        }
+    }
+
+    journal.commit(fs, &locations)?;
+
+    metrics::increment_counter(metrics::UPDATES, &[], 1);
+    if has_recorded_change {
+        metrics::increment_counter(metrics::CHANGES_RECORDED, &[], 1);
+    }
+    trace::event(
+        Level::Info,
+        if has_recorded_change {
+            "Recorded a change."
+        } else {
+            "Nothing changed."
+        },
+        &[
+            ("cursor", &repository_history.cursor),
+            ("skipped", &summary.skipped.len()),
+            ("racy", &summary.racy.len()),
+        ],
+    );
+
+    Ok(summary)
+}
+
+// Whether the file was recorded less than its minimum change interval before `timestamp`.
+fn is_deferred(
+    config: &Config,
+    repository_history: &RepositoryHistory,
+    working_path: &Path,
+    timestamp: u64,
+) -> Result<bool> {
+    let interval = match config.get_min_change_interval(working_path)? {
+        Some(interval) => interval,
+        None => return Ok(false),
+    };
+    Ok(repository_history
+        .get_changes()
+        .iter()
+        .take(repository_history.cursor)
+        .rev()
+        .take_while(|change| timestamp.saturating_sub(change.timestamp) < interval)
+        .any(|change| change.affected_files.contains(&working_path.to_path_buf())))
+}
+
+// Whether the file at its own cursor can be recorded like any other. That's only the case if
+// it has the latest content of its history, which the repository is also past. Otherwise it's
+// left out, which is only worth an error if it was changed.
+fn check_file_cursor<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    config: &Config,
+    working_path: &Path,
+    file_cursor: usize,
+    cursor: usize,
+) -> Result<bool> {
+    let history_path = locations.history_from_working(working_path)?;
+    if !fs.path_exists(&history_path) {
+        return Ok(true);
+    }
+
+    let mut history_file = fs.open_readable_file(&history_path)?;
+    let file_history = FileHistory::from_file(fs, locations, &mut history_file)?;
+    let is_latest = file_history
+        .get_changes()
+        .last()
+        .is_none_or(|change| change.change_index <= file_cursor);
+    if is_latest && file_cursor <= cursor {
+        return Ok(true);
+    }
+
+    let working_content = if fs.path_exists(working_path) {
+        let working_content = read_working_file(fs, locations, working_path)?;
+        Some(line_endings::normalize(
+            config,
+            working_path,
+            working_content,
+        ))
+    } else {
+        None
+    };
+    let file_content = if file_history.does_file_exist(file_cursor) {
+        Some(file_history.get_content(file_cursor)?)
+    } else {
+        None
+    };
+    if working_content == file_content {
+        return Ok(false);
+    }
+
+    bail!(
+        "The file is at change {} instead of {}, so its changes can't be recorded. Sync it first.",
+        file_cursor,
+        cursor
+    )
+}
+
+// Whether the working file still has the content with the given hash, or is still
+// missing if there is no hash.
+fn is_unchanged<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    working_path: &Path,
+    fingerprint: Option<[u8; 32]>,
+) -> bool {
+    if !fs.path_exists(working_path) {
+        return fingerprint.is_none();
+    }
+
+    match read_working_file(fs, locations, working_path) {
+        Ok(working_content) => fingerprint == Some(crypto::sha256(&working_content)),
+        Err(_) => false,
+    }
+}
+
+fn read_working_file<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    working_path: &Path,
+) -> Result<Vec<u8>> {
+    let mut working_file = fs.open_readable_file(working_path)?;
+    fs.read_from_file(&mut working_file).with_context(|| {
+        let relative_path = working_path
+            .strip_prefix(&locations.repository_path)
+            .unwrap_or(working_path);
+        format!("Failed reading '{}'.", relative_path.display())
+    })
+}
+
+enum HistoryWrite {
+    Append {
+        stored_length: usize,
+        change: FileChange,
+    },
+    Replace(FileHistory),
+}
+
+impl HistoryWrite {
+    fn get_change(&self) -> Result<&FileChange> {
+        match self {
+            HistoryWrite::Append { change, .. } => Ok(change),
+            HistoryWrite::Replace(new_history) => new_history
+                .get_changes()
+                .last()
+                .context("The new file history has no changes."),
+        }
+    }
+
+    fn get_change_mut(&mut self) -> Result<&mut FileChange> {
+        match self {
+            HistoryWrite::Append { change, .. } => Ok(change),
+            HistoryWrite::Replace(new_history) => new_history
+                .get_latest_change_mut()
+                .context("The new file history has no changes."),
+        }
+    }
+}
+
+// Only legacy histories and those which have enough changes to seal some of them are loaded
+// completely, all others just get the change appended.
+// Goes through the tip cache if it's enabled, instead of replaying the whole history.
+fn get_tip<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    config: &Config,
+    history_path: &Path,
+    cursor: usize,
+) -> Result<FileTip> {
+    if config.tip_cache {
+        return TipCache::new(fs, locations).get_tip(history_path, cursor);
+    }
+    let mut history_file = fs.open_readable_file(history_path)?;
+    FileHistory::get_tip(fs, locations, &mut history_file, cursor)
+}
+
+fn add_file_change<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    history_path: &Path,
+    stored_length: Option<usize>,
+    unsealed_count: usize,
+    change: FileChange,
+) -> Result<HistoryWrite> {
+    match stored_length {
+        Some(stored_length) if unsealed_count + 1 < FILE_SEGMENT_LENGTH => {
+            Ok(HistoryWrite::Append {
+                stored_length,
+                change,
+            })
+        }
+        _ => {
+            let mut history_file = fs.open_readable_file(history_path)?;
+            let mut new_history = FileHistory::from_file(fs, locations, &mut history_file)?;
+            new_history.add_change(change);
+            Ok(HistoryWrite::Replace(new_history))
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn get_new_history_for_file<FS: Fs>(
+    fs: &FS,
+    cursor: usize,
+    file_state: &FileState,
+    working_content: Vec<u8>,
+    locations: &Locations,
+    config: &Config,
+    is_orphaned: bool,
+    select: &mut Option<HunkSelector>,
+    copy_sources: Option<&mut HashMap<String, PathBuf>>,
+    is_over_budget: &mut bool,
+) -> Result<Option<(PathBuf, HistoryWrite)>> {
+    match file_state {
+        FileState::Deleted(deleted) => {
+            let tip = get_tip(fs, locations, config, &deleted.history_path, cursor)?;
+            if tip.is_deleted || tip.is_forgotten {
+                return Ok(None);
+            }
+            add_copy_source(copy_sources, locations, &deleted.history_path, &tip.content)?;
+
+            let working_path = locations.working_from_history(&deleted.history_path)?;
+            if select_hunks(select, &working_path, &tip.content, Vec::new()).is_some() {
+                let change = FileChange {
+                    change_index: cursor + 1,
+                    variant: FileChangeVariant::Deleted,
+                    degraded: false,
+                    content_hash: None,
+                    change_id: None,
+                    content_type: None,
+                };
+                let history_write = add_file_change(
+                    fs,
+                    locations,
+                    &deleted.history_path,
+                    tip.stored_length,
+                    tip.unsealed_count,
+                    change,
+                )?;
+                Ok(Some((deleted.history_path.clone(), history_write)))
+            } else {
+                Ok(None)
+            }
+        }
+        FileState::Untracked(untracked) => {
+            let working_content = match select_hunks(select, &untracked.path, &[], working_content)
+            {
+                Some(working_content) => working_content,
+                None => return Ok(None),
+            };
+
+            if let FilePolicy::Skip(_) = get_file_policy(config, &untracked.path, &working_content)
+            {
+                return Ok(None);
+            }
+
+            if config.tip_cache {
+                let history_path = locations.history_from_working(&untracked.path)?;
+                TipCache::new(fs, locations).store(&history_path, cursor + 1, &working_content)?;
+            }
+            let change = FileChange {
+                change_index: cursor + 1,
+                content_hash: Some(FileChange::hash_content(&working_content)),
+                content_type: Some(get_content_type(config, &untracked.path, &working_content)),
+                variant: FileChangeVariant::Updated(vec![ContentChange::Inserted {
+                    at: 0,
+                    new_content: working_content,
+                }]),
+                degraded: false,
+                change_id: None,
+            };
+
+            let mut new_history = FileHistory::default();
+            new_history.add_change(change);
+            if is_orphaned {
+                new_history.provenance = Some(get_adopted_provenance(cursor + 1));
+            }
+
+            Ok(Some((
+                locations.history_from_working(&untracked.path)?,
+                HistoryWrite::Replace(new_history),
+            )))
+        }
+        FileState::Tracked(tracked) => {
+            let tip = get_tip(fs, locations, config, &tracked.history_path, cursor)?;
+            if tip.is_forgotten {
+                return Ok(None);
+            }
+            if !tip.is_deleted {
+                add_copy_source(copy_sources, locations, &tracked.history_path, &tip.content)?;
+            }
+            let old_content = tip.content;
+
+            let working_content =
+                match select_hunks(select, &tracked.working_path, &old_content, working_content) {
+                    Some(working_content) => working_content,
+                    None => return Ok(None),
+                };
+
+            let delta = match get_file_policy(config, &tracked.working_path, &working_content) {
+                FilePolicy::Skip(_) => return Ok(None),
+                FilePolicy::Track(TrackingMode::Snapshot) => Delta {
+                    changes: ContentChange::snapshot(&old_content, &working_content),
+                    degraded: false,
+                },
+                FilePolicy::Track(_) => {
+                    let bytes_diffed = old_content.len() + working_content.len();
+                    metrics::increment_counter(metrics::BYTES_DIFFED, &[], bytes_diffed as u64);
+                    trace::event(
+                        Level::Trace,
+                        "Diffing the file.",
+                        &[
+                            ("path", &tracked.working_path.display()),
+                            ("old_bytes", &old_content.len()),
+                            ("new_bytes", &working_content.len()),
+                        ],
+                    );
+                    // Diffing takes memory on top of both contents, so past the budget only the
+                    // part which changed is looked at.
+                    match config.memory_budget {
+                        Some(memory_budget) if bytes_diffed as u64 > memory_budget => {
+                            *is_over_budget = true;
+                            ContentChange::diff_within(
+                                &old_content,
+                                &working_content,
+                                &config.diff,
+                                memory_budget,
+                            )
+                        }
+                        _ => match tabular::diff_rows(
+                            config,
+                            &tracked.working_path,
+                            &old_content,
+                            &working_content,
+                        ) {
+                            Some(changes) => Delta {
+                                changes,
+                                degraded: false,
+                            },
+                            None => ContentChange::diff_with(
+                                &old_content,
+                                &working_content,
+                                &config.diff,
+                            ),
+                        },
+                    }
+                }
+            };
+
+            // A deleted file coming back empty doesn't differ in content, but still changed.
+            if !delta.changes.is_empty() || tip.is_deleted {
+                if config.tip_cache {
+                    TipCache::new(fs, locations).store(
+                        &tracked.history_path,
+                        cursor + 1,
+                        &working_content,
+                    )?;
+                }
+                // Parsing the contents for a structured change would blow the budget as well.
+                let variant = if *is_over_budget {
+                    FileChangeVariant::Updated(delta.changes)
+                } else {
+                    get_updated_variant(
+                        config,
+                        &tracked.working_path,
+                        &old_content,
+                        &working_content,
+                        delta.changes,
+                    )
+                };
+                let change = FileChange {
+                    change_index: cursor + 1,
+                    variant,
+                    degraded: delta.degraded,
+                    content_hash: Some(FileChange::hash_content(&working_content)),
+                    change_id: None,
+                    content_type: Some(get_content_type(
+                        config,
+                        &tracked.working_path,
+                        &working_content,
+                    )),
+                };
+                let history_write = add_file_change(
+                    fs,
+                    locations,
+                    &tracked.history_path,
+                    tip.stored_length,
+                    tip.unsealed_count,
+                    change,
+                )?;
+
+                Ok(Some((tracked.history_path.clone(), history_write)))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+// Files the config asks for are recorded as a structured change instead, where they can be.
+fn get_updated_variant(
+    config: &Config,
+    working_path: &Path,
+    old_content: &[u8],
+    new_content: &[u8],
+    changes: Vec<ContentChange>,
+) -> FileChangeVariant {
+    if structured::is_structured(config, working_path) {
+        if let Some(structured) = structured::diff(old_content, new_content) {
+            return FileChangeVariant::Structured(structured);
+        }
+    }
+    FileChangeVariant::Updated(changes)
+}
+
+fn add_copy_source(
+    copy_sources: Option<&mut HashMap<String, PathBuf>>,
+    locations: &Locations,
+    history_path: &Path,
+    content: &[u8],
+) -> Result<()> {
+    if let Some(copy_sources) = copy_sources {
+        if !content.is_empty() {
+            let path = normalize_path(history_path.strip_prefix(&locations.ka_files_path)?);
+            copy_sources.insert(FileChange::hash_content(content), path);
+        }
+    }
+    Ok(())
+}
+
+// A new file with the same content another file had at the cursor starts its history by
+// referring to that file, instead of storing the content again.
+fn use_copy_source(
+    history_write: &mut HistoryWrite,
+    copy_sources: &HashMap<String, PathBuf>,
+    cursor: usize,
+) {
+    let new_history = match history_write {
+        HistoryWrite::Replace(new_history)
+            if new_history.get_changes().len() == 1 && new_history.provenance.is_none() =>
+        {
+            new_history
+        }
+        _ => return,
+    };
+    let change = &mut new_history.get_changes_mut()[0];
+    let path = match change
+        .content_hash
+        .as_ref()
+        .and_then(|hash| copy_sources.get(hash))
+    {
+        Some(path) if change.change_index == cursor + 1 => path.clone(),
+        _ => return,
+    };
+
+    change.variant = FileChangeVariant::CopiedFrom {
+        path: path.clone(),
+        cursor,
+    };
+    new_history.copied_from = Some(CopySource { path, cursor });
+}
+
+// Applies the hunks which `select` accepts to the recorded content, or takes the working
+// content as a whole without a selector. Returns `None` if no hunk was accepted.
+fn select_hunks(
+    select: &mut Option<HunkSelector>,
+    working_path: &Path,
+    old_content: &[u8],
+    working_content: Vec<u8>,
+) -> Option<Vec<u8>> {
+    let select = match select {
+        Some(select) => select,
+        None => return Some(working_content),
+    };
+
+    let hunks: Vec<Hunk> = Hunk::diff_lines(old_content, &working_content)
+        .into_iter()
+        .filter(|hunk| select(working_path, old_content, hunk))
+        .collect();
+    if hunks.is_empty() {
+        return None;
+    }
+
+    let mut selected_content = old_content.to_vec();
+    Hunk::apply_all(&hunks, &mut selected_content);
+    Some(selected_content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::Mutex,
+    };
+
+    use anyhow::{bail, Result};
+
+    use crate::{
+        actions::{create, status, update, update_interactive, ActionOptions},
+        collisions::CaseCollisionPolicy,
+        config::Config,
+        diff::{ContentChange, Hunk},
+        files::Locations,
+        filesystem::{
+            mock::{write_file, EntryMock, FileMock, FsMock, FsState},
+            Fs,
+        },
+        history::{
+            Annotations, FileChange, FileChangeVariant, FileHistory, RepositoryChange,
+            RepositoryHistory, FILE_SEGMENT_LENGTH,
+        },
+        policy::ContentType,
+    };
+
+    #[test]
+    fn no_update_if_no_change() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+
+        fs_mock.set_state(FsState::new(vec![EntryMock::file("./test", &[1, 2, 3])]));
+
+        // We create the initial Fs state by running the Create action.
+        create(ActionOptions::from_path("."), &fs_mock, now)
+            .expect("Creating expected state failed.");
+        let state = fs_mock.get_state();
+
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).expect("Action failed.");
+
+        // No change should have happened.
+        fs_mock.assert_match(state);
+    }
+
+    #[test]
+    fn selective_update() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        let mut repo_history = RepositoryHistory::default();
+
+        repo_history.add_change(RepositoryChange {
+            affected_files: vec![
+                Path::new("./changed_file").into(),
+                Path::new("./unchanged_file").into(),
+            ],
+            timestamp: now,
+            message: None,
+            id: None,
+            author: None,
+            annotations: Annotations::new(),
+        });
+        repo_history.cursor = 1;
+        let initial_index = repo_history.encode().unwrap();
+
+        let mut file_history = FileHistory::default();
+
+        file_history.add_change(FileChange {
+            change_index: 1,
+            variant: FileChangeVariant::Updated(vec![ContentChange::Inserted {
+                at: 0,
+                new_content: vec![1, 2, 3],
+            }]),
+            degraded: false,
+            content_hash: Some(FileChange::hash_content(&[1, 2, 3])),
+            change_id: None,
+            content_type: Some(ContentType::Text),
+        });
+        let initial_file_history = file_history.encode().unwrap();
+
+        let mut file_change = FileChange {
+            change_index: 2,
+            variant: FileChangeVariant::Updated(vec![ContentChange::Inserted {
+                at: 3,
+                new_content: vec![4, 5],
+            }]),
+            degraded: false,
+            content_hash: Some(FileChange::hash_content(&[1, 2, 3, 4, 5])),
+            change_id: None,
+            content_type: Some(ContentType::Text),
+        };
+        let change_id = RepositoryChange::compute_id(
+            None,
+            now + 1,
+            &[(Path::new("./changed_file"), &file_change)],
+        );
+        file_change.change_id = Some(change_id.clone());
+        file_history.add_change(file_change);
+        let updated_file_history = file_history.encode().unwrap();
+
+        repo_history.add_change(RepositoryChange {
+            affected_files: vec![Path::new("./changed_file").into()],
+            timestamp: now + 1,
+            message: None,
+            id: Some(change_id),
+            author: None,
+            annotations: Annotations::new(),
+        });
+        repo_history.cursor = 2;
+        let mut updated_index = initial_index.clone();
+        updated_index.extend(repo_history.encode_latest_change().unwrap());
+
+        fs_mock.set_state(FsState::new(vec![
+            EntryMock::file("./changed_file", &[1, 2, 3, 4, 5]),
+            EntryMock::file("./unchanged_file", &[1, 2, 3]),
+
+            EntryMock::dir("./.ka"),
+            EntryMock::file("./.ka/index", &initial_index),
+            EntryMock::dir("./.ka/files"),
+            EntryMock::file("./.ka/files/changed_file", &initial_file_history),
+            EntryMock::file("./.ka/files/unchanged_file", &initial_file_history),
+        ]));
+
+        update(options, &fs_mock, now + 1).expect("Action failed.");
+
+        fs_mock.assert_match(FsState::new(vec![
+            EntryMock::file("./changed_file", &[1, 2, 3, 4, 5]),
+            EntryMock::file("./unchanged_file", &[1, 2, 3]),
+
+            EntryMock::dir("./.ka"),
+            EntryMock::file("./.ka/index", &updated_index),
+            EntryMock::dir("./.ka/files"),
+            EntryMock::file("./.ka/files/changed_file", &updated_file_history),
+            EntryMock::file("./.ka/files/unchanged_file", &initial_file_history),
+        ]))
+    }
+
+    #[test]
+    fn skip_nested_repositories() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        fs_mock.set_state(FsState::new(vec![
+            EntryMock::file("./own", &[1]),
+            EntryMock::dir("./nested"),
+            EntryMock::file("./nested/file", &[2]),
+            EntryMock::dir("./nested/.git"),
+            EntryMock::file("./nested/.git/HEAD", &[3]),
+        ]));
+
+        create(options.clone(), &fs_mock, now).expect("Action failed.");
+
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/own")));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/nested/file")));
+
+        let config = Config {
+            track_nested_repositories: true,
+            ..Config::default()
+        };
+        config.write(&fs_mock, &Locations::from(&options)).unwrap();
+
+        update(options, &fs_mock, now + 1).expect("Action failed.");
+
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/nested/file")));
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/nested/.git/HEAD")));
+    }
+
+    // Delegates to the mock, but refuses to read one of the files.
+    // Runs `on_read` whenever a file is opened for reading, which can fail the read or
+    // change the file first.
+    struct HookedFs<H: Fn(&FsMock, &Path) -> Result<()>> {
+        inner: FsMock,
+        on_read: H,
+    }
+
+    impl<H: Fn(&FsMock, &Path) -> Result<()>> Fs for HookedFs<H> {
+        type File = FileMock;
+        type Entry = EntryMock;
+
+        fn create_file(&self, path: &Path) -> Result<Self::File> {
+            self.inner.create_file(path)
+        }
+
+        fn delete_file(&self, path: &Path) -> Result<()> {
+            self.inner.delete_file(path)
+        }
+
+        fn open_readable_file(&self, path: &Path) -> Result<Self::File> {
+            (self.on_read)(&self.inner, path)?;
+            self.inner.open_readable_file(path)
+        }
+
+        fn open_writable_file(&self, path: &Path) -> Result<Self::File> {
+            self.inner.open_writable_file(path)
+        }
+
+        fn create_directory(&self, path: &Path) -> Result<()> {
+            self.inner.create_directory(path)
+        }
+
+        fn read_directory(&self, path: &Path) -> Result<Vec<Self::Entry>> {
+            self.inner.read_directory(path)
+        }
+
+        fn delete_directory(&self, path: &Path) -> Result<()> {
+            self.inner.delete_directory(path)
+        }
+
+        fn write_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            self.inner.write_to_file(file, buffer)
+        }
+
+        fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            self.inner.append_to_file(file, buffer)
+        }
+
+        fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>> {
+            self.inner.read_from_file(file)
+        }
+
+        fn path_exists(&self, path: &Path) -> bool {
+            self.inner.path_exists(path)
+        }
+    }
+
+    #[test]
+    fn skip_unreadable_files() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+        fs_mock.set_state(FsState::new(vec![
+            EntryMock::file("./locked", &[1, 2, 3]),
+            EntryMock::file("./readable", &[4, 5, 6]),
+        ]));
+        let fs = HookedFs {
+            inner: fs_mock,
+            on_read: |_: &FsMock, path: &Path| {
+                if path == Path::new("./locked") {
+                    bail!("Permission denied.");
+                }
+                Ok(())
+            },
+        };
+
+        let summary = create(ActionOptions::from_path("."), &fs, now).expect("Action failed.");
+
+        assert_eq!(summary.skipped.len(), 1);
+        assert_eq!(summary.skipped[0].0, Path::new("./locked"));
+        assert!(fs.path_exists(Path::new("./.ka/files/readable")));
+        assert!(!fs.path_exists(Path::new("./.ka/files/locked")));
+    }
+
+    #[test]
+    fn skip_case_collisions() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        fs_mock.set_state(FsState::new(vec![EntryMock::file("./Readme", &[1, 2, 3])]));
+        create(options.clone(), &fs_mock, now).expect("Action failed.");
+
+        let config = Config {
+            case_collisions: CaseCollisionPolicy::Error,
+            ..Config::default()
+        };
+        config.write(&fs_mock, &Locations::from(&options)).unwrap();
+        let mut file = fs_mock.create_file(Path::new("./README")).unwrap();
+        fs_mock.write_to_file(&mut file, vec![4, 5, 6]).unwrap();
+
+        let summary = update(options, &fs_mock, now + 1).expect("Action failed.");
+
+        assert_eq!(summary.skipped.len(), 1);
+        assert_eq!(summary.skipped[0].0, Path::new("./README"));
+        assert!(summary.skipped[0].1.to_string().contains("'Readme'"));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/README")));
+    }
+
+    #[test]
+    fn report_reserved_names() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+        fs_mock.set_state(FsState::new(vec![
+            EntryMock::file("./con.txt", &[1, 2, 3]),
+            EntryMock::file("./notes.", &[4, 5, 6]),
+            EntryMock::file("./console", &[7, 8, 9]),
+        ]));
+
+        let mut summary = create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        summary.reserved.sort();
+
+        assert_eq!(
+            summary.reserved,
+            vec![PathBuf::from("./con.txt"), PathBuf::from("./notes.")]
+        );
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/con.txt")));
+
+        let mut file = fs_mock.create_file(Path::new("./con.txt")).unwrap();
+        fs_mock.write_to_file(&mut file, vec![0]).unwrap();
+        let summary = update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+        assert!(summary.reserved.is_empty());
+    }
+
+    #[test]
+    fn seal_long_file_histories() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let write_file = |content: usize| {
+            let mut file = fs_mock.create_file(Path::new("./test")).unwrap();
+            fs_mock
+                .write_to_file(&mut file, content.to_string().into_bytes())
+                .unwrap();
+        };
+
+        write_file(0);
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        for content in 1..FILE_SEGMENT_LENGTH + 2 {
+            write_file(content);
+            let timestamp = now + content as u64;
+            update(ActionOptions::from_path("."), &fs_mock, timestamp).unwrap();
+        }
+
+        assert!(fs_mock.path_exists(Path::new("./.ka/file-segments/test.1")));
+        let mut history_file = fs_mock
+            .open_readable_file(Path::new("./.ka/files/test"))
+            .unwrap();
+        let stored = FileHistory::decode(&fs_mock.read_from_file(&mut history_file).unwrap());
+        assert_eq!(stored.unwrap().get_changes().len(), 3);
+
+        let tip_cursor = FILE_SEGMENT_LENGTH + 2;
+        for (cursor, content) in [(tip_cursor, FILE_SEGMENT_LENGTH + 1), (3, 2)] {
+            let mut history_file = fs_mock
+                .open_readable_file(Path::new("./.ka/files/test"))
+                .unwrap();
+            let tip = FileHistory::get_tip(&fs_mock, &locations, &mut history_file, cursor);
+            assert_eq!(tip.unwrap().content, content.to_string().into_bytes());
+        }
+
+        let mut history_file = fs_mock
+            .open_readable_file(Path::new("./.ka/files/test"))
+            .unwrap();
+        let history = FileHistory::from_file(&fs_mock, &locations, &mut history_file).unwrap();
+        assert_eq!(history.get_changes().len(), tip_cursor);
+        assert_eq!(history.get_content(1).unwrap(), b"0");
+
+        // The latest content doesn't depend on the sealed changes at all.
+        fs_mock
+            .delete_file(Path::new("./.ka/file-segments/test.1"))
+            .unwrap();
+        let mut history_file = fs_mock
+            .open_readable_file(Path::new("./.ka/files/test"))
+            .unwrap();
+        assert!(FileHistory::get_tip(&fs_mock, &locations, &mut history_file, tip_cursor).is_ok());
+    }
+
+    #[test]
+    fn convert_legacy_history() {
+        let now = 0xC0FFEE;
+        let mut fs_mock = FsMock::new();
+
+        let legacy_index =
+            br#"{"cursor":1,"changes":[{"affected_files":["./test"],"timestamp":1}]}"#;
+        let legacy_history = br#"{"changes":[{"change_index":1,"variant":{"Updated":[{"Inserted":{"at":0,"new_content":[97]}}]}}]}"#;
+
+        fs_mock.set_state(FsState::new(vec![
+            EntryMock::file("./test", b"ab"),
+            EntryMock::dir("./.ka"),
+            EntryMock::file("./.ka/index", legacy_index),
+            EntryMock::dir("./.ka/files"),
+            EntryMock::file("./.ka/files/test", legacy_history),
+        ]));
+
+        update(ActionOptions::from_path("."), &fs_mock, now).expect("Action failed.");
+
+        let mut history_file = fs_mock
+            .open_readable_file(Path::new("./.ka/files/test"))
+            .unwrap();
+        let history_buffer = fs_mock.read_from_file(&mut history_file).unwrap();
+        let history = FileHistory::decode(&history_buffer).unwrap();
+        assert_eq!(history.get_changes().len(), 2);
+        assert_eq!(history.get_content(2).unwrap(), b"ab");
+        // The history is rewritten in the record format, so the next update can append.
+        assert_eq!(history.encode().unwrap(), history_buffer);
+
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let mut index_file = fs_mock
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        let index = RepositoryHistory::from_file(&fs_mock, &locations, &mut index_file).unwrap();
+        assert_eq!(index.cursor, 2);
+        assert!(index.get_stored_length().is_some());
+    }
+
+    #[test]
+    fn record_selected_hunks() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let read_history = |path: &str| {
+            let mut file = fs_mock.open_readable_file(Path::new(path)).unwrap();
+            FileHistory::decode(&fs_mock.read_from_file(&mut file).unwrap()).unwrap()
+        };
+
+        write_file(&fs_mock, "./test", b"first\nsecond\nthird\n");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./test", b"first\nchanged\nthird\nfourth\n");
+        write_file(&fs_mock, "./new", b"new\n");
+
+        let mut select = |path: &Path, _: &[u8], hunk: &Hunk| {
+            path != Path::new("./new") && hunk.new_content != b"fourth\n"
+        };
+        update_interactive(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            now + 1,
+            &mut select,
+        )
+        .expect("Action failed.");
+
+        let history = read_history("./.ka/files/test");
+        assert_eq!(history.get_content(2).unwrap(), b"first\nchanged\nthird\n");
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/new")));
+
+        // The rejected hunks are still there to be recorded later.
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+        let history = read_history("./.ka/files/test");
+        assert_eq!(
+            history.get_content(3).unwrap(),
+            b"first\nchanged\nthird\nfourth\n"
+        );
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/new")));
+    }
+
+    #[test]
+    fn reread_files_changed_while_reading() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let mut file = fs_mock.create_file(Path::new("./busy")).unwrap();
+        fs_mock.write_to_file(&mut file, b"first".to_vec()).unwrap();
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        // Every read sees the file written to once more, until the writes run out.
+        let writes = Mutex::new(vec![
+            b"fourth".to_vec(),
+            b"third".to_vec(),
+            b"second".to_vec(),
+        ]);
+        let fs = HookedFs {
+            inner: fs_mock,
+            on_read: |inner: &FsMock, path: &Path| {
+                if path == Path::new("./busy") {
+                    if let Some(content) = writes.lock().unwrap().pop() {
+                        let mut file = inner.create_file(path)?;
+                        inner.write_to_file(&mut file, content)?;
+                    }
+                }
+                Ok(())
+            },
+        };
+
+        let summary = update(ActionOptions::from_path("."), &fs, now + 1).expect("Action failed.");
+        assert!(summary.racy.is_empty());
+
+        let mut history_file = fs
+            .open_readable_file(Path::new("./.ka/files/busy"))
+            .unwrap();
+        let history = FileHistory::decode(&fs.read_from_file(&mut history_file).unwrap()).unwrap();
+        assert_eq!(history.get_content(2).unwrap(), b"fourth");
+
+        // A file which never settles is recorded anyway, but reported.
+        *writes.lock().unwrap() = (0..10).map(|index| vec![index]).collect();
+        let summary = update(ActionOptions::from_path("."), &fs, now + 2).expect("Action failed.");
+        assert_eq!(summary.racy, vec![PathBuf::from("./busy")]);
+    }
+
+    #[test]
+    fn record_structured_changes() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let locations = Locations::from(&options);
+        let write_file = |content: &[u8]| {
+            let mut file = fs_mock.create_file(Path::new("./package.json")).unwrap();
+            fs_mock.write_to_file(&mut file, content.to_vec()).unwrap();
+        };
+
+        write_file(b"{\n  \"name\": \"ka\",\n  \"tags\": []\n}\n");
+        create(options.clone(), &fs_mock, now).unwrap();
+        let config = Config {
+            structured_diff: vec!["*.json".into()],
+            ..Config::default()
+        };
+        config.write(&fs_mock, &locations).unwrap();
+        let second = b"{\n  \"name\": \"ka\",\n  \"tags\": [\n    \"history\"\n  ]\n}\n";
+        write_file(second);
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        // Formatting which can't be reproduced is recorded as content changes.
+        write_file(b"{ \"name\": \"ka\" }");
+        update(options, &fs_mock, now + 2).unwrap();
+
+        let history_path = Path::new("./.ka/files/package.json");
+        let mut history_file = fs_mock.open_readable_file(history_path).unwrap();
+        let stored = FileHistory::decode(&fs_mock.read_from_file(&mut history_file).unwrap());
+        let variants: Vec<_> = stored
+            .unwrap()
+            .get_changes()
+            .iter()
+            .map(|change| match change.variant {
+                FileChangeVariant::Structured(_) => "structured",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(variants, ["other", "structured", "other"]);
+
+        let mut history_file = fs_mock.open_readable_file(history_path).unwrap();
+        let tip = FileHistory::get_tip(&fs_mock, &locations, &mut history_file, 2).unwrap();
+        assert_eq!(tip.content, second);
+
+        // Loading the history turns it into content changes.
+        let mut history_file = fs_mock.open_readable_file(history_path).unwrap();
+        let history = FileHistory::from_file(&fs_mock, &locations, &mut history_file).unwrap();
+        assert!(history
+            .get_changes()
+            .iter()
+            .all(|change| matches!(change.variant, FileChangeVariant::Updated(_))));
+        assert_eq!(history.get_content(2).unwrap(), second);
+    }
+
+    #[test]
+    fn record_over_memory_budget() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let locations = Locations::from(&options);
+
+        let large: Vec<u8> = (0..64).map(|i| b'a' + i % 26).collect();
+        write_file(&fs_mock, "./large", &large);
+        write_file(&fs_mock, "./small", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+        let config = Config {
+            memory_budget: Some(100),
+            ..Config::default()
+        };
+        config.write(&fs_mock, &locations).unwrap();
+
+        let mut changed_large = large.clone();
+        changed_large[30] = b'!';
+        write_file(&fs_mock, "./large", &changed_large);
+        write_file(&fs_mock, "./small", b"second");
+        let summary = update(options, &fs_mock, now + 1).unwrap();
+        assert_eq!(summary.over_budget, [Path::new("./large")]);
+
+        let history_path = Path::new("./.ka/files/large");
+        let mut history_file = fs_mock.open_readable_file(history_path).unwrap();
+        let history = FileHistory::from_file(&fs_mock, &locations, &mut history_file).unwrap();
+        let change = &history.get_changes()[1];
+        assert!(!change.degraded);
+        assert_eq!(history.get_content(2).unwrap(), changed_large);
+    }
+
+    #[test]
+    fn defer_changes_within_min_interval() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let locations = Locations::from(&options);
+
+        write_file(&fs_mock, "./notes.md", b"first");
+        write_file(&fs_mock, "./todo", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+        let mut config = Config {
+            min_change_interval: Some("10s".to_string()),
+            ..Config::default()
+        };
+        config
+            .min_change_intervals
+            .insert("*.md".to_string(), "1m".to_string());
+        config.write(&fs_mock, &locations).unwrap();
+
+        write_file(&fs_mock, "./notes.md", b"second");
+        write_file(&fs_mock, "./todo", b"second");
+        let summary = update(options.clone(), &fs_mock, now + 5).unwrap();
+        assert_eq!(summary.deferred.len(), 2);
+        assert_eq!(status(options.clone(), &fs_mock).unwrap().cursor, 1);
+
+        write_file(&fs_mock, "./notes.md", b"third");
+        let summary = update(options.clone(), &fs_mock, now + 10).unwrap();
+        assert_eq!(summary.deferred, [Path::new("./notes.md")]);
+        assert_eq!(summary.recorded, [Path::new("./todo")]);
+        let summary = update(options.clone(), &fs_mock, now + 60).unwrap();
+        assert!(summary.deferred.is_empty());
+        assert_eq!(summary.recorded, [Path::new("./notes.md")]);
+
+        // Both saves of the notes ended up in a single change.
+        let history_path = Path::new("./.ka/files/notes.md");
+        let mut history_file = fs_mock.open_readable_file(history_path).unwrap();
+        let history = FileHistory::from_file(&fs_mock, &locations, &mut history_file).unwrap();
+        assert_eq!(history.get_changes().len(), 2);
+        assert_eq!(history.get_content(3).unwrap(), b"third");
+        assert_eq!(status(options, &fs_mock).unwrap().cursor, 3);
+    }
+}
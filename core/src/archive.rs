@@ -0,0 +1,557 @@
+// Minimal writers for the archive formats snapshots can be exported as: ustar for tar, and
+// gzip and zip compressed with deflate. Only the fixed Huffman codes of deflate are used,
+// which compresses text well enough without building code tables, and whatever that doesn't
+// make smaller is stored as it is.
+
+use std::convert::TryFrom;
+
+use anyhow::{bail, Result};
+
+use crate::time::{to_civil_date, SECONDS_PER_DAY};
+
+const TAR_BLOCK_SIZE: usize = 512;
+// A stored deflate block can't hold more than this.
+const MAX_STORED_BLOCK_SIZE: usize = 0xFFFF;
+// How far back deflate can refer to, and the lengths a match can have.
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH_LENGTH: usize = 3;
+const MAX_MATCH_LENGTH: usize = 258;
+// How many earlier positions with the same hash are tried for each match.
+const MAX_CHAIN_LENGTH: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+pub struct ArchiveEntry {
+    // Relative and separated by `/`, as archives expect.
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+pub fn write_tar(entries: &[ArchiveEntry], mtime: u64) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    for entry in entries {
+        buffer.extend(tar_header(entry, mtime)?);
+        buffer.extend(&entry.content);
+        buffer.resize(buffer.len().next_multiple_of(TAR_BLOCK_SIZE), 0);
+    }
+    // The end of the archive is marked by two empty blocks.
+    buffer.resize(buffer.len() + 2 * TAR_BLOCK_SIZE, 0);
+    Ok(buffer)
+}
+
+fn tar_header(entry: &ArchiveEntry, mtime: u64) -> Result<[u8; TAR_BLOCK_SIZE]> {
+    // Names which don't fit are split into a prefix and a name at one of their separators.
+    let (prefix, name) = match entry.name.len() {
+        0..=100 => ("", entry.name.as_str()),
+        _ => match entry.name.as_bytes()[..entry.name.len().min(156)]
+            .iter()
+            .rposition(|byte| *byte == b'/')
+        {
+            Some(split) if split <= 155 && entry.name.len() - split - 1 <= 100 => {
+                (&entry.name[..split], &entry.name[split + 1..])
+            }
+            _ => bail!("The path '{}' is too long for a tar archive.", entry.name),
+        },
+    };
+
+    let mut header = [0; TAR_BLOCK_SIZE];
+    let mut set = |at: usize, value: &[u8]| header[at..at + value.len()].copy_from_slice(value);
+    set(0, name.as_bytes());
+    set(100, b"0000644\0");
+    set(108, b"0000000\0");
+    set(116, b"0000000\0");
+    set(124, format!("{:011o}\0", entry.content.len()).as_bytes());
+    set(136, format!("{:011o}\0", mtime).as_bytes());
+    // The checksum is computed as if its own field was filled with spaces.
+    set(148, b"        ");
+    set(156, b"0");
+    set(257, b"ustar\0");
+    set(263, b"00");
+    set(345, prefix.as_bytes());
+
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
+
+pub fn write_gzip(data: &[u8], mtime: u64) -> Vec<u8> {
+    let mut buffer = vec![0x1F, 0x8B, 8, 0];
+    buffer.extend((mtime as u32).to_le_bytes());
+    // No extra flags, and an unknown operating system.
+    buffer.extend([0, 0xFF]);
+    buffer.extend(compress(data).unwrap_or_else(|| store(data)));
+    buffer.extend(crc32(data).to_le_bytes());
+    buffer.extend((data.len() as u32).to_le_bytes());
+    buffer
+}
+
+// The data as stored deflate blocks.
+fn store(data: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut blocks = data.chunks(MAX_STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        buffer.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        buffer.push(is_final as u8);
+        buffer.extend((block.len() as u16).to_le_bytes());
+        buffer.extend((!(block.len() as u16)).to_le_bytes());
+        buffer.extend(block);
+    }
+    buffer
+}
+
+// The data as a single deflate block with the fixed Huffman codes, where each position is
+// either a literal or the longest earlier match found. None if that isn't any smaller.
+fn compress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut writer = BitWriter::default();
+    // The final block, with fixed codes.
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let mut matcher = Matcher::new(data);
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = matcher.find(position);
+        if length >= MIN_MATCH_LENGTH {
+            write_match(&mut writer, length, distance);
+            for inserted in position..position + length {
+                matcher.insert(inserted);
+            }
+            position += length;
+        } else {
+            write_symbol(&mut writer, data[position] as u16);
+            matcher.insert(position);
+            position += 1;
+        }
+        if writer.buffer.len() > data.len() {
+            return None;
+        }
+    }
+    write_symbol(&mut writer, 256);
+
+    let compressed = writer.finish();
+    if compressed.len() < data.len() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+// Finds earlier occurrences of the data through the hashes of their first bytes.
+struct Matcher<'a> {
+    data: &'a [u8],
+    // The latest position of each hash, and the one before each position with the same hash.
+    heads: Vec<usize>,
+    previous: Vec<usize>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            heads: vec![usize::MAX; 1 << HASH_BITS],
+            previous: vec![usize::MAX; WINDOW_SIZE],
+        }
+    }
+
+    fn get_hash(&self, position: usize) -> Option<usize> {
+        let bytes = self.data.get(position..position + MIN_MATCH_LENGTH)?;
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        Some((value.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize)
+    }
+
+    fn insert(&mut self, position: usize) {
+        if let Some(hash) = self.get_hash(position) {
+            self.previous[position % WINDOW_SIZE] = self.heads[hash];
+            self.heads[hash] = position;
+        }
+    }
+
+    // The longest match for the data at `position` among the earlier positions with its
+    // hash, as its length and distance.
+    fn find(&self, position: usize) -> (usize, usize) {
+        let hash = match self.get_hash(position) {
+            Some(hash) => hash,
+            None => return (0, 0),
+        };
+        let max_length = (self.data.len() - position).min(MAX_MATCH_LENGTH);
+        let wanted = &self.data[position..position + max_length];
+
+        let mut best = (0, 0);
+        let mut candidate = self.heads[hash];
+        for _ in 0..MAX_CHAIN_LENGTH {
+            if candidate == usize::MAX || position - candidate > WINDOW_SIZE {
+                break;
+            }
+            let length = self.data[candidate..]
+                .iter()
+                .zip(wanted)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, position - candidate);
+                if length == max_length {
+                    break;
+                }
+            }
+
+            // Older positions are overwritten once they're out of the window.
+            let next = self.previous[candidate % WINDOW_SIZE];
+            if next == usize::MAX || next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        best
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASES
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap();
+    write_symbol(writer, 257 + code as u16);
+    writer.write(
+        (length - LENGTH_BASES[code] as usize) as u32,
+        LENGTH_EXTRA_BITS[code],
+    );
+
+    let code = DISTANCE_BASES
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write(
+        (distance - DISTANCE_BASES[code] as usize) as u32,
+        DISTANCE_EXTRA_BITS[code],
+    );
+}
+
+// Literals, the end of the block and lengths in the fixed Huffman codes.
+fn write_symbol(writer: &mut BitWriter, symbol: u16) {
+    let (code, length) = match symbol {
+        0..=143 => (0x30 + symbol as u32, 8),
+        144..=255 => (0x190 + (symbol - 144) as u32, 9),
+        256..=279 => ((symbol - 256) as u32, 7),
+        _ => (0xC0 + (symbol - 280) as u32, 8),
+    };
+    writer.write_code(code, length);
+}
+
+// Deflate packs values starting at the least significant bit, except for Huffman codes,
+// which start at their most significant one.
+#[derive(Default)]
+struct BitWriter {
+    buffer: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, length: u32) {
+        self.bits |= value << self.count;
+        self.count += length;
+        while self.count >= 8 {
+            self.buffer.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.buffer.push(self.bits as u8);
+        }
+        self.buffer
+    }
+}
+
+pub fn write_zip(entries: &[ArchiveEntry], mtime: u64) -> Result<Vec<u8>> {
+    if entries.len() > u16::MAX as usize {
+        bail!("A zip archive can't hold more than {} files.", u16::MAX);
+    }
+    let (dos_time, dos_date) = to_dos_time(mtime);
+    // Names are UTF-8.
+    let flags: u16 = 0x0800;
+
+    let mut buffer = Vec::new();
+    let mut central_directory = Vec::new();
+    for entry in entries {
+        let offset = to_u32(buffer.len())?;
+        let size = to_u32(entry.content.len())?;
+        let crc = crc32(&entry.content);
+        // Entries are deflated, unless they're stored as they are.
+        let (method, compressed) = match compress(&entry.content) {
+            Some(compressed) => (8u16, compressed),
+            None => (0u16, entry.content.clone()),
+        };
+        let compressed_size = to_u32(compressed.len())?;
+
+        // The fields shared by the local header and the central directory.
+        let mut fields = Vec::new();
+        fields.extend(20u16.to_le_bytes());
+        fields.extend(flags.to_le_bytes());
+        fields.extend(method.to_le_bytes());
+        fields.extend(dos_time.to_le_bytes());
+        fields.extend(dos_date.to_le_bytes());
+        fields.extend(crc.to_le_bytes());
+        fields.extend(compressed_size.to_le_bytes());
+        fields.extend(size.to_le_bytes());
+        fields.extend((entry.name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+
+        buffer.extend(0x04034B50u32.to_le_bytes());
+        buffer.extend(&fields);
+        buffer.extend(entry.name.as_bytes());
+        buffer.extend(compressed);
+
+        central_directory.extend(0x02014B50u32.to_le_bytes());
+        central_directory.extend(20u16.to_le_bytes());
+        central_directory.extend(&fields);
+        // No comment, on the first disk, without any attributes.
+        central_directory.extend([0; 10]);
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(entry.name.as_bytes());
+    }
+
+    let central_directory_offset = to_u32(buffer.len())?;
+    let central_directory_size = to_u32(central_directory.len())?;
+    buffer.extend(central_directory);
+
+    buffer.extend(0x06054B50u32.to_le_bytes());
+    buffer.extend([0; 4]);
+    buffer.extend((entries.len() as u16).to_le_bytes());
+    buffer.extend((entries.len() as u16).to_le_bytes());
+    buffer.extend(central_directory_size.to_le_bytes());
+    buffer.extend(central_directory_offset.to_le_bytes());
+    buffer.extend(0u16.to_le_bytes());
+    Ok(buffer)
+}
+
+fn to_u32(size: usize) -> Result<u32> {
+    match u32::try_from(size) {
+        Ok(size) => Ok(size),
+        Err(_) => bail!("The zip archive would be larger than 4 GiB."),
+    }
+}
+
+// Zip stores times in the local time of MS-DOS, which starts at 1980.
+fn to_dos_time(timestamp: u64) -> (u16, u16) {
+    let seconds = timestamp % SECONDS_PER_DAY;
+    let (year, month, day) = to_civil_date(timestamp / SECONDS_PER_DAY);
+
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((seconds / 3600) << 11) | ((seconds % 3600 / 60) << 5) | (seconds % 60 / 2);
+    let date = ((year - 1980).min(127) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_dos_time() {
+        // 2021-03-04 05:06:08
+        assert_eq!(
+            to_dos_time(1614834368),
+            ((5 << 11) | (6 << 5) | 4, (41 << 9) | (3 << 5) | 4)
+        );
+    }
+
+    #[test]
+    fn test_tar_header() {
+        let entry = ArchiveEntry {
+            name: "notes/todo".into(),
+            content: b"Water plants".to_vec(),
+        };
+        let archive = write_tar(&[entry], 0).unwrap();
+        assert_eq!(archive.len(), 4 * TAR_BLOCK_SIZE);
+        assert_eq!(&archive[..10], b"notes/todo");
+        assert_eq!(&archive[124..136], b"00000000014\0");
+        assert_eq!(
+            &archive[TAR_BLOCK_SIZE..TAR_BLOCK_SIZE + 12],
+            b"Water plants"
+        );
+
+        let long_name = format!("{}/{}", "a".repeat(120), "b".repeat(90));
+        let entry = ArchiveEntry {
+            name: long_name,
+            content: Vec::new(),
+        };
+        let header = &write_tar(&[entry], 0).unwrap()[..TAR_BLOCK_SIZE];
+        assert_eq!(&header[..90], "b".repeat(90).as_bytes());
+        assert_eq!(&header[345..465], "a".repeat(120).as_bytes());
+
+        let entry = ArchiveEntry {
+            name: "a".repeat(300),
+            content: Vec::new(),
+        };
+        assert!(write_tar(&[entry], 0).is_err());
+    }
+
+    #[test]
+    fn test_stored_blocks() {
+        let data = vec![7; MAX_STORED_BLOCK_SIZE + 1];
+        let stored = store(&data);
+        // Two blocks with their own headers.
+        assert_eq!(stored.len(), 5 + MAX_STORED_BLOCK_SIZE + 5 + 1);
+        assert_eq!(stored[0], 0);
+        assert_eq!(stored[5 + MAX_STORED_BLOCK_SIZE], 1);
+        assert_eq!(inflate(&stored), data);
+        assert_eq!(inflate(&store(b"")), b"");
+    }
+
+    #[test]
+    fn test_compress() {
+        let text = b"Water plants\nFeed cat\nWater plants\nWalk dog\n".repeat(500);
+        let compressed = compress(&text).unwrap();
+        assert!(compressed.len() < text.len() / 20);
+        assert_eq!(inflate(&compressed), text);
+
+        // Long runs and matches further back than the window reaches.
+        let mut data = vec![0; 70000];
+        data.extend((0..WINDOW_SIZE + 1000).map(|i| (i * 7 % 251) as u8));
+        data.extend(data[70000..71000].to_vec());
+        assert_eq!(inflate(&compress(&data).unwrap()), data);
+
+        // Data which doesn't get smaller is left to be stored.
+        assert!(compress(b"ab").is_none());
+        let mut state = 0x2545F491u32;
+        let noise: Vec<u8> = (0..1000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert!(compress(&noise).is_none());
+
+        let gzip = write_gzip(&text, 0);
+        assert_eq!(inflate(&gzip[10..gzip.len() - 8]), text);
+        assert_eq!(&gzip[gzip.len() - 4..], &(text.len() as u32).to_le_bytes());
+
+        let entry = ArchiveEntry {
+            name: "todo".into(),
+            content: text.clone(),
+        };
+        let zip = write_zip(&[entry], 0).unwrap();
+        assert_eq!(&zip[8..10], &8u16.to_le_bytes());
+        assert_eq!(&zip[18..22], &(compressed.len() as u32).to_le_bytes());
+        assert_eq!(inflate(&zip[34..34 + compressed.len()]), text);
+    }
+
+    // Decodes the stored and fixed Huffman blocks the writers produce.
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut reader = BitReader { data, position: 0 };
+        let mut output: Vec<u8> = Vec::new();
+        loop {
+            let is_final = reader.read(1) == 1;
+            match reader.read(2) {
+                0 => {
+                    reader.position = reader.position.next_multiple_of(8);
+                    let length = reader.read(16) as usize;
+                    reader.read(16);
+                    output.extend((0..length).map(|_| reader.read(8) as u8));
+                }
+                1 => loop {
+                    let symbol = match reader.read_code(7) {
+                        code @ 0..=23 => code + 256,
+                        code => match code << 1 | reader.read(1) {
+                            code @ 0x30..=0xBF => code - 0x30,
+                            code @ 0xC0..=0xC7 => code - 0xC0 + 280,
+                            code => (code << 1 | reader.read(1)) - 0x190 + 144,
+                        },
+                    };
+                    if symbol < 256 {
+                        output.push(symbol as u8);
+                        continue;
+                    } else if symbol == 256 {
+                        break;
+                    }
+
+                    let code = symbol as usize - 257;
+                    let length =
+                        LENGTH_BASES[code] as usize + reader.read(LENGTH_EXTRA_BITS[code]) as usize;
+                    let code = reader.read_code(5) as usize;
+                    let distance = DISTANCE_BASES[code] as usize
+                        + reader.read(DISTANCE_EXTRA_BITS[code]) as usize;
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance]);
+                    }
+                },
+                _ => unreachable!(),
+            }
+            if is_final {
+                return output;
+            }
+        }
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read(&mut self, length: u32) -> u32 {
+            let mut value = 0;
+            for bit in 0..length {
+                let byte = self.data[self.position / 8];
+                value |= ((byte >> (self.position % 8)) as u32 & 1) << bit;
+                self.position += 1;
+            }
+            value
+        }
+
+        fn read_code(&mut self, length: u32) -> u32 {
+            (0..length).fold(0, |code, _| code << 1 | self.read(1))
+        }
+    }
+}
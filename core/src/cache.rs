@@ -0,0 +1,340 @@
+use std::{
+    collections::BTreeSet,
+    convert::TryInto,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto,
+    files::Locations,
+    filesystem::Fs,
+    history::{FileChange, FileChangeVariant, FileHistory, FileTip},
+    ignore::{get_global_ignore_path, IGNORE_FILE_NAME},
+};
+
+const CHANGE_INDEX_SIZE: usize = 8;
+
+// Keeps the latest content of each file below `.ka/cache/tips`, as it was last reconstructed
+// from or recorded to its history, so getting it again is a single read instead of replaying
+// the history. An entry is only used while the latest change of the history still has the
+// same index and content hash, so nothing rewriting histories has to clear the cache.
+pub struct TipCache<'a, FS: Fs> {
+    fs: &'a FS,
+    locations: &'a Locations,
+}
+
+impl<'a, FS: Fs> TipCache<'a, FS> {
+    pub fn new(fs: &'a FS, locations: &'a Locations) -> Self {
+        Self { fs, locations }
+    }
+
+    // Like `FileHistory::get_tip`.
+    pub fn get_tip(&self, history_path: &Path, at_cursor: usize) -> Result<FileTip> {
+        let mut history_file = self.fs.open_readable_file(history_path)?;
+        let buffer = self
+            .fs
+            .read_from_file(&mut history_file)
+            .context("Failed reading file history.")?;
+
+        let latest_change = match FileHistory::get_latest_change(&buffer)? {
+            Some(latest_change) if latest_change.change.change_index <= at_cursor => latest_change,
+            _ => {
+                return FileHistory::get_tip_from_buffer(self.fs, self.locations, buffer, at_cursor)
+            }
+        };
+
+        if let Some(content) = self.load(history_path, &latest_change.change) {
+            return Ok(FileTip {
+                content,
+                is_deleted: false,
+                is_forgotten: latest_change.is_forgotten,
+                stored_length: Some(latest_change.stored_length),
+                unsealed_count: latest_change.unsealed_count,
+            });
+        }
+
+        let tip = FileHistory::get_tip_from_buffer(self.fs, self.locations, buffer, at_cursor)?;
+        if !tip.is_deleted {
+            self.store(
+                history_path,
+                latest_change.change.change_index,
+                &tip.content,
+            )?;
+        }
+        Ok(tip)
+    }
+
+    // Records the content the file has after the change, e.g. once it was recorded.
+    pub fn store(&self, history_path: &Path, change_index: usize, content: &[u8]) -> Result<()> {
+        let mut buffer = (change_index as u64).to_le_bytes().to_vec();
+        buffer.extend_from_slice(content);
+
+        let mut entry_file = self.fs.create_file(&self.get_entry_path(history_path)?)?;
+        self.fs.write_to_file(&mut entry_file, buffer)
+    }
+
+    // Entries which can't be read, or aren't current anymore, are just missed.
+    fn load(&self, history_path: &Path, latest_change: &FileChange) -> Option<Vec<u8>> {
+        let content_hash = match latest_change.variant {
+            FileChangeVariant::Deleted => return None,
+            _ => latest_change.content_hash.as_ref()?,
+        };
+
+        let entry_path = self.get_entry_path(history_path).ok()?;
+        if !self.fs.path_exists(&entry_path) {
+            return None;
+        }
+        let mut entry_file = self.fs.open_readable_file(&entry_path).ok()?;
+        let mut buffer = self.fs.read_from_file(&mut entry_file).ok()?;
+        if buffer.len() < CHANGE_INDEX_SIZE {
+            return None;
+        }
+
+        let content = buffer.split_off(CHANGE_INDEX_SIZE);
+        let change_index = u64::from_le_bytes(buffer.try_into().ok()?) as usize;
+        let is_current = change_index == latest_change.change_index
+            && FileChange::hash_content(&content) == *content_hash;
+        is_current.then_some(content)
+    }
+
+    // Named after a hash of the history's path, so the cache doesn't mirror the directories.
+    fn get_entry_path(&self, history_path: &Path) -> Result<PathBuf> {
+        let relative_path = history_path.strip_prefix(&self.locations.ka_files_path)?;
+        let hash = crypto::sha256(relative_path.to_string_lossy().as_bytes());
+        Ok(self
+            .locations
+            .get_tip_cache_path()
+            .join(crypto::to_hex(&hash)))
+    }
+}
+
+// Keeps how many files `status` found to be dirty in `.ka/cache/stat`, along with the stamps of
+// everything it looked at, so the count can be told again without reading any file. Writing a
+// file, adding one to a directory or removing one from it changes their stamps, and recording
+// or shifting changes the index, both of which make the entry stale. Filesystems which don't
+// support stamps can't tell either, so nothing is cached for them.
+pub struct StatCache<'a, FS: Fs> {
+    fs: &'a FS,
+    locations: &'a Locations,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StatCacheEntry {
+    // The length of the index the count was found for.
+    index_length: usize,
+    dirty_count: usize,
+    stamps: Vec<(PathBuf, Option<(u64, u64)>)>,
+}
+
+impl<'a, FS: Fs> StatCache<'a, FS> {
+    pub fn new(fs: &'a FS, locations: &'a Locations) -> Self {
+        Self { fs, locations }
+    }
+
+    // The stored count, unless the index or anything `status` looked at changed since.
+    // Entries which can't be read are just missed.
+    pub fn get_dirty_count(&self, index_length: usize) -> Option<usize> {
+        let cache_path = self.locations.get_stat_cache_path();
+        if !self.fs.supports_stamps() || !self.fs.path_exists(&cache_path) {
+            return None;
+        }
+        let mut cache_file = self.fs.open_readable_file(&cache_path).ok()?;
+        let buffer = self.fs.read_from_file(&mut cache_file).ok()?;
+        let entry: StatCacheEntry = serde_json::from_slice(&buffer).ok()?;
+
+        let is_current = entry.index_length == index_length
+            && entry
+                .stamps
+                .iter()
+                .all(|(path, stamp)| self.fs.stamp(path) == *stamp);
+        is_current.then_some(entry.dirty_count)
+    }
+
+    // Stamps the working files along with the directories they are in, the config and the
+    // ignore files, which decide what `status` looks at.
+    pub fn store(
+        &self,
+        index_length: usize,
+        dirty_count: usize,
+        working_paths: &[PathBuf],
+    ) -> Result<()> {
+        if !self.fs.supports_stamps() {
+            return Ok(());
+        }
+
+        let mut paths = BTreeSet::new();
+        paths.insert(self.locations.get_config_path());
+        paths.insert(self.locations.repository_path.clone());
+        paths.insert(self.locations.repository_path.join(IGNORE_FILE_NAME));
+        paths.extend(get_global_ignore_path());
+        for working_path in working_paths {
+            paths.insert(working_path.clone());
+            paths.extend(
+                working_path
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|path| path.starts_with(&self.locations.repository_path))
+                    .map(Path::to_path_buf),
+            );
+        }
+
+        let entry = StatCacheEntry {
+            index_length,
+            dirty_count,
+            stamps: paths
+                .into_iter()
+                .map(|path| {
+                    let stamp = self.fs.stamp(&path);
+                    (path, stamp)
+                })
+                .collect(),
+        };
+        let buffer = serde_json::to_vec(&entry).context("Failed encoding the stat cache.")?;
+        let mut cache_file = self.fs.create_file(&self.locations.get_stat_cache_path())?;
+        self.fs.write_to_file(&mut cache_file, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        config::Config,
+        files::Locations,
+        filesystem::{
+            mock::{write_file, EntryMock, FileMock, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{StatCache, TipCache};
+
+    #[test]
+    fn skip_stale_tips() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let locations = Locations::from(&options);
+
+        write_file(&fs_mock, "./test", b"a");
+        create(options.clone(), &fs_mock, now).unwrap();
+        let config = Config {
+            tip_cache: true,
+            ..Config::default()
+        };
+        config.write(&fs_mock, &locations).unwrap();
+        write_file(&fs_mock, "./test", b"ab");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+
+        let cache = TipCache::new(&fs_mock, &locations);
+        let history_path = Path::new("./.ka/files/test");
+        let entry_path = cache.get_entry_path(history_path).unwrap();
+        assert!(entry_path.starts_with("./.ka/cache/tips"));
+        assert_eq!(cache.get_tip(history_path, 2).unwrap().content, b"ab");
+
+        // Neither an older change nor other content is taken for the latest one.
+        for (change_index, content) in [(1, b"ab"), (2, b"xy")] {
+            cache.store(history_path, change_index, content).unwrap();
+            assert_eq!(cache.get_tip(history_path, 2).unwrap().content, b"ab");
+        }
+
+        let mut entry_file = fs_mock.open_readable_file(&entry_path).unwrap();
+        let entry = fs_mock.read_from_file(&mut entry_file).unwrap();
+        assert_eq!(entry, [&2u64.to_le_bytes()[..], b"ab"].concat());
+
+        // Going back in time still replays the history.
+        assert_eq!(cache.get_tip(history_path, 1).unwrap().content, b"a");
+
+        write_file(&fs_mock, "./test", b"abc");
+        update(options, &fs_mock, now + 2).unwrap();
+        assert_eq!(cache.get_tip(history_path, 3).unwrap().content, b"abc");
+    }
+
+    #[test]
+    fn miss_stale_dirty_counts() {
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let cache = StatCache::new(&fs_mock, &locations);
+
+        fs_mock.create_directory(Path::new("./.ka")).unwrap();
+        write_file(&fs_mock, "./test", b"a");
+        cache.store(10, 1, &["./test".into()]).unwrap();
+        assert_eq!(cache.get_dirty_count(10), Some(1));
+        assert_eq!(cache.get_dirty_count(11), None);
+
+        // Ignoring other files changes what `status` finds, without touching any of them.
+        write_file(&fs_mock, "./.kaignore", b"*.bak");
+        assert_eq!(cache.get_dirty_count(10), None);
+        cache.store(10, 1, &["./test".into()]).unwrap();
+        assert_eq!(cache.get_dirty_count(10), Some(1));
+
+        // Without stamps, nothing could be told apart, so nothing is cached.
+        let unstamped = UnstampedFs(fs_mock);
+        let cache = StatCache::new(&unstamped, &locations);
+        assert_eq!(cache.get_dirty_count(10), None);
+        unstamped
+            .delete_file(&locations.get_stat_cache_path())
+            .unwrap();
+        cache.store(10, 1, &["./test".into()]).unwrap();
+        assert!(!unstamped.path_exists(&locations.get_stat_cache_path()));
+    }
+
+    // Delegates to the mock, but doesn't support stamps.
+    struct UnstampedFs(FsMock);
+
+    impl Fs for UnstampedFs {
+        type File = FileMock;
+        type Entry = EntryMock;
+
+        fn create_file(&self, path: &Path) -> Result<Self::File> {
+            self.0.create_file(path)
+        }
+
+        fn delete_file(&self, path: &Path) -> Result<()> {
+            self.0.delete_file(path)
+        }
+
+        fn open_readable_file(&self, path: &Path) -> Result<Self::File> {
+            self.0.open_readable_file(path)
+        }
+
+        fn open_writable_file(&self, path: &Path) -> Result<Self::File> {
+            self.0.open_writable_file(path)
+        }
+
+        fn create_directory(&self, path: &Path) -> Result<()> {
+            self.0.create_directory(path)
+        }
+
+        fn read_directory(&self, path: &Path) -> Result<Vec<Self::Entry>> {
+            self.0.read_directory(path)
+        }
+
+        fn delete_directory(&self, path: &Path) -> Result<()> {
+            self.0.delete_directory(path)
+        }
+
+        fn write_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            self.0.write_to_file(file, buffer)
+        }
+
+        fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            self.0.append_to_file(file, buffer)
+        }
+
+        fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>> {
+            self.0.read_from_file(file)
+        }
+
+        fn path_exists(&self, path: &Path) -> bool {
+            self.0.path_exists(path)
+        }
+    }
+}
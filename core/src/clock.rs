@@ -0,0 +1,58 @@
+// Where actions and the CLI take the current time from, in seconds since the Unix epoch.
+// Actions still take the timestamps they record as arguments, the clock of the options is what
+// callers get them from. Embedders can pass their own, e.g. a logical clock, and tests a
+// `MockClock` to know every timestamp in advance.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::SystemTime;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+
+    // There's no clock to ask on `wasm32`, where `SystemTime::now` panics. Hosts there pass
+    // the timestamps themselves, or a clock of their own.
+    #[cfg(target_arch = "wasm32")]
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+// A clock which only moves when it's told to.
+pub struct MockClock {
+    now: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(now: u64) -> Self {
+        MockClock {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
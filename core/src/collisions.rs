@@ -0,0 +1,115 @@
+// Paths which only differ by case, like `README` and `Readme`. Case-insensitive filesystems,
+// like the default ones of macOS and Windows, can only hold one of them, so restoring both
+// would silently overwrite one with the other.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::files::normalize_path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseCollisionPolicy {
+    // Restore all paths as they are, for filesystems which tell them apart.
+    Allow,
+    // Refuse to restore any of them, listing the colliding paths.
+    Error,
+    // Restore the later path with a suffix, like `Readme (2)`.
+    Suffix,
+    // Leave the later path out.
+    Skip,
+}
+
+impl Default for CaseCollisionPolicy {
+    fn default() -> Self {
+        if cfg!(any(target_os = "macos", target_os = "windows")) {
+            CaseCollisionPolicy::Error
+        } else {
+            CaseCollisionPolicy::Allow
+        }
+    }
+}
+
+// Remembers paths by their case-folded form, to find the ones colliding with them.
+#[derive(Debug, Default)]
+pub struct CasePaths {
+    paths: HashMap<String, PathBuf>,
+}
+
+impl CasePaths {
+    // Adds the path unless it collides with one added before, which is returned instead.
+    // The same path twice isn't a collision.
+    pub fn insert(&mut self, path: &Path) -> Option<PathBuf> {
+        let folded_path = fold_case(path);
+        match self.paths.get(&folded_path) {
+            Some(other_path) if normalize_path(other_path) != normalize_path(path) => {
+                Some(other_path.clone())
+            }
+            Some(_) => None,
+            None => {
+                self.paths.insert(folded_path, path.to_path_buf());
+                None
+            }
+        }
+    }
+
+    // Whether the path or one only differing by case from it was added.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.contains_key(&fold_case(path))
+    }
+}
+
+pub fn fold_case(path: &Path) -> String {
+    normalize_path(path).to_string_lossy().to_lowercase()
+}
+
+// The first of `name (2)`, `name (3)` and so on, keeping the extension, which isn't taken yet.
+pub fn get_suffixed_path(path: &Path, is_taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let extension = path.extension();
+    (2..)
+        .map(|number| {
+            let mut file_name = OsString::from(stem);
+            file_name.push(format!(" ({})", number));
+            if let Some(extension) = extension {
+                file_name.push(".");
+                file_name.push(extension);
+            }
+            path.with_file_name(file_name)
+        })
+        .find(|suffixed_path| !is_taken(suffixed_path))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{get_suffixed_path, CasePaths};
+
+    #[test]
+    fn test_case_paths() {
+        let mut paths = CasePaths::default();
+        assert_eq!(paths.insert(Path::new("./docs/README.md")), None);
+        assert_eq!(paths.insert(Path::new("./docs/notes")), None);
+        assert_eq!(paths.insert(Path::new("docs/README.md")), None);
+        assert_eq!(
+            paths.insert(Path::new("./Docs/Readme.md")),
+            Some(Path::new("./docs/README.md").to_path_buf())
+        );
+
+        let taken = [Path::new("./Readme (2).md")];
+        assert_eq!(
+            get_suffixed_path(Path::new("./Readme.md"), |path| taken.contains(&path)),
+            Path::new("./Readme (3).md")
+        );
+        assert_eq!(
+            get_suffixed_path(Path::new("./Makefile"), |_| false),
+            Path::new("./Makefile (2)")
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::ActionOptions,
+    collisions::CaseCollisionPolicy,
+    consistency::ConsistencyPolicy,
+    diff::DiffOptions,
+    files::{normalize_path, Locations},
+    filesystem::Fs,
+    ignore::match_glob,
+    line_endings::LineEndings,
+    notifications::NotificationSink,
+    policy::{ContentType, TrackingMode},
+    tabular::TableConfig,
+    trash::RemovalPolicy,
+    watch::WatchFilters,
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    // How long changes are kept before being pruned, e.g. "30d" or "12h".
+    pub retention: Option<String>,
+    // Files larger than this amount of bytes aren't recorded.
+    pub max_file_size: Option<u64>,
+    pub binary: TrackingMode,
+    // Tracking modes by file extension, which take precedence over the binary mode.
+    pub extensions: BTreeMap<String, TrackingMode>,
+    // Content types by patterns for file names, like `*.md`, instead of telling them by their
+    // content. Binary files are tracked by the binary mode.
+    pub content_types: BTreeMap<String, ContentType>,
+    pub encryption: Option<EncryptionConfig>,
+    // Descend into directories which are repositories of their own, e.g. with a `.ka` or `.git`.
+    pub track_nested_repositories: bool,
+    // How many directories deep working files are looked for, 128 if not set.
+    pub max_depth: Option<usize>,
+    // Directories relative to the repository which are recorded, everything if empty.
+    pub tracked_paths: Vec<PathBuf>,
+    pub ignore: IgnoreConfig,
+    pub diff: DiffOptions,
+    pub consistency: ConsistencyPolicy,
+    // What to do with paths which only differ by case, an error on macOS and Windows.
+    pub case_collisions: CaseCollisionPolicy,
+    // Who changes recorded here are attributed to. The config isn't synced, so each machine
+    // can have its own.
+    pub identity: Identity,
+    // Keep the latest content of every file in `.ka/cache`, so updates don't have to replay
+    // histories to diff against it. It takes about as much space as the working files.
+    pub tip_cache: bool,
+    // What shifting does with working files which don't exist at the cursor it shifts to.
+    pub removed_files: RemovalPolicy,
+    // How line endings of text files are recorded by updates and restored by shifts.
+    pub line_endings: LineEndings,
+    // Merge drivers by patterns for file names, like `CHANGELOG* = union`, for combining
+    // edits both sides made when pulling. Files no pattern matches are merged with `text3`,
+    // see `merge`.
+    pub merge_drivers: BTreeMap<String, String>,
+    // Where the watcher sends what it recorded and its errors, see `notifications`.
+    pub notifications: Vec<NotificationSink>,
+    // Patterns for file names, like `*.json`, of files whose changes are recorded as
+    // operations on their parsed tree, see `structured`.
+    pub structured_diff: Vec<String>,
+    // Tables by patterns for file names, like `*.csv`, whose rows are diffed by their key
+    // column, see `tabular`.
+    pub tables: BTreeMap<String, TableConfig>,
+    // Compare changed images by how different they look as well, in diffs and statuses.
+    // Only 8 bit PNGs can be compared like this, see `images`.
+    pub perceptual_hashes: bool,
+    // How many bytes of old and new content an update diffs at once. Larger files only have
+    // the part between what they start and end with in common diffed, or replaced whole if
+    // that's still too large. No limit if unset.
+    pub memory_budget: Option<u64>,
+    // The least time between two recorded changes of the same file, e.g. "1m". Changes within
+    // it are left for a later update, which records them as one, so auto-saving editors don't
+    // bury everything else in the history. Files aren't limited if unset.
+    pub min_change_interval: Option<String>,
+    // The same by patterns for file names, like `*.md = 30s`, which take precedence.
+    pub min_change_intervals: BTreeMap<String, String>,
+    // Which saves the watcher records, see `watch`. It picks up changes to them while running.
+    pub watch: WatchFilters,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptionConfig {
+    // Parameters of Argon2id for deriving the key from the passphrase, the salt in hex and
+    // the memory in KiB.
+    pub salt: String,
+    pub memory_cost: u32,
+    pub iterations: u32,
+    // Lets us tell a wrong passphrase apart from corrupted files.
+    pub key_check: String,
+}
+
+// Which patterns are combined with the `.kaignore` of the repository.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct IgnoreConfig {
+    // Files nobody wants recorded, like `.DS_Store` or `*.swp`.
+    pub defaults: bool,
+    // `node_modules/`, which tends to be huge and can be installed again.
+    pub node_modules: bool,
+    // The ignore file of the user, `~/.config/ka/ignore`.
+    pub global: bool,
+}
+
+impl Default for IgnoreConfig {
+    fn default() -> Self {
+        Self {
+            defaults: true,
+            node_modules: false,
+            global: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Identity {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    // Tells apart the machines of the same person, e.g. "laptop".
+    pub machine: Option<String>,
+}
+
+impl Identity {
+    // How changes are attributed, like `Ada <ada@example.com> on laptop`. Nothing is recorded
+    // if the identity is empty.
+    pub fn get_author(&self) -> Option<String> {
+        let mut author = self.name.clone().unwrap_or_default();
+        if let Some(ref email) = self.email {
+            if !author.is_empty() {
+                author.push(' ');
+            }
+            author.push_str(&format!("<{}>", email));
+        }
+        if let Some(ref machine) = self.machine {
+            if !author.is_empty() {
+                author.push_str(" on ");
+            }
+            author.push_str(machine);
+        }
+        Some(author).filter(|author| !author.is_empty())
+    }
+}
+
+impl Config {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self).context("Failed encoding config.")
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        serde_json::from_slice::<Self>(buffer).context("Failed decoding config.")
+    }
+
+    // Repositories without a config file just use the defaults.
+    pub fn load<FS: Fs>(fs: &FS, locations: &Locations) -> Result<Self> {
+        let config_path = locations.get_config_path();
+        if !fs.path_exists(&config_path) {
+            return Ok(Self::default());
+        }
+
+        let mut config_file = fs.open_readable_file(&config_path)?;
+        let buffer = fs
+            .read_from_file(&mut config_file)
+            .context("Failed reading config.")?;
+
+        Self::decode(&buffer)
+    }
+
+    pub fn load_from<FS: Fs>(fs: &FS, options: &ActionOptions) -> Result<Self> {
+        Self::load(fs, &Locations::from(options))
+    }
+
+    pub fn write<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<()> {
+        let mut config_file = fs.create_file(&locations.get_config_path())?;
+        fs.write_to_file(&mut config_file, self.encode()?)
+    }
+
+    // Whether the working file is in one of the tracked directories, if there are any.
+    pub fn is_in_scope(&self, locations: &Locations, working_path: &Path) -> bool {
+        if self.tracked_paths.is_empty() {
+            return true;
+        }
+
+        let relative_path = match working_path.strip_prefix(&locations.repository_path) {
+            Ok(relative_path) => normalize_path(relative_path),
+            Err(_) => return false,
+        };
+        self.tracked_paths
+            .iter()
+            .any(|tracked_path| relative_path.starts_with(tracked_path))
+    }
+
+    // The minimum change interval of the working file in seconds, if it has one.
+    pub fn get_min_change_interval(&self, working_path: &Path) -> Result<Option<u64>> {
+        let name = working_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        self.min_change_intervals
+            .iter()
+            .find(|(pattern, _)| match_glob(pattern.as_bytes(), name.as_bytes()))
+            .map(|(_, interval)| interval)
+            .or(self.min_change_interval.as_ref())
+            .map(|interval| parse_duration(interval))
+            .transpose()
+            .context("Invalid minimum change interval.")
+    }
+
+    pub fn get_retention_seconds(&self) -> Result<Option<u64>> {
+        self.retention
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+            .context("Invalid retention policy.")
+    }
+}
+
+pub fn parse_duration(duration: &str) -> Result<u64> {
+    let duration = duration.trim();
+    let unit_start = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (amount, unit) = duration.split_at(unit_start);
+
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("'{}' doesn't start with a number.", duration))?;
+
+    let unit_seconds = match unit.trim() {
+        "s" | "" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        unit => return Err(anyhow!("Unknown unit '{}' in '{}'.", unit, duration)),
+    };
+
+    Ok(amount * unit_seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30d").unwrap(), 30 * 24 * 60 * 60);
+        assert_eq!(parse_duration("12h").unwrap(), 12 * 60 * 60);
+        assert_eq!(parse_duration("90").unwrap(), 90);
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
+
+    #[test]
+    fn test_identity_author() {
+        let mut identity = Identity::default();
+        assert_eq!(identity.get_author(), None);
+
+        identity.machine = Some("laptop".to_string());
+        assert_eq!(identity.get_author().unwrap(), "laptop");
+
+        identity.name = Some("Ada".to_string());
+        identity.email = Some("ada@example.com".to_string());
+        assert_eq!(
+            identity.get_author().unwrap(),
+            "Ada <ada@example.com> on laptop"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    history::{FileHistory, RepositoryHistory},
+};
+
+// Whether the index is cross-checked against the file histories when it's loaded. The check
+// reads every file history, so it's off unless asked for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsistencyPolicy {
+    #[default]
+    Off,
+    // Report inconsistencies, but leave the index as it is.
+    Warn,
+    // Also add the files the index is missing to its changes.
+    Heal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    // The index lists the file as affected by a change its history doesn't have.
+    MissingFileChange { change_index: usize, path: PathBuf },
+    // The history of the file has a change the index doesn't list it for.
+    MissingIndexEntry { change_index: usize, path: PathBuf },
+}
+
+pub fn check_consistency<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    repository_history: &RepositoryHistory,
+) -> Result<Vec<Inconsistency>> {
+    let mut recorded = HashSet::new();
+
+    if fs.path_exists(&locations.ka_files_path) {
+        let mut history_paths = Vec::new();
+        collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+
+        for history_path in history_paths {
+            let working_path = locations.working_from_history(&history_path)?;
+            let mut history_file = fs.open_readable_file(&history_path)?;
+            for file_change in FileHistory::iter_changes(fs, locations, &mut history_file)? {
+                let file_change = file_change
+                    .with_context(|| format!("Failed loading '{}'.", history_path.display()))?;
+                recorded.insert((file_change.change_index, working_path.clone()));
+            }
+        }
+    }
+
+    let mut inconsistencies = Vec::new();
+    let mut listed = HashSet::new();
+
+    for (index, change) in repository_history.get_changes().iter().enumerate() {
+        for path in change.affected_files.iter() {
+            let entry = (index + 1, path.clone());
+            if !recorded.contains(&entry) {
+                inconsistencies.push(Inconsistency::MissingFileChange {
+                    change_index: entry.0,
+                    path: entry.1.clone(),
+                });
+            }
+            listed.insert(entry);
+        }
+    }
+
+    let mut missing_entries: Vec<_> = recorded.difference(&listed).cloned().collect();
+    missing_entries.sort();
+    inconsistencies.extend(
+        missing_entries
+            .into_iter()
+            .map(|(change_index, path)| Inconsistency::MissingIndexEntry { change_index, path }),
+    );
+
+    Ok(inconsistencies)
+}
+
+// Adds the missing index entries which belong to a change the index has. Returns whether
+// anything was healed.
+pub fn heal(repository_history: &mut RepositoryHistory, inconsistencies: &[Inconsistency]) -> bool {
+    let mut healed = false;
+
+    for inconsistency in inconsistencies {
+        if let Inconsistency::MissingIndexEntry { change_index, path } = inconsistency {
+            let change = change_index
+                .checked_sub(1)
+                .and_then(|index| repository_history.get_changes_mut().get_mut(index));
+            if let Some(change) = change {
+                change.affected_files.push(path.clone());
+                healed = true;
+            }
+        }
+    }
+
+    healed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        files::Locations,
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+        history::RepositoryHistory,
+    };
+
+    use super::{check_consistency, heal, Inconsistency};
+
+    fn read_index(fs: &FsMock) -> RepositoryHistory {
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let mut file = fs
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        RepositoryHistory::from_file(fs, &locations, &mut file).unwrap()
+    }
+
+    #[test]
+    fn detect_and_heal_tampered_index() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+
+        write_file(&fs_mock, "./first", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./first", b"first changed");
+        write_file(&fs_mock, "./second", b"second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let mut index = read_index(&fs_mock);
+        assert!(check_consistency(&fs_mock, &locations, &index)
+            .unwrap()
+            .is_empty());
+
+        // Drop one file from the second change, and make up another.
+        index.get_changes_mut()[1].affected_files = vec![
+            Path::new("./second").to_path_buf(),
+            Path::new("./made_up").to_path_buf(),
+        ];
+
+        let inconsistencies = check_consistency(&fs_mock, &locations, &index).unwrap();
+        assert_eq!(
+            inconsistencies,
+            [
+                Inconsistency::MissingFileChange {
+                    change_index: 2,
+                    path: "./made_up".into()
+                },
+                Inconsistency::MissingIndexEntry {
+                    change_index: 2,
+                    path: "./first".into()
+                },
+            ]
+        );
+
+        assert!(heal(&mut index, &inconsistencies));
+        assert!(index.get_changes()[1]
+            .affected_files
+            .contains(&Path::new("./first").to_path_buf()));
+    }
+}
@@ -0,0 +1,1089 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    iter,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, DiffOp};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum ContentChange {
+    Inserted {
+        at: usize,
+        new_content: Vec<u8>,
+    },
+    Deleted {
+        at: usize,
+        upto: usize,
+        // Histories written before this was recorded don't have it, which means the
+        // change can't be undone without replaying the history up to it.
+        #[serde(default)]
+        old_content: Option<Vec<u8>>,
+    },
+    // Large insertions are stored in the object store and only referenced in encoded
+    // histories. They are always resolved back into `Inserted` when a history is loaded.
+    InsertedObject {
+        at: usize,
+        object: String,
+    },
+}
+
+// Why a change doesn't fit the content it is applied to, which means the history it is from
+// is truncated or corrupted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    OutOfBounds {
+        at: usize,
+        upto: usize,
+        length: usize,
+    },
+    UnresolvedObject(String),
+}
+
+impl Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::OutOfBounds { at, upto, length } if at == upto => write!(
+                f,
+                "Can't insert at {} into content of length {}.",
+                at, length
+            ),
+            ApplyError::OutOfBounds { at, upto, length } => write!(
+                f,
+                "Can't change {}..{} of content of length {}.",
+                at, upto, length
+            ),
+            ApplyError::UnresolvedObject(object) => {
+                write!(f, "The object {} was never resolved.", object)
+            }
+        }
+    }
+}
+
+impl Error for ApplyError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    Patience,
+    Lcs,
+}
+
+impl From<DiffAlgorithm> for Algorithm {
+    fn from(algorithm: DiffAlgorithm) -> Self {
+        match algorithm {
+            DiffAlgorithm::Myers => Algorithm::Myers,
+            DiffAlgorithm::Patience => Algorithm::Patience,
+            DiffAlgorithm::Lcs => Algorithm::Lcs,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DiffOptions {
+    pub algorithm: DiffAlgorithm,
+    // Once a diff takes longer than this, a coarser one is accepted. No limit if unset.
+    pub deadline_ms: Option<u64>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: DiffAlgorithm::Myers,
+            deadline_ms: Some(100),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Delta {
+    pub changes: Vec<ContentChange>,
+    // The deadline was hit, so the changes are likely larger than they need to be.
+    pub degraded: bool,
+}
+
+impl ContentChange {
+    pub fn diff(old: &[u8], new: &[u8]) -> Vec<Self> {
+        Self::diff_with(old, new, &DiffOptions::default()).changes
+    }
+
+    pub fn diff_with(old: &[u8], new: &[u8], options: &DiffOptions) -> Delta {
+        let (change_set, degraded) = capture_diff(old, new, options);
+
+        let mut at = 0;
+        let mut changes = Vec::new();
+
+        for diff in change_set {
+            match diff {
+                DiffOp::Delete {
+                    old_index, old_len, ..
+                } => {
+                    changes.push(ContentChange::Deleted {
+                        at,
+                        upto: at + old_len,
+                        old_content: Some(old[old_index..old_index + old_len].to_vec()),
+                    });
+                }
+                DiffOp::Insert {
+                    new_index, new_len, ..
+                } => {
+                    let new_content = &new[new_index..new_index + new_len];
+                    let change = ContentChange::Inserted {
+                        at,
+                        new_content: new_content.to_vec(),
+                    };
+                    at += new_len;
+                    changes.push(change);
+                }
+                DiffOp::Replace {
+                    old_index,
+                    old_len,
+                    new_index,
+                    new_len,
+                } => {
+                    let new_content = &new[new_index..new_index + new_len];
+
+                    let removed_change = ContentChange::Deleted {
+                        at,
+                        upto: at + old_len,
+                        old_content: Some(old[old_index..old_index + old_len].to_vec()),
+                    };
+                    let added_change = ContentChange::Inserted {
+                        at,
+                        new_content: new_content.to_vec(),
+                    };
+
+                    changes.push(removed_change);
+                    changes.push(added_change);
+
+                    at += new_len;
+                }
+                DiffOp::Equal { len, .. } => {
+                    at += len;
+                }
+            }
+        }
+
+        Delta { changes, degraded }
+    }
+
+    // Like `diff_with`, for contents which together are larger than `budget` bytes. Only the
+    // part between their common prefix and suffix is diffed, if it fits into the budget, and
+    // replaced whole otherwise. Replacing it counts as degraded, so `redelta` can still find a
+    // smaller delta later.
+    pub fn diff_within(old: &[u8], new: &[u8], options: &DiffOptions, budget: u64) -> Delta {
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_middle = &old[prefix..old.len() - suffix];
+        let new_middle = &new[prefix..new.len() - suffix];
+
+        let mut delta = if (old_middle.len() + new_middle.len()) as u64 <= budget {
+            Self::diff_with(old_middle, new_middle, options)
+        } else {
+            Delta {
+                changes: Self::snapshot(old_middle, new_middle),
+                degraded: true,
+            }
+        };
+        for change in delta.changes.iter_mut() {
+            match change {
+                ContentChange::Inserted { at, .. } | ContentChange::InsertedObject { at, .. } => {
+                    *at += prefix
+                }
+                ContentChange::Deleted { at, upto, .. } => {
+                    *at += prefix;
+                    *upto += prefix;
+                }
+            }
+        }
+        delta
+    }
+
+    // Replaces the whole content instead of computing a delta, which is what we want for
+    // content where deltas don't make sense, like compressed files.
+    pub fn snapshot(old: &[u8], new: &[u8]) -> Vec<Self> {
+        if old == new {
+            return Vec::new();
+        }
+
+        let mut changes = Vec::new();
+        if !old.is_empty() {
+            changes.push(ContentChange::Deleted {
+                at: 0,
+                upto: old.len(),
+                old_content: Some(old.to_vec()),
+            });
+        }
+        if !new.is_empty() {
+            changes.push(ContentChange::Inserted {
+                at: 0,
+                new_content: new.to_vec(),
+            });
+        }
+        changes
+    }
+
+    // Changes come from history files, which might be corrupted or edited by hand, so ranges
+    // outside of the buffer are an error instead of a panic.
+    pub fn apply(&self, buffer: &mut Vec<u8>) -> Result<(), ApplyError> {
+        match self {
+            ContentChange::Deleted { at, upto, .. } => {
+                if at > upto || *upto > buffer.len() {
+                    return Err(ApplyError::OutOfBounds {
+                        at: *at,
+                        upto: *upto,
+                        length: buffer.len(),
+                    });
+                }
+                buffer.drain(at..upto);
+            }
+            ContentChange::Inserted { at, new_content } => {
+                if *at > buffer.len() {
+                    return Err(ApplyError::OutOfBounds {
+                        at: *at,
+                        upto: *at,
+                        length: buffer.len(),
+                    });
+                }
+                buffer.splice(at..at, new_content.clone());
+            }
+            ContentChange::InsertedObject { object, .. } => {
+                return Err(ApplyError::UnresolvedObject(object.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    // Undoes the change on the buffer it was applied to. If the change can't be undone,
+    // because the deleted content is unknown or the buffer doesn't contain what the change
+    // produced, `false` is returned and the buffer is left untouched.
+    pub fn unapply(&self, buffer: &mut Vec<u8>) -> bool {
+        match self {
+            ContentChange::Inserted { at, new_content } => {
+                let inserted = buffer
+                    .get(*at..)
+                    .and_then(|rest| rest.get(..new_content.len()));
+                if inserted != Some(new_content.as_slice()) {
+                    return false;
+                }
+                buffer.drain(*at..at + new_content.len());
+                true
+            }
+            ContentChange::Deleted {
+                at,
+                upto,
+                old_content: Some(old_content),
+            } => {
+                if *at > buffer.len() || upto.checked_sub(*at) != Some(old_content.len()) {
+                    return false;
+                }
+                buffer.splice(at..at, old_content.clone());
+                true
+            }
+            ContentChange::Deleted {
+                old_content: None, ..
+            } => false,
+            ContentChange::InsertedObject { .. } => false,
+        }
+    }
+}
+
+// A replacement of the range `at..upto` of some base content, where unlike with
+// `ContentChange` all positions refer to the base content itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub at: usize,
+    pub upto: usize,
+    pub new_content: Vec<u8>,
+}
+
+impl Hunk {
+    pub fn diff(old: &[u8], new: &[u8]) -> Vec<Self> {
+        let (change_set, _) = capture_diff(old, new, &DiffOptions::default());
+
+        change_set
+            .into_iter()
+            .filter_map(|diff| match diff {
+                DiffOp::Delete {
+                    old_index, old_len, ..
+                } => Some(Hunk {
+                    at: old_index,
+                    upto: old_index + old_len,
+                    new_content: Vec::new(),
+                }),
+                DiffOp::Insert {
+                    old_index,
+                    new_index,
+                    new_len,
+                } => Some(Hunk {
+                    at: old_index,
+                    upto: old_index,
+                    new_content: new[new_index..new_index + new_len].to_vec(),
+                }),
+                DiffOp::Replace {
+                    old_index,
+                    old_len,
+                    new_index,
+                    new_len,
+                } => Some(Hunk {
+                    at: old_index,
+                    upto: old_index + old_len,
+                    new_content: new[new_index..new_index + new_len].to_vec(),
+                }),
+                DiffOp::Equal { .. } => None,
+            })
+            .collect()
+    }
+
+    // Like `diff`, but the hunks always span whole lines, which is what people expect
+    // when reviewing them.
+    pub fn diff_lines(old: &[u8], new: &[u8]) -> Vec<Self> {
+        let old_lines: Vec<&[u8]> = old.split_inclusive(|byte| *byte == b'\n').collect();
+        let new_lines: Vec<&[u8]> = new.split_inclusive(|byte| *byte == b'\n').collect();
+        let old_offsets = get_line_offsets(&old_lines);
+        let new_offsets = get_line_offsets(&new_lines);
+
+        similar::capture_diff_slices(Algorithm::Myers, &old_lines, &new_lines)
+            .into_iter()
+            .filter_map(|diff| {
+                let (old_index, old_len, new_index, new_len) = match diff {
+                    DiffOp::Delete {
+                        old_index,
+                        old_len,
+                        new_index,
+                    } => (old_index, old_len, new_index, 0),
+                    DiffOp::Insert {
+                        old_index,
+                        new_index,
+                        new_len,
+                    } => (old_index, 0, new_index, new_len),
+                    DiffOp::Replace {
+                        old_index,
+                        old_len,
+                        new_index,
+                        new_len,
+                    } => (old_index, old_len, new_index, new_len),
+                    DiffOp::Equal { .. } => return None,
+                };
+
+                Some(Hunk {
+                    at: old_offsets[old_index],
+                    upto: old_offsets[old_index + old_len],
+                    new_content: new[new_offsets[new_index]..new_offsets[new_index + new_len]]
+                        .to_vec(),
+                })
+            })
+            .collect()
+    }
+
+    // Moves the hunk onto the content produced by applying `change` to its base content.
+    // Returns `None` if the change touches the replaced range, as we can't tell which
+    // of the two edits should win.
+    pub fn rebase(&self, change: &ContentChange) -> Option<Self> {
+        let (at, upto) = match *change {
+            ContentChange::Inserted {
+                at,
+                ref new_content,
+            } => {
+                if at <= self.at {
+                    (self.at + new_content.len(), self.upto + new_content.len())
+                } else if at >= self.upto {
+                    (self.at, self.upto)
+                } else {
+                    return None;
+                }
+            }
+            ContentChange::Deleted { at, upto, .. } => {
+                if upto <= self.at {
+                    (self.at - (upto - at), self.upto - (upto - at))
+                } else if at >= self.upto {
+                    (self.at, self.upto)
+                } else {
+                    return None;
+                }
+            }
+            ContentChange::InsertedObject { .. } => return None,
+        };
+
+        Some(Hunk {
+            at,
+            upto,
+            new_content: self.new_content.clone(),
+        })
+    }
+
+    pub fn apply_all(hunks: &[Self], buffer: &mut Vec<u8>) {
+        // Applying from back to front keeps the positions of the remaining hunks valid.
+        for hunk in hunks.iter().rev() {
+            buffer.splice(hunk.at..hunk.upto, hunk.new_content.clone());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextGranularity {
+    Line,
+    Word,
+    Character,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Equal,
+    Inserted,
+    Deleted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextSegment {
+    pub kind: SegmentKind,
+    pub text: String,
+}
+
+// Diffs text for presenting it, as opposed to `ContentChange::diff` which works on bytes.
+// Adjacent tokens of the same kind are merged into one segment.
+pub fn diff_text(old: &str, new: &str, granularity: TextGranularity) -> Vec<TextSegment> {
+    let old_tokens = tokenize(old, granularity);
+    let new_tokens = tokenize(new, granularity);
+    let change_set = similar::capture_diff_slices(Algorithm::Myers, &old_tokens, &new_tokens);
+
+    let mut segments = Vec::new();
+    for diff in change_set {
+        match diff {
+            DiffOp::Equal { old_index, len, .. } => push_segment(
+                &mut segments,
+                SegmentKind::Equal,
+                &old_tokens[old_index..old_index + len],
+            ),
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => push_segment(
+                &mut segments,
+                SegmentKind::Deleted,
+                &old_tokens[old_index..old_index + old_len],
+            ),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => push_segment(
+                &mut segments,
+                SegmentKind::Inserted,
+                &new_tokens[new_index..new_index + new_len],
+            ),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                push_segment(
+                    &mut segments,
+                    SegmentKind::Deleted,
+                    &old_tokens[old_index..old_index + old_len],
+                );
+                push_segment(
+                    &mut segments,
+                    SegmentKind::Inserted,
+                    &new_tokens[new_index..new_index + new_len],
+                );
+            }
+        }
+    }
+    segments
+}
+
+// A change to text, for consumers like editors which work on strings rather than bytes, such
+// as the plugins using `ka_get_text_changes` of the C interface.
+// Positions count characters, and `upto` is where the deletion ends, not its length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextChange {
+    Inserted { at: usize, text: String },
+    Deleted { at: usize, upto: usize },
+}
+
+impl TextChange {
+    // The changes which turn `old` into `new` when applied in order. Like `diff_text`, this
+    // never splits what is displayed as a single character.
+    pub fn diff(old: &str, new: &str) -> Vec<TextChange> {
+        let mut changes = Vec::new();
+        let mut position = 0;
+
+        for segment in diff_text(old, new, TextGranularity::Character) {
+            let length = segment.text.chars().count();
+            match segment.kind {
+                SegmentKind::Equal => position += length,
+                SegmentKind::Deleted => changes.push(TextChange::Deleted {
+                    at: position,
+                    upto: position + length,
+                }),
+                SegmentKind::Inserted => {
+                    changes.push(TextChange::Inserted {
+                        at: position,
+                        text: segment.text,
+                    });
+                    position += length;
+                }
+            }
+        }
+        changes
+    }
+
+    // Returns `false` and leaves the text untouched if the positions are out of range.
+    pub fn apply(&self, text: &mut String) -> bool {
+        match self {
+            TextChange::Inserted { at, text: inserted } => match get_byte_offset(text, *at) {
+                Some(offset) => {
+                    text.insert_str(offset, inserted);
+                    true
+                }
+                None => false,
+            },
+            TextChange::Deleted { at, upto } => {
+                match (get_byte_offset(text, *at), get_byte_offset(text, *upto)) {
+                    (Some(start), Some(end)) if start <= end => {
+                        text.replace_range(start..end, "");
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+fn get_byte_offset(text: &str, position: usize) -> Option<usize> {
+    text.char_indices()
+        .map(|(offset, _)| offset)
+        .chain(iter::once(text.len()))
+        .nth(position)
+}
+
+fn push_segment(segments: &mut Vec<TextSegment>, kind: SegmentKind, tokens: &[&str]) {
+    if tokens.is_empty() {
+        return;
+    }
+
+    match segments.last_mut() {
+        Some(last) if last.kind == kind => last.text.extend(tokens.iter().copied()),
+        _ => segments.push(TextSegment {
+            kind,
+            text: tokens.concat(),
+        }),
+    }
+}
+
+fn tokenize(text: &str, granularity: TextGranularity) -> Vec<&str> {
+    match granularity {
+        TextGranularity::Line => text.split_inclusive('\n').collect(),
+        TextGranularity::Word => split_where(text, |previous, next| {
+            let class = get_word_class(next);
+            class != get_word_class(previous) || class == WordClass::Other
+        }),
+        TextGranularity::Character => split_where(text, |_, _| true),
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum WordClass {
+    Word,
+    Whitespace,
+    Other,
+}
+
+fn get_word_class(c: char) -> WordClass {
+    if c.is_alphanumeric() || c == '_' {
+        WordClass::Word
+    } else if c.is_whitespace() {
+        WordClass::Whitespace
+    } else {
+        WordClass::Other
+    }
+}
+
+// Splits between two characters if `is_boundary` says so, but never inside of what is
+// displayed as a single character. Without a segmentation table this only approximates
+// grapheme clusters, covering combining marks, variation selectors, emoji sequences
+// and CRLF.
+fn split_where(text: &str, is_boundary: impl Fn(char, char) -> bool) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut previous = None;
+
+    for (index, c) in text.char_indices() {
+        if let Some(previous) = previous {
+            let is_cluster =
+                is_extending(c) || previous == '\u{200D}' || (previous == '\r' && c == '\n');
+            if !is_cluster && is_boundary(previous, c) {
+                tokens.push(&text[start..index]);
+                start = index;
+            }
+        }
+        previous = Some(c);
+    }
+
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+fn is_extending(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{FE20}'..='\u{FE2F}'
+        | '\u{200D}'
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0100}'..='\u{E01EF}')
+}
+
+// The offset at which each line starts, followed by the length of all lines.
+fn get_line_offsets(lines: &[&[u8]]) -> Vec<usize> {
+    let mut offsets = vec![0];
+    for line in lines {
+        offsets.push(offsets[offsets.len() - 1] + line.len());
+    }
+    offsets
+}
+
+fn capture_diff(old: &[u8], new: &[u8], options: &DiffOptions) -> (Vec<DiffOp>, bool) {
+    // There is no clock on `wasm32`, so diffs there always run to completion.
+    let deadline = options
+        .deadline_ms
+        .filter(|_| cfg!(not(target_arch = "wasm32")))
+        .map(|deadline_ms| Instant::now() + Duration::from_millis(deadline_ms));
+    let change_set = match options.algorithm {
+        DiffAlgorithm::Lcs => capture_lcs_diff(old, new, deadline),
+        algorithm => similar::capture_diff_slices_deadline(algorithm.into(), old, new, deadline),
+    };
+
+    // `similar` doesn't tell us whether it gave up early, only that it stops refining
+    // once the deadline has passed.
+    let degraded = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    (change_set, degraded)
+}
+
+// The LCS of `similar` overflows when the common prefix and suffix overlap, like for "a" and
+// "aa", so they are split off before it gets to see them.
+fn capture_lcs_diff(old: &[u8], new: &[u8], deadline: Option<Instant>) -> Vec<DiffOp> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut change_set = Vec::new();
+    if prefix > 0 {
+        change_set.push(DiffOp::Equal {
+            old_index: 0,
+            new_index: 0,
+            len: prefix,
+        });
+    }
+    // It also gives nothing at all if either side is empty.
+    match (old_middle.is_empty(), new_middle.is_empty()) {
+        (true, true) => {}
+        (true, false) => change_set.push(DiffOp::Insert {
+            old_index: prefix,
+            new_index: prefix,
+            new_len: new_middle.len(),
+        }),
+        (false, true) => change_set.push(DiffOp::Delete {
+            old_index: prefix,
+            old_len: old_middle.len(),
+            new_index: prefix,
+        }),
+        (false, false) => {
+            let middle = similar::capture_diff_slices_deadline(
+                Algorithm::Lcs,
+                old_middle,
+                new_middle,
+                deadline,
+            );
+            change_set.extend(middle.into_iter().map(|op| offset_op(op, prefix)));
+        }
+    }
+    if suffix > 0 {
+        change_set.push(DiffOp::Equal {
+            old_index: old.len() - suffix,
+            new_index: new.len() - suffix,
+            len: suffix,
+        });
+    }
+    change_set
+}
+
+fn offset_op(op: DiffOp, by: usize) -> DiffOp {
+    match op {
+        DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } => DiffOp::Equal {
+            old_index: old_index + by,
+            new_index: new_index + by,
+            len,
+        },
+        DiffOp::Delete {
+            old_index,
+            old_len,
+            new_index,
+        } => DiffOp::Delete {
+            old_index: old_index + by,
+            old_len,
+            new_index: new_index + by,
+        },
+        DiffOp::Insert {
+            old_index,
+            new_index,
+            new_len,
+        } => DiffOp::Insert {
+            old_index: old_index + by,
+            new_index: new_index + by,
+            new_len,
+        },
+        DiffOp::Replace {
+            old_index,
+            old_len,
+            new_index,
+            new_len,
+        } => DiffOp::Replace {
+            old_index: old_index + by,
+            old_len,
+            new_index: new_index + by,
+            new_len,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentChange::*, *};
+
+    #[test]
+    fn test_diff() {
+        let old = "This is an old string...";
+        let new = "This is a new string...!";
+
+        let changes = ContentChange::diff(old.as_bytes(), new.as_bytes());
+        assert_eq!(
+            changes.as_slice(),
+            [
+                Inserted {
+                    at: 9,
+                    new_content: " ".into()
+                },
+                Deleted {
+                    at: 11,
+                    upto: 15,
+                    old_content: Some(" old".into())
+                },
+                Inserted {
+                    at: 11,
+                    new_content: "ew".into()
+                },
+                Inserted {
+                    at: 23,
+                    new_content: "!".into()
+                }
+            ],
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let old = "This is an old string...";
+        let new = "This is a new text...!";
+
+        let changes = ContentChange::diff(old.as_bytes(), new.as_bytes());
+
+        let mut buffer = old.as_bytes().to_vec();
+        for change in changes {
+            change.apply(&mut buffer).unwrap();
+        }
+
+        assert_eq!(&buffer, new.as_bytes());
+
+        // Ranges outside of the buffer leave it untouched.
+        for change in [
+            Inserted {
+                at: 100,
+                new_content: b"!".to_vec(),
+            },
+            Deleted {
+                at: 4,
+                upto: 100,
+                old_content: None,
+            },
+            Deleted {
+                at: 4,
+                upto: 2,
+                old_content: Some(Vec::new()),
+            },
+        ] {
+            assert!(change.apply(&mut buffer).is_err());
+            assert!(!change.unapply(&mut buffer));
+        }
+        assert_eq!(&buffer, new.as_bytes());
+    }
+
+    #[test]
+    fn test_unapply() {
+        let old = "This is an old string...";
+        let new = "This is a new text...!";
+
+        let changes = ContentChange::diff(old.as_bytes(), new.as_bytes());
+
+        let mut buffer = new.as_bytes().to_vec();
+        for change in changes.iter().rev() {
+            assert!(change.unapply(&mut buffer));
+        }
+
+        assert_eq!(&buffer, old.as_bytes());
+
+        let unknown_deletion = Deleted {
+            at: 0,
+            upto: 4,
+            old_content: None,
+        };
+        assert!(!unknown_deletion.unapply(&mut buffer));
+        assert_eq!(&buffer, old.as_bytes());
+    }
+
+    #[test]
+    fn test_hunk_rebase() {
+        let base = "first line\nsecond line\n";
+        let reverted = "first line\nsecond\n";
+        let edited = "zeroth line\nfirst line\nsecond line\n";
+
+        let hunks: Option<Vec<Hunk>> = Hunk::diff(base.as_bytes(), reverted.as_bytes())
+            .iter()
+            .map(|hunk| {
+                ContentChange::diff(base.as_bytes(), edited.as_bytes())
+                    .iter()
+                    .try_fold(hunk.clone(), |hunk, change| hunk.rebase(change))
+            })
+            .collect();
+
+        let mut buffer = edited.as_bytes().to_vec();
+        Hunk::apply_all(&hunks.unwrap(), &mut buffer);
+
+        assert_eq!(&buffer, "zeroth line\nfirst line\nsecond\n".as_bytes());
+    }
+
+    #[test]
+    fn test_hunk_rebase_conflict() {
+        let hunk = Hunk {
+            at: 2,
+            upto: 6,
+            new_content: Vec::new(),
+        };
+
+        assert_eq!(
+            hunk.rebase(&Deleted {
+                at: 4,
+                upto: 8,
+                old_content: None
+            }),
+            None
+        );
+        assert_eq!(
+            hunk.rebase(&Inserted {
+                at: 3,
+                new_content: vec![0]
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_diff_options() {
+        let old = "first line\nsecond line\nthird line\n".as_bytes();
+        let new = "first line\nthird line\nfourth line\n".as_bytes();
+
+        // The common prefix and suffix of the latter overlap, which trips up some algorithms.
+        for (old, new) in [(old, new), (b"a".as_slice(), b"aa".as_slice())] {
+            for algorithm in [
+                DiffAlgorithm::Myers,
+                DiffAlgorithm::Patience,
+                DiffAlgorithm::Lcs,
+            ] {
+                let options = DiffOptions {
+                    algorithm,
+                    deadline_ms: None,
+                };
+                let delta = ContentChange::diff_with(old, new, &options);
+                assert!(!delta.degraded);
+
+                let mut buffer = old.to_vec();
+                for change in delta.changes {
+                    change.apply(&mut buffer).unwrap();
+                }
+                assert_eq!(buffer, new);
+            }
+        }
+
+        // A deadline which has already passed still yields a valid, but degraded diff.
+        let options = DiffOptions {
+            algorithm: DiffAlgorithm::Myers,
+            deadline_ms: Some(0),
+        };
+        let delta = ContentChange::diff_with(old, new, &options);
+        assert!(delta.degraded);
+        let mut buffer = old.to_vec();
+        for change in delta.changes {
+            change.apply(&mut buffer).unwrap();
+        }
+        assert_eq!(buffer, new);
+    }
+
+    #[test]
+    fn test_diff_within() {
+        let old = "header\nfirst line\nsecond line\nfooter\n".as_bytes();
+        let new = "header\nfirst line\nthird line\nfooter\n".as_bytes();
+        let options = DiffOptions {
+            algorithm: DiffAlgorithm::Myers,
+            deadline_ms: None,
+        };
+
+        // The changed middle fits into the larger budget, but not into the smaller one.
+        for (budget, degraded) in [(16, false), (8, true)] {
+            let delta = ContentChange::diff_within(old, new, &options, budget);
+            assert_eq!(delta.degraded, degraded);
+            let mut buffer = old.to_vec();
+            for change in delta.changes {
+                change.apply(&mut buffer).unwrap();
+            }
+            assert_eq!(buffer, new);
+        }
+
+        let delta = ContentChange::diff_within(old, new, &options, 8);
+        assert_eq!(
+            delta.changes,
+            [
+                Deleted {
+                    at: 18,
+                    upto: 23,
+                    old_content: Some("secon".into())
+                },
+                Inserted {
+                    at: 18,
+                    new_content: "thir".into()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_text() {
+        let old = "The quick brown fox.";
+        let new = "The quick red fox!";
+
+        assert_eq!(
+            diff_text(old, new, TextGranularity::Word),
+            [
+                TextSegment {
+                    kind: SegmentKind::Equal,
+                    text: "The quick ".into()
+                },
+                TextSegment {
+                    kind: SegmentKind::Deleted,
+                    text: "brown".into()
+                },
+                TextSegment {
+                    kind: SegmentKind::Inserted,
+                    text: "red".into()
+                },
+                TextSegment {
+                    kind: SegmentKind::Equal,
+                    text: " fox".into()
+                },
+                TextSegment {
+                    kind: SegmentKind::Deleted,
+                    text: ".".into()
+                },
+                TextSegment {
+                    kind: SegmentKind::Inserted,
+                    text: "!".into()
+                },
+            ]
+        );
+
+        // Combining marks stay with the character they belong to.
+        assert_eq!(
+            tokenize("cafe\u{301}!", TextGranularity::Character),
+            ["c", "a", "f", "e\u{301}", "!"]
+        );
+        assert_eq!(
+            tokenize("one two\nthree", TextGranularity::Line),
+            ["one two\n", "three"]
+        );
+    }
+
+    #[test]
+    fn test_hunk_diff_lines() {
+        let old = "first\nsecond\nthird\nfourth\n";
+        let new = "first\nchanged\nthird\nfourth\nfifth\n";
+
+        let hunks = Hunk::diff_lines(old.as_bytes(), new.as_bytes());
+        assert_eq!(
+            hunks,
+            [
+                Hunk {
+                    at: 6,
+                    upto: 13,
+                    new_content: "changed\n".into()
+                },
+                Hunk {
+                    at: 26,
+                    upto: 26,
+                    new_content: "fifth\n".into()
+                }
+            ]
+        );
+
+        let mut buffer = old.as_bytes().to_vec();
+        Hunk::apply_all(&hunks, &mut buffer);
+        assert_eq!(&buffer, new.as_bytes());
+    }
+
+    #[test]
+    fn test_text_changes() {
+        let old = "grüße, café\u{301} 🙂";
+        let new = "grüsse, cafe 🙃!";
+
+        let changes = TextChange::diff(old, new);
+        assert!(changes.contains(&TextChange::Deleted { at: 3, upto: 4 }));
+
+        let mut text = old.to_string();
+        for change in changes.iter() {
+            assert!(change.apply(&mut text));
+        }
+        assert_eq!(text, new);
+
+        let mut text = "äö".to_string();
+        assert!(!TextChange::Deleted { at: 1, upto: 3 }.apply(&mut text));
+        assert!(!TextChange::Deleted { at: 2, upto: 1 }.apply(&mut text));
+        assert!(TextChange::Inserted {
+            at: 2,
+            text: "ü".into()
+        }
+        .apply(&mut text));
+        assert_eq!(text, "äöü");
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    convert::TryFrom,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+
+use crate::{
+    actions::ActionOptions,
+    config::{Config, EncryptionConfig},
+    crypto,
+    files::{collect_files, Locations},
+    filesystem::Fs,
+    journal::{self, Journal},
+};
+
+// Ahead of the frames, so encrypted files can't be mistaken for plain ones.
+const MAGIC: &[u8] = b"KAENC2";
+const KEY_CHECK_MESSAGE: &[u8] = b"ka key check";
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+const LENGTH_BYTES: usize = 8;
+// The parameters the Argon2 crate recommends, in KiB and passes over the memory.
+pub const DEFAULT_MEMORY_COST: u32 = Params::DEFAULT_M_COST;
+pub const DEFAULT_ITERATIONS: u32 = Params::DEFAULT_T_COST;
+
+// Wraps another filesystem, transparently encrypting everything written to the `.ka`
+// directory with XChaCha20-Poly1305. The config is left as it is, as it holds the parameters
+// needed to derive the key, and so are the hooks, which are run by the system.
+pub struct EncryptedFs<F: Fs> {
+    inner: F,
+    cipher: XChaCha20Poly1305,
+    ka_path: PathBuf,
+    config_path: PathBuf,
+    hooks_path: PathBuf,
+}
+
+pub struct EncryptedFile<F: Fs> {
+    inner: F::File,
+    path: PathBuf,
+    is_encrypted: bool,
+}
+
+impl<F: Fs> EncryptedFs<F> {
+    pub fn open(inner: F, options: &ActionOptions, passphrase: &str) -> Result<Self> {
+        let locations = Locations::from(options);
+        let config = Config::load(&inner, &locations)?;
+        let encryption = config
+            .encryption
+            .context("The repository isn't encrypted.")?;
+
+        let key = derive_key(&encryption, passphrase)?;
+        let fs = Self::with_key(inner, &locations, &key);
+        let key_check = from_hex(&encryption.key_check).context("Invalid key check.")?;
+        if fs.decrypt(&key_check).ok().as_deref() != Some(KEY_CHECK_MESSAGE) {
+            bail!("The passphrase for the repository is wrong.");
+        }
+
+        Ok(fs)
+    }
+
+    fn with_key(inner: F, locations: &Locations, key: &[u8; KEY_LENGTH]) -> Self {
+        Self {
+            inner,
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+            ka_path: locations.ka_path.clone(),
+            config_path: locations.get_config_path(),
+            hooks_path: locations.get_hooks_path(),
+        }
+    }
+
+    fn is_encrypted(&self, path: &Path) -> bool {
+        path.starts_with(&self.ka_path)
+            && path != self.config_path
+            && !path.starts_with(&self.hooks_path)
+    }
+
+    fn wrap(&self, path: &Path, file: F::File) -> EncryptedFile<F> {
+        EncryptedFile {
+            inner: file,
+            path: path.to_path_buf(),
+            is_encrypted: self.is_encrypted(path),
+        }
+    }
+
+    // Encrypted files are the magic followed by one frame for every write and append, so
+    // appending doesn't have to encrypt what's already there. A frame is its nonce, the length
+    // of its ciphertext as 8 bytes of little endian, and the ciphertext, which is bound to the
+    // position of the frame so frames can't be reordered.
+    fn encrypt_frame(&self, index: usize, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        getrandom::getrandom(&mut nonce)
+            .map_err(|error| anyhow!("Failed getting random bytes, {}.", error))?;
+        let aad = get_frame_aad(index);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow!("Failed encrypting."))?;
+
+        let mut frame = Vec::with_capacity(NONCE_LENGTH + LENGTH_BYTES + ciphertext.len());
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+        frame.extend(ciphertext);
+        Ok(frame)
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut payload = MAGIC.to_vec();
+        payload.extend(self.encrypt_frame(0, plaintext)?);
+        Ok(payload)
+    }
+
+    fn decrypt(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let (frames, _) = split_frames(payload)?;
+        let mut plaintext = Vec::new();
+        for (index, (nonce, ciphertext)) in frames.into_iter().enumerate() {
+            let aad = get_frame_aad(index);
+            plaintext.extend(
+                self.cipher
+                    .decrypt(
+                        XNonce::from_slice(nonce),
+                        Payload {
+                            msg: ciphertext,
+                            aad: &aad,
+                        },
+                    )
+                    .map_err(|_| anyhow!("The file was changed, or the key is wrong."))?,
+            );
+        }
+        Ok(plaintext)
+    }
+}
+
+impl<F: Fs> Fs for EncryptedFs<F> {
+    type File = EncryptedFile<F>;
+    type Entry = F::Entry;
+
+    fn create_file(&self, path: &Path) -> Result<Self::File> {
+        Ok(self.wrap(path, self.inner.create_file(path)?))
+    }
+
+    fn delete_file(&self, path: &Path) -> Result<()> {
+        self.inner.delete_file(path)
+    }
+
+    fn open_readable_file(&self, path: &Path) -> Result<Self::File> {
+        Ok(self.wrap(path, self.inner.open_readable_file(path)?))
+    }
+
+    fn open_writable_file(&self, path: &Path) -> Result<Self::File> {
+        Ok(self.wrap(path, self.inner.open_writable_file(path)?))
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn read_directory(&self, path: &Path) -> Result<Vec<Self::Entry>> {
+        self.inner.read_directory(path)
+    }
+
+    fn delete_directory(&self, path: &Path) -> Result<()> {
+        self.inner.delete_directory(path)
+    }
+
+    fn write_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+        let buffer = if file.is_encrypted {
+            self.encrypt(&buffer)?
+        } else {
+            buffer
+        };
+        self.inner.write_to_file(&mut file.inner, buffer)
+    }
+
+    // Only the frames which are already there are counted, to bind the new one to its position.
+    fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+        if !file.is_encrypted {
+            return self.inner.append_to_file(&mut file.inner, buffer);
+        }
+
+        let mut content_file = self.inner.open_readable_file(&file.path)?;
+        let content = self.inner.read_from_file(&mut content_file)?;
+        if content.is_empty() {
+            return self.write_to_file(file, buffer);
+        }
+
+        let (frames, is_complete) = split_frames(&content)?;
+        if !is_complete {
+            bail!(
+                "Can't append to '{}', its last write was cut short.",
+                file.path.display()
+            );
+        }
+        let frame = self.encrypt_frame(frames.len(), &buffer)?;
+        self.inner.append_to_file(&mut file.inner, frame)
+    }
+
+    fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>> {
+        let buffer = self.inner.read_from_file(&mut file.inner)?;
+        // Newly created files are empty until something is written to them.
+        if file.is_encrypted && !buffer.is_empty() {
+            self.decrypt(&buffer)
+                .with_context(|| format!("Failed decrypting '{}'.", file.path.display()))
+        } else {
+            Ok(buffer)
+        }
+    }
+
+    fn path_exists(&self, path: &Path) -> bool {
+        self.inner.path_exists(path)
+    }
+
+    fn available_space(&self, path: &Path) -> Result<Option<u64>> {
+        self.inner.available_space(path)
+    }
+
+    fn supports_stamps(&self) -> bool {
+        self.inner.supports_stamps()
+    }
+
+    fn stamp(&self, path: &Path) -> Option<(u64, u64)> {
+        self.inner.stamp(path)
+    }
+
+    fn sync_file(&self, file: &mut Self::File) -> Result<()> {
+        self.inner.sync_file(&mut file.inner)
+    }
+
+    fn sync_directory(&self, path: &Path) -> Result<()> {
+        self.inner.sync_directory(path)
+    }
+}
+
+// The nonce and ciphertext of a frame.
+type Frame<'a> = (&'a [u8], &'a [u8]);
+
+// The frames of an encrypted file, and whether the last one is complete. One which was cut
+// short is left out, as an append which was interrupted, which the journal completes.
+fn split_frames(payload: &[u8]) -> Result<(Vec<Frame<'_>>, bool)> {
+    let mut rest = payload
+        .strip_prefix(MAGIC)
+        .context("The file isn't encrypted.")?;
+
+    let mut frames = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < NONCE_LENGTH + LENGTH_BYTES {
+            return Ok((frames, false));
+        }
+        let (nonce, after_nonce) = rest.split_at(NONCE_LENGTH);
+        let (length, after_length) = after_nonce.split_at(LENGTH_BYTES);
+        let mut length_bytes = [0u8; LENGTH_BYTES];
+        length_bytes.copy_from_slice(length);
+        let length = match usize::try_from(u64::from_le_bytes(length_bytes)) {
+            Ok(length) if length <= after_length.len() => length,
+            _ => return Ok((frames, false)),
+        };
+        let (ciphertext, after_ciphertext) = after_length.split_at(length);
+        frames.push((nonce, ciphertext));
+        rest = after_ciphertext;
+    }
+    Ok((frames, true))
+}
+
+fn get_frame_aad(index: usize) -> Vec<u8> {
+    let mut aad = MAGIC.to_vec();
+    aad.extend_from_slice(&(index as u64).to_le_bytes());
+    aad
+}
+
+// Encrypts every file of an unencrypted repository in place and stores the key parameters
+// in the config, after which the repository can only be used through `EncryptedFs`.
+pub fn encrypt_repository(options: &ActionOptions, fs: &impl Fs, passphrase: &str) -> Result<()> {
+    encrypt_repository_with(
+        options,
+        fs,
+        passphrase,
+        DEFAULT_MEMORY_COST,
+        DEFAULT_ITERATIONS,
+    )
+}
+
+fn encrypt_repository_with(
+    options: &ActionOptions,
+    fs: &impl Fs,
+    passphrase: &str,
+    memory_cost: u32,
+    iterations: u32,
+) -> Result<()> {
+    let locations = Locations::from(options);
+
+    journal::complete_pending(fs, &locations)?;
+
+    let mut config = Config::load(fs, &locations)?;
+    if config.encryption.is_some() {
+        bail!("The repository is already encrypted.");
+    }
+
+    let mut salt = [0u8; SALT_LENGTH];
+    getrandom::getrandom(&mut salt)
+        .map_err(|error| anyhow!("Failed getting random bytes, {}.", error))?;
+
+    let mut encryption = EncryptionConfig {
+        salt: crypto::to_hex(&salt),
+        memory_cost,
+        iterations,
+        key_check: String::new(),
+    };
+    let key = derive_key(&encryption, passphrase)?;
+    let encrypted_fs = EncryptedFs::with_key(fs, &locations, &key);
+    encryption.key_check = crypto::to_hex(&encrypted_fs.encrypt(KEY_CHECK_MESSAGE)?);
+
+    // The cached contents would stay readable, they're filled again as they're needed.
+    if fs.path_exists(&locations.get_cache_path()) {
+        fs.delete_directory(&locations.get_cache_path())?;
+    }
+
+    let mut paths = Vec::new();
+    collect_files(fs, &locations.ka_path, &mut paths)?;
+
+    // The journal is encrypted itself, so it can only be completed once the config says that
+    // the repository is. Until it does, it's taken for one which was never written.
+    config.encryption = Some(encryption);
+    let mut journal = Journal::default();
+    journal.add_write(locations.get_config_path(), config.encode()?);
+    for path in paths {
+        if !encrypted_fs.is_encrypted(&path) {
+            continue;
+        }
+        let mut plain_file = fs.open_readable_file(&path)?;
+        journal.add_write(path, fs.read_from_file(&mut plain_file)?);
+    }
+    journal.commit(&encrypted_fs, &locations)
+}
+
+fn derive_key(encryption: &EncryptionConfig, passphrase: &str) -> Result<[u8; KEY_LENGTH]> {
+    let salt = from_hex(&encryption.salt).context("Invalid encryption salt.")?;
+    let params = Params::new(
+        encryption.memory_cost,
+        encryption.iterations,
+        1,
+        Some(KEY_LENGTH),
+    )
+    .map_err(|error| anyhow!("Invalid key derivation parameters, {}.", error))?;
+
+    let mut key = [0u8; KEY_LENGTH];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|error| anyhow!("Failed deriving the key, {}.", error))?;
+    Ok(key)
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, shift, update, ActionOptions, ShiftMode, UntrackedFiles},
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{encrypt_repository_with, EncryptedFs, MAGIC};
+
+    // The smallest parameters Argon2 takes, so the tests don't spend their time deriving keys.
+    const MEMORY_COST: u32 = 8;
+    const ITERATIONS: u32 = 1;
+
+    #[test]
+    fn encrypted_repository() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        write_file(&fs_mock, "./secret", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+        encrypt_repository_with(&options, &fs_mock, "hunter2", MEMORY_COST, ITERATIONS)
+            .expect("Encrypting failed.");
+
+        assert!(read_file(&fs_mock, "./.ka/index").starts_with(MAGIC));
+        let history = read_file(&fs_mock, "./.ka/files/secret");
+        assert!(history.starts_with(MAGIC));
+        assert!(!history.windows(5).any(|window| window == b"first"));
+
+        assert!(EncryptedFs::open(&fs_mock, &options, "wrong").is_err());
+        let encrypted_fs = EncryptedFs::open(&fs_mock, &options, "hunter2").unwrap();
+
+        write_file(&encrypted_fs, "./secret", b"second");
+        update(options.clone(), &encrypted_fs, now + 1).expect("Updating failed.");
+        assert!(read_file(&fs_mock, "./.ka/files/secret").starts_with(MAGIC));
+
+        shift(
+            options,
+            &encrypted_fs,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .expect("Shifting failed.");
+        assert_eq!(read_file(&fs_mock, "./secret"), b"first");
+    }
+
+    #[test]
+    fn append_frames() {
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let path = "./.ka/appended";
+
+        write_file(&fs_mock, "./test", b"first");
+        create(options.clone(), &fs_mock, 0xC0FFEE).unwrap();
+        encrypt_repository_with(&options, &fs_mock, "hunter2", MEMORY_COST, ITERATIONS).unwrap();
+        let encrypted_fs = EncryptedFs::open(&fs_mock, &options, "hunter2").unwrap();
+
+        write_file(&encrypted_fs, path, b"first");
+        let written = read_file(&fs_mock, path);
+        let mut file = encrypted_fs.open_writable_file(Path::new(path)).unwrap();
+        encrypted_fs
+            .append_to_file(&mut file, b" second".to_vec())
+            .unwrap();
+
+        // What was there is kept as it is, instead of being encrypted again.
+        let appended = read_file(&fs_mock, path);
+        assert!(appended.starts_with(&written));
+        assert_eq!(read_file(&encrypted_fs, path), b"first second");
+
+        // An append which was cut short is left out, while frames which were swapped aren't read.
+        let mut file = fs_mock.create_file(Path::new(path)).unwrap();
+        fs_mock
+            .write_to_file(&mut file, appended[..appended.len() - 3].to_vec())
+            .unwrap();
+        assert_eq!(read_file(&encrypted_fs, path), b"first");
+
+        let first_frame = written[MAGIC.len()..].to_vec();
+        let second_frame = appended[written.len()..].to_vec();
+        let swapped = [MAGIC.to_vec(), second_frame, first_frame].concat();
+        let mut file = fs_mock.create_file(Path::new(path)).unwrap();
+        fs_mock.write_to_file(&mut file, swapped).unwrap();
+        let mut file = encrypted_fs.open_readable_file(Path::new(path)).unwrap();
+        assert!(encrypted_fs.read_from_file(&mut file).is_err());
+    }
+}
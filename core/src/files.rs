@@ -0,0 +1,436 @@
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Error, Result};
+
+use crate::{
+    actions::ActionOptions,
+    config::Config,
+    filesystem::{Fs, FsEntry},
+    ignore::{get_global_ignore_path, IgnoreRules},
+};
+
+// Directories containing one of these belong to another repository.
+const REPOSITORY_MARKERS: [&str; 2] = [".ka", ".git"];
+
+const DEFAULT_MAX_DEPTH: usize = 128;
+
+// Keeps walking the working directory from going on forever, because of a directory which
+// contains itself or one which is nested absurdly deep. Only the directories above the current
+// one are kept, as the same directory linked in twice side by side is fine.
+struct Walk {
+    stop_at_nested: bool,
+    max_depth: usize,
+    ancestors: HashSet<(u64, u64)>,
+}
+
+#[derive(Clone)]
+pub struct Locations {
+    pub repository_path: PathBuf,
+    pub ka_path: PathBuf,
+    pub ka_files_path: PathBuf,
+}
+
+impl Locations {
+    pub fn get_repository_index_path(&self) -> PathBuf {
+        self.ka_path.join("index")
+    }
+
+    // The oldest changes are sealed in numbered segments, which the index lists.
+    pub fn get_index_segments_path(&self) -> PathBuf {
+        self.ka_path.join("segments")
+    }
+
+    pub fn get_index_segment_path(&self, number: usize) -> PathBuf {
+        self.get_index_segments_path()
+            .join(format!("{:04}", number))
+    }
+
+    // Sealed changes of file histories, under the same relative path as the history itself.
+    pub fn get_file_segments_path(&self) -> PathBuf {
+        self.ka_path.join("file-segments")
+    }
+
+    pub fn get_file_segment_path(&self, history_path: &Path, number: usize) -> Result<PathBuf> {
+        let relative_path = history_path.strip_prefix(&self.ka_files_path)?;
+        let mut segment_path = self
+            .get_file_segments_path()
+            .join(relative_path)
+            .into_os_string();
+        segment_path.push(format!(".{}", number));
+        Ok(segment_path.into())
+    }
+
+    pub fn get_config_path(&self) -> PathBuf {
+        self.ka_path.join("config")
+    }
+
+    pub fn get_objects_path(&self) -> PathBuf {
+        self.ka_path.join("objects")
+    }
+
+    pub fn get_cache_path(&self) -> PathBuf {
+        self.ka_path.join("cache")
+    }
+
+    pub fn get_tip_cache_path(&self) -> PathBuf {
+        self.get_cache_path().join("tips")
+    }
+
+    pub fn get_stat_cache_path(&self) -> PathBuf {
+        self.get_cache_path().join("stat")
+    }
+
+    pub fn get_trash_path(&self) -> PathBuf {
+        self.ka_path.join("trash")
+    }
+
+    pub fn get_undo_path(&self) -> PathBuf {
+        self.ka_path.join("undo")
+    }
+
+    pub fn get_hooks_path(&self) -> PathBuf {
+        self.ka_path.join("hooks")
+    }
+
+    // Where `doctor` keeps its scratch repository while it runs.
+    pub fn get_doctor_path(&self) -> PathBuf {
+        self.ka_path.join("doctor")
+    }
+
+    pub fn get_journal_path(&self) -> PathBuf {
+        self.ka_path.join("journal")
+    }
+
+    pub fn get_import_state_path(&self) -> PathBuf {
+        self.ka_path.join("import")
+    }
+
+    pub fn get_locks_path(&self) -> PathBuf {
+        self.ka_path.join("locks")
+    }
+
+    pub fn get_watch_state_path(&self) -> PathBuf {
+        self.ka_path.join("watch-state")
+    }
+
+    pub fn get_watch_socket_path(&self) -> PathBuf {
+        self.ka_path.join("watch.sock")
+    }
+
+    pub fn get_repository_files<FS: Fs>(&self, fs: &FS) -> Result<Vec<FileState>, Error> {
+        let config = Config::load(fs, self)?;
+        let global_ignore_path = get_global_ignore_path();
+        let ignore_rules =
+            IgnoreRules::load(fs, self, &config.ignore, global_ignore_path.as_deref())?;
+        // Ignoring only applies to what isn't recorded yet, tracked files keep being updated.
+        let is_ignored = |working_path: &Path, is_directory: bool| {
+            working_path
+                .strip_prefix(&self.repository_path)
+                .map(|relative_path| ignore_rules.is_ignored(relative_path, is_directory))
+                .unwrap_or(false)
+        };
+
+        let mut working_walk = Walk {
+            stop_at_nested: !config.track_nested_repositories,
+            max_depth: config.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+            ancestors: HashSet::new(),
+        };
+        let mut history_walk = Walk {
+            stop_at_nested: false,
+            max_depth: working_walk.max_depth,
+            ancestors: HashSet::new(),
+        };
+
+        let working_entries = fs
+            .read_directory(&self.repository_path)
+            .context("Failed reading working file entries.")?
+            .into_iter()
+            .filter(|e| e.path() != self.ka_path)
+            .collect();
+        let history_entries = fs
+            .read_directory(&self.ka_files_path)
+            .context("Failed reading history file entries.")?;
+
+        let working_files = Self::walk_directory(
+            fs,
+            working_entries,
+            &mut working_walk,
+            1,
+            &|directory_path| {
+                let history_path = self.history_from_working(directory_path).ok()?;
+                Some(is_ignored(directory_path, true) && !fs.path_exists(&history_path))
+            },
+            &|entry| match FileState::from_working(fs, self, &entry.path()).ok()? {
+                FileState::Untracked(untracked) if is_ignored(&untracked.path, false) => None,
+                file => Some(file),
+            },
+        )?;
+
+        let deleted_files = Self::walk_directory(
+            fs,
+            history_entries,
+            &mut history_walk,
+            1,
+            &|_| None,
+            &|entry| {
+                let file_path = entry.path();
+                let file = FileState::from_history(fs, self, &file_path).ok()?;
+                match file {
+                    FileState::Deleted { .. } => Some(file),
+                    FileState::Tracked { .. } => None,
+                    _ => unreachable!(),
+                }
+            },
+        )?;
+
+        let mut all_files = working_files;
+        all_files.extend(deleted_files);
+
+        Ok(all_files)
+    }
+
+    // Turns a path relative to the repository into a working path. Paths which would leave
+    // the repository or reach into `.ka` are rejected.
+    pub fn get_working_path(&self, path: &Path) -> Result<PathBuf> {
+        let is_plain = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        let path = normalize_path(path);
+        if !is_plain || path.as_os_str().is_empty() || path.starts_with(".ka") {
+            bail!(
+                "'{}' is not a path to a working file of the repository.",
+                path.display()
+            );
+        }
+        Ok(self.repository_path.join(path))
+    }
+
+    pub fn working_from_history(&self, history_file_path: &Path) -> Result<PathBuf> {
+        let raw_path = history_file_path.strip_prefix(&self.ka_files_path)?;
+        Ok(self.repository_path.join(raw_path))
+    }
+
+    pub fn history_from_working(&self, working_file_path: &Path) -> Result<PathBuf> {
+        let raw_path = working_file_path.strip_prefix(&self.repository_path)?;
+        Ok(self.ka_files_path.join(raw_path))
+    }
+
+    fn walk_directory<FS: Fs>(
+        fs: &FS,
+        directory: Vec<FS::Entry>,
+        walk: &mut Walk,
+        depth: usize,
+        skip_directory: &dyn Fn(&Path) -> Option<bool>,
+        filter_map: &dyn Fn(&FS::Entry) -> Option<FileState>,
+    ) -> Result<Vec<FileState>> {
+        let mut entries = Vec::new();
+
+        for entry in directory {
+            if entry.is_directory()? {
+                if walk.stop_at_nested && Self::is_nested_repository(fs, &entry.path()) {
+                    continue;
+                }
+                if skip_directory(&entry.path()).unwrap_or(false) {
+                    continue;
+                }
+                if depth > walk.max_depth {
+                    bail!(
+                        "Stopped looking for files at '{}', as it's nested more than {} directories deep. Raise `max_depth` in the config if that's intended.",
+                        entry.path().display(),
+                        walk.max_depth
+                    );
+                }
+                let identity = entry.identity();
+                if let Some(identity) = identity {
+                    if !walk.ancestors.insert(identity) {
+                        bail!(
+                            "Stopped looking for files at '{}', as the directory contains itself.",
+                            entry.path().display()
+                        );
+                    }
+                }
+
+                let nested_directory = fs.read_directory(&entry.path())?;
+                let nested_files = Self::walk_directory(
+                    fs,
+                    nested_directory,
+                    walk,
+                    depth + 1,
+                    skip_directory,
+                    filter_map,
+                )?;
+                entries.extend(nested_files);
+
+                if let Some(identity) = identity {
+                    walk.ancestors.remove(&identity);
+                }
+            } else if let Some(states) = filter_map(&entry) {
+                entries.push(states);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn is_nested_repository<FS: Fs>(fs: &FS, directory_path: &Path) -> bool {
+        REPOSITORY_MARKERS
+            .iter()
+            .any(|marker| fs.path_exists(&directory_path.join(marker)))
+    }
+}
+
+impl From<&ActionOptions> for Locations {
+    fn from(options: &ActionOptions) -> Self {
+        let ka_path = options.repository_path.join(".ka");
+        let ka_files_path = ka_path.join("files");
+
+        Self {
+            repository_path: options.repository_path.clone(),
+            ka_path,
+            ka_files_path,
+        }
+    }
+}
+
+// Moves a working path from the repository at `from` to the one at `to`, e.g. for a repository
+// restored somewhere else. Paths outside of `from` are kept as they are.
+pub fn rebase_path(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(relative_path) => to.join(relative_path),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+// Resolves `.` and `..` without looking at the filesystem, so it also works for paths which
+// don't exist (anymore).
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+pub fn collect_files<FS: Fs>(fs: &FS, directory: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs.read_directory(directory)? {
+        if entry.is_directory()? {
+            collect_files(fs, &entry.path(), paths)?;
+        } else {
+            paths.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+pub enum FileState {
+    Deleted(FileDeleted),
+    Untracked(FileUntracked),
+    Tracked(FileTracked),
+}
+
+impl FileState {
+    pub fn from_history<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        history_file_path: &Path,
+    ) -> Result<Self> {
+        let working_path = locations.working_from_history(history_file_path)?;
+        Ok(if !fs.path_exists(&working_path) {
+            FileState::Deleted(FileDeleted {
+                history_path: history_file_path.to_path_buf(),
+            })
+        } else {
+            FileState::Tracked(FileTracked {
+                history_path: history_file_path.to_path_buf(),
+                working_path,
+            })
+        })
+    }
+
+    pub fn from_working<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        working_file_path: &Path,
+    ) -> Result<Self> {
+        let history_path = locations.history_from_working(working_file_path)?;
+        // TODO: Think whether abstracting Path would be needed for Fs abstraction.
+        Ok(if !fs.path_exists(&history_path) {
+            FileState::Untracked(FileUntracked {
+                path: working_file_path.to_path_buf(),
+            })
+        } else {
+            FileState::Tracked(FileTracked {
+                history_path,
+                working_path: working_file_path.to_path_buf(),
+            })
+        })
+    }
+
+    pub fn get_working_path(&self, locations: &Locations) -> Result<PathBuf> {
+        match self {
+            FileState::Deleted(deleted) => locations.working_from_history(&deleted.history_path),
+            FileState::Untracked(untracked) => Ok(untracked.path.clone()),
+            FileState::Tracked(tracked) => Ok(tracked.working_path.clone()),
+        }
+    }
+}
+
+pub struct FileDeleted {
+    pub history_path: PathBuf,
+}
+
+impl FileDeleted {
+    pub fn load_history_file<FS: Fs>(&self, fs: &FS) -> Result<FS::File> {
+        fs.open_writable_file(&self.history_path)
+    }
+
+    pub fn create_working_file<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<FS::File> {
+        let working_path = locations.working_from_history(&self.history_path)?;
+        fs.create_file(&working_path)
+    }
+}
+
+pub struct FileUntracked {
+    pub path: PathBuf,
+}
+
+impl FileUntracked {
+    pub fn load_file<FS: Fs>(&self, fs: &FS) -> Result<FS::File> {
+        fs.open_readable_file(&self.path)
+    }
+
+    pub fn create_history_file<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<FS::File> {
+        let history_path = locations.history_from_working(&self.path)?;
+        fs.create_file(&history_path)
+    }
+}
+
+pub struct FileTracked {
+    pub history_path: PathBuf,
+    pub working_path: PathBuf,
+}
+
+impl FileTracked {
+    pub fn load_history_file<FS: Fs>(&self, fs: &FS) -> Result<FS::File> {
+        fs.open_writable_file(&self.history_path)
+    }
+
+    pub fn load_working_file<FS: Fs>(&self, fs: &FS) -> Result<FS::File> {
+        fs.open_readable_file(&self.working_path)
+    }
+
+    pub fn create_working_file<FS: Fs>(&self, fs: &FS) -> Result<FS::File> {
+        fs.create_file(&self.working_path)
+    }
+}
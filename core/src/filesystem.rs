@@ -1,10 +1,15 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use std::{
-    fs::{self, DirEntry, File, OpenOptions},
-    io::{self, Read, Seek, Write},
-    path::{Path, PathBuf},
+    io,
+    path::{Component, Path, PathBuf},
 };
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::FsImpl;
+
+// Keeps repositories entirely in memory, e.g. on targets without a filesystem.
+pub use mock::FsMock as MemoryFs;
+
 pub trait Fs {
     type File;
     type Entry: FsEntry;
@@ -19,111 +24,492 @@ pub trait Fs {
     fn delete_directory(&self, path: &Path) -> Result<()>;
 
     fn write_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()>;
+    // Writes the buffer to the end of the file, keeping everything already in it.
+    fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()>;
     fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>>;
 
     fn path_exists(&self, path: &Path) -> bool;
-}
 
-pub trait FsEntry {
-    fn path(&self) -> PathBuf;
-    fn is_directory(&self) -> Result<bool>;
-}
+    // How many bytes can still be written to the filesystem `path` is on, or `None` if that
+    // can't be told, in which case writes aren't checked for space.
+    fn available_space(&self, _path: &Path) -> Result<Option<u64>> {
+        Ok(None)
+    }
 
-pub struct FsImpl {}
+    // Whether `stamp` tells anything, as `None` is otherwise taken for a missing path.
+    fn supports_stamps(&self) -> bool {
+        false
+    }
 
-impl Fs for FsImpl {
-    type File = File;
-    type Entry = DirEntry;
+    // The length and modification time of the file or directory at `path`, which change
+    // whenever it's written to, or entries are added to or removed from it. Paths which don't
+    // exist return `None`, and so does every path on filesystems which don't support stamps.
+    fn stamp(&self, _path: &Path) -> Option<(u64, u64)> {
+        None
+    }
 
-    fn create_file(&self, path: &Path) -> Result<Self::File> {
-        if let Some(parent_path) = path.parent() {
-            if !parent_path.exists() {
-                fs::create_dir_all(parent_path)?;
-            }
-        }
+    // Waits until what was written to the file is on the disk, so it's there before anything
+    // written afterwards. Filesystems which aren't on a disk have nothing to wait for.
+    fn sync_file(&self, _file: &mut Self::File) -> Result<()> {
+        Ok(())
+    }
 
-        OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(path)
-            .with_context(|| format!("Failed creating '{}'.", path.display()))
+    // The same for the entries of a directory, e.g. a file which was just created in it.
+    fn sync_directory(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: Fs> Fs for &F {
+    type File = F::File;
+    type Entry = F::Entry;
+
+    fn create_file(&self, path: &Path) -> Result<Self::File> {
+        (*self).create_file(path)
     }
 
     fn delete_file(&self, path: &Path) -> Result<()> {
-        fs::remove_file(path)?;
-        Ok(())
+        (*self).delete_file(path)
     }
 
     fn open_readable_file(&self, path: &Path) -> Result<Self::File> {
-        File::open(path)
-            .with_context(|| format!("Failed opening '{}' for reading.", path.display()))
+        (*self).open_readable_file(path)
     }
 
     fn open_writable_file(&self, path: &Path) -> Result<Self::File> {
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(path)
-            .with_context(|| {
-                format!(
-                    "Failed opening '{}' for reading and writing.",
-                    path.display()
-                )
-            })
+        (*self).open_writable_file(path)
     }
 
     fn create_directory(&self, path: &Path) -> Result<()> {
-        fs::create_dir_all(path)
-            .with_context(|| format!("Failed creating directory '{}'.", path.display()))
+        (*self).create_directory(path)
     }
 
     fn read_directory(&self, path: &Path) -> Result<Vec<Self::Entry>> {
-        let result: io::Result<_> = fs::read_dir(path)?.collect();
-        result.with_context(|| format!("Failed reading directory {}", path.display()))
+        (*self).read_directory(path)
     }
 
     fn delete_directory(&self, path: &Path) -> Result<()> {
-        fs::remove_dir_all(path)
-            .with_context(|| format!("Failed deleting directory '{}'.", path.display()))
+        (*self).delete_directory(path)
     }
 
     fn write_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
-        file.rewind()?;
-        file.set_len(0)?;
-        file.write_all(&buffer)?;
-        Ok(())
+        (*self).write_to_file(file, buffer)
+    }
+
+    fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+        (*self).append_to_file(file, buffer)
     }
 
     fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>> {
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        Ok(buffer)
+        (*self).read_from_file(file)
     }
 
     fn path_exists(&self, path: &Path) -> bool {
-        path.exists()
+        (*self).path_exists(path)
+    }
+
+    fn available_space(&self, path: &Path) -> Result<Option<u64>> {
+        (*self).available_space(path)
+    }
+
+    fn supports_stamps(&self) -> bool {
+        (*self).supports_stamps()
+    }
+
+    fn stamp(&self, path: &Path) -> Option<(u64, u64)> {
+        (*self).stamp(path)
+    }
+
+    fn sync_file(&self, file: &mut Self::File) -> Result<()> {
+        (*self).sync_file(file)
+    }
+
+    fn sync_directory(&self, path: &Path) -> Result<()> {
+        (*self).sync_directory(path)
+    }
+}
+
+// Fails early if writing `needed_bytes` at `path` would fill up its filesystem, rather than
+// leaving files half written. The error is a full disk, like the one writing would have
+// run into.
+pub fn ensure_available_space(fs: &impl Fs, path: &Path, needed_bytes: u64) -> Result<()> {
+    match fs.available_space(path)? {
+        Some(available_bytes) if available_bytes < needed_bytes => Err(anyhow::Error::new(
+            io::Error::from(io::ErrorKind::StorageFull),
+        )
+        .context(format!(
+            "Writing {} bytes to '{}' needs more space than the {} bytes which are left.",
+            needed_bytes,
+            path.display(),
+            available_bytes
+        ))),
+        _ => Ok(()),
     }
 }
 
-impl FsEntry for DirEntry {
-    fn path(&self) -> PathBuf {
-        self.path()
+pub trait FsEntry {
+    fn path(&self) -> PathBuf;
+    fn is_directory(&self) -> Result<bool>;
+
+    // Tells apart directories which are reachable through more than one path, like the device
+    // and inode on Unix. Entries which can't tell return `None`.
+    fn identity(&self) -> Option<(u64, u64)> {
+        None
+    }
+}
+
+// Names Windows keeps for devices, whatever their case and extension, like `nul.txt`.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Whether Windows can't open a file at `path` by its plain path, because one of its names is
+// reserved for a device or ends in a dot or space, which Windows drops.
+pub fn is_reserved_on_windows(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => {
+            let name = name.to_string_lossy();
+            let stem = name.split('.').next().unwrap_or_default().trim_end();
+            name.ends_with(['.', ' '])
+                || RESERVED_NAMES
+                    .iter()
+                    .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        }
+        _ => false,
+    })
+}
+
+// Everything touching the real filesystem lives here, as there is none on `wasm32`.
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use anyhow::{Context, Result};
+    use std::{
+        borrow::Cow,
+        fs::{self, DirEntry, File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        time::UNIX_EPOCH,
+    };
+
+    use super::{Fs, FsEntry};
+    use crate::trace::{self, Level};
+
+    pub struct FsImpl {}
+
+    // Entries keep the path their directory was read with by the caller, rather than the
+    // long one it might have been read with in the end.
+    pub struct EntryImpl {
+        entry: DirEntry,
+        path: PathBuf,
+    }
+
+    fn trace_path(message: &str, path: &Path) {
+        trace::event(Level::Trace, message, &[("path", &path.display())]);
+    }
+
+    fn trace_bytes(message: &str, bytes: usize) {
+        trace::event(Level::Trace, message, &[("bytes", &bytes)]);
+    }
+
+    // Windows refuses paths longer than `MAX_PATH` and files with reserved names like `CON`,
+    // unless the path is verbatim, starting with `\\?\`. Verbatim paths are taken as they are,
+    // so they are made absolute and normalized first.
+    #[cfg(windows)]
+    fn get_long_path(path: &Path) -> Cow<'_, Path> {
+        use std::{
+            ffi::OsString,
+            path::{Component, Prefix},
+        };
+
+        use super::is_reserved_on_windows;
+        use crate::files::normalize_path;
+
+        const MAX_PATH: usize = 260;
+
+        if path.as_os_str().len() < MAX_PATH && !is_reserved_on_windows(path) {
+            return Cow::Borrowed(path);
+        }
+        let absolute_path = match std::env::current_dir() {
+            Ok(current_directory) => normalize_path(&current_directory.join(path)),
+            Err(_) => return Cow::Borrowed(path),
+        };
+
+        let mut components = absolute_path.components();
+        let mut long_path = match components.next() {
+            Some(Component::Prefix(prefix)) => match prefix.kind() {
+                Prefix::Disk(letter) => OsString::from(format!(r"\\?\{}:", letter as char)),
+                Prefix::UNC(server, share) => {
+                    let mut long_path = OsString::from(r"\\?\UNC\");
+                    long_path.push(server);
+                    long_path.push(r"\");
+                    long_path.push(share);
+                    long_path
+                }
+                // Already verbatim, or a device path.
+                _ => return Cow::Borrowed(path),
+            },
+            _ => return Cow::Borrowed(path),
+        };
+        for component in components {
+            if let Component::Normal(name) = component {
+                long_path.push(r"\");
+                long_path.push(name);
+            }
+        }
+        Cow::Owned(PathBuf::from(long_path))
+    }
+
+    #[cfg(not(windows))]
+    fn get_long_path(path: &Path) -> Cow<'_, Path> {
+        Cow::Borrowed(path)
+    }
+
+    impl Fs for FsImpl {
+        type File = File;
+        type Entry = EntryImpl;
+
+        fn create_file(&self, path: &Path) -> Result<Self::File> {
+            trace_path("Creating the file.", path);
+            let long_path = get_long_path(path);
+            if let Some(parent_path) = long_path.parent() {
+                if !parent_path.exists() {
+                    fs::create_dir_all(parent_path)?;
+                }
+            }
+
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .read(true)
+                .write(true)
+                .open(long_path)
+                .with_context(|| format!("Failed creating '{}'.", path.display()))
+        }
+
+        fn delete_file(&self, path: &Path) -> Result<()> {
+            trace_path("Deleting the file.", path);
+            fs::remove_file(get_long_path(path))?;
+            Ok(())
+        }
+
+        fn open_readable_file(&self, path: &Path) -> Result<Self::File> {
+            trace_path("Opening the file for reading.", path);
+            File::open(get_long_path(path))
+                .with_context(|| format!("Failed opening '{}' for reading.", path.display()))
+        }
+
+        fn open_writable_file(&self, path: &Path) -> Result<Self::File> {
+            trace_path("Opening the file for writing.", path);
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(get_long_path(path))
+                .with_context(|| {
+                    format!(
+                        "Failed opening '{}' for reading and writing.",
+                        path.display()
+                    )
+                })
+        }
+
+        fn create_directory(&self, path: &Path) -> Result<()> {
+            fs::create_dir_all(get_long_path(path))
+                .with_context(|| format!("Failed creating directory '{}'.", path.display()))
+        }
+
+        fn read_directory(&self, path: &Path) -> Result<Vec<Self::Entry>> {
+            trace_path("Reading the directory.", path);
+            let result: io::Result<Vec<_>> = fs::read_dir(get_long_path(path))?
+                .map(|entry| {
+                    let entry = entry?;
+                    let entry_path = path.join(entry.file_name());
+                    Ok(EntryImpl {
+                        entry,
+                        path: entry_path,
+                    })
+                })
+                .collect();
+            result.with_context(|| format!("Failed reading directory {}", path.display()))
+        }
+
+        fn delete_directory(&self, path: &Path) -> Result<()> {
+            trace_path("Deleting the directory.", path);
+            fs::remove_dir_all(get_long_path(path))
+                .with_context(|| format!("Failed deleting directory '{}'.", path.display()))
+        }
+
+        fn write_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            file.rewind()?;
+            file.set_len(0)?;
+            file.write_all(&buffer)?;
+            trace_bytes("Wrote the file.", buffer.len());
+            Ok(())
+        }
+
+        fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(&buffer)?;
+            trace_bytes("Appended to the file.", buffer.len());
+            Ok(())
+        }
+
+        fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>> {
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
+            trace_bytes("Read the file.", buffer.len());
+            Ok(buffer)
+        }
+
+        fn path_exists(&self, path: &Path) -> bool {
+            get_long_path(path).exists()
+        }
+
+        // Only asked on Linux, where `struct statvfs` has the same layout on every 64 bit
+        // target: 11 fields of 8 bytes followed by 24 spare ones, with the fragment size second
+        // and the blocks available to users fifth.
+        #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+        fn available_space(&self, path: &Path) -> Result<Option<u64>> {
+            use std::{ffi::CString, os::raw::c_char, os::unix::ffi::OsStrExt};
+
+            extern "C" {
+                fn statvfs(path: *const c_char, buffer: *mut [u64; 14]) -> i32;
+            }
+
+            // Whatever is about to be written might not exist yet.
+            let existing_path = match path.ancestors().find(|path| path.exists()) {
+                Some(existing_path) => existing_path,
+                None => return Ok(None),
+            };
+            let c_path = CString::new(existing_path.as_os_str().as_bytes())?;
+            let mut buffer = [0u64; 14];
+            // Safe, as the path is NUL terminated and the buffer is as large as the struct.
+            if unsafe { statvfs(c_path.as_ptr(), &mut buffer) } != 0 {
+                return Err(io::Error::last_os_error())
+                    .with_context(|| format!("Failed checking space at '{}'.", path.display()));
+            }
+            Ok(Some(buffer[1].saturating_mul(buffer[4])))
+        }
+
+        fn supports_stamps(&self) -> bool {
+            true
+        }
+
+        fn stamp(&self, path: &Path) -> Option<(u64, u64)> {
+            let metadata = fs::metadata(get_long_path(path)).ok()?;
+            let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+            Some((metadata.len(), modified.as_nanos() as u64))
+        }
+
+        fn sync_file(&self, file: &mut Self::File) -> Result<()> {
+            file.sync_all().context("Failed syncing the file.")
+        }
+
+        // Only Unix lets directories be opened and synced, Windows keeps their entries along
+        // with the files.
+        #[cfg(unix)]
+        fn sync_directory(&self, path: &Path) -> Result<()> {
+            File::open(get_long_path(path))
+                .and_then(|directory| directory.sync_all())
+                .with_context(|| format!("Failed syncing directory '{}'.", path.display()))
+        }
     }
 
-    fn is_directory(&self) -> Result<bool> {
-        let file_type = self.file_type()?;
-        Ok(file_type.is_dir())
+    impl FsEntry for EntryImpl {
+        fn path(&self) -> PathBuf {
+            self.path.clone()
+        }
+
+        fn is_directory(&self) -> Result<bool> {
+            let file_type = self.entry.file_type()?;
+            if file_type.is_symlink() {
+                // Links to directories are walked like the directory, dangling links are left
+                // to fail when they're read.
+                return Ok(fs::metadata(self.entry.path()).is_ok_and(|metadata| metadata.is_dir()));
+            }
+            Ok(file_type.is_dir())
+        }
+
+        #[cfg(unix)]
+        fn identity(&self) -> Option<(u64, u64)> {
+            use std::os::unix::fs::MetadataExt;
+
+            let metadata = fs::metadata(self.entry.path()).ok()?;
+            Some((metadata.dev(), metadata.ino()))
+        }
+    }
+
+    #[cfg(all(test, windows))]
+    mod tests {
+        use std::path::{Path, PathBuf};
+
+        use super::{get_long_path, FsImpl};
+        use crate::filesystem::{Fs, FsEntry};
+
+        fn get_test_directory(name: &str) -> PathBuf {
+            let directory = std::env::temp_dir().join(name);
+            let _ = FsImpl {}.delete_directory(&directory);
+            directory
+        }
+
+        #[test]
+        fn long_paths() {
+            let fs = FsImpl {};
+            let directory = get_test_directory("ka-long-paths");
+            let nested_path: PathBuf = (0..20).map(|_| "nested directory").collect();
+            let path = directory.join(nested_path).join("file");
+            assert!(path.as_os_str().len() > 260);
+            assert!(get_long_path(&path).starts_with(r"\\?\"));
+
+            let mut file = fs.create_file(&path).unwrap();
+            fs.write_to_file(&mut file, b"content".to_vec()).unwrap();
+            drop(file);
+
+            assert!(fs.path_exists(&path));
+            let mut file = fs.open_readable_file(&path).unwrap();
+            assert_eq!(fs.read_from_file(&mut file).unwrap(), b"content");
+            let entries = fs.read_directory(path.parent().unwrap()).unwrap();
+            assert_eq!(entries[0].path(), path);
+            drop(file);
+
+            fs.delete_directory(&directory).unwrap();
+            assert!(!fs.path_exists(&path));
+        }
+
+        #[test]
+        fn reserved_names() {
+            let fs = FsImpl {};
+            let directory = get_test_directory("ka-reserved-names");
+            let path = directory.join("CON.txt");
+
+            let mut file = fs.create_file(&path).unwrap();
+            fs.write_to_file(&mut file, b"not a console".to_vec())
+                .unwrap();
+            drop(file);
+
+            let entries = fs.read_directory(&directory).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].path(), path);
+            let mut file = fs.open_readable_file(&path).unwrap();
+            assert_eq!(fs.read_from_file(&mut file).unwrap(), b"not a console");
+            drop(file);
+
+            fs.delete_file(&path).unwrap();
+            fs.delete_directory(&directory).unwrap();
+            assert!(!fs.path_exists(Path::new(&directory)));
+        }
     }
 }
 
-// TODO: This will be used for tests. Write them.
 #[allow(dead_code)]
-#[cfg(test)]
 pub mod mock {
     use anyhow::{anyhow, Result};
     use std::{
-        collections::{hash_map, HashMap, HashSet},
+        collections::{
+            hash_map::{self, DefaultHasher},
+            HashMap, HashSet,
+        },
+        hash::{Hash, Hasher},
         path::{Path, PathBuf},
         sync::{Arc, Mutex, MutexGuard},
     };
@@ -132,6 +518,13 @@ pub mod mock {
 
     pub struct FsMock {
         state: Arc<Mutex<FsState>>,
+        available_space: Arc<Mutex<Option<u64>>>,
+    }
+
+    impl Default for FsMock {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl FsMock {
@@ -142,9 +535,18 @@ pub mod mock {
 
             FsMock {
                 state: Arc::new(Mutex::new(state)),
+                available_space: Arc::new(Mutex::new(None)),
             }
         }
 
+        // Space is unlimited unless it's set.
+        pub fn set_available_space(&self, available_space: Option<u64>) {
+            *self
+                .available_space
+                .lock()
+                .expect("FsMock space lock poisoned.") = available_space;
+        }
+
         pub fn set_state(&mut self, new_state: FsState) {
             let mut state = self.state.lock().expect("FsMock state lock poisoned.");
             *state = new_state;
@@ -164,12 +566,25 @@ pub mod mock {
             }
         }
 
-        fn state(&self) -> MutexGuard<FsState> {
+        fn state(&self) -> MutexGuard<'_, FsState> {
             self.state.lock().expect("FsMock state lock poisoned.")
         }
     }
 
-    impl<'fs> Fs for FsMock {
+    // Writes or reads a whole file for a test, which fails if that doesn't work.
+    #[cfg(test)]
+    pub fn write_file(fs: &impl Fs, path: &str, content: &[u8]) {
+        let mut file = fs.create_file(Path::new(path)).unwrap();
+        fs.write_to_file(&mut file, content.to_vec()).unwrap();
+    }
+
+    #[cfg(test)]
+    pub fn read_file(fs: &impl Fs, path: &str) -> Vec<u8> {
+        let mut file = fs.open_readable_file(Path::new(path)).unwrap();
+        fs.read_from_file(&mut file).unwrap()
+    }
+
+    impl Fs for FsMock {
         type File = FileMock;
 
         type Entry = EntryMock;
@@ -329,6 +744,12 @@ pub mod mock {
             }
         }
 
+        fn append_to_file(&self, file: &mut Self::File, buffer: Vec<u8>) -> Result<()> {
+            let mut content = self.read_from_file(file)?;
+            content.extend(buffer);
+            self.write_to_file(file, content)
+        }
+
         fn read_from_file(&self, file: &mut Self::File) -> Result<Vec<u8>> {
             let state = self.state();
             if let Some(content) = state.get_content_if_file(&file.path) {
@@ -351,6 +772,37 @@ pub mod mock {
         fn path_exists(&self, path: &Path) -> bool {
             self.state().exists(path)
         }
+
+        fn available_space(&self, _path: &Path) -> Result<Option<u64>> {
+            Ok(*self
+                .available_space
+                .lock()
+                .expect("FsMock space lock poisoned."))
+        }
+
+        fn supports_stamps(&self) -> bool {
+            true
+        }
+
+        // There are no modification times here, so the content of files and the names in
+        // directories stand in for them.
+        fn stamp(&self, path: &Path) -> Option<(u64, u64)> {
+            let state = self.state();
+            let mut hasher = DefaultHasher::new();
+            if let Some(content) = state.get_content_if_file(path) {
+                content.hash(&mut hasher);
+                return Some((content.len() as u64, hasher.finish()));
+            }
+
+            let mut names = state
+                .get_entries_if_directory(path)?
+                .iter()
+                .map(|entry| entry.path())
+                .collect::<Vec<_>>();
+            names.sort();
+            names.hash(&mut hasher);
+            Some((names.len() as u64, hasher.finish()))
+        }
     }
 
     #[derive(Clone)]
@@ -471,10 +923,7 @@ pub mod mock {
 
         fn get_file(&self, path: &Path) -> Option<FileMock> {
             match self.entries.get(path) {
-                Some(entry) => match entry {
-                    EntryMock::File(file) => Some(file.clone()),
-                    _ => None,
-                },
+                Some(EntryMock::File(file)) => Some(file.clone()),
                 _ => None,
             }
         }
@@ -492,13 +941,10 @@ pub mod mock {
 
         fn write_to_if_file(&mut self, path: &Path, buffer: Vec<u8>) -> bool {
             match self.entries.get_mut(path) {
-                Some(entry) => match entry {
-                    EntryMock::File(file) => {
-                        file.content = buffer;
-                        true
-                    }
-                    _ => false,
-                },
+                Some(EntryMock::File(file)) => {
+                    file.content = buffer;
+                    true
+                }
                 _ => false,
             }
         }
@@ -555,7 +1001,7 @@ pub mod mock {
         fn is_file(&self, path: &Path) -> bool {
             self.entries
                 .get(path)
-                .map_or(false, |e| matches!(e, EntryMock::File(_)))
+                .is_some_and(|e| matches!(e, EntryMock::File(_)))
         }
 
         fn is_directory(&self, path: &Path) -> bool {
@@ -566,7 +1012,7 @@ pub mod mock {
 
             self.entries
                 .get(path)
-                .map_or(false, |e| matches!(e, EntryMock::Dir { .. }))
+                .is_some_and(|e| matches!(e, EntryMock::Dir { .. }))
         }
 
         fn exists(&self, path: &Path) -> bool {
@@ -616,6 +1062,7 @@ pub mod mock {
         }
     }
 
+    #[cfg(test)]
     mod tests {
         use std::path::Path;
 
@@ -672,8 +1119,8 @@ pub mod mock {
             // Diffing is easier when we do it from FsState, so we use it here for the test,
             // even though it isn't an actual filesystem state, which is sort of hacky.
             let expected_read_files = FsState::new(vec![
-                EntryMock::file("./folder/file", &vec![]),
-                EntryMock::file("./folder/another_file", &vec![]),
+                EntryMock::file("./folder/file", &[]),
+                EntryMock::file("./folder/another_file", &[]),
                 EntryMock::dir("./folder/nested"),
             ]);
 
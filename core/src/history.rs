@@ -0,0 +1,2176 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    error::Error,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    vec::IntoIter,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    consistency::{self, ConsistencyPolicy, Inconsistency},
+    crypto,
+    diff::ContentChange,
+    files::{collect_files, rebase_path, Locations},
+    filesystem::Fs,
+    migrations::{
+        migrate_file_history, migrate_repository_history, FILE_HISTORY_VERSION,
+        REPOSITORY_HISTORY_VERSION,
+    },
+    objects::{ObjectStore, OBJECT_THRESHOLD},
+    policy::ContentType,
+    records::{decode_records, encode_record, is_record_format, RecordReader, MAGIC},
+    structured::{self, StructuredChange},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RepositoryHistory {
+    #[serde(default)]
+    pub version: u32,
+    pub cursor: usize,
+    changes: Vec<RepositoryChange>,
+    // Working files which shifts leave alone, so they stay at the version they had when pinned.
+    #[serde(default)]
+    pub pinned_files: Vec<PathBuf>,
+    // Working files which aren't at the cursor, e.g. pinned ones after a shift, with the
+    // cursor they are at instead.
+    #[serde(default)]
+    pub file_cursors: BTreeMap<PathBuf, usize>,
+    #[serde(default)]
+    pub sessions: Vec<Session>,
+    // The length of the encoded history this was decoded from, if more records can be
+    // appended to it.
+    #[serde(skip)]
+    stored_length: Option<usize>,
+    // The segments the oldest changes are sealed in, oldest first.
+    #[serde(skip)]
+    segments: Vec<IndexSegment>,
+    // Whether changes which are already sealed were changed, which means all segments have
+    // to be written again.
+    #[serde(skip)]
+    are_segments_modified: bool,
+    // The changes which affected each working file, built once it's first asked for and
+    // kept up to date as changes are added.
+    #[serde(skip)]
+    path_index: OnceLock<BTreeMap<PathBuf, Vec<usize>>>,
+}
+
+impl Default for RepositoryHistory {
+    fn default() -> Self {
+        Self {
+            version: REPOSITORY_HISTORY_VERSION,
+            cursor: 0,
+            changes: Vec::new(),
+            pinned_files: Vec::new(),
+            file_cursors: BTreeMap::new(),
+            sessions: Vec::new(),
+            stored_length: None,
+            segments: Vec::new(),
+            are_segments_modified: false,
+            path_index: OnceLock::new(),
+        }
+    }
+}
+
+// Once this many changes piled up in the index, they are sealed into a segment file of their
+// own. Segments are never appended to, and only read when their changes are needed.
+pub const SEGMENT_LENGTH: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexSegment {
+    pub number: usize,
+    pub change_count: usize,
+    // The latest timestamp of its changes, so reading recent changes can skip the segment.
+    pub last_timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RepositoryHistoryHeader {
+    version: u32,
+}
+
+// Moving the cursor only appends a small record, the latest one is the current cursor.
+// The same goes for the pinned files, file cursors and sessions.
+#[derive(Serialize, Deserialize)]
+enum IndexRecord<C> {
+    Change(C),
+    Segment(IndexSegment),
+    Cursor(usize),
+    Pins(Vec<PathBuf>),
+    FileCursors(BTreeMap<PathBuf, usize>),
+    Sessions(Vec<Session>),
+}
+
+impl RepositoryHistory {
+    // Only the index itself, the changes of the segments it lists are written by
+    // `encode_segments`.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let segments = self.get_segment_layout();
+        let sealed_count = count_sealed_changes(&segments);
+
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend(encode_record(&RepositoryHistoryHeader {
+            version: REPOSITORY_HISTORY_VERSION,
+        })?);
+        for segment in segments {
+            buffer.extend(encode_record(&IndexRecord::<&RepositoryChange>::Segment(
+                segment,
+            ))?);
+        }
+        for change in self.changes[sealed_count..].iter() {
+            buffer.extend(encode_record(&IndexRecord::Change(change))?);
+        }
+        buffer.extend(self.encode_cursor()?);
+        if !self.pinned_files.is_empty() {
+            buffer.extend(self.encode_pins()?);
+        }
+        if !self.file_cursors.is_empty() {
+            buffer.extend(self.encode_file_cursors()?);
+        }
+        if !self.sessions.is_empty() {
+            buffer.extend(self.encode_sessions()?);
+        }
+        Ok(buffer)
+    }
+
+    // The segments which are new or changed since the history was loaded, by their path.
+    pub fn encode_segments(&self, locations: &Locations) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let segments = self.get_segment_layout();
+        let unchanged_count = if self.are_segments_modified {
+            0
+        } else {
+            self.segments.len()
+        };
+
+        let mut start = count_sealed_changes(&segments[..unchanged_count]);
+        let mut encoded_segments = Vec::new();
+        for segment in segments[unchanged_count..].iter() {
+            let end = start + segment.change_count;
+            let mut buffer = MAGIC.to_vec();
+            buffer.extend(encode_record(&RepositoryHistoryHeader {
+                version: REPOSITORY_HISTORY_VERSION,
+            })?);
+            for change in self.changes[start..end].iter() {
+                buffer.extend(encode_record(change)?);
+            }
+            encoded_segments.push((locations.get_index_segment_path(segment.number), buffer));
+            start = end;
+        }
+        Ok(encoded_segments)
+    }
+
+    // Whether so many changes were added that the next write has to seal some of them, instead
+    // of appending to the index.
+    pub fn needs_sealing(&self) -> bool {
+        self.changes.len() - count_sealed_changes(&self.segments) >= SEGMENT_LENGTH
+    }
+
+    // Which segments the changes are sealed in when written. The stored segments are kept,
+    // unless their changes were modified, and all complete runs of changes after them are
+    // sealed into new ones.
+    fn get_segment_layout(&self) -> Vec<IndexSegment> {
+        let mut segments = if self.are_segments_modified {
+            Vec::new()
+        } else {
+            self.segments.clone()
+        };
+
+        let mut start = count_sealed_changes(&segments);
+        while self.changes.len() - start >= SEGMENT_LENGTH {
+            let end = start + SEGMENT_LENGTH;
+            segments.push(IndexSegment {
+                number: segments.len() + 1,
+                change_count: SEGMENT_LENGTH,
+                last_timestamp: self.changes[start..end]
+                    .iter()
+                    .map(|change| change.timestamp)
+                    .max()
+                    .unwrap_or_default(),
+            });
+            start = end;
+        }
+        segments
+    }
+
+    // The records to append to the stored history after a change was added.
+    pub fn encode_latest_change(&self) -> Result<Vec<u8>> {
+        let change = self
+            .changes
+            .last()
+            .context("The repository history has no changes.")?;
+
+        let mut buffer = encode_record(&IndexRecord::Change(change))?;
+        buffer.extend(self.encode_cursor()?);
+        Ok(buffer)
+    }
+
+    // The record to append to the stored history after the cursor was moved.
+    pub fn encode_cursor(&self) -> Result<Vec<u8>> {
+        encode_record(&IndexRecord::<&RepositoryChange>::Cursor(self.cursor))
+    }
+
+    // The record to append to the stored history after files were pinned or unpinned.
+    pub fn encode_pins(&self) -> Result<Vec<u8>> {
+        encode_record(&IndexRecord::<&RepositoryChange>::Pins(
+            self.pinned_files.clone(),
+        ))
+    }
+
+    // The record to append to the stored history after files were moved to another cursor
+    // than the repository's, or back to it.
+    pub fn encode_file_cursors(&self) -> Result<Vec<u8>> {
+        encode_record(&IndexRecord::<&RepositoryChange>::FileCursors(
+            self.file_cursors.clone(),
+        ))
+    }
+
+    // The record to append to the stored history after a session was started or ended.
+    pub fn encode_sessions(&self) -> Result<Vec<u8>> {
+        encode_record(&IndexRecord::<&RepositoryChange>::Sessions(
+            self.sessions.clone(),
+        ))
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        if !is_record_format(buffer) {
+            // Histories from before the record format are plain JSON, which is migrated first.
+            let mut value: Value = serde_json::from_slice(buffer)
+                .context(corrupted("Failed decoding repository history."))?;
+            migrate_repository_history(&mut value)?;
+            return serde_json::from_value(value)
+                .context(corrupted("Failed decoding repository history."));
+        }
+
+        let records =
+            decode_records(buffer).context(corrupted("Failed decoding repository history."))?;
+        let (header, records) = records
+            .split_first()
+            .context(corrupted("The repository history has no header."))?;
+        let header: RepositoryHistoryHeader = serde_json::from_slice(header)
+            .context(corrupted("Failed decoding repository history."))?;
+        check_version(header.version, REPOSITORY_HISTORY_VERSION)?;
+
+        let mut history = Self {
+            stored_length: Some(buffer.len()),
+            ..Self::default()
+        };
+        for record in records {
+            match serde_json::from_slice(record)
+                .context(corrupted("Failed decoding repository history."))?
+            {
+                IndexRecord::Change(change) => history.changes.push(change),
+                IndexRecord::Segment(segment) => history.segments.push(segment),
+                IndexRecord::Cursor(cursor) => history.cursor = cursor,
+                IndexRecord::Pins(pinned_files) => history.pinned_files = pinned_files,
+                IndexRecord::FileCursors(file_cursors) => history.file_cursors = file_cursors,
+                IndexRecord::Sessions(sessions) => history.sessions = sessions,
+            }
+        }
+
+        Ok(history)
+    }
+
+    pub fn get_stored_length(&self) -> Option<usize> {
+        self.stored_length
+    }
+
+    // When the latest change was recorded. Without its segments loaded, that's still known
+    // from the last of them.
+    pub fn get_latest_timestamp(&self) -> Option<u64> {
+        self.changes
+            .iter()
+            .map(|change| change.timestamp)
+            .max()
+            .or_else(|| self.segments.last().map(|segment| segment.last_timestamp))
+    }
+
+    // Decoding only reads the index itself, this also loads the changes sealed in segments.
+    pub fn from_file<FS: Fs>(fs: &FS, locations: &Locations, file: &mut FS::File) -> Result<Self> {
+        let buffer = fs
+            .read_from_file(file)
+            .context("Failed reading repository history.")?;
+
+        let mut history = Self::decode(&buffer)?;
+        let mut changes = Vec::new();
+        for segment in history.segments.iter() {
+            changes.extend(load_segment(fs, locations, segment)?);
+        }
+        changes.append(&mut history.changes);
+        history.changes = changes;
+        Ok(history)
+    }
+
+    // The changes recorded at or after `since`, and the cursor the first of them comes after.
+    // Segments with only older changes aren't read at all.
+    pub fn load_changes_since<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        file: &mut FS::File,
+        since: u64,
+    ) -> Result<(usize, Vec<RepositoryChange>)> {
+        let buffer = fs
+            .read_from_file(file)
+            .context("Failed reading repository history.")?;
+        let history = Self::decode(&buffer)?;
+
+        let mut skipped_count = 0;
+        let mut changes = Vec::new();
+        for segment in history.segments.iter() {
+            if changes.is_empty() && segment.last_timestamp < since {
+                skipped_count += segment.change_count;
+            } else {
+                changes.extend(load_segment(fs, locations, segment)?);
+            }
+        }
+        changes.extend(history.changes);
+        Ok((skipped_count, changes))
+    }
+
+    // Like `from_file`, but also cross-checks the index with the file histories if the policy
+    // asks for it. A healed index can't be appended to anymore, it has to be written completely.
+    pub fn from_file_checked<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        file: &mut FS::File,
+        policy: ConsistencyPolicy,
+    ) -> Result<(Self, Vec<Inconsistency>)> {
+        let mut history = Self::from_file(fs, locations, file)?;
+        if policy == ConsistencyPolicy::Off {
+            return Ok((history, Vec::new()));
+        }
+
+        let inconsistencies = consistency::check_consistency(fs, locations, &history)?;
+        if policy == ConsistencyPolicy::Heal && consistency::heal(&mut history, &inconsistencies) {
+            history.stored_length = None;
+        }
+        Ok((history, inconsistencies))
+    }
+
+    pub fn write_to_file<FS: Fs>(
+        &self,
+        fs: &FS,
+        locations: &Locations,
+        file: &mut FS::File,
+    ) -> Result<()> {
+        for (segment_path, encoded_segment) in self.encode_segments(locations)? {
+            let mut segment_file = fs.create_file(&segment_path)?;
+            fs.write_to_file(&mut segment_file, encoded_segment)?;
+        }
+        // Segments which lost their changes, e.g. to pruning, aren't listed anymore.
+        for number in self.get_segment_layout().len() + 1..=self.segments.len() {
+            let segment_path = locations.get_index_segment_path(number);
+            if fs.path_exists(&segment_path) {
+                fs.delete_file(&segment_path)?;
+            }
+        }
+
+        let encoded: Vec<u8> = self.encode()?;
+        fs.write_to_file(file, encoded)?;
+        Ok(())
+    }
+
+    pub fn get_changes(&self) -> &Vec<RepositoryChange> {
+        &self.changes
+    }
+
+    pub fn get_changes_mut(&mut self) -> &mut Vec<RepositoryChange> {
+        self.are_segments_modified |= !self.segments.is_empty();
+        self.path_index = OnceLock::new();
+        &mut self.changes
+    }
+
+    pub fn add_change(&mut self, change: RepositoryChange) {
+        if let Some(path_index) = self.path_index.get_mut() {
+            for working_path in change.affected_files.iter() {
+                let change_indices = path_index.entry(working_path.clone()).or_default();
+                change_indices.push(self.changes.len() + 1);
+            }
+        }
+        self.changes.push(change);
+    }
+
+    // The indices of the changes which affected any working file at or below `path_prefix`,
+    // oldest first.
+    pub fn changes_for(&self, path_prefix: &Path) -> Vec<usize> {
+        let path_index = self.path_index.get_or_init(|| {
+            let mut path_index = BTreeMap::<PathBuf, Vec<usize>>::new();
+            for (index, change) in self.changes.iter().enumerate() {
+                for working_path in change.affected_files.iter() {
+                    path_index
+                        .entry(working_path.clone())
+                        .or_default()
+                        .push(index + 1);
+                }
+            }
+            path_index
+        });
+
+        // Paths are ordered by their components, so everything below the prefix follows it.
+        let mut change_indices = path_index
+            .range(path_prefix.to_path_buf()..)
+            .take_while(|(working_path, _)| working_path.starts_with(path_prefix))
+            .flat_map(|(_, change_indices)| change_indices.iter().copied())
+            .collect::<Vec<_>>();
+        change_indices.sort_unstable();
+        change_indices.dedup();
+        change_indices
+    }
+
+    // Moves every working path below `from` to the same place below `to`. The working paths
+    // are stored as they are, so a repository which was moved has to have them moved along
+    // with it, which means writing it out completely, segments included.
+    pub fn rebase_paths(&mut self, from: &Path, to: &Path) {
+        for change in self.get_changes_mut() {
+            for path in change.affected_files.iter_mut() {
+                *path = rebase_path(path, from, to);
+            }
+        }
+        for path in self.pinned_files.iter_mut() {
+            *path = rebase_path(path, from, to);
+        }
+        self.file_cursors = self
+            .file_cursors
+            .iter()
+            .map(|(path, cursor)| (rebase_path(path, from, to), *cursor))
+            .collect();
+        self.stored_length = None;
+    }
+
+    // The cursor the working file is at, which is the repository's unless it was overridden.
+    pub fn get_file_cursor(&self, working_path: &Path) -> usize {
+        self.file_cursors
+            .get(working_path)
+            .copied()
+            .unwrap_or(self.cursor)
+    }
+
+    pub fn is_pinned(&self, working_path: &Path) -> bool {
+        self.pinned_files.iter().any(|path| path == working_path)
+    }
+
+    // The ID of the change at `cursor`, which the next change recorded there has as its parent.
+    pub fn get_change_id(&self, cursor: usize) -> Option<&str> {
+        cursor
+            .checked_sub(1)
+            .and_then(|index| self.changes.get(index))
+            .and_then(|change| change.id.as_deref())
+    }
+
+    // Maps the IDs of all changes which have one to their cursors.
+    pub fn get_change_ids(&self) -> BTreeMap<&str, usize> {
+        self.changes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, change)| Some((change.id.as_deref()?, index + 1)))
+            .collect()
+    }
+
+    // Finds the cursor of a change given either as its cursor or as a prefix of its ID.
+    pub fn resolve_change(&self, reference: &str) -> Result<usize> {
+        if let Ok(cursor) = reference.parse() {
+            return Ok(cursor);
+        }
+        if reference.is_empty() {
+            bail!("Expected a cursor or a change ID.");
+        }
+
+        let change_ids = self.get_change_ids();
+        let mut matches = change_ids
+            .range(reference..)
+            .take_while(|(id, _)| id.starts_with(reference));
+        match (matches.next(), matches.next()) {
+            (Some((_, &cursor)), None) => Ok(cursor),
+            (Some(_), Some(_)) => bail!("The change ID '{}' is ambiguous.", reference),
+            (None, _) => bail!("There is no change with the ID '{}'.", reference),
+        }
+    }
+
+    pub fn get_open_session(&self) -> Option<&Session> {
+        self.sessions.last().filter(|session| session.end.is_none())
+    }
+
+    // The cursors the latest session with that name started and ended at. One which is still
+    // going ends at the latest change.
+    pub fn get_session_bounds(&self, name: &str) -> Option<(usize, usize)> {
+        self.sessions
+            .iter()
+            .rev()
+            .find(|session| session.name == name)
+            .map(|session| {
+                let end = session.end.unwrap_or(self.changes.len());
+                (session.start, end.max(session.start))
+            })
+    }
+
+    pub fn get_tracked_paths(&self) -> HashSet<&PathBuf> {
+        self.changes
+            .iter()
+            .flat_map(|change| change.affected_files.iter())
+            .collect()
+    }
+}
+
+// A named run of consecutive changes, e.g. everything recorded while trying out an idea.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub name: String,
+    // The cursor the session started at, its first change is the one after it.
+    pub start: usize,
+    // The cursor the session ended at, unless it's still going.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<usize>,
+}
+
+// Keys mapped to values, both of them up to the caller.
+pub type Annotations = BTreeMap<String, String>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepositoryChange {
+    pub affected_files: Vec<PathBuf>,
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    // Unlike its position, which moves when earlier changes are squashed or pruned, this
+    // identifies the change for good. Older changes don't have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // Who recorded the change, from the identity in the config of the repository it was
+    // recorded in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    // Whatever context whoever recorded the change wanted to keep with it, like the app which
+    // saved the files.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: Annotations,
+}
+
+impl RepositoryChange {
+    // Derived from the parent's ID and what the change recorded for each of its files.
+    pub fn compute_id(
+        parent_id: Option<&str>,
+        timestamp: u64,
+        file_changes: &[(&Path, &FileChange)],
+    ) -> String {
+        let mut buffer = parent_id.unwrap_or_default().as_bytes().to_vec();
+        buffer.extend(timestamp.to_le_bytes());
+        for (working_path, file_change) in file_changes {
+            buffer.push(0);
+            buffer.extend(working_path.to_string_lossy().as_bytes());
+            buffer.push(0);
+            match (&file_change.variant, &file_change.content_hash) {
+                (FileChangeVariant::Deleted, _) => buffer.extend(b"deleted"),
+                (
+                    FileChangeVariant::Updated(_)
+                    | FileChangeVariant::CopiedFrom { .. }
+                    | FileChangeVariant::Structured(_),
+                    Some(content_hash),
+                ) => buffer.extend(content_hash.as_bytes()),
+                (FileChangeVariant::Updated(changes), None) => {
+                    buffer.extend(serde_json::to_vec(changes).unwrap_or_default())
+                }
+                (
+                    variant @ (FileChangeVariant::CopiedFrom { .. }
+                    | FileChangeVariant::Structured(_)),
+                    None,
+                ) => buffer.extend(serde_json::to_vec(variant).unwrap_or_default()),
+            }
+        }
+        crypto::to_hex(&crypto::sha256(&buffer))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileHistory {
+    #[serde(default)]
+    pub version: u32,
+    changes: Vec<FileChange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<String>,
+    // The file isn't tracked anymore. Its history can still be looked at, but updates and
+    // shifts leave the working file alone.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub forgotten: bool,
+    // The file started out as a copy of another one, which is kept even once its first change
+    // doesn't refer to the other file anymore.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copied_from: Option<CopySource>,
+    // The segments the oldest changes are sealed in, oldest first.
+    #[serde(skip)]
+    segments: Vec<FileSegment>,
+    // The state after the last sealed change, which replaying the latest content starts from
+    // instead of the sealed changes.
+    #[serde(skip)]
+    base: Option<FileChange>,
+    // Whether changes which are already sealed were changed, which means they are written to
+    // the history file again.
+    #[serde(skip)]
+    are_segments_modified: bool,
+    // The variants of the copies and structured changes `from_file` resolved, by their change
+    // index, which are written instead of the content changes they were resolved into as long
+    // as the changes aren't modified.
+    #[serde(skip)]
+    records: BTreeMap<usize, FileChangeVariant>,
+}
+
+impl Default for FileHistory {
+    fn default() -> Self {
+        Self {
+            version: FILE_HISTORY_VERSION,
+            changes: Vec::new(),
+            provenance: None,
+            forgotten: false,
+            copied_from: None,
+            segments: Vec::new(),
+            base: None,
+            are_segments_modified: false,
+            records: BTreeMap::new(),
+        }
+    }
+}
+
+// Once this many changes piled up in a history file, all but the latest one are sealed into a
+// segment of their own, so reconstructing the latest content doesn't replay all of them.
+pub const FILE_SEGMENT_LENGTH: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileSegment {
+    // Relative to the `.ka` directory.
+    pub path: PathBuf,
+    pub change_count: usize,
+    pub last_change_index: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CopySource {
+    // Relative to the repository.
+    pub path: PathBuf,
+    pub cursor: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileHistoryHeader {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provenance: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    forgotten: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    copied_from: Option<CopySource>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<FileSegment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base: Option<FileChange>,
+}
+
+impl FileHistory {
+    // Only the history file itself, the sealed changes are written by `seal`.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let sealed_count = self.get_sealed_count();
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend(encode_record(&FileHistoryHeader {
+            version: FILE_HISTORY_VERSION,
+            provenance: self.provenance.clone(),
+            forgotten: self.forgotten,
+            copied_from: self.copied_from.clone(),
+            segments: self.segments[..self.get_sealed_segment_count()].to_vec(),
+            base: self.base.clone().filter(|_| sealed_count > 0),
+        })?);
+        for change in self.changes[sealed_count..].iter() {
+            buffer.extend(encode_record(&self.get_record(change))?);
+        }
+        Ok(buffer)
+    }
+
+    // The change as it's written, with the variant it was resolved from, if it was.
+    fn get_record(&self, change: &FileChange) -> FileChange {
+        match self.records.get(&change.change_index) {
+            Some(variant) => FileChange {
+                variant: variant.clone(),
+                ..change.clone()
+            },
+            None => change.clone(),
+        }
+    }
+
+    // Seals all changes but the latest one into a new segment, once enough of them piled up
+    // in the history file. Returns the encoded segment by its path, which has to be written
+    // along with the history file.
+    pub fn seal<FS: Fs>(
+        &mut self,
+        fs: &FS,
+        locations: &Locations,
+        history_path: &Path,
+    ) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let sealed_count = self.get_sealed_count();
+        if self.are_segments_modified || self.changes.len() - sealed_count < FILE_SEGMENT_LENGTH {
+            return Ok(Vec::new());
+        }
+
+        let end = self.changes.len() - 1;
+        let last_change = &self.changes[end - 1];
+        let variant = match last_change.variant {
+            FileChangeVariant::Deleted => FileChangeVariant::Deleted,
+            _ => FileChangeVariant::Updated(ContentChange::snapshot(
+                &[],
+                &self.get_content(last_change.change_index)?,
+            )),
+        };
+        let base = FileChange {
+            change_index: last_change.change_index,
+            variant,
+            degraded: false,
+            content_hash: last_change.content_hash.clone(),
+            change_id: last_change.change_id.clone(),
+            content_type: last_change.content_type,
+        };
+
+        let store = ObjectStore::new(fs, locations);
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend(encode_record(&FileHistoryHeader {
+            version: FILE_HISTORY_VERSION,
+            provenance: None,
+            forgotten: false,
+            copied_from: None,
+            segments: Vec::new(),
+            base: None,
+        })?);
+        for change in self.changes[sealed_count..end].iter() {
+            let mut change = self.get_record(change);
+            store_objects(&mut change, &store)?;
+            buffer.extend(encode_record(&change)?);
+        }
+
+        let segment_path =
+            locations.get_file_segment_path(history_path, self.segments.len() + 1)?;
+        self.segments.push(FileSegment {
+            path: segment_path.strip_prefix(&locations.ka_path)?.to_path_buf(),
+            change_count: end - sealed_count,
+            last_change_index: base.change_index,
+        });
+        self.base = Some(base);
+        Ok(vec![(segment_path, buffer)])
+    }
+
+    fn get_sealed_segment_count(&self) -> usize {
+        if self.are_segments_modified {
+            0
+        } else {
+            self.segments.len()
+        }
+    }
+
+    fn get_sealed_count(&self) -> usize {
+        self.segments[..self.get_sealed_segment_count()]
+            .iter()
+            .map(|segment| segment.change_count)
+            .sum()
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        if !is_record_format(buffer) {
+            let mut value: Value = serde_json::from_slice(buffer)
+                .context(corrupted("Failed decoding file history."))?;
+            migrate_file_history(&mut value)?;
+            return serde_json::from_value(value)
+                .context(corrupted("Failed decoding file history."));
+        }
+
+        let records = decode_records(buffer).context(corrupted("Failed decoding file history."))?;
+        let (header, records) = records
+            .split_first()
+            .context(corrupted("The file history has no header."))?;
+        let header: FileHistoryHeader =
+            serde_json::from_slice(header).context(corrupted("Failed decoding file history."))?;
+        check_version(header.version, FILE_HISTORY_VERSION)?;
+
+        let changes = records
+            .iter()
+            .map(|record| serde_json::from_slice(record))
+            .collect::<Result<_, _>>()
+            .context(corrupted("Failed decoding file history."))?;
+
+        Ok(Self {
+            version: FILE_HISTORY_VERSION,
+            changes,
+            provenance: header.provenance,
+            forgotten: header.forgotten,
+            copied_from: header.copied_from,
+            segments: header.segments,
+            base: header.base,
+            are_segments_modified: false,
+            records: BTreeMap::new(),
+        })
+    }
+
+    // Decoding only reads the history file itself, this also loads the sealed changes and
+    // resolves the content stored elsewhere. Only the changes the content is needed of go
+    // through this, rewrites which leave the changes alone read the history as it's stored.
+    pub fn from_file<FS: Fs>(fs: &FS, locations: &Locations, file: &mut FS::File) -> Result<Self> {
+        let mut history = Self::read_stored(fs, locations, file)?;
+        for file_change in history.changes.iter() {
+            if let FileChangeVariant::CopiedFrom { .. } | FileChangeVariant::Structured(_) =
+                file_change.variant
+            {
+                history
+                    .records
+                    .insert(file_change.change_index, file_change.variant.clone());
+            }
+        }
+
+        let store = ObjectStore::new(fs, locations);
+        for file_change in history.changes.iter_mut().chain(history.base.iter_mut()) {
+            resolve_objects(file_change, &store)?;
+            resolve_copy(file_change, fs, locations)?;
+        }
+        resolve_structured(&mut history.changes)?;
+        Ok(history)
+    }
+
+    // Like `from_file`, but with the changes as they are stored, e.g. with their large
+    // insertions still referring to the object store.
+    pub fn read_stored<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        file: &mut FS::File,
+    ) -> Result<Self> {
+        let buffer = fs
+            .read_from_file(file)
+            .context("Failed reading file history.")?;
+
+        let mut history = Self::decode(&buffer)?;
+        let mut changes = Vec::new();
+        for segment in history.segments.iter() {
+            changes.extend(load_file_segment(fs, locations, segment)?);
+        }
+        changes.append(&mut history.changes);
+        history.changes = changes;
+        Ok(history)
+    }
+
+    // Removes the history file along with the segments of its sealed changes.
+    pub fn delete_file<FS: Fs>(fs: &FS, locations: &Locations, history_path: &Path) -> Result<()> {
+        let mut history_file = fs.open_readable_file(history_path)?;
+        let history = Self::decode(&fs.read_from_file(&mut history_file)?)?;
+        fs.delete_file(history_path)?;
+        history.delete_segments(fs, locations)
+    }
+
+    fn delete_segments<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<()> {
+        for segment in self.segments.iter() {
+            let segment_path = locations.ka_path.join(&segment.path);
+            if fs.path_exists(&segment_path) {
+                fs.delete_file(&segment_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Like `from_file`, but only decodes each change when the iterator gets to it.
+    pub fn iter_changes<'a, FS: Fs>(
+        fs: &'a FS,
+        locations: &Locations,
+        file: &mut FS::File,
+    ) -> Result<FileChanges<'a, FS>> {
+        let buffer = fs
+            .read_from_file(file)
+            .context("Failed reading file history.")?;
+
+        Self::iter_changes_for(fs, locations, buffer, None)
+    }
+
+    // Starts from the base instead of the sealed changes, if only the content at `at_cursor`
+    // is needed and that's after them.
+    fn iter_changes_for<'a, FS: Fs>(
+        fs: &'a FS,
+        locations: &Locations,
+        buffer: Vec<u8>,
+        at_cursor: Option<usize>,
+    ) -> Result<FileChanges<'a, FS>> {
+        let mut segment_paths = VecDeque::new();
+        let mut base = None;
+        let (source, forgotten) = if is_record_format(&buffer) {
+            let mut records = RecordReader::new(buffer)?;
+            let header = records
+                .next_record()?
+                .context(corrupted("The file history has no header."))?;
+            let header: FileHistoryHeader = serde_json::from_slice(header)
+                .context(corrupted("Failed decoding file history."))?;
+            check_version(header.version, FILE_HISTORY_VERSION)?;
+
+            match header.base {
+                Some(header_base)
+                    if at_cursor.is_some_and(|cursor| cursor >= header_base.change_index) =>
+                {
+                    base = Some(header_base)
+                }
+                _ => {
+                    segment_paths = header
+                        .segments
+                        .iter()
+                        .map(|segment| locations.ka_path.join(&segment.path))
+                        .collect()
+                }
+            }
+            (ChangeSource::Records(records), header.forgotten)
+        } else {
+            let history = Self::decode(&buffer)?;
+            (
+                ChangeSource::Decoded(history.changes.into_iter()),
+                history.forgotten,
+            )
+        };
+
+        Ok(FileChanges {
+            source,
+            segment_paths,
+            segment: None,
+            base,
+            unsealed_count: 0,
+            fs,
+            locations: locations.clone(),
+            store: ObjectStore::new(fs, locations),
+            forgotten,
+        })
+    }
+
+    // The state at `at_cursor`, replayed without keeping the changes leading up to it.
+    pub fn get_tip<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        file: &mut FS::File,
+        at_cursor: usize,
+    ) -> Result<FileTip> {
+        let buffer = fs
+            .read_from_file(file)
+            .context("Failed reading file history.")?;
+
+        Self::get_tip_from_buffer(fs, locations, buffer, at_cursor)
+    }
+
+    // Like `get_tip`, for a history which was already read.
+    pub fn get_tip_from_buffer<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        buffer: Vec<u8>,
+        at_cursor: usize,
+    ) -> Result<FileTip> {
+        let mut changes = Self::iter_changes_for(fs, locations, buffer, Some(at_cursor))?;
+        let mut tip = FileTip {
+            content: Vec::new(),
+            is_deleted: false,
+            is_forgotten: changes.forgotten,
+            stored_length: changes.get_stored_length(),
+            unsealed_count: 0,
+        };
+
+        let mut last_change = None;
+        for file_change in changes.by_ref() {
+            let file_change = file_change?;
+            if file_change.change_index > at_cursor {
+                break;
+            }
+
+            match file_change.variant {
+                FileChangeVariant::Updated(ref updated) => {
+                    for change in updated.iter() {
+                        change
+                            .apply(&mut tip.content)
+                            .with_context(|| replay_error(file_change.change_index))?;
+                    }
+                    tip.is_deleted = false;
+                }
+                FileChangeVariant::Deleted => {
+                    tip.content.clear();
+                    tip.is_deleted = true;
+                }
+                // Changes read one by one can't be resolved before, as that needs the content.
+                FileChangeVariant::Structured(ref structured) => {
+                    tip.content = structured::apply(&tip.content, structured)
+                        .with_context(|| replay_error(file_change.change_index))?;
+                    tip.is_deleted = false;
+                }
+                FileChangeVariant::CopiedFrom { .. } => {
+                    bail!(unresolved_copy(file_change.change_index))
+                }
+            }
+            last_change = Some(file_change);
+        }
+
+        if let Some(last_change) = last_change {
+            last_change.verify(&tip.content)?;
+        }
+        tip.unsealed_count = changes.unsealed_count;
+        Ok(tip)
+    }
+
+    // What the stored history says about its latest change, without replaying anything.
+    // Legacy histories and those without changes don't say anything.
+    pub fn get_latest_change(buffer: &[u8]) -> Result<Option<LatestChange>> {
+        if !is_record_format(buffer) {
+            return Ok(None);
+        }
+
+        let records = decode_records(buffer).context(corrupted("Failed decoding file history."))?;
+        let (header, records) = records
+            .split_first()
+            .context(corrupted("The file history has no header."))?;
+        let header: FileHistoryHeader =
+            serde_json::from_slice(header).context(corrupted("Failed decoding file history."))?;
+        check_version(header.version, FILE_HISTORY_VERSION)?;
+
+        let change = match records.last() {
+            Some(record) => serde_json::from_slice(record)
+                .context(corrupted("Failed decoding file history."))?,
+            None => return Ok(None),
+        };
+        Ok(Some(LatestChange {
+            change,
+            is_forgotten: header.forgotten,
+            stored_length: buffer.len(),
+            unsealed_count: records.len(),
+        }))
+    }
+
+    pub fn write_to_file<FS: Fs>(
+        &self,
+        fs: &FS,
+        locations: &Locations,
+        file: &mut FS::File,
+    ) -> Result<()> {
+        let encoded: Vec<u8> = self.encode_for_storage(fs, locations)?;
+        fs.write_to_file(file, encoded)?;
+        // Modified sealed changes were written to the history file instead.
+        if self.are_segments_modified {
+            self.delete_segments(fs, locations)?;
+        }
+        Ok(())
+    }
+
+    // Like `encode`, but with large insertions moved into the object store.
+    pub fn encode_for_storage<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<Vec<u8>> {
+        let store = ObjectStore::new(fs, locations);
+        let sealed_count = self.get_sealed_count();
+        let mut stored = self.clone();
+        for file_change in stored.changes[sealed_count..]
+            .iter_mut()
+            .filter(|file_change| !self.records.contains_key(&file_change.change_index))
+            .chain(stored.base.iter_mut())
+        {
+            store_objects(file_change, &store)?;
+        }
+        stored.encode()
+    }
+
+    // The record to append to a stored history to add the change to it.
+    pub fn encode_change_for_storage<FS: Fs>(
+        change: &FileChange,
+        fs: &FS,
+        locations: &Locations,
+    ) -> Result<Vec<u8>> {
+        let mut change = change.clone();
+        store_objects(&mut change, &ObjectStore::new(fs, locations))?;
+        encode_record(&change)
+    }
+
+    pub fn is_file_deleted(&self, at_cursor: usize) -> bool {
+        match self
+            .changes
+            .iter()
+            .take_while(|c| c.change_index <= at_cursor)
+            .last()
+        {
+            Some(change) => match change.variant {
+                FileChangeVariant::Deleted => true,
+                FileChangeVariant::Updated(_)
+                | FileChangeVariant::CopiedFrom { .. }
+                | FileChangeVariant::Structured(_) => false,
+            },
+            None => false,
+        }
+    }
+
+    pub fn does_file_exist(&self, at_cursor: usize) -> bool {
+        let has_changes = self
+            .changes
+            .iter()
+            .any(|change| change.change_index <= at_cursor);
+
+        has_changes && !self.is_file_deleted(at_cursor)
+    }
+
+    // The content type recorded with the latest change up to `at_cursor`, if it has one.
+    pub fn get_content_type(&self, at_cursor: usize) -> Option<ContentType> {
+        self.changes
+            .iter()
+            .take_while(|c| c.change_index <= at_cursor)
+            .last()
+            .and_then(|change| change.content_type)
+    }
+
+    // Reconstructs the content at `to_cursor` by undoing the changes on top of the given
+    // content at `from_cursor`, which avoids replaying the whole history when moving back.
+    // Returns `None` if any of the changes in between can't be undone.
+    pub fn get_content_backwards(
+        &self,
+        content: Vec<u8>,
+        from_cursor: usize,
+        to_cursor: usize,
+    ) -> Option<Vec<u8>> {
+        let mut buffer = content;
+
+        for file_change in self
+            .changes
+            .iter()
+            .rev()
+            .skip_while(|change| change.change_index > from_cursor)
+            .take_while(|change| change.change_index > to_cursor)
+        {
+            match file_change.variant {
+                FileChangeVariant::Updated(ref updated) => {
+                    for change in updated.iter().rev() {
+                        if !change.unapply(&mut buffer) {
+                            return None;
+                        }
+                    }
+                }
+                FileChangeVariant::Deleted
+                | FileChangeVariant::CopiedFrom { .. }
+                | FileChangeVariant::Structured(_) => return None,
+            }
+        }
+
+        // Undoing changes can go wrong in ways applying them can't, so a mismatch only
+        // means the content has to be replayed instead.
+        let target_change = self
+            .changes
+            .iter()
+            .take_while(|change| change.change_index <= to_cursor)
+            .last();
+        if target_change.is_some_and(|change| change.verify(&buffer).is_err()) {
+            return None;
+        }
+
+        Some(buffer)
+    }
+
+    pub fn get_content(&self, at_cursor: usize) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut last_change = None;
+
+        for file_change in self
+            .changes
+            .iter()
+            .take_while(|change| change.change_index <= at_cursor)
+        {
+            if let FileChangeVariant::Updated(ref updated) = file_change.variant {
+                for change in updated.iter() {
+                    change
+                        .apply(&mut buffer)
+                        .with_context(|| replay_error(file_change.change_index))?;
+                }
+            } else {
+                buffer.drain(0..);
+            }
+            last_change = Some(file_change);
+        }
+
+        if let Some(last_change) = last_change {
+            last_change.verify(&buffer)?;
+        }
+        Ok(buffer)
+    }
+
+    pub fn get_changes(&self) -> &Vec<FileChange> {
+        &self.changes
+    }
+
+    // Modified changes are written as the content changes they were resolved into, as the
+    // copies and structured changes they came from might not reproduce them anymore.
+    pub fn get_changes_mut(&mut self) -> &mut Vec<FileChange> {
+        self.are_segments_modified |= !self.segments.is_empty();
+        self.records.clear();
+        &mut self.changes
+    }
+
+    // The state after the last sealed change, if any changes are sealed.
+    pub fn get_base(&self) -> Option<&FileChange> {
+        self.base.as_ref()
+    }
+
+    pub fn get_segments(&self) -> &[FileSegment] {
+        &self.segments
+    }
+
+    // Unlike `get_changes_mut`, this keeps the sealed changes as they are, as the latest
+    // change is never sealed.
+    pub fn get_latest_change_mut(&mut self) -> Option<&mut FileChange> {
+        self.changes.last_mut()
+    }
+
+    pub fn add_change(&mut self, change: FileChange) {
+        self.changes.push(change);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileChange {
+    pub change_index: usize,
+    pub variant: FileChangeVariant,
+    // The diff hit its deadline, so the change is probably larger than it has to be.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub degraded: bool,
+    // A hash of the whole content after this change, so a replay which doesn't reproduce it
+    // is caught before it overwrites working files. Older changes don't have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    // The ID of the repository change this is part of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_id: Option<String>,
+    // What the content after this change is. Deletions and older changes don't have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<ContentType>,
+}
+
+impl FileChange {
+    pub fn hash_content(content: &[u8]) -> String {
+        crypto::to_hex(&crypto::sha256(content))
+    }
+
+    pub fn verify(&self, content: &[u8]) -> Result<()> {
+        match self.content_hash {
+            Some(ref content_hash) if *content_hash != Self::hash_content(content) => {
+                bail!(CorruptionError(format!(
+                    "Replaying the history up to change {} doesn't reproduce the recorded content, the history is corrupted.",
+                    self.change_index
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FileChangeVariant {
+    Updated(Vec<ContentChange>),
+    Deleted,
+    // Starts the history of a copy with the content the other file had at the cursor, instead
+    // of storing it again. It's always resolved into `Updated` when a history is loaded, and
+    // only written out in full once the changes are modified, or the other file's history is
+    // about to go away, see `materialize_copies`.
+    CopiedFrom { path: PathBuf, cursor: usize },
+    // Operations on the parsed tree of a JSON file, see `structured`. Like copies, they are
+    // turned into `Updated` when a history is loaded, as that needs the content before them,
+    // and only written out as content changes once the changes are modified.
+    Structured(StructuredChange),
+}
+
+pub struct FileTip {
+    pub content: Vec<u8>,
+    pub is_deleted: bool,
+    pub is_forgotten: bool,
+    // Where the next change can be appended, if the history isn't in the legacy format.
+    pub stored_length: Option<usize>,
+    // How many changes of the history file were replayed, which aren't sealed yet.
+    pub unsealed_count: usize,
+}
+
+// The latest change as it's stored, e.g. with its insertions still referring to the object
+// store, along with what a tip has to know about the history besides its content.
+pub struct LatestChange {
+    pub change: FileChange,
+    pub is_forgotten: bool,
+    pub stored_length: usize,
+    pub unsealed_count: usize,
+}
+
+pub struct FileChanges<'a, FS: Fs> {
+    source: ChangeSource,
+    // The sealed changes come first, from the segments which weren't read yet.
+    segment_paths: VecDeque<PathBuf>,
+    segment: Option<RecordReader>,
+    base: Option<FileChange>,
+    unsealed_count: usize,
+    fs: &'a FS,
+    locations: Locations,
+    store: ObjectStore<'a, FS>,
+    pub forgotten: bool,
+}
+
+enum ChangeSource {
+    Records(RecordReader),
+    // Legacy histories can't be read record by record.
+    Decoded(IntoIter<FileChange>),
+}
+
+impl<'a, FS: Fs> FileChanges<'a, FS> {
+    pub fn get_stored_length(&self) -> Option<usize> {
+        match self.source {
+            ChangeSource::Records(ref records) => Some(records.len()),
+            ChangeSource::Decoded(_) => None,
+        }
+    }
+
+    fn next_sealed_change(&mut self) -> Result<Option<FileChange>> {
+        loop {
+            if let Some(ref mut segment) = self.segment {
+                if let Some(record) = segment.next_record()? {
+                    return serde_json::from_slice(record)
+                        .map(Some)
+                        .context(corrupted("Failed decoding file history segment."));
+                }
+                self.segment = None;
+            }
+
+            let segment_path = match self.segment_paths.pop_front() {
+                Some(segment_path) => segment_path,
+                None => return Ok(None),
+            };
+            let mut segment_file = self.fs.open_readable_file(&segment_path)?;
+            let mut segment = RecordReader::new(self.fs.read_from_file(&mut segment_file)?)
+                .with_context(|| format!("Failed loading '{}'.", segment_path.display()))?;
+            let header = segment
+                .next_record()?
+                .context(corrupted("The file history segment has no header."))?;
+            let header: FileHistoryHeader = serde_json::from_slice(header)
+                .context(corrupted("Failed decoding file history segment."))?;
+            check_version(header.version, FILE_HISTORY_VERSION)?;
+            self.segment = Some(segment);
+        }
+    }
+
+    // Copies are resolved wherever they come from, as they are sealed as they are.
+    fn next_change(&mut self) -> Result<Option<FileChange>> {
+        if let Some(mut file_change) = self.base.take() {
+            resolve_objects(&mut file_change, &self.store)?;
+            resolve_copy(&mut file_change, self.fs, &self.locations)?;
+            return Ok(Some(file_change));
+        }
+        if let Some(mut file_change) = self.next_sealed_change()? {
+            resolve_objects(&mut file_change, &self.store)?;
+            resolve_copy(&mut file_change, self.fs, &self.locations)?;
+            return Ok(Some(file_change));
+        }
+
+        let mut file_change: FileChange = match self.source {
+            ChangeSource::Records(ref mut records) => match records.next_record()? {
+                Some(record) => serde_json::from_slice(record)
+                    .context(corrupted("Failed decoding file history."))?,
+                None => return Ok(None),
+            },
+            ChangeSource::Decoded(ref mut changes) => match changes.next() {
+                Some(file_change) => file_change,
+                None => return Ok(None),
+            },
+        };
+        self.unsealed_count += 1;
+
+        resolve_objects(&mut file_change, &self.store)?;
+        resolve_copy(&mut file_change, self.fs, &self.locations)?;
+        Ok(Some(file_change))
+    }
+}
+
+impl<'a, FS: Fs> Iterator for FileChanges<'a, FS> {
+    type Item = Result<FileChange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_change().transpose()
+    }
+}
+
+fn resolve_objects<FS: Fs>(file_change: &mut FileChange, store: &ObjectStore<FS>) -> Result<()> {
+    if let FileChangeVariant::Updated(ref mut updated) = file_change.variant {
+        for change in updated.iter_mut() {
+            if let ContentChange::InsertedObject { at, object } = change {
+                *change = ContentChange::Inserted {
+                    at: *at,
+                    new_content: store.load(object)?,
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
+// Copies of the files are written out in full, before the histories they refer to are
+// rewritten or removed. The paths are relative to the repository.
+pub fn materialize_copies<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    source_paths: &HashSet<PathBuf>,
+) -> Result<()> {
+    if source_paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut history_paths = Vec::new();
+    collect_files(fs, &locations.ka_files_path, &mut history_paths)?;
+    for history_path in history_paths {
+        // Copies can also be sealed, so the segments are read too.
+        let mut history_file = fs.open_readable_file(&history_path)?;
+        let stored_history = FileHistory::read_stored(fs, locations, &mut history_file)?;
+        let is_copy = stored_history.changes.iter().any(|change| {
+            matches!(&change.variant, FileChangeVariant::CopiedFrom { path, .. } if source_paths.contains(path))
+        });
+
+        if is_copy {
+            let mut history_file = fs.open_writable_file(&history_path)?;
+            let mut file_history = FileHistory::from_file(fs, locations, &mut history_file)?;
+            file_history.get_changes_mut();
+            file_history.write_to_file(fs, locations, &mut history_file)?;
+        }
+    }
+    Ok(())
+}
+
+// Copies are replayed from the history of the file they were copied from.
+fn resolve_copy<FS: Fs>(
+    file_change: &mut FileChange,
+    fs: &FS,
+    locations: &Locations,
+) -> Result<()> {
+    if let FileChangeVariant::CopiedFrom { ref path, cursor } = file_change.variant {
+        let history_path = locations.ka_files_path.join(path);
+        let mut history_file = fs.open_readable_file(&history_path).with_context(|| {
+            CorruptionError(format!("The history of '{}' is missing.", path.display()))
+        })?;
+        let tip = FileHistory::get_tip(fs, locations, &mut history_file, cursor)?;
+        file_change.variant = FileChangeVariant::Updated(ContentChange::diff(&[], &tip.content));
+    }
+    Ok(())
+}
+
+// Structured changes are replayed once, in order, to turn them into the content changes they
+// amount to. Histories without any aren't replayed.
+fn resolve_structured(changes: &mut [FileChange]) -> Result<()> {
+    let has_structured = changes
+        .iter()
+        .any(|change| matches!(change.variant, FileChangeVariant::Structured(_)));
+    if !has_structured {
+        return Ok(());
+    }
+
+    let mut content = Vec::new();
+    for file_change in changes.iter_mut() {
+        let new_content = match file_change.variant {
+            FileChangeVariant::Updated(ref updated) => {
+                for change in updated.iter() {
+                    change
+                        .apply(&mut content)
+                        .with_context(|| replay_error(file_change.change_index))?;
+                }
+                continue;
+            }
+            FileChangeVariant::Deleted => {
+                content.clear();
+                continue;
+            }
+            FileChangeVariant::Structured(ref structured) => {
+                structured::apply(&content, structured)
+                    .with_context(|| replay_error(file_change.change_index))?
+            }
+            FileChangeVariant::CopiedFrom { .. } => {
+                bail!(unresolved_copy(file_change.change_index))
+            }
+        };
+        file_change.verify(&new_content)?;
+        file_change.variant =
+            FileChangeVariant::Updated(ContentChange::diff(&content, &new_content));
+        content = new_content;
+    }
+    Ok(())
+}
+
+fn store_objects<FS: Fs>(file_change: &mut FileChange, store: &ObjectStore<FS>) -> Result<()> {
+    if let FileChangeVariant::Updated(ref mut updated) = file_change.variant {
+        for change in updated.iter_mut() {
+            if let ContentChange::Inserted { at, new_content } = change {
+                if new_content.len() >= OBJECT_THRESHOLD {
+                    *change = ContentChange::InsertedObject {
+                        at: *at,
+                        object: store.store(new_content)?,
+                    };
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn count_sealed_changes(segments: &[IndexSegment]) -> usize {
+    segments.iter().map(|segment| segment.change_count).sum()
+}
+
+fn load_segment<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    segment: &IndexSegment,
+) -> Result<Vec<RepositoryChange>> {
+    let segment_path = locations.get_index_segment_path(segment.number);
+    let error = || {
+        format!(
+            "Failed loading the index segment '{}'.",
+            segment_path.display()
+        )
+    };
+
+    let mut segment_file = fs.open_readable_file(&segment_path).with_context(error)?;
+    let buffer = fs.read_from_file(&mut segment_file).with_context(error)?;
+    let records = decode_records(&buffer).with_context(error)?;
+    let (header, records) = records.split_first().with_context(error)?;
+    let header: RepositoryHistoryHeader = serde_json::from_slice(header).with_context(error)?;
+    check_version(header.version, REPOSITORY_HISTORY_VERSION)?;
+
+    let changes = records
+        .iter()
+        .map(|record| serde_json::from_slice(record))
+        .collect::<Result<Vec<RepositoryChange>, _>>()
+        .with_context(error)?;
+    if changes.len() != segment.change_count {
+        bail!(
+            "The index segment '{}' has {} changes, but the index expects {}.",
+            segment_path.display(),
+            changes.len(),
+            segment.change_count
+        );
+    }
+    Ok(changes)
+}
+
+fn load_file_segment<FS: Fs>(
+    fs: &FS,
+    locations: &Locations,
+    segment: &FileSegment,
+) -> Result<Vec<FileChange>> {
+    let segment_path = locations.ka_path.join(&segment.path);
+    let error = || {
+        format!(
+            "Failed loading the file history segment '{}'.",
+            segment_path.display()
+        )
+    };
+
+    let mut segment_file = fs.open_readable_file(&segment_path).with_context(error)?;
+    let buffer = fs.read_from_file(&mut segment_file).with_context(error)?;
+    let history = FileHistory::decode(&buffer).with_context(error)?;
+    if history.changes.len() != segment.change_count {
+        bail!(
+            "The file history segment '{}' has {} changes, but the history expects {}.",
+            segment_path.display(),
+            history.changes.len(),
+            segment.change_count
+        );
+    }
+    Ok(history.changes)
+}
+
+fn check_version(version: u32, current_version: u32) -> Result<()> {
+    if version > current_version {
+        bail!(
+            "The history has the format version {}, but only versions up to {} are supported. Update ka to open this repository.",
+            version,
+            current_version
+        );
+    }
+    Ok(())
+}
+
+fn replay_error(change_index: usize) -> CorruptionError {
+    CorruptionError(format!(
+        "Replaying change {} failed, the file history is truncated or corrupted.",
+        change_index
+    ))
+}
+
+// Why something in `.ka` can't be read, because it's truncated or corrupted, as opposed to
+// missing or unreadable. It's attached to the errors of reading histories, so callers can tell
+// those apart from any other error by looking for it in the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionError(pub String);
+
+impl Display for CorruptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for CorruptionError {}
+
+// Copies are resolved as they're read, so one left over means its record is out of place.
+fn unresolved_copy(change_index: usize) -> CorruptionError {
+    CorruptionError(format!(
+        "Change {} is a copy which wasn't resolved, the file history is corrupted.",
+        change_index
+    ))
+}
+
+fn corrupted(message: &str) -> CorruptionError {
+    CorruptionError(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::ActionOptions,
+        filesystem::mock::{read_file, write_file, FsMock},
+    };
+
+    use super::*;
+
+    #[test]
+    fn find_changes_for_paths() {
+        let get_change = |paths: &[&str]| RepositoryChange {
+            affected_files: paths.iter().map(PathBuf::from).collect(),
+            timestamp: 0,
+            message: None,
+            id: None,
+            author: None,
+            annotations: Annotations::new(),
+        };
+        let mut history = RepositoryHistory::default();
+        history.add_change(get_change(&["./a/x", "./b"]));
+        history.add_change(get_change(&["./a-b"]));
+
+        assert_eq!(history.changes_for(Path::new("./a")), vec![1]);
+        assert_eq!(history.changes_for(Path::new("./a-b")), vec![2]);
+        assert_eq!(history.changes_for(Path::new(".")), vec![1, 2]);
+
+        // Once built, the index is kept up to date.
+        history.add_change(get_change(&["./a/y/z", "./a/x"]));
+        assert_eq!(history.changes_for(Path::new("./a")), vec![1, 3]);
+        history.get_changes_mut().remove(0);
+        assert_eq!(history.changes_for(Path::new("./a")), vec![2]);
+    }
+
+    #[test]
+    fn test_get_content() {
+        let stages = &[
+            "",
+            "hiii!",
+            "yes hii? this is a test.",
+            "yes bye! this is not a test.",
+        ];
+
+        let mut history = FileHistory::default();
+
+        history.add_change(FileChange {
+            change_index: 0,
+            variant: FileChangeVariant::Updated(Vec::new()),
+            degraded: false,
+            content_hash: None,
+            change_id: None,
+            content_type: None,
+        });
+
+        for old_index in 0..stages.len() - 1 {
+            let old = stages[old_index].as_bytes();
+            let new = stages[old_index + 1].as_bytes();
+
+            let stage_difference = ContentChange::diff(old, new);
+
+            history.add_change(FileChange {
+                change_index: old_index + 1,
+                variant: FileChangeVariant::Updated(stage_difference),
+                degraded: false,
+                content_hash: None,
+                change_id: None,
+                content_type: None,
+            });
+        }
+
+        for (index, stage) in stages.iter().enumerate() {
+            assert_eq!(stage.as_bytes(), history.get_content(index).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_verify_content_hash() {
+        let mut history = FileHistory::default();
+        history.add_change(FileChange {
+            change_index: 1,
+            variant: FileChangeVariant::Updated(ContentChange::diff(b"", b"one")),
+            degraded: false,
+            content_hash: Some(FileChange::hash_content(b"one")),
+            change_id: None,
+            content_type: None,
+        });
+        // Pretend the diff of the second change was recorded wrong.
+        history.add_change(FileChange {
+            change_index: 2,
+            variant: FileChangeVariant::Updated(ContentChange::diff(b"one", b"one too")),
+            degraded: false,
+            content_hash: Some(FileChange::hash_content(b"one two")),
+            change_id: None,
+            content_type: None,
+        });
+
+        assert_eq!(history.get_content(1).unwrap(), b"one");
+        assert!(history.get_content(2).is_err());
+        assert_eq!(
+            history.get_content_backwards(b"one too".to_vec(), 2, 1),
+            Some(b"one".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_get_content_backwards() {
+        let stages = &["", "one", "one two", "one three", "three"];
+
+        let mut history = FileHistory::default();
+
+        for old_index in 0..stages.len() - 1 {
+            let old = stages[old_index].as_bytes();
+            let new = stages[old_index + 1].as_bytes();
+
+            history.add_change(FileChange {
+                change_index: old_index + 1,
+                variant: FileChangeVariant::Updated(ContentChange::diff(old, new)),
+                degraded: false,
+                content_hash: None,
+                change_id: None,
+                content_type: None,
+            });
+        }
+
+        let tip = stages.len() - 1;
+        for (index, stage) in stages.iter().enumerate() {
+            let content = history.get_content_backwards(stages[tip].into(), tip, index);
+            assert_eq!(Some(stage.as_bytes().to_vec()), content);
+        }
+
+        // Content which doesn't match the history can't be moved back.
+        assert_eq!(None, history.get_content_backwards("four".into(), tip, 0));
+    }
+
+    #[test]
+    fn test_append_records() {
+        let mut repository_history = RepositoryHistory::default();
+        let mut encoded_index = repository_history.encode().unwrap();
+
+        for timestamp in 1..4 {
+            repository_history.add_change(RepositoryChange {
+                affected_files: vec!["./test".into()],
+                timestamp,
+                message: None,
+                id: None,
+                author: None,
+                annotations: Annotations::new(),
+            });
+            repository_history.cursor += 1;
+            encoded_index.extend(repository_history.encode_latest_change().unwrap());
+        }
+        repository_history.cursor = 1;
+        encoded_index.extend(repository_history.encode_cursor().unwrap());
+
+        let decoded = RepositoryHistory::decode(&encoded_index).unwrap();
+        assert_eq!(decoded.cursor, 1);
+        assert_eq!(decoded.get_changes().len(), 3);
+        assert_eq!(decoded.get_stored_length(), Some(encoded_index.len()));
+
+        // Legacy histories can still be read, but have to be rewritten before appending.
+        let legacy = RepositoryHistory::decode(br#"{"cursor":0,"changes":[]}"#).unwrap();
+        assert_eq!(legacy.get_stored_length(), None);
+    }
+
+    #[test]
+    fn test_seal_segments() {
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let index_path = locations.get_repository_index_path();
+
+        let mut repository_history = RepositoryHistory::default();
+        for timestamp in 0..2 * SEGMENT_LENGTH as u64 + 3 {
+            repository_history.add_change(RepositoryChange {
+                affected_files: vec!["./test".into()],
+                timestamp,
+                message: None,
+                id: None,
+                author: None,
+                annotations: Annotations::new(),
+            });
+        }
+        repository_history.cursor = repository_history.get_changes().len();
+        let mut index_file = fs_mock.create_file(&index_path).unwrap();
+        repository_history
+            .write_to_file(&fs_mock, &locations, &mut index_file)
+            .unwrap();
+
+        assert!(fs_mock.path_exists(&locations.get_index_segment_path(1)));
+        assert!(fs_mock.path_exists(&locations.get_index_segment_path(2)));
+        let mut index_file = fs_mock.open_readable_file(&index_path).unwrap();
+        let head = RepositoryHistory::decode(&fs_mock.read_from_file(&mut index_file).unwrap());
+        assert_eq!(head.unwrap().get_changes().len(), 3);
+
+        let mut index_file = fs_mock.open_readable_file(&index_path).unwrap();
+        let mut loaded =
+            RepositoryHistory::from_file(&fs_mock, &locations, &mut index_file).unwrap();
+        let timestamps: Vec<_> = loaded
+            .get_changes()
+            .iter()
+            .map(|change| change.timestamp)
+            .collect();
+        assert_eq!(
+            timestamps,
+            (0..2 * SEGMENT_LENGTH as u64 + 3).collect::<Vec<_>>()
+        );
+
+        let mut index_file = fs_mock.open_readable_file(&index_path).unwrap();
+        let (skipped_count, changes) = RepositoryHistory::load_changes_since(
+            &fs_mock,
+            &locations,
+            &mut index_file,
+            SEGMENT_LENGTH as u64 + 5,
+        )
+        .unwrap();
+        assert_eq!(skipped_count, SEGMENT_LENGTH);
+        assert_eq!(changes.len(), SEGMENT_LENGTH + 3);
+
+        // Only the new segment is written once enough changes were added again.
+        while !loaded.needs_sealing() {
+            loaded.add_change(RepositoryChange {
+                affected_files: vec!["./test".into()],
+                timestamp: 0,
+                message: None,
+                id: None,
+                author: None,
+                annotations: Annotations::new(),
+            });
+        }
+        let segments = loaded.encode_segments(&locations).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].0, locations.get_index_segment_path(3));
+
+        // Modifying sealed changes rewrites all segments, and removes those left empty.
+        loaded.get_changes_mut().truncate(SEGMENT_LENGTH + 1);
+        let mut index_file = fs_mock.create_file(&index_path).unwrap();
+        loaded
+            .write_to_file(&fs_mock, &locations, &mut index_file)
+            .unwrap();
+        assert!(!fs_mock.path_exists(&locations.get_index_segment_path(2)));
+
+        let mut index_file = fs_mock.open_readable_file(&index_path).unwrap();
+        let loaded = RepositoryHistory::from_file(&fs_mock, &locations, &mut index_file).unwrap();
+        assert_eq!(loaded.get_changes().len(), SEGMENT_LENGTH + 1);
+    }
+
+    #[test]
+    fn test_resolve_change() {
+        let mut repository_history = RepositoryHistory::default();
+        for id in ["c0ffee", "c0ffea", "beef"] {
+            repository_history.add_change(RepositoryChange {
+                affected_files: vec!["./test".into()],
+                timestamp: 0,
+                message: None,
+                id: Some(id.into()),
+                author: None,
+                annotations: Annotations::new(),
+            });
+        }
+
+        assert_eq!(repository_history.resolve_change("2").unwrap(), 2);
+        assert_eq!(repository_history.resolve_change("c0ffee").unwrap(), 1);
+        assert_eq!(repository_history.resolve_change("be").unwrap(), 3);
+        assert!(repository_history.resolve_change("c0ff").is_err());
+        assert!(repository_history.resolve_change("dead").is_err());
+    }
+
+    #[test]
+    fn test_get_tip() {
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let stages = &["", "one", "one two", "one three"];
+
+        let mut history = FileHistory::default();
+        for old_index in 0..stages.len() - 1 {
+            history.add_change(FileChange {
+                change_index: old_index + 1,
+                variant: FileChangeVariant::Updated(ContentChange::diff(
+                    stages[old_index].as_bytes(),
+                    stages[old_index + 1].as_bytes(),
+                )),
+                degraded: false,
+                content_hash: None,
+                change_id: None,
+                content_type: None,
+            });
+        }
+        history.add_change(FileChange {
+            change_index: stages.len(),
+            variant: FileChangeVariant::Deleted,
+            degraded: false,
+            content_hash: None,
+            change_id: None,
+            content_type: None,
+        });
+
+        let legacy = serde_json::to_vec(&history).unwrap();
+        for (encoded, is_appendable) in [(history.encode().unwrap(), true), (legacy, false)] {
+            let mut file = fs_mock.create_file(Path::new("./history")).unwrap();
+            fs_mock.write_to_file(&mut file, encoded.clone()).unwrap();
+
+            for (cursor, stage) in stages.iter().enumerate() {
+                let mut file = fs_mock.open_readable_file(Path::new("./history")).unwrap();
+                let tip = FileHistory::get_tip(&fs_mock, &locations, &mut file, cursor).unwrap();
+                assert_eq!(tip.content, stage.as_bytes());
+                assert!(!tip.is_deleted);
+                assert_eq!(tip.stored_length.is_some(), is_appendable);
+            }
+
+            let mut file = fs_mock.open_readable_file(Path::new("./history")).unwrap();
+            let tip = FileHistory::get_tip(&fs_mock, &locations, &mut file, stages.len()).unwrap();
+            assert!(tip.is_deleted);
+        }
+    }
+
+    #[test]
+    fn test_copies() {
+        use crate::actions::{create, show, squash, update};
+
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let read_history = |path: &str| {
+            let mut file = fs_mock.open_readable_file(Path::new(path)).unwrap();
+            FileHistory::decode(&fs_mock.read_from_file(&mut file).unwrap()).unwrap()
+        };
+        let show = |path: &str, cursor: usize| {
+            show(
+                ActionOptions::from_path("."),
+                &fs_mock,
+                Path::new(path),
+                cursor,
+            )
+            .unwrap()
+        };
+
+        write_file(&fs_mock, "./original", b"Water plants");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./copy", b"Water plants");
+        write_file(&fs_mock, "./original", b"Water plants\nFeed cat");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        let copy_history = read_history("./.ka/files/copy");
+        let copy_source = CopySource {
+            path: "original".into(),
+            cursor: 1,
+        };
+        assert_eq!(copy_history.copied_from, Some(copy_source.clone()));
+        assert!(matches!(
+            &copy_history.get_changes()[0].variant,
+            FileChangeVariant::CopiedFrom { path, cursor: 1 } if path == Path::new("original")
+        ));
+        assert_eq!(show("copy", 2), b"Water plants");
+
+        write_file(&fs_mock, "./copy", b"Water plants\nWalk dog");
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+        assert_eq!(show("copy", 3), b"Water plants\nWalk dog");
+
+        // Rewriting the original writes out the copy first.
+        squash(ActionOptions::from_path("."), &fs_mock, 1, 2, None).unwrap();
+        let copy_history = read_history("./.ka/files/copy");
+        assert_eq!(copy_history.copied_from, Some(copy_source));
+        assert!(matches!(
+            copy_history.get_changes()[0].variant,
+            FileChangeVariant::Updated(_)
+        ));
+        assert_eq!(show("copy", 1), b"Water plants");
+        assert_eq!(show("copy", 2), b"Water plants\nWalk dog");
+        assert_eq!(show("original", 1), b"Water plants\nFeed cat");
+    }
+
+    #[test]
+    fn keep_records_when_rewriting() {
+        use crate::actions::{create, forget, show, update};
+
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let locations = Locations::from(&options);
+        let read_stored = |path: &str| {
+            let mut file = fs_mock.open_readable_file(Path::new(path)).unwrap();
+            FileHistory::read_stored(&fs_mock, &locations, &mut file).unwrap()
+        };
+
+        write_file(&fs_mock, "./original", b"0");
+        create(options.clone(), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./copy", b"0");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+
+        // Sealing the copy replaces its history, but the copy stays one.
+        for content in 1..FILE_SEGMENT_LENGTH + 1 {
+            write_file(&fs_mock, "./copy", content.to_string().as_bytes());
+            update(options.clone(), &fs_mock, now + 1 + content as u64).unwrap();
+        }
+        assert!(fs_mock.path_exists(Path::new("./.ka/file-segments/copy.1")));
+        assert!(matches!(
+            &read_stored("./.ka/files/copy").get_changes()[0].variant,
+            FileChangeVariant::CopiedFrom { path, cursor: 1 } if path == Path::new("original")
+        ));
+        let copy = show(options.clone(), &fs_mock, Path::new("copy"), 2).unwrap();
+        assert_eq!(copy, b"0");
+
+        // Neither do rewrites which leave the changes alone, nor loaded histories written back.
+        forget(options, &fs_mock, Path::new("copy"), false).unwrap();
+        assert!(matches!(
+            read_stored("./.ka/files/copy").get_changes()[0].variant,
+            FileChangeVariant::CopiedFrom { .. }
+        ));
+
+        let old = b"{\n  \"version\": 1\n}\n";
+        let new = b"{\n  \"version\": 2\n}\n";
+        let mut history = FileHistory::default();
+        history.add_change(FileChange {
+            change_index: 1,
+            variant: FileChangeVariant::Updated(ContentChange::snapshot(&[], old)),
+            degraded: false,
+            content_hash: None,
+            change_id: None,
+            content_type: None,
+        });
+        history.add_change(FileChange {
+            change_index: 2,
+            variant: FileChangeVariant::Structured(structured::diff(old, new).unwrap()),
+            degraded: false,
+            content_hash: None,
+            change_id: None,
+            content_type: None,
+        });
+        let mut file = fs_mock
+            .create_file(Path::new("./.ka/files/config"))
+            .unwrap();
+        history
+            .write_to_file(&fs_mock, &locations, &mut file)
+            .unwrap();
+
+        let mut file = fs_mock
+            .open_writable_file(Path::new("./.ka/files/config"))
+            .unwrap();
+        let history = FileHistory::from_file(&fs_mock, &locations, &mut file).unwrap();
+        assert_eq!(history.get_content(2).unwrap(), new);
+        history
+            .write_to_file(&fs_mock, &locations, &mut file)
+            .unwrap();
+        assert!(matches!(
+            read_stored("./.ka/files/config").get_changes()[1].variant,
+            FileChangeVariant::Structured(_)
+        ));
+    }
+
+    #[test]
+    fn replay_sealed_copies() {
+        use crate::actions::{create, shift, show, update, ShiftMode, UntrackedFiles};
+
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+
+        write_file(&fs_mock, "./a", b"Water plants");
+        create(options.clone(), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./b", b"Water plants");
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        for content in 0..300 {
+            write_file(&fs_mock, "./b", content.to_string().as_bytes());
+            update(options.clone(), &fs_mock, now + 2 + content as u64).unwrap();
+        }
+        assert!(fs_mock.path_exists(Path::new("./.ka/file-segments/b.1")));
+
+        // Going back to the copy replays it from the segment, both when shifting and updating.
+        let b = show(options.clone(), &fs_mock, Path::new("b"), 2).unwrap();
+        assert_eq!(b, b"Water plants");
+        shift(
+            options.clone(),
+            &fs_mock,
+            2,
+            ShiftMode::Force,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert_eq!(read_file(&fs_mock, "./b"), b"Water plants");
+        write_file(&fs_mock, "./b", b"Water plants\nFeed cat");
+        update(options, &fs_mock, now + 400).unwrap();
+    }
+
+    #[test]
+    fn truncated_histories() {
+        use crate::{
+            actions::{create, shift, update, ShiftMode, UntrackedFiles},
+            diff::ApplyError,
+        };
+
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let shift = |cursor: usize| {
+            shift(
+                ActionOptions::from_path("."),
+                &fs_mock,
+                cursor,
+                ShiftMode::Safe,
+                UntrackedFiles::Keep,
+            )
+        };
+
+        write_file(&fs_mock, "./notes", b"hello world");
+        write_file(&fs_mock, "./todo", b"one");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        write_file(&fs_mock, "./notes", b"hello world!");
+        write_file(&fs_mock, "./todo", b"two");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        // Without its first change, the second one inserts past the end of the content.
+        let notes_history = read_file(&fs_mock, "./.ka/files/notes");
+        let mut truncated = FileHistory::decode(&notes_history).unwrap();
+        truncated.get_changes_mut().remove(0);
+        let error = truncated.get_content(2).unwrap_err();
+        assert!(error.chain().any(|cause| cause.downcast_ref()
+            == Some(&ApplyError::OutOfBounds {
+                at: 11,
+                upto: 11,
+                length: 0
+            })));
+        assert!(error.downcast_ref::<CorruptionError>().is_some());
+        let truncated = truncated.encode().unwrap();
+
+        write_file(&fs_mock, "./.ka/files/notes", &truncated);
+        write_file(&fs_mock, "./notes", b"hello");
+        let index = read_file(&fs_mock, "./.ka/index");
+        let error = update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap_err();
+        assert!(error.to_string().contains("'notes'"));
+        assert!(error.downcast_ref::<CorruptionError>().is_some());
+        assert_eq!(read_file(&fs_mock, "./.ka/index"), index);
+
+        write_file(&fs_mock, "./.ka/files/notes", &notes_history);
+        write_file(&fs_mock, "./notes", b"hello world!");
+        shift(1).unwrap();
+
+        // Nothing is touched if any of the files can't be restored.
+        write_file(&fs_mock, "./.ka/files/notes", &truncated);
+        let index = read_file(&fs_mock, "./.ka/index");
+        assert!(shift(2).is_err());
+        assert_eq!(read_file(&fs_mock, "./.ka/index"), index);
+        assert_eq!(read_file(&fs_mock, "./notes"), b"hello world");
+        assert_eq!(read_file(&fs_mock, "./todo"), b"one");
+
+        // The same goes for histories cut off in the middle of a record.
+        write_file(&fs_mock, "./.ka/files/notes", &notes_history);
+        let todo_history = read_file(&fs_mock, "./.ka/files/todo");
+        write_file(
+            &fs_mock,
+            "./.ka/files/todo",
+            &todo_history[..todo_history.len() - 4],
+        );
+        let error = shift(2).unwrap_err();
+        assert!(error.downcast_ref::<CorruptionError>().is_some());
+        assert_eq!(read_file(&fs_mock, "./.ka/index"), index);
+        assert_eq!(read_file(&fs_mock, "./notes"), b"hello world");
+    }
+}
@@ -0,0 +1,121 @@
+// Executables in `.ka/hooks` which are run after some actions, named after the event, like
+// `post-shift`. They get the event as JSON on their standard input, and run in the directory
+// of the repository. A failing hook can't undo what the action already did, so it's only
+// traced as a warning.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    files::Locations,
+    filesystem::Fs,
+    trace::{self, Level},
+};
+
+pub const POST_SHIFT: &str = "post-shift";
+
+// After the working files were shifted from one cursor to the other. Paths are relative to
+// the repository, restored files with a suffix are listed under the path they got.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ShiftEvent {
+    pub old_cursor: usize,
+    pub new_cursor: usize,
+    pub restored: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+pub fn get_hook_path(locations: &Locations, name: &str) -> PathBuf {
+    locations.get_hooks_path().join(name)
+}
+
+// Runs the hook if the repository has one, and waits for it to finish.
+pub fn run_hook(fs: &impl Fs, locations: &Locations, name: &str, event: &impl Serialize) {
+    let hook_path = get_hook_path(locations, name);
+    if !fs.path_exists(&hook_path) {
+        return;
+    }
+
+    let result = serde_json::to_vec(event)
+        .map_err(anyhow::Error::from)
+        .and_then(|payload| execute(&hook_path, &[], &locations.repository_path, &payload));
+    match result {
+        Ok(true) => trace::event(Level::Debug, "Ran the hook.", &[("hook", &name)]),
+        Ok(false) => trace::event(Level::Warn, "The hook failed.", &[("hook", &name)]),
+        Err(error) => trace::event(
+            Level::Warn,
+            "Failed running the hook.",
+            &[("hook", &name), ("error", &error)],
+        ),
+    }
+}
+
+// Runs the program with the payload on its standard input, and returns whether it succeeded.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn execute(
+    program: &Path,
+    args: &[String],
+    current_dir: &Path,
+    payload: &[u8],
+) -> anyhow::Result<bool> {
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(current_dir)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Programs which don't care about the payload may exit without reading it.
+        let _ = stdin.write_all(payload);
+    }
+    Ok(child.wait()?.success())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn execute(
+    _program: &Path,
+    _args: &[String],
+    _current_dir: &Path,
+    _payload: &[u8],
+) -> anyhow::Result<bool> {
+    anyhow::bail!("Programs can't be run here.")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use crate::{actions::ActionOptions, files::Locations, filesystem::FsImpl};
+
+    use super::{get_hook_path, run_hook, ShiftEvent, POST_SHIFT};
+
+    #[test]
+    fn run_post_shift_hook() {
+        let repository_path =
+            std::env::temp_dir().join(format!("ka-hooks-test-{}", std::process::id()));
+        let locations = Locations::from(&ActionOptions::new(repository_path.clone()));
+        let hook_path = get_hook_path(&locations, POST_SHIFT);
+        fs::create_dir_all(hook_path.parent().unwrap()).unwrap();
+        fs::write(&hook_path, "#!/bin/sh\ncat > shifted.json\n").unwrap();
+        fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let event = ShiftEvent {
+            old_cursor: 3,
+            new_cursor: 1,
+            restored: vec![PathBuf::from("notes/todo")],
+            removed: vec![PathBuf::from("draft")],
+        };
+        run_hook(&FsImpl {}, &locations, POST_SHIFT, &event);
+        let payload = fs::read_to_string(repository_path.join("shifted.json"));
+        fs::remove_dir_all(&repository_path).unwrap();
+
+        assert_eq!(
+            payload.unwrap(),
+            r#"{"old_cursor":3,"new_cursor":1,"restored":["notes/todo"],"removed":["draft"]}"#
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{config::IgnoreConfig, files::Locations, filesystem::Fs};
+
+// Files nobody wants recorded, which are ignored unless the config turns them off.
+const DEFAULT_PATTERNS: &[&str] = &[".DS_Store", "Thumbs.db", "*.swp", "*.swo"];
+const NODE_MODULES_PATTERN: &str = "node_modules/";
+pub const IGNORE_FILE_NAME: &str = ".kaignore";
+
+// Patterns in the style of `.gitignore`, deciding which untracked files aren't recorded.
+// Files which already have a history are always recorded, whatever the patterns say.
+#[derive(Debug, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    // Split at `/`, where `**` stands for any amount of directories.
+    segments: Vec<String>,
+    negated: bool,
+    directory_only: bool,
+}
+
+impl IgnoreRules {
+    // Rules are applied in order, the last matching one wins. So the built-in defaults come
+    // first, then the global ignore file, and then the `.kaignore` of the repository, which can
+    // override both of them, e.g. with `!*.swp`.
+    pub fn load<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        config: &IgnoreConfig,
+        global_ignore_path: Option<&Path>,
+    ) -> Result<Self> {
+        let mut rules = Self::default();
+        if config.defaults {
+            rules.add_patterns(DEFAULT_PATTERNS.join("\n").as_str());
+        }
+        if config.node_modules {
+            rules.add_patterns(NODE_MODULES_PATTERN);
+        }
+
+        let global_ignore_path = global_ignore_path.filter(|_| config.global);
+        let ignore_paths = global_ignore_path
+            .into_iter()
+            .chain(Some(
+                locations.repository_path.join(IGNORE_FILE_NAME).as_path(),
+            ))
+            .filter(|path| fs.path_exists(path))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        for ignore_path in ignore_paths {
+            let mut ignore_file = fs.open_readable_file(&ignore_path)?;
+            let content = fs
+                .read_from_file(&mut ignore_file)
+                .with_context(|| format!("Failed reading '{}'.", ignore_path.display()))?;
+            rules.add_patterns(&String::from_utf8_lossy(&content));
+        }
+
+        Ok(rules)
+    }
+
+    pub fn add_patterns(&mut self, patterns: &str) {
+        for line in patterns.lines() {
+            let mut pattern = line.trim_end();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+
+            let negated = pattern.starts_with('!');
+            if negated {
+                pattern = &pattern[1..];
+            }
+            let directory_only = pattern.ends_with('/');
+            pattern = pattern.trim_end_matches('/');
+
+            // Patterns with a separator are relative to the repository, others match at
+            // any depth.
+            let mut segments = Vec::new();
+            if !pattern.contains('/') {
+                segments.push("**".to_string());
+            }
+            segments.extend(
+                pattern
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .map(String::from),
+            );
+            if segments.iter().all(|segment| segment == "**") {
+                continue;
+            }
+
+            self.rules.push(Rule {
+                segments,
+                negated,
+                directory_only,
+            });
+        }
+    }
+
+    // Whether the path, relative to the repository, is ignored. A path in an ignored directory
+    // is ignored as well.
+    pub fn is_ignored(&self, relative_path: &Path, is_directory: bool) -> bool {
+        let segments: Vec<_> = relative_path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(segment) => Some(segment.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(|segment| segment.as_ref()).collect();
+
+        (1..segments.len()).any(|length| self.matches(&segments[..length], true))
+            || self.matches(&segments, is_directory)
+    }
+
+    fn matches(&self, segments: &[&str], is_directory: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_directory || !rule.directory_only) && match_segments(&rule.segments, segments)
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+// `~/.config/ka/ignore`, or the same in `XDG_CONFIG_HOME` if that's set.
+pub fn get_global_ignore_path() -> Option<PathBuf> {
+    Some(get_user_config_path()?.join("ignore"))
+}
+
+// `~/.config/ka`, where whatever applies to all repositories of the user is kept.
+pub fn get_user_config_path() -> Option<PathBuf> {
+    let config_path = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_path) if !config_path.is_empty() => PathBuf::from(config_path),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_path.join("ka"))
+}
+
+fn match_segments(pattern: &[String], segments: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => segments.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=segments.len()).any(|skipped| match_segments(rest, &segments[skipped..]))
+        }
+        Some((first, rest)) => match segments.split_first() {
+            Some((segment, remaining)) => {
+                match_glob(first.as_bytes(), segment.as_bytes()) && match_segments(rest, remaining)
+            }
+            None => false,
+        },
+    }
+}
+
+// Matches a single segment, where `*` stands for any amount of characters and `?` for one.
+pub(crate) fn match_glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skipped| match_glob(rest, &name[skipped..])),
+        Some((b'?', rest)) => !name.is_empty() && match_glob(rest, &name[1..]),
+        Some((character, rest)) => name.first() == Some(character) && match_glob(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, show, update, ActionOptions},
+        config::IgnoreConfig,
+        files::Locations,
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::IgnoreRules;
+
+    #[test]
+    fn test_ignore_patterns() {
+        let mut rules = IgnoreRules::default();
+        rules.add_patterns(
+            "# Comments are skipped.\n*.log\n!keep.log\nbuild/\n/todo\ndocs/**/*.tmp\n",
+        );
+
+        let is_ignored = |path: &str| rules.is_ignored(Path::new(path), false);
+        assert!(is_ignored("debug.log"));
+        assert!(is_ignored("nested/debug.log"));
+        assert!(!is_ignored("keep.log"));
+        assert!(!is_ignored("debug.txt"));
+
+        assert!(rules.is_ignored(Path::new("build"), true));
+        assert!(!rules.is_ignored(Path::new("build"), false));
+        assert!(is_ignored("build/output"));
+        assert!(is_ignored("nested/build/output"));
+
+        assert!(is_ignored("todo"));
+        assert!(!is_ignored("nested/todo"));
+
+        assert!(is_ignored("docs/draft.tmp"));
+        assert!(is_ignored("docs/a/b/draft.tmp"));
+        assert!(!is_ignored("other/draft.tmp"));
+    }
+
+    #[test]
+    fn later_ignore_files_take_precedence() {
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        write_file(&fs_mock, "/global-ignore", b"*.bak\n!.DS_Store\n");
+        write_file(&fs_mock, "./.kaignore", b"!keep.bak\n");
+
+        let rules = IgnoreRules::load(
+            &fs_mock,
+            &locations,
+            &IgnoreConfig::default(),
+            Some(Path::new("/global-ignore")),
+        )
+        .unwrap();
+        assert!(rules.is_ignored(Path::new("notes.swp"), false));
+        assert!(!rules.is_ignored(Path::new(".DS_Store"), false));
+        assert!(rules.is_ignored(Path::new("notes.bak"), false));
+        assert!(!rules.is_ignored(Path::new("keep.bak"), false));
+        assert!(!rules.is_ignored(Path::new("node_modules"), true));
+
+        let config = IgnoreConfig {
+            defaults: false,
+            node_modules: true,
+            global: false,
+        };
+        let rules = IgnoreRules::load(
+            &fs_mock,
+            &locations,
+            &config,
+            Some(Path::new("/global-ignore")),
+        )
+        .unwrap();
+        assert!(!rules.is_ignored(Path::new("notes.swp"), false));
+        assert!(!rules.is_ignored(Path::new("notes.bak"), false));
+        assert!(rules.is_ignored(Path::new("web/node_modules/left-pad"), false));
+    }
+
+    #[test]
+    fn ignored_files_are_not_recorded() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./.kaignore", b"*.tmp\n");
+        write_file(&fs_mock, "./tracked.log", b"one");
+        write_file(&fs_mock, "./draft.tmp", b"draft");
+        fs_mock.create_directory(Path::new("./build")).unwrap();
+        write_file(&fs_mock, "./build/output", b"binary");
+        write_file(&fs_mock, "./notes", b"notes");
+        write_file(&fs_mock, "./notes.swp", b"swap");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/tracked.log")));
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/build/output")));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/draft.tmp")));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/notes.swp")));
+
+        // Files already recorded stay recorded, only new ones are ignored.
+        write_file(&fs_mock, "./.kaignore", b"*.log\nbuild/\n");
+        write_file(&fs_mock, "./new.log", b"new");
+        write_file(&fs_mock, "./tracked.log", b"two");
+        write_file(&fs_mock, "./build/output", b"other binary");
+        write_file(&fs_mock, "./build/new", b"new");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        assert!(fs_mock.path_exists(Path::new("./.ka/files/notes")));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/new.log")));
+        assert!(!fs_mock.path_exists(Path::new("./.ka/files/build/new")));
+        for (path, content) in [("tracked.log", "two"), ("build/output", "other binary")] {
+            assert_eq!(
+                show(ActionOptions::from_path("."), &fs_mock, Path::new(path), 2).unwrap(),
+                content.as_bytes()
+            );
+        }
+    }
+}
@@ -0,0 +1,631 @@
+// What diffs and statuses say about images instead of their bytes: their format, dimensions
+// and size, and if `perceptual_hashes` of the config is set, how different they look. The
+// perceptual hash is a difference hash of the image scaled down to 9x8 gray pixels, so images
+// which look alike have hashes which differ in few of their 64 bits.
+//
+// The dimensions are read from the headers of PNG, GIF, JPEG, BMP and WebP files. Hashes need
+// the pixels, which are only decoded for 8 bit PNGs without interlacing, with the inflate
+// below, which is all of zlib they need. Storing images is up to the tracking modes as before.
+
+use std::{
+    convert::TryInto,
+    fmt::{self, Display},
+};
+
+use serde::Serialize;
+
+// Larger images aren't decoded for hashing, as their pixels alone would take too much memory.
+const MAX_HASHED_PIXELS: u64 = 64 * 1024 * 1024;
+const HASH_WIDTH: usize = 9;
+const HASH_HEIGHT: usize = 8;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    Gif,
+    Jpeg,
+    Bmp,
+    Webp,
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ImageFormat::Png => "PNG",
+            ImageFormat::Gif => "GIF",
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::Bmp => "BMP",
+            ImageFormat::Webp => "WebP",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageInfo {
+    pub fn inspect(content: &[u8]) -> Option<Self> {
+        let (format, width, height) = if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+            (
+                ImageFormat::Png,
+                read_u32_be(content, 16)?,
+                read_u32_be(content, 20)?,
+            )
+        } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+            (
+                ImageFormat::Gif,
+                read_u16_le(content, 6)? as u32,
+                read_u16_le(content, 8)? as u32,
+            )
+        } else if content.starts_with(b"\xff\xd8") {
+            let (width, height) = read_jpeg_dimensions(content)?;
+            (ImageFormat::Jpeg, width, height)
+        } else if content.starts_with(b"BM") {
+            (
+                ImageFormat::Bmp,
+                read_u32_le(content, 18)? as i32 as u32,
+                (read_u32_le(content, 22)? as i32).unsigned_abs(),
+            )
+        } else if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") {
+            let (width, height) = read_webp_dimensions(content)?;
+            (ImageFormat::Webp, width, height)
+        } else {
+            return None;
+        };
+
+        Some(ImageInfo {
+            format,
+            width,
+            height,
+        })
+    }
+}
+
+impl Display for ImageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}x{}", self.format, self.width, self.height)
+    }
+}
+
+// How an image changed, if either side is one. Sizes are in bytes, a side which doesn't
+// exist is empty.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageChange {
+    pub old: Option<ImageInfo>,
+    pub new: Option<ImageInfo>,
+    pub old_size: usize,
+    pub new_size: usize,
+    // How many bits of the perceptual hashes differ, out of 64, if both were computed.
+    pub hash_distance: Option<u32>,
+}
+
+impl ImageChange {
+    pub fn compare(old: &[u8], new: &[u8], with_hashes: bool) -> Option<Self> {
+        let old_info = ImageInfo::inspect(old);
+        let new_info = ImageInfo::inspect(new);
+        if old_info.is_none() && new_info.is_none() {
+            return None;
+        }
+
+        let hash_distance = if with_hashes {
+            perceptual_hash(old)
+                .zip(perceptual_hash(new))
+                .map(|(old_hash, new_hash)| (old_hash ^ new_hash).count_ones())
+        } else {
+            None
+        };
+        Some(ImageChange {
+            old: old_info,
+            new: new_info,
+            old_size: old.len(),
+            new_size: new.len(),
+            hash_distance,
+        })
+    }
+
+    // Like `PNG 640x480, 1024 bytes`, for either side.
+    pub fn describe_old(&self) -> String {
+        describe(self.old, self.old_size)
+    }
+
+    pub fn describe_new(&self) -> String {
+        describe(self.new, self.new_size)
+    }
+}
+
+fn describe(info: Option<ImageInfo>, size: usize) -> String {
+    match info {
+        Some(info) => format!("{}, {} bytes", info, size),
+        None => format!("Not an image, {} bytes", size),
+    }
+}
+
+// A difference hash of the image, if its pixels can be decoded here.
+pub fn perceptual_hash(content: &[u8]) -> Option<u64> {
+    let (width, height, gray) = decode_png_gray(content)?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    // Every cell of the scaled down image is the average of the pixels it covers.
+    let mut cells = [[0u64; HASH_WIDTH]; HASH_HEIGHT];
+    for (cell_y, row) in cells.iter_mut().enumerate() {
+        let (top, bottom) = get_span(cell_y, HASH_HEIGHT, height);
+        for (cell_x, cell) in row.iter_mut().enumerate() {
+            let (left, right) = get_span(cell_x, HASH_WIDTH, width);
+            let mut sum = 0;
+            for y in top..bottom {
+                sum += gray[y * width + left..y * width + right]
+                    .iter()
+                    .map(|&value| value as u64)
+                    .sum::<u64>();
+            }
+            *cell = sum / ((bottom - top) * (right - left)) as u64;
+        }
+    }
+
+    let mut hash = 0u64;
+    for row in cells.iter() {
+        for pair in row.windows(2) {
+            hash = (hash << 1) | (pair[0] < pair[1]) as u64;
+        }
+    }
+    Some(hash)
+}
+
+// The pixels a cell covers along one axis, at least one.
+fn get_span(cell: usize, cell_count: usize, length: usize) -> (usize, usize) {
+    let start = cell * length / cell_count;
+    let end = ((cell + 1) * length / cell_count)
+        .max(start + 1)
+        .min(length);
+    (start.min(length - 1), end)
+}
+
+fn decode_png_gray(content: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    if !content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return None;
+    }
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut position = 8;
+    while position + 8 <= content.len() {
+        let length = read_u32_be(content, position)? as usize;
+        let kind = &content[position + 4..position + 8];
+        let data = content.get(position + 8..(position + 8).checked_add(length)?)?;
+        match kind {
+            b"IHDR" => header = Some(data),
+            b"PLTE" => palette = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        // Skips the CRC as well, which isn't checked.
+        position += 12 + length;
+    }
+
+    let header = header?;
+    let width = read_u32_be(header, 0)? as usize;
+    let height = read_u32_be(header, 4)? as usize;
+    let (bit_depth, color_type, interlace) = (*header.get(8)?, *header.get(9)?, *header.get(12)?);
+    let channels = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return None,
+    };
+    if bit_depth != 8 || interlace != 0 || (width as u64) * (height as u64) > MAX_HASHED_PIXELS {
+        return None;
+    }
+
+    let stride = width * channels;
+    let filtered = inflate_zlib(&compressed, height * (stride + 1))?;
+    if filtered.len() < height * (stride + 1) {
+        return None;
+    }
+
+    let mut previous = vec![0u8; stride];
+    let mut gray = Vec::with_capacity(width * height);
+    for row in filtered.chunks_exact(stride + 1).take(height) {
+        let current = unfilter(row[0], &row[1..], &previous, channels)?;
+        for pixel in current.chunks_exact(channels) {
+            let (red, green, blue) = match color_type {
+                0 | 4 => (pixel[0], pixel[0], pixel[0]),
+                3 => {
+                    let entry = palette.get(pixel[0] as usize * 3..pixel[0] as usize * 3 + 3)?;
+                    (entry[0], entry[1], entry[2])
+                }
+                _ => (pixel[0], pixel[1], pixel[2]),
+            };
+            let luma = (red as u32 * 299 + green as u32 * 587 + blue as u32 * 114) / 1000;
+            gray.push(luma as u8);
+        }
+        previous = current;
+    }
+    Some((width, height, gray))
+}
+
+fn unfilter(filter: u8, row: &[u8], previous: &[u8], channels: usize) -> Option<Vec<u8>> {
+    let mut current = row.to_vec();
+    for index in 0..current.len() {
+        let left = if index >= channels {
+            current[index - channels]
+        } else {
+            0
+        };
+        let up = previous[index];
+        let up_left = if index >= channels {
+            previous[index - channels]
+        } else {
+            0
+        };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return None,
+        };
+        current[index] = current[index].wrapping_add(predicted);
+    }
+    Some(current)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (left_distance, up_distance, up_left_distance) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if left_distance <= up_distance && left_distance <= up_left_distance {
+        left
+    } else if up_distance <= up_left_distance {
+        up
+    } else {
+        up_left
+    }
+}
+
+// Inflates a zlib stream, without checking its checksum. Stops once `limit` bytes are out.
+fn inflate_zlib(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let compression = data.first()?;
+    if compression & 0x0F != 8 {
+        return None;
+    }
+    inflate(data.get(2..)?, limit)
+}
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order code lengths of the code length alphabet are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    // In bits.
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    // Bits are packed starting with the least significant bit of each byte.
+    fn read(&mut self, count: u8) -> Option<u32> {
+        let mut value = 0;
+        for bit in 0..count {
+            let byte = self.data.get(self.position / 8)?;
+            value |= (((byte >> (self.position % 8)) & 1) as u32) << bit;
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+}
+
+// A canonical Huffman code, by how many codes each length has and the symbols in the order
+// of their codes.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    // Codes are packed starting with their most significant bit, one bit at a time.
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.read(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn inflate(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut reader = BitReader { data, position: 0 };
+    let mut output = Vec::new();
+    loop {
+        let is_last = reader.read(1)? == 1;
+        match reader.read(2)? {
+            0 => {
+                reader.align_to_byte();
+                let length = reader.read(16)? as usize;
+                let start = reader.position / 8 + 2;
+                output.extend_from_slice(data.get(start..start + length)?);
+                reader.position = (start + length) * 8;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &literals, &distances, &mut output, limit)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &distances, &mut output, limit)?;
+            }
+            _ => return None,
+        }
+        if is_last || output.len() >= limit {
+            output.truncate(limit);
+            return Some(output);
+        }
+    }
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    let literal_count = reader.read(5)? as usize + 257;
+    let distance_count = reader.read(5)? as usize + 1;
+    let code_length_count = reader.read(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[symbol] = reader.read(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, reader.read(2)? + 3),
+            17 => (0, reader.read(3)? + 3),
+            18 => (0, reader.read(7)? + 11),
+            _ => return None,
+        };
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+    if lengths.len() != literal_count + distance_count {
+        return None;
+    }
+
+    Some((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> Option<()> {
+    while output.len() < limit {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Some(());
+        }
+
+        let index = symbol - 257;
+        let length = *LENGTH_BASES.get(index)? as usize
+            + reader.read(*LENGTH_EXTRA_BITS.get(index)?)? as usize;
+        let index = distances.decode(reader)? as usize;
+        let distance = *DISTANCE_BASES.get(index)? as usize
+            + reader.read(*DISTANCE_EXTRA_BITS.get(index)?)? as usize;
+        if distance > output.len() {
+            return None;
+        }
+        // The copy may overlap what it adds, so it goes byte by byte.
+        let start = output.len() - distance;
+        for offset in 0..length {
+            output.push(output[start + offset]);
+        }
+    }
+    Some(())
+}
+
+fn read_jpeg_dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    let mut position = 2;
+    loop {
+        while *content.get(position)? != 0xFF {
+            position += 1;
+        }
+        while *content.get(position)? == 0xFF {
+            position += 1;
+        }
+        let marker = *content.get(position)?;
+        position += 1;
+        match marker {
+            // Markers without a segment.
+            0x01 | 0xD0..=0xD7 => continue,
+            // Start of frame, except for the markers between them which aren't.
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                let height = read_u16_be(content, position + 3)?;
+                let width = read_u16_be(content, position + 5)?;
+                return Some((width as u32, height as u32));
+            }
+            _ => position += read_u16_be(content, position)? as usize,
+        }
+    }
+}
+
+fn read_webp_dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    match content.get(12..16)? {
+        b"VP8 " => Some((
+            (read_u16_le(content, 26)? & 0x3FFF) as u32,
+            (read_u16_le(content, 28)? & 0x3FFF) as u32,
+        )),
+        b"VP8L" => {
+            let bits = read_u32_le(content, 21)?;
+            Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        b"VP8X" => Some((
+            (read_u32_le(content, 24)? & 0xFF_FFFF) + 1,
+            (read_u32_le(content, 27)? & 0xFF_FFFF) + 1,
+        )),
+        _ => None,
+    }
+}
+
+fn read_u16_be(content: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        content.get(at..at + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u16_le(content: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        content.get(at..at + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32_be(content: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        content.get(at..at + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32_le(content: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        content.get(at..at + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inflate_zlib, perceptual_hash, ImageChange, ImageFormat, ImageInfo};
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    // 12x8 RGB images, one getting brighter from left to right and the other darker, with
+    // rows using every filter.
+    const BRIGHTER: &str = "89504e470d0a1a0a0000000d494844520000000c000000080802000000428689a6000000664944415478daad8e2b15c03014c5b20f1a19291eae8827a2222aa2228a2fae8827a2222a663d13b091e5040604200462c48c94c89952a895d670a777c660816b46efae33fa74033b8ef3ddfd69bf51088a51664a4939ab14d5aad6e4aede3586e6b8ff337e03ab9d24a3162ffc660000000049454e44ae426082";
+    const DARKER: &str = "89504e470d0a1a0a0000000d494844520000000c000000080802000000428689a6000000704944415478daadce2d15c0201885e1bb1fbf063384c0ac01210841011c0dae2104660d084108d0d86f3b0b0066f73cf28a1722a815a52067a4841811029c83b530065a43292c22676b185b81736af3feeafd18dbbfef6c22ac95a53067a6c41819029da3b534865a5329bee1f73fe10f328169973e62da890000000049454e44ae426082";
+
+    #[test]
+    fn inflate_blocks() {
+        let text =
+            b"It was the best of times, it was the worst of times, it was the age of wisdom.";
+        let fixed = from_hex("78daf32c51284f2c5628c94855484a2d2e51c84f5328c9cc4d2dd651c844c894e717e1924a4c4f0549946716a7e4e7ea010030371b32");
+        assert_eq!(inflate_zlib(&fixed, usize::MAX).unwrap(), text);
+
+        let mut stored = vec![0x78, 0x01, 0x01, 5, 0, !5, !0];
+        stored.extend_from_slice(b"hello");
+        assert_eq!(inflate_zlib(&stored, usize::MAX).unwrap(), b"hello");
+        assert_eq!(inflate_zlib(&stored, 4).unwrap(), b"hell");
+    }
+
+    #[test]
+    fn compare_images() {
+        let brighter = from_hex(BRIGHTER);
+        let darker = from_hex(DARKER);
+        assert_eq!(
+            ImageInfo::inspect(&brighter),
+            Some(ImageInfo {
+                format: ImageFormat::Png,
+                width: 12,
+                height: 8,
+            })
+        );
+        assert_eq!(perceptual_hash(&brighter), Some(u64::MAX));
+        assert_eq!(perceptual_hash(&darker), Some(0));
+
+        let change = ImageChange::compare(&brighter, &darker, true).unwrap();
+        assert_eq!(change.hash_distance, Some(64));
+        assert_eq!(change.describe_old(), "PNG 12x8, 159 bytes");
+        assert_eq!(
+            ImageChange::compare(&brighter, &brighter, true)
+                .unwrap()
+                .hash_distance,
+            Some(0)
+        );
+
+        let gif = b"GIF89a\x40\x01\xf0\x00";
+        let change = ImageChange::compare(b"text", gif, false).unwrap();
+        assert_eq!(change.describe_old(), "Not an image, 4 bytes");
+        assert_eq!(change.describe_new(), "GIF 320x240, 10 bytes");
+        assert_eq!(change.hash_distance, None);
+        assert!(ImageChange::compare(b"old", b"new", true).is_none());
+    }
+}
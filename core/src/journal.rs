@@ -0,0 +1,346 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    crypto,
+    files::Locations,
+    filesystem::{ensure_available_space, Fs},
+};
+
+// Collects all writes of an action, so they can be applied as a whole. The writes are
+// recorded in `.ka/journal`, which is synced to the disk before any of them are applied, and
+// if the action is interrupted, the next action completes them from there.
+#[derive(Debug, Default)]
+pub struct Journal {
+    writes: Vec<JournalWrite>,
+}
+
+#[derive(Debug)]
+struct JournalWrite {
+    path: PathBuf,
+    content: Vec<u8>,
+    // Appends record the length the file had before, so replaying them doesn't append
+    // the content a second time.
+    append_at: Option<usize>,
+}
+
+// Ahead of the writes, so journals of other formats aren't mistaken for this one.
+const JOURNAL_MAGIC: &[u8] = b"ka-journal-1\n";
+const CHECKSUM_BYTES: usize = 32;
+
+impl Journal {
+    // The writes one after another, as the path, a kind byte which is 0 for writes and 1 for
+    // appends followed by the length to append at, and the content. Lengths are 8 bytes of
+    // little endian and go ahead of the path and content. A checksum of everything before it
+    // ends the journal, telling a completely written one apart from one which was cut short.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let content_length: usize = self.writes.iter().map(|write| write.content.len()).sum();
+        let mut buffer = Vec::with_capacity(content_length + 64 * self.writes.len());
+        buffer.extend(JOURNAL_MAGIC);
+        for write in self.writes.iter() {
+            let path = write.path.to_str().with_context(|| {
+                format!(
+                    "Can't journal '{}', as it isn't valid UTF-8.",
+                    write.path.display()
+                )
+            })?;
+            buffer.extend((path.len() as u64).to_le_bytes());
+            buffer.extend(path.as_bytes());
+            match write.append_at {
+                Some(append_at) => {
+                    buffer.push(1);
+                    buffer.extend((append_at as u64).to_le_bytes());
+                }
+                None => buffer.push(0),
+            }
+            buffer.extend((write.content.len() as u64).to_le_bytes());
+            buffer.extend(&write.content);
+        }
+        let checksum = crypto::sha256(&buffer);
+        buffer.extend(checksum);
+        Ok(buffer)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        if buffer.len() < JOURNAL_MAGIC.len() + CHECKSUM_BYTES || !buffer.starts_with(JOURNAL_MAGIC)
+        {
+            bail!("Failed decoding journal, it's too short or of another format.");
+        }
+        let (body, checksum) = buffer.split_at(buffer.len() - CHECKSUM_BYTES);
+        if crypto::sha256(body)[..] != *checksum {
+            bail!("Failed decoding journal, its checksum doesn't match.");
+        }
+
+        let mut reader = JournalReader {
+            buffer: &body[JOURNAL_MAGIC.len()..],
+        };
+        let mut writes = Vec::new();
+        while !reader.buffer.is_empty() {
+            let path_length = reader.read_length()?;
+            let path = std::str::from_utf8(reader.read_bytes(path_length)?)
+                .context("Failed decoding journal, a path isn't valid UTF-8.")?;
+            let append_at = match reader.read_bytes(1)? {
+                [0] => None,
+                [1] => Some(reader.read_length()?),
+                _ => bail!("Failed decoding journal, a write is of an unknown kind."),
+            };
+            let content_length = reader.read_length()?;
+            let content = reader.read_bytes(content_length)?.to_vec();
+            writes.push(JournalWrite {
+                path: PathBuf::from(path),
+                content,
+                append_at,
+            });
+        }
+        Ok(Self { writes })
+    }
+
+    pub fn add_write(&mut self, path: PathBuf, content: Vec<u8>) {
+        self.writes.push(JournalWrite {
+            path,
+            content,
+            append_at: None,
+        });
+    }
+
+    pub fn add_append(&mut self, path: PathBuf, append_at: usize, content: Vec<u8>) {
+        self.writes.push(JournalWrite {
+            path,
+            content,
+            append_at: Some(append_at),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn commit<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<()> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+
+        // The journal is written in full before any of its writes, so both need the space.
+        let encoded = self.encode()?;
+        let content_length: usize = self.writes.iter().map(|write| write.content.len()).sum();
+        ensure_available_space(
+            fs,
+            &locations.ka_path,
+            (encoded.len() + content_length) as u64,
+        )?;
+
+        // Nothing is applied before the journal is on the disk, and it's only deleted once
+        // everything it lists is, so a crash at any point leaves one of the two complete.
+        let journal_path = locations.get_journal_path();
+        let mut journal_file = fs.create_file(&journal_path)?;
+        fs.write_to_file(&mut journal_file, encoded)?;
+        fs.sync_file(&mut journal_file)?;
+        fs.sync_directory(&locations.ka_path)?;
+
+        for write in self.writes.iter() {
+            let mut file = if write.append_at.is_some() {
+                let mut file = fs.open_writable_file(&write.path)?;
+                fs.append_to_file(&mut file, write.content.clone())?;
+                file
+            } else {
+                let mut file = fs.create_file(&write.path)?;
+                fs.write_to_file(&mut file, write.content.clone())?;
+                file
+            };
+            fs.sync_file(&mut file)?;
+        }
+
+        fs.delete_file(&journal_path)
+    }
+
+    // Unlike `commit`, this can't tell which writes already happened, so it has to be
+    // safe to apply them again.
+    fn replay<FS: Fs>(&self, fs: &FS) -> Result<()> {
+        for write in self.writes.iter() {
+            let content = match write.append_at {
+                Some(append_at) => {
+                    let mut file = fs.open_readable_file(&write.path)?;
+                    let mut content = fs.read_from_file(&mut file)?;
+                    if content.len() < append_at {
+                        bail!(
+                            "Can't complete the append to '{}', the file is shorter than before.",
+                            write.path.display()
+                        );
+                    }
+                    content.truncate(append_at);
+                    content.extend_from_slice(&write.content);
+                    content
+                }
+                None => write.content.clone(),
+            };
+
+            let mut file = fs.create_file(&write.path)?;
+            fs.write_to_file(&mut file, content)?;
+            fs.sync_file(&mut file)?;
+        }
+        Ok(())
+    }
+}
+
+struct JournalReader<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> JournalReader<'a> {
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.buffer.len() < length {
+            bail!("Failed decoding journal, a write is cut short.");
+        }
+        let (bytes, rest) = self.buffer.split_at(length);
+        self.buffer = rest;
+        Ok(bytes)
+    }
+
+    fn read_length(&mut self) -> Result<usize> {
+        let bytes = self.read_bytes(8)?;
+        let length = u64::from_le_bytes(bytes.try_into()?);
+        usize::try_from(length).context("Failed decoding journal, a length is too large.")
+    }
+}
+
+// Completes the writes of an interrupted action, if there was one.
+pub fn complete_pending<FS: Fs>(fs: &FS, locations: &Locations) -> Result<()> {
+    let journal_path = locations.get_journal_path();
+    if !fs.path_exists(&journal_path) {
+        return Ok(());
+    }
+
+    let mut journal_file = fs.open_readable_file(&journal_path)?;
+    let buffer = fs
+        .read_from_file(&mut journal_file)
+        .context("Failed reading journal.")?;
+
+    // A journal which can't be decoded was never completely written, which means none
+    // of its writes were applied yet and the repository is still in its previous state.
+    if let Ok(journal) = Journal::decode(&buffer) {
+        journal.replay(fs)?;
+    }
+
+    fs.delete_file(&journal_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, update, ActionOptions},
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+        history::{FileHistory, RepositoryHistory},
+    };
+
+    use super::Journal;
+
+    #[test]
+    fn complete_interrupted_update() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        // Pretend an update was interrupted right after its journal was written.
+        write_file(&fs_mock, "./test", b"first second");
+        let before_update = fs_mock.get_state();
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+        let mut journal = Journal::default();
+        journal.add_write("./.ka/index".into(), read_file(&fs_mock, "./.ka/index"));
+        journal.add_write(
+            "./.ka/files/test".into(),
+            read_file(&fs_mock, "./.ka/files/test"),
+        );
+
+        let mut fs_mock = fs_mock;
+        fs_mock.set_state(before_update);
+        write_file(&fs_mock, "./.ka/journal", &journal.encode().unwrap());
+
+        update(ActionOptions::from_path("."), &fs_mock, now + 2).unwrap();
+
+        assert!(!fs_mock.path_exists(&locations.get_journal_path()));
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.cursor, 2);
+        assert_eq!(index.get_changes()[1].timestamp, now + 1);
+        let history = FileHistory::decode(&read_file(&fs_mock, "./.ka/files/test")).unwrap();
+        assert_eq!(history.get_content(2).unwrap(), b"first second");
+    }
+
+    #[test]
+    fn discard_incomplete_journal() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        let mut journal = Journal::default();
+        journal.add_write("./.ka/index".into(), b"garbage".to_vec());
+        let encoded = journal.encode().unwrap();
+        write_file(&fs_mock, "./.ka/journal", &encoded[..encoded.len() / 2]);
+
+        write_file(&fs_mock, "./test", b"first second");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        assert!(!fs_mock.path_exists(Path::new("./.ka/journal")));
+        let index = RepositoryHistory::decode(&read_file(&fs_mock, "./.ka/index")).unwrap();
+        assert_eq!(index.cursor, 2);
+    }
+
+    #[test]
+    fn encode_content_verbatim() {
+        let mut journal = Journal::default();
+        journal.add_write("./.ka/index".into(), vec![0xFF; 1000]);
+        journal.add_append("./.ka/files/test".into(), 7, b"appended".to_vec());
+        let encoded = journal.encode().unwrap();
+        assert!(encoded.len() < 1200);
+
+        let decoded = Journal::decode(&encoded).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded.writes[0].path, Path::new("./.ka/index"));
+        assert_eq!(decoded.writes[0].content, vec![0xFF; 1000]);
+        assert_eq!(decoded.writes[0].append_at, None);
+        assert_eq!(decoded.writes[1].content, b"appended");
+        assert_eq!(decoded.writes[1].append_at, Some(7));
+
+        // Journals cut short at a write's end, or damaged anywhere, aren't taken for complete.
+        let mut single = Journal::default();
+        single.add_write("./.ka/index".into(), vec![0xFF; 1000]);
+        let single_length = single.encode().unwrap().len() - 32;
+        assert!(Journal::decode(&encoded[..single_length]).is_err());
+        let mut damaged = encoded.clone();
+        damaged[100] ^= 1;
+        assert!(Journal::decode(&damaged).is_err());
+    }
+
+    #[test]
+    fn replay_append_once() {
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+
+        write_file(&fs_mock, "./.ka/index", b"first");
+        let mut journal = Journal::default();
+        journal.add_append("./.ka/index".into(), 5, b" second".to_vec());
+        write_file(&fs_mock, "./.ka/journal", &journal.encode().unwrap());
+
+        // The append may or may not have happened before the interruption.
+        super::complete_pending(&fs_mock, &locations).unwrap();
+        assert_eq!(read_file(&fs_mock, "./.ka/index"), b"first second");
+
+        write_file(&fs_mock, "./.ka/journal", &journal.encode().unwrap());
+        super::complete_pending(&fs_mock, &locations).unwrap();
+        assert_eq!(read_file(&fs_mock, "./.ka/index"), b"first second");
+    }
+}
@@ -0,0 +1,74 @@
+//! Local, file based version history. `Repository` is the entry point, and together with the
+//! other items re-exported here it is what the crate keeps stable between minor versions.
+//! The public modules are reachable for tooling which needs more, but may change at any time.
+
+pub mod actions;
+pub mod clock;
+pub mod collisions;
+pub mod config;
+pub mod consistency;
+pub mod diff;
+// There's no random source on `wasm32` to encrypt with.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod encryption;
+pub mod files;
+pub mod filesystem;
+pub mod history;
+pub mod hooks;
+pub mod ignore;
+pub mod images;
+pub mod line_endings;
+pub mod locks;
+pub mod memory;
+pub mod merge;
+pub mod metrics;
+pub mod notifications;
+pub mod policy;
+pub mod progress;
+pub mod protocol;
+pub mod repository;
+pub mod structured;
+pub mod tabular;
+pub mod templates;
+// Randomized checks of diffs and histories, for the tests of this and other crates.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod trace;
+pub mod trash;
+pub mod watch;
+// Repositories are handled on threads of their own, which `wasm32` doesn't have.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod workspace;
+
+#[cfg(feature = "archive")]
+mod archive;
+mod cache;
+mod crypto;
+mod journal;
+mod migrations;
+mod objects;
+mod records;
+mod regex;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod time;
+mod undo;
+
+#[cfg(test)]
+mod scenarios;
+
+pub use actions::{
+    BackupSummary, DeletedFile, FileLogEntry, FileLogKind, FileStatus, GrepMatch, LogBucket,
+    LogEntry, PullConflict, PullSummary, ShiftMode, Status, Summary, Timeline, UntrackedFiles,
+    UpdateSummary,
+};
+pub use diff::DiffOptions;
+pub use filesystem::Fs;
+#[cfg(not(target_arch = "wasm32"))]
+pub use filesystem::FsImpl;
+pub use history::{Annotations, CorruptionError};
+pub use locks::LockedError;
+pub use progress::Progress;
+pub use protocol::{MemoryTransport, Transport};
+pub use repository::Repository;
+pub use templates::Template;
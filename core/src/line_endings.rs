@@ -0,0 +1,145 @@
+// How line endings of text files are recorded and restored, so a file edited on Windows and
+// elsewhere in turn doesn't change entirely with every edit. Binary files are always kept
+// as they are.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    policy::{get_content_type, ContentType},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    #[default]
+    Verbatim,
+    // Record text with LF, and restore it like that.
+    Lf,
+    // Record text with LF, but restore it with the line endings of the platform, which is
+    // CRLF on Windows.
+    Native,
+}
+
+// The content as it's recorded, which is also what it's compared with the history as.
+pub fn normalize(config: &Config, path: &Path, content: Vec<u8>) -> Vec<u8> {
+    if config.line_endings == LineEndings::Verbatim || !is_text(config, path, &content) {
+        return content;
+    }
+
+    let mut normalized = Vec::with_capacity(content.len());
+    let mut bytes = content.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+            continue;
+        }
+        normalized.push(byte);
+    }
+    normalized
+}
+
+// The content as it's written to the working file.
+pub fn restore(config: &Config, path: &Path, content: Vec<u8>) -> Vec<u8> {
+    if config.line_endings != LineEndings::Native || !cfg!(windows) {
+        return content;
+    }
+    to_crlf(config, path, content)
+}
+
+fn to_crlf(config: &Config, path: &Path, content: Vec<u8>) -> Vec<u8> {
+    if !is_text(config, path, &content) {
+        return content;
+    }
+
+    let mut restored = Vec::with_capacity(content.len());
+    let mut previous = None;
+    for byte in content {
+        if byte == b'\n' && previous != Some(b'\r') {
+            restored.push(b'\r');
+        }
+        restored.push(byte);
+        previous = Some(byte);
+    }
+    restored
+}
+
+fn is_text(config: &Config, path: &Path, content: &[u8]) -> bool {
+    get_content_type(config, path, content) == ContentType::Text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, status, update, ActionOptions},
+        config::Config,
+        files::Locations,
+        filesystem::{mock::FsMock, Fs},
+        history::{FileHistory, RepositoryHistory},
+    };
+
+    use super::{normalize, to_crlf, LineEndings};
+
+    #[test]
+    fn convert_line_endings() {
+        let mut config = Config::default();
+        let path = Path::new("notes.txt");
+        assert_eq!(normalize(&config, path, b"a\r\nb".to_vec()), b"a\r\nb");
+
+        config.line_endings = LineEndings::Lf;
+        assert_eq!(
+            normalize(&config, path, b"a\r\nb\rc\n".to_vec()),
+            b"a\nb\rc\n"
+        );
+        assert_eq!(normalize(&config, path, b"\0\r\n".to_vec()), b"\0\r\n");
+
+        assert_eq!(to_crlf(&config, path, b"a\nb\r\n".to_vec()), b"a\r\nb\r\n");
+    }
+
+    fn get_change_count(fs: &FsMock, locations: &Locations) -> usize {
+        let mut index_file = fs
+            .open_readable_file(&locations.get_repository_index_path())
+            .unwrap();
+        RepositoryHistory::from_file(fs, locations, &mut index_file)
+            .unwrap()
+            .get_changes()
+            .len()
+    }
+
+    #[test]
+    fn ignore_changed_line_endings() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let write_file = |content: &[u8]| {
+            let mut file = fs_mock.create_file(Path::new("./notes")).unwrap();
+            fs_mock.write_to_file(&mut file, content.to_vec()).unwrap();
+        };
+
+        write_file(b"first\nsecond\n");
+        create(options.clone(), &fs_mock, now).unwrap();
+        let config = Config {
+            line_endings: LineEndings::Lf,
+            ..Config::default()
+        };
+        let locations = Locations::from(&options);
+        config.write(&fs_mock, &locations).unwrap();
+
+        write_file(b"first\r\nsecond\r\n");
+        assert!(status(options.clone(), &fs_mock).unwrap().files.is_empty());
+        update(options.clone(), &fs_mock, now + 1).unwrap();
+        assert_eq!(get_change_count(&fs_mock, &locations), 1);
+
+        write_file(b"first\r\nthird\r\n");
+        update(options, &fs_mock, now + 2).unwrap();
+        assert_eq!(get_change_count(&fs_mock, &locations), 2);
+        let mut history_file = fs_mock
+            .open_readable_file(Path::new("./.ka/files/notes"))
+            .unwrap();
+        let history = FileHistory::from_file(&fs_mock, &locations, &mut history_file).unwrap();
+        assert_eq!(history.get_content(2).unwrap(), b"first\nthird\n");
+    }
+}
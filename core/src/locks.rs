@@ -0,0 +1,90 @@
+// Advisory locks on working files, which tools working on the same repository, like two
+// editors, can use to stay out of each other's way. Nothing keeps anyone from changing a locked
+// file, `update` only warns about recording files someone else locked.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{actions::ActionOptions, config::Config, files::Locations, filesystem::Fs};
+
+// Who locks files if neither the options nor the identity in the config say.
+const ANONYMOUS_OWNER: &str = "anonymous";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Lock {
+    pub owner: String,
+    // When the file was locked.
+    pub since: u64,
+}
+
+// The locked working files, stored in `.ka/locks`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Locks {
+    pub files: BTreeMap<PathBuf, Lock>,
+}
+
+impl Locks {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).context("Failed encoding locks.")
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        serde_json::from_slice::<Self>(buffer).context("Failed decoding locks.")
+    }
+
+    pub fn load<FS: Fs>(fs: &FS, locations: &Locations) -> Result<Self> {
+        let locks_path = locations.get_locks_path();
+        if !fs.path_exists(&locks_path) {
+            return Ok(Self::default());
+        }
+
+        let mut locks_file = fs.open_readable_file(&locks_path)?;
+        let buffer = fs
+            .read_from_file(&mut locks_file)
+            .context("Failed reading locks.")?;
+        Self::decode(&buffer)
+    }
+
+    pub fn write<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<()> {
+        let locks_path = locations.get_locks_path();
+        if self.files.is_empty() {
+            if fs.path_exists(&locks_path) {
+                fs.delete_file(&locks_path)?;
+            }
+            return Ok(());
+        }
+
+        let mut locks_file = fs.create_file(&locks_path)?;
+        fs.write_to_file(&mut locks_file, self.encode()?)
+    }
+}
+
+// Someone else holds the lock of a file, so callers can tell this apart from other errors, e.g.
+// to try again later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedError(pub String);
+
+impl Display for LockedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for LockedError {}
+
+// Whom locks taken with `options` belong to. That's the lock owner of the options if they have
+// one, which tells apart several tools of the same person, and otherwise their identity.
+pub fn get_owner(options: &ActionOptions, config: &Config) -> String {
+    options
+        .lock_owner
+        .clone()
+        .or_else(|| config.identity.get_author())
+        .unwrap_or_else(|| ANONYMOUS_OWNER.to_string())
+}
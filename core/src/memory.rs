@@ -0,0 +1,132 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    actions::{
+        create, shift, show, update, ActionOptions, ShiftMode, UntrackedFiles, UpdateSummary,
+    },
+    files::{collect_files, Locations},
+    filesystem::{Fs, MemoryFs},
+};
+
+// A repository which lives entirely in memory, for hosts without a filesystem like browsers.
+// This is the surface the wasm bindings wrap, so it only takes and returns plain values, and
+// the whole repository can be exported to be stored wherever the host likes.
+pub struct MemoryRepository {
+    fs: MemoryFs,
+}
+
+impl MemoryRepository {
+    pub fn create(timestamp: u64) -> Result<Self> {
+        let repository = Self {
+            fs: MemoryFs::new(),
+        };
+        create(get_options(), &repository.fs, timestamp)?;
+        Ok(repository)
+    }
+
+    // Restores a repository from the output of `export`.
+    pub fn import(buffer: &[u8]) -> Result<Self> {
+        let files: BTreeMap<PathBuf, Vec<u8>> =
+            serde_json::from_slice(buffer).context("Failed decoding exported repository.")?;
+
+        let fs = MemoryFs::new();
+        for (path, content) in files {
+            let mut file = fs.create_file(&Path::new(".").join(path))?;
+            fs.write_to_file(&mut file, content)?;
+        }
+
+        if !fs.path_exists(Path::new("./.ka")) {
+            bail!("The exported repository has no history.");
+        }
+        Ok(Self { fs })
+    }
+
+    // All files, both the working files and the history, relative to the repository.
+    pub fn export(&self) -> Result<Vec<u8>> {
+        let mut paths = Vec::new();
+        collect_files(&self.fs, Path::new("."), &mut paths)?;
+
+        let mut files = BTreeMap::new();
+        for path in paths {
+            let mut file = self.fs.open_readable_file(&path)?;
+            let content = self.fs.read_from_file(&mut file)?;
+            files.insert(path.strip_prefix(".")?.to_path_buf(), content);
+        }
+
+        serde_json::to_vec(&files).context("Failed encoding repository.")
+    }
+
+    pub fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        let working_path = get_working_path(path)?;
+        let mut file = self.fs.create_file(&working_path)?;
+        self.fs.write_to_file(&mut file, content.to_vec())
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let working_path = get_working_path(path)?;
+        let mut file = self.fs.open_readable_file(&working_path)?;
+        self.fs.read_from_file(&mut file)
+    }
+
+    pub fn delete_file(&self, path: &str) -> Result<()> {
+        self.fs.delete_file(&get_working_path(path)?)
+    }
+
+    pub fn update(&self, timestamp: u64) -> Result<UpdateSummary> {
+        update(get_options(), &self.fs, timestamp)
+    }
+
+    pub fn shift(&self, cursor: usize, force: bool) -> Result<()> {
+        let mode = if force {
+            ShiftMode::Force
+        } else {
+            ShiftMode::Safe
+        };
+        shift(get_options(), &self.fs, cursor, mode, UntrackedFiles::Keep)?;
+        Ok(())
+    }
+
+    pub fn show(&self, path: &str, cursor: usize) -> Result<Vec<u8>> {
+        show(get_options(), &self.fs, Path::new(path), cursor)
+    }
+}
+
+fn get_options() -> ActionOptions {
+    ActionOptions::from_path(".")
+}
+
+fn get_working_path(path: &str) -> Result<PathBuf> {
+    Locations::from(&get_options()).get_working_path(Path::new(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryRepository;
+
+    #[test]
+    fn record_and_restore_in_memory() {
+        let now = 0xC0FFEE;
+
+        let repository = MemoryRepository::create(now).unwrap();
+        repository.write_file("notes/today", b"first").unwrap();
+        repository.update(now + 1).unwrap();
+        repository
+            .write_file("notes/today", b"first second")
+            .unwrap();
+        repository.update(now + 2).unwrap();
+
+        assert!(repository.write_file(".ka/index", b"garbage").is_err());
+        assert!(repository.write_file("../outside", b"garbage").is_err());
+
+        let repository = MemoryRepository::import(&repository.export().unwrap()).unwrap();
+        assert_eq!(repository.show("notes/today", 1).unwrap(), b"first");
+
+        repository.shift(1, false).unwrap();
+        assert_eq!(repository.read_file("notes/today").unwrap(), b"first");
+    }
+}
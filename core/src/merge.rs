@@ -0,0 +1,289 @@
+// How edits both sides made to the same file are combined when pulling diverged histories.
+// Which driver handles a file is decided by the `merge_drivers` of the config, patterns for
+// file names like `CHANGELOG*` mapped to the name of a driver, and `text3` for everything
+// else. Besides the built-in drivers, callers can register their own under a name of their
+// choosing, e.g. one which understands JSON.
+
+use std::{collections::BTreeMap, ops::Range, path::Path};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::Config,
+    diff::{ContentChange, Hunk},
+    ignore::match_glob,
+};
+
+pub const TEXT3: &str = "text3";
+pub const UNION: &str = "union";
+pub const TAKE_NEWER: &str = "take_newer";
+
+// The content of a file before either side edited it, and after each side did. A missing
+// content means the file didn't exist, or was deleted.
+pub struct MergeInput<'a> {
+    pub path: &'a Path,
+    pub base: Option<&'a [u8]>,
+    pub ours: Option<&'a [u8]>,
+    pub ours_timestamp: u64,
+    pub theirs: Option<&'a [u8]>,
+    pub theirs_timestamp: u64,
+}
+
+pub trait MergeDriver {
+    // The merged content, which is `None` inside if the file should be deleted. Returns
+    // `None` if the edits conflict, in which case our content is kept.
+    fn merge(&self, input: &MergeInput) -> Option<Option<Vec<u8>>>;
+}
+
+// Applies both edits, as long as they don't touch the same part of the content, and neither
+// side deleted the file while the other edited it.
+pub struct Text3;
+
+impl MergeDriver for Text3 {
+    fn merge(&self, input: &MergeInput) -> Option<Option<Vec<u8>>> {
+        if input.theirs == input.base || input.theirs == input.ours {
+            return Some(input.ours.map(<[u8]>::to_vec));
+        }
+        if input.ours == input.base {
+            return Some(input.theirs.map(<[u8]>::to_vec));
+        }
+
+        match (input.base, input.ours, input.theirs) {
+            (Some(base), Some(ours), Some(theirs)) => {
+                let mut hunks = Hunk::diff(base, ours);
+                for change in ContentChange::diff(base, theirs) {
+                    hunks = hunks
+                        .iter()
+                        .map(|hunk| hunk.rebase(&change))
+                        .collect::<Option<_>>()?;
+                }
+                let mut content = theirs.to_vec();
+                Hunk::apply_all(&hunks, &mut content);
+                Some(Some(content))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Keeps the lines of both sides where they edited the same lines, theirs first, which suits
+// files both sides only add to, like changelogs. It never conflicts, unless one side deleted
+// the file.
+pub struct Union;
+
+// A hunk, and whether it's one of ours.
+type SideHunk = (Hunk, bool);
+
+impl MergeDriver for Union {
+    fn merge(&self, input: &MergeInput) -> Option<Option<Vec<u8>>> {
+        let (base, ours, theirs) = match (input.base, input.ours, input.theirs) {
+            (Some(base), Some(ours), Some(theirs)) => (base, ours, theirs),
+            (None, Some(ours), Some(theirs)) => (&[][..], ours, theirs),
+            _ => return Text3.merge(input),
+        };
+
+        // Hunks of both sides which overlap, or insert at the same line, end up in one group.
+        let mut hunks: Vec<SideHunk> = Hunk::diff_lines(base, theirs)
+            .into_iter()
+            .map(|hunk| (hunk, false))
+            .chain(
+                Hunk::diff_lines(base, ours)
+                    .into_iter()
+                    .map(|hunk| (hunk, true)),
+            )
+            .collect();
+        hunks.sort_by_key(|(hunk, _)| hunk.at);
+        let mut groups: Vec<(Range<usize>, Vec<SideHunk>)> = Vec::new();
+        for (hunk, is_ours) in hunks {
+            match groups.last_mut() {
+                Some((range, group)) if hunk.at < range.end || hunk.at == range.start => {
+                    range.end = range.end.max(hunk.upto);
+                    group.push((hunk, is_ours));
+                }
+                _ => groups.push((hunk.at..hunk.upto, vec![(hunk, is_ours)])),
+            }
+        }
+
+        let mut content = base.to_vec();
+        for (range, group) in groups.into_iter().rev() {
+            let get_side = |is_ours: bool| {
+                let side_hunks: Vec<Hunk> = group
+                    .iter()
+                    .filter(|(_, is_side)| *is_side == is_ours)
+                    .map(|(hunk, _)| Hunk {
+                        at: hunk.at - range.start,
+                        upto: hunk.upto - range.start,
+                        new_content: hunk.new_content.clone(),
+                    })
+                    .collect();
+                let mut side = base[range.clone()].to_vec();
+                Hunk::apply_all(&side_hunks, &mut side);
+                (side, !side_hunks.is_empty())
+            };
+            let (their_lines, has_theirs) = get_side(false);
+            let (our_lines, has_ours) = get_side(true);
+
+            let mut lines = Vec::new();
+            if has_theirs {
+                lines.extend_from_slice(&their_lines);
+            }
+            if has_ours && !(has_theirs && our_lines == their_lines) {
+                lines.extend_from_slice(&our_lines);
+            }
+            content.splice(range, lines);
+        }
+        Some(Some(content))
+    }
+}
+
+// Takes the content of the side which changed the file last, whole. Meant for binary files,
+// where combining edits rarely gives anything useful.
+pub struct TakeNewer;
+
+impl MergeDriver for TakeNewer {
+    fn merge(&self, input: &MergeInput) -> Option<Option<Vec<u8>>> {
+        let newer = if input.theirs_timestamp > input.ours_timestamp {
+            input.theirs
+        } else {
+            input.ours
+        };
+        Some(newer.map(<[u8]>::to_vec))
+    }
+}
+
+pub struct MergeDrivers {
+    drivers: BTreeMap<String, Box<dyn MergeDriver>>,
+    // Patterns for file names, and the name of the driver which handles them.
+    patterns: BTreeMap<String, String>,
+}
+
+impl MergeDrivers {
+    // The built-in drivers, used for the files the config says.
+    pub fn from_config(config: &Config) -> Self {
+        let mut merge_drivers = MergeDrivers {
+            drivers: BTreeMap::new(),
+            patterns: config.merge_drivers.clone(),
+        };
+        merge_drivers.register(TEXT3, Text3);
+        merge_drivers.register(UNION, Union);
+        merge_drivers.register(TAKE_NEWER, TakeNewer);
+        merge_drivers
+    }
+
+    // Makes the driver available to the config under `name`, in place of any other.
+    pub fn register(&mut self, name: &str, driver: impl MergeDriver + 'static) {
+        self.drivers.insert(name.to_string(), Box::new(driver));
+    }
+
+    pub fn get_driver(&self, path: &Path) -> Result<&dyn MergeDriver> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let driver_name = self
+            .patterns
+            .iter()
+            .find(|(pattern, _)| match_glob(pattern.as_bytes(), name.as_bytes()))
+            .map_or(TEXT3, |(_, driver_name)| driver_name.as_str());
+
+        match self.drivers.get(driver_name) {
+            Some(driver) => Ok(driver.as_ref()),
+            None => bail!(
+                "There is no merge driver '{}' for '{}'.",
+                driver_name,
+                path.display()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::config::Config;
+
+    use super::{MergeDriver, MergeDrivers, MergeInput, TakeNewer, Text3, Union};
+
+    fn merge(
+        driver: &impl MergeDriver,
+        base: &[u8],
+        ours: &[u8],
+        theirs: &[u8],
+    ) -> Option<Option<Vec<u8>>> {
+        driver.merge(&MergeInput {
+            path: Path::new("./CHANGELOG"),
+            base: Some(base),
+            ours: Some(ours),
+            ours_timestamp: 2,
+            theirs: Some(theirs),
+            theirs_timestamp: 1,
+        })
+    }
+
+    #[test]
+    fn merge_with_builtin_drivers() {
+        let base = b"# Changes\n- First\n";
+        let ours = b"# Changes\n- Ours\n- First\n";
+        let theirs = b"# Changes\n- Theirs\n- First\n";
+
+        assert_eq!(
+            merge(
+                &Text3,
+                base,
+                b"# Changes\n- Ours\n",
+                b"# Changes\n- Theirs\n"
+            ),
+            None
+        );
+        assert_eq!(
+            merge(
+                &Union,
+                base,
+                b"# Changes\n- Ours\n",
+                b"# Changes\n- Theirs\n"
+            ),
+            Some(Some(b"# Changes\n- Theirs\n- Ours\n".to_vec()))
+        );
+        assert_eq!(
+            merge(&Text3, base, ours, b"# All changes\n- First\n"),
+            Some(Some(b"# All changes\n- Ours\n- First\n".to_vec()))
+        );
+
+        assert_eq!(
+            merge(&Union, base, ours, theirs),
+            Some(Some(b"# Changes\n- Theirs\n- Ours\n- First\n".to_vec()))
+        );
+        assert_eq!(
+            merge(&Union, base, ours, b"# Changes\n- First\n- Last\n"),
+            Some(Some(b"# Changes\n- Ours\n- First\n- Last\n".to_vec()))
+        );
+
+        assert_eq!(
+            merge(&TakeNewer, b"\0base", b"\0ours", b"\0theirs"),
+            Some(Some(b"\0ours".to_vec()))
+        );
+    }
+
+    #[test]
+    fn pick_drivers_by_config() {
+        let mut config = Config::default();
+        config
+            .merge_drivers
+            .insert("CHANGELOG*".into(), "union".into());
+        config.merge_drivers.insert("*.json".into(), "json".into());
+        let drivers = MergeDrivers::from_config(&config);
+
+        let base = b"- First\n";
+        let input = MergeInput {
+            path: Path::new("./docs/CHANGELOG.md"),
+            base: Some(base),
+            ours: Some(b"- Ours\n- First\n"),
+            ours_timestamp: 2,
+            theirs: Some(b"- Theirs\n- First\n"),
+            theirs_timestamp: 1,
+        };
+        let driver = drivers.get_driver(input.path).unwrap();
+        assert!(driver.merge(&input).is_some());
+
+        assert!(drivers.get_driver(Path::new("./notes.txt")).is_ok());
+        assert!(drivers.get_driver(Path::new("./data.json")).is_err());
+    }
+}
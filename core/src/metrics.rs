@@ -0,0 +1,259 @@
+// Counters and histograms about what the repository is doing, e.g. for monitoring a watcher.
+// They go to whichever recorder was installed, or nowhere. Without the `metrics` feature,
+// recording them compiles to nothing.
+
+#[cfg(feature = "metrics")]
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+#[cfg(feature = "metrics")]
+use anyhow::{bail, Result};
+
+pub const UPDATES: &str = "ka_updates_total";
+pub const CHANGES_RECORDED: &str = "ka_changes_recorded_total";
+pub const BYTES_DIFFED: &str = "ka_diffed_bytes_total";
+// The size of every file history an update wrote to.
+pub const HISTORY_BYTES: &str = "ka_file_history_bytes";
+pub const ACTION_DURATION: &str = "ka_action_duration_seconds";
+
+#[cfg(feature = "metrics")]
+pub trait Recorder: Send + Sync {
+    fn increment_counter(&self, name: &'static str, labels: &[(&'static str, &str)], value: u64);
+    fn record_histogram(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64);
+}
+
+#[cfg(feature = "metrics")]
+static RECORDER: OnceLock<&'static dyn Recorder> = OnceLock::new();
+
+// Can only be done once per process.
+#[cfg(feature = "metrics")]
+pub fn set_recorder(recorder: &'static dyn Recorder) -> Result<()> {
+    if RECORDER.set(recorder).is_err() {
+        bail!("A metrics recorder is already installed.");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn increment_counter(name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.increment_counter(name, labels, value);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn increment_counter(
+    _name: &'static str,
+    _labels: &[(&'static str, &str)],
+    _value: u64,
+) {
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_histogram(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.record_histogram(name, labels, value);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn record_histogram(_name: &'static str, _labels: &[(&'static str, &str)], _value: f64) {
+}
+
+// Records how long the action took once it's dropped, whether it succeeded or not.
+pub(crate) struct ActionTimer {
+    #[cfg(feature = "metrics")]
+    action: &'static str,
+    #[cfg(feature = "metrics")]
+    start: Instant,
+}
+
+impl ActionTimer {
+    #[cfg(feature = "metrics")]
+    pub fn start(action: &'static str) -> Self {
+        Self {
+            action,
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    #[inline(always)]
+    pub fn start(_action: &'static str) -> Self {
+        Self {}
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for ActionTimer {
+    fn drop(&mut self) {
+        let seconds = self.start.elapsed().as_secs_f64();
+        record_histogram(ACTION_DURATION, &[("action", self.action)], seconds);
+    }
+}
+
+// A recorder keeping everything in memory, which renders it in the text format Prometheus
+// scrapes.
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+pub struct Registry {
+    counters: Mutex<BTreeMap<(&'static str, String), u64>>,
+    histograms: Mutex<BTreeMap<(&'static str, String), Histogram>>,
+}
+
+#[cfg(feature = "metrics")]
+struct Histogram {
+    bounds: &'static [f64],
+    // How many values were at most each of the bounds.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl Registry {
+    pub fn render(&self) -> String {
+        let mut rendered = String::new();
+
+        let counters = self.counters.lock().unwrap();
+        let mut last_name = None;
+        for ((name, labels), value) in counters.iter() {
+            if last_name != Some(name) {
+                let _ = writeln!(rendered, "# TYPE {} counter", name);
+                last_name = Some(name);
+            }
+            let _ = writeln!(
+                rendered,
+                "{}{} {}",
+                name,
+                format_labels(labels, None),
+                value
+            );
+        }
+
+        let histograms = self.histograms.lock().unwrap();
+        let mut last_name = None;
+        for ((name, labels), histogram) in histograms.iter() {
+            if last_name != Some(name) {
+                let _ = writeln!(rendered, "# TYPE {} histogram", name);
+                last_name = Some(name);
+            }
+            for (bound, count) in histogram.bounds.iter().zip(histogram.counts.iter()) {
+                let bucket_labels = format_labels(labels, Some(&bound.to_string()));
+                let _ = writeln!(rendered, "{}_bucket{} {}", name, bucket_labels, count);
+            }
+            let bucket_labels = format_labels(labels, Some("+Inf"));
+            let _ = writeln!(
+                rendered,
+                "{}_bucket{} {}",
+                name, bucket_labels, histogram.count
+            );
+            let labels = format_labels(labels, None);
+            let _ = writeln!(rendered, "{}_sum{} {}", name, labels, histogram.sum);
+            let _ = writeln!(rendered, "{}_count{} {}", name, labels, histogram.count);
+        }
+
+        rendered
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Recorder for Registry {
+    fn increment_counter(&self, name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+        let key = (name, encode_labels(labels));
+        *self.counters.lock().unwrap().entry(key).or_default() += value;
+    }
+
+    fn record_histogram(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        let key = (name, encode_labels(labels));
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry(key).or_insert_with(|| {
+            let bounds = get_bucket_bounds(name);
+            Histogram {
+                bounds,
+                counts: vec![0; bounds.len()],
+                sum: 0.0,
+                count: 0,
+            }
+        });
+
+        for (bound, count) in histogram.bounds.iter().zip(histogram.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn get_bucket_bounds(name: &str) -> &'static [f64] {
+    if name.ends_with("_seconds") {
+        &[0.001, 0.01, 0.1, 1.0, 10.0, 60.0]
+    } else {
+        &[1024.0, 16384.0, 262144.0, 4194304.0, 67108864.0]
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn encode_labels(labels: &[(&'static str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(feature = "metrics")]
+fn format_labels(labels: &str, bucket: Option<&str>) -> String {
+    let mut labels = labels.to_string();
+    if let Some(bucket) = bucket {
+        if !labels.is_empty() {
+            labels.push(',');
+        }
+        let _ = write!(labels, "le=\"{}\"", bucket);
+    }
+    if labels.is_empty() {
+        labels
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::{Recorder, Registry, ACTION_DURATION, UPDATES};
+
+    #[test]
+    fn render_for_prometheus() {
+        let registry = Registry::default();
+        registry.increment_counter(UPDATES, &[], 1);
+        registry.increment_counter(UPDATES, &[], 2);
+        registry.record_histogram(ACTION_DURATION, &[("action", "update")], 0.05);
+        registry.record_histogram(ACTION_DURATION, &[("action", "update")], 5.0);
+
+        let rendered = registry.render();
+        assert!(rendered.contains("# TYPE ka_updates_total counter\nka_updates_total 3\n"));
+        assert!(rendered.contains("# TYPE ka_action_duration_seconds histogram\n"));
+        assert!(rendered
+            .contains("ka_action_duration_seconds_bucket{action=\"update\",le=\"0.01\"} 0\n"));
+        assert!(rendered
+            .contains("ka_action_duration_seconds_bucket{action=\"update\",le=\"0.1\"} 1\n"));
+        assert!(rendered
+            .contains("ka_action_duration_seconds_bucket{action=\"update\",le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains("ka_action_duration_seconds_count{action=\"update\"} 2\n"));
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::records::{decode_records, is_record_format};
+
+// The current on-disk format versions. Files written before versioning was introduced
+// don't have a version field, and count as version 0.
+pub const REPOSITORY_HISTORY_VERSION: u32 = 2;
+pub const FILE_HISTORY_VERSION: u32 = 2;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+// The migration at index N upgrades an encoded history from version N to N + 1.
+const REPOSITORY_HISTORY_MIGRATIONS: [Migration; REPOSITORY_HISTORY_VERSION as usize] =
+    [add_version_field, use_record_format];
+const FILE_HISTORY_MIGRATIONS: [Migration; FILE_HISTORY_VERSION as usize] =
+    [add_version_field, use_record_format];
+
+// The version of an encoded history, no matter which format it's in.
+pub fn get_encoded_version(buffer: &[u8]) -> Result<u32> {
+    let header = if is_record_format(buffer) {
+        decode_records(buffer)?
+            .first()
+            .copied()
+            .context("The history has no header.")?
+    } else {
+        buffer
+    };
+
+    let value: Value = serde_json::from_slice(header).context("Failed decoding history.")?;
+    get_version(&value)
+}
+
+pub fn get_version(value: &Value) -> Result<u32> {
+    match value.get("version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .map(|version| version as u32)
+            .context("The format version has to be a number."),
+    }
+}
+
+pub fn migrate_repository_history(value: &mut Value) -> Result<()> {
+    migrate(
+        value,
+        "repository history",
+        REPOSITORY_HISTORY_VERSION,
+        &REPOSITORY_HISTORY_MIGRATIONS,
+    )
+}
+
+pub fn migrate_file_history(value: &mut Value) -> Result<()> {
+    migrate(
+        value,
+        "file history",
+        FILE_HISTORY_VERSION,
+        &FILE_HISTORY_MIGRATIONS,
+    )
+}
+
+fn migrate(
+    value: &mut Value,
+    kind: &str,
+    current_version: u32,
+    migrations: &[Migration],
+) -> Result<()> {
+    let version = get_version(value)?;
+    if version > current_version {
+        bail!(
+            "The {} has the format version {}, but only versions up to {} are supported. Update ka to open this repository.",
+            kind,
+            version,
+            current_version
+        );
+    }
+
+    for (from_version, migration) in migrations.iter().enumerate().skip(version as usize) {
+        migration(value).with_context(|| {
+            format!(
+                "Failed migrating the {} from version {}.",
+                kind, from_version
+            )
+        })?;
+        value["version"] = Value::from(from_version + 1);
+    }
+
+    Ok(())
+}
+
+// Version 1 only introduced the version field itself.
+fn add_version_field(value: &mut Value) -> Result<()> {
+    if !value.is_object() {
+        bail!("Expected an object.");
+    }
+    Ok(())
+}
+
+// Version 2 stores histories as appendable records, the content itself stays the same.
+fn use_record_format(_value: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let mut legacy = json!({ "cursor": 1, "changes": [] });
+        migrate_repository_history(&mut legacy).unwrap();
+        assert_eq!(get_version(&legacy).unwrap(), REPOSITORY_HISTORY_VERSION);
+
+        let mut current = json!({ "version": FILE_HISTORY_VERSION, "changes": [] });
+        migrate_file_history(&mut current).unwrap();
+        assert_eq!(get_version(&current).unwrap(), FILE_HISTORY_VERSION);
+
+        let mut future = json!({ "version": FILE_HISTORY_VERSION + 1, "changes": [] });
+        assert!(migrate_file_history(&mut future).is_err());
+    }
+}
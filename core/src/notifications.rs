@@ -0,0 +1,245 @@
+// Where the watcher reports what it recorded and what went wrong, so an unattended watcher
+// which stopped recording doesn't go unnoticed. Sinks are set up in `notifications` of the
+// config, and each notification is sent to all of them as JSON. A failing sink is only traced
+// as a warning, like a failing hook.
+
+use std::{io, path::PathBuf};
+
+use anyhow::{bail, Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    files::Locations,
+    trace::{self, Level},
+};
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    // A change with the pending files was recorded. Paths are relative to the repository.
+    Recorded { timestamp: u64, files: Vec<PathBuf> },
+    // Working files which couldn't be read or collide with others, and were left out of the
+    // recorded change.
+    Skipped { files: Vec<SkippedFile> },
+    // Recording failed altogether, and is tried again with the next poll. Watchers wait a
+    // while longer once the disk is full.
+    Failed { disk_full: bool, error: String },
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl Notification {
+    pub fn failed(error: &Error) -> Self {
+        Notification::Failed {
+            disk_full: is_disk_full(error),
+            error: format!("{:#}", error),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationSink {
+    // POSTs the notification to the URL. Only plain `http://` is spoken, anything else is
+    // better sent by a command like `curl`.
+    Webhook {
+        url: String,
+    },
+    // Runs the program in the directory of the repository, with the notification on its
+    // standard input.
+    Command {
+        program: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+pub trait Notifier {
+    fn notify(&self, locations: &Locations, payload: &[u8]) -> Result<()>;
+}
+
+impl Notifier for NotificationSink {
+    fn notify(&self, locations: &Locations, payload: &[u8]) -> Result<()> {
+        match self {
+            NotificationSink::Webhook { url } => post(url, payload),
+            NotificationSink::Command { program, args } => {
+                let program = locations.repository_path.join(program);
+                if !crate::hooks::execute(&program, args, &locations.repository_path, payload)? {
+                    bail!("'{}' failed.", program.display());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Sends the notification to every sink, one after the other.
+pub fn notify_all(sinks: &[impl Notifier], locations: &Locations, notification: &Notification) {
+    if sinks.is_empty() {
+        return;
+    }
+    let payload = match serde_json::to_vec(notification) {
+        Ok(payload) => payload,
+        Err(error) => {
+            trace::event(
+                Level::Warn,
+                "Failed encoding the notification.",
+                &[("error", &error)],
+            );
+            return;
+        }
+    };
+
+    for sink in sinks {
+        if let Err(error) = sink.notify(locations, &payload) {
+            trace::event(
+                Level::Warn,
+                "Failed sending the notification.",
+                &[("error", &error)],
+            );
+        }
+    }
+}
+
+pub fn is_disk_full(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|error| error.kind() == io::ErrorKind::StorageFull)
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn post(url: &str, payload: &[u8]) -> Result<()> {
+    use std::{
+        io::{Read, Write},
+        net::{TcpStream, ToSocketAddrs},
+        time::Duration,
+    };
+
+    use anyhow::Context;
+
+    // A webhook which doesn't answer shouldn't hold up the watcher for long.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => bail!("The webhook '{}' isn't an http:// URL.", url),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let socket_address = address
+        .to_socket_addrs()
+        .with_context(|| format!("Failed resolving '{}'.", authority))?
+        .next()
+        .with_context(|| format!("Failed resolving '{}'.", authority))?;
+
+    let mut stream = TcpStream::connect_timeout(&socket_address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        payload.len()
+    )?;
+    stream.write_all(payload)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line.split(' ').nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        bail!("The webhook '{}' answered '{}'.", url, status_line);
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn post(_url: &str, _payload: &[u8]) -> Result<()> {
+    bail!("Webhooks can't be called here.")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        net::TcpListener,
+        path::PathBuf,
+        thread,
+    };
+
+    use anyhow::{Context, Error};
+
+    use crate::{actions::ActionOptions, files::Locations};
+
+    use super::{is_disk_full, notify_all, Notification, NotificationSink};
+
+    #[test]
+    fn post_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ka", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            // The request is complete once its body is, which ends with the notification.
+            while !request.ends_with(b"}") {
+                let length = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..length]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let notification = Notification::Recorded {
+            timestamp: 0xC0FFEE,
+            files: vec![PathBuf::from("notes/todo")],
+        };
+        notify_all(
+            &[NotificationSink::Webhook { url }],
+            &locations,
+            &notification,
+        );
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /ka HTTP/1.1\r\n"));
+        assert!(request
+            .ends_with(r#"{"event":"recorded","timestamp":12648430,"files":["notes/todo"]}"#));
+    }
+
+    #[test]
+    fn tell_full_disks_apart() {
+        let disk_full = Error::from(io::Error::from(io::ErrorKind::StorageFull))
+            .context("Failed writing history.");
+        assert!(is_disk_full(&disk_full));
+        assert_eq!(
+            Notification::failed(&disk_full),
+            Notification::Failed {
+                disk_full: true,
+                error: "Failed writing history.: no storage space".into(),
+            }
+        );
+
+        let not_found: Result<(), _> = Err(io::Error::from(io::ErrorKind::NotFound));
+        assert!(!is_disk_full(
+            &not_found.context("Failed reading.").unwrap_err()
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::{crypto, files::Locations, filesystem::Fs, history::CorruptionError};
+
+// Insertions at least this large are moved into the object store instead of being
+// embedded in the history file.
+pub const OBJECT_THRESHOLD: usize = 4096;
+
+// A content-addressed store below `.ka/objects`, where every distinct content is only
+// stored once, no matter how many changes or files refer to it.
+pub struct ObjectStore<'a, FS: Fs> {
+    fs: &'a FS,
+    objects_path: PathBuf,
+}
+
+impl<'a, FS: Fs> ObjectStore<'a, FS> {
+    pub fn new(fs: &'a FS, locations: &Locations) -> Self {
+        Self {
+            fs,
+            objects_path: locations.get_objects_path(),
+        }
+    }
+
+    pub fn store(&self, content: &[u8]) -> Result<String> {
+        let id = get_object_id(content);
+        let object_path = self.get_object_path(&id);
+
+        if !self.fs.path_exists(&object_path) {
+            let mut object_file = self.fs.create_file(&object_path)?;
+            self.fs.write_to_file(&mut object_file, content.to_vec())?;
+        }
+
+        Ok(id)
+    }
+
+    pub fn load(&self, id: &str) -> Result<Vec<u8>> {
+        let object_path = self.get_object_path(id);
+        let mut object_file = self
+            .fs
+            .open_readable_file(&object_path)
+            .with_context(|| CorruptionError(format!("The object '{}' is missing.", id)))?;
+        self.fs.read_from_file(&mut object_file)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let object_path = self.get_object_path(id);
+        if self.fs.path_exists(&object_path) {
+            self.fs.delete_file(&object_path)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_object_path(&self, id: &str) -> PathBuf {
+        // Splitting like Git does keeps the directories from growing too large.
+        let (prefix, rest) = id.split_at(2.min(id.len()));
+        self.objects_path.join(prefix).join(rest)
+    }
+}
+
+pub fn get_object_id(content: &[u8]) -> String {
+    crypto::to_hex(&crypto::sha256(content))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        actions::{create, shift, update, ActionOptions, ShiftMode, UntrackedFiles},
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs, FsEntry,
+        },
+    };
+
+    use super::OBJECT_THRESHOLD;
+
+    #[test]
+    fn deduplicate_large_insertions() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let large_content = vec![42; OBJECT_THRESHOLD * 2];
+
+        write_file(&fs_mock, "./first", &large_content);
+        write_file(&fs_mock, "./second", &large_content);
+        write_file(&fs_mock, "./small", b"small");
+        create(ActionOptions::from_path("."), &fs_mock, now).unwrap();
+
+        // Both files share a single object, and don't embed the content themselves.
+        let locations = Locations::from(&ActionOptions::from_path("."));
+        let object_directories = fs_mock
+            .read_directory(&locations.get_objects_path())
+            .unwrap();
+        assert_eq!(object_directories.len(), 1);
+        let objects = fs_mock
+            .read_directory(&object_directories[0].path())
+            .unwrap();
+        assert_eq!(objects.len(), 1);
+        assert!(read_file(&fs_mock, "./.ka/files/first").len() < OBJECT_THRESHOLD);
+
+        write_file(&fs_mock, "./first", b"replaced");
+        update(ActionOptions::from_path("."), &fs_mock, now + 1).unwrap();
+
+        shift(
+            ActionOptions::from_path("."),
+            &fs_mock,
+            1,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .unwrap();
+        assert_eq!(read_file(&fs_mock, "./first"), large_content);
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, ignore::match_glob};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackingMode {
+    Skip,
+    Snapshot,
+    #[default]
+    Diff,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    TooLarge { size: u64, max_file_size: u64 },
+    Binary,
+    Excluded,
+    // Outside of the tracked directories in the config.
+    OutsideScope,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilePolicy {
+    Track(TrackingMode),
+    Skip(SkipReason),
+}
+
+// What the content of a file is, which decides how it's diffed and shown. It's recorded with
+// every change, so the history tells when a file stopped being text.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    Text,
+    Binary,
+}
+
+// Same heuristic as Git uses: a NUL byte near the start means the content isn't text.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&byte| byte == 0)
+}
+
+// Text is UTF-8 without NUL bytes near the start, unless the `content_types` of the config
+// say otherwise for the name of the file, like `*.md = text`.
+pub fn get_content_type(config: &Config, path: &Path, content: &[u8]) -> ContentType {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let override_type = config
+        .content_types
+        .iter()
+        .find(|(pattern, _)| match_glob(pattern.as_bytes(), name.as_bytes()))
+        .map(|(_, content_type)| *content_type);
+
+    match override_type {
+        Some(content_type) => content_type,
+        None if is_binary(content) || std::str::from_utf8(content).is_err() => ContentType::Binary,
+        None => ContentType::Text,
+    }
+}
+
+pub fn get_file_policy(config: &Config, path: &Path, content: &[u8]) -> FilePolicy {
+    let size = content.len() as u64;
+    if let Some(max_file_size) = config.max_file_size {
+        if size > max_file_size {
+            return FilePolicy::Skip(SkipReason::TooLarge {
+                size,
+                max_file_size,
+            });
+        }
+    }
+
+    let extension_mode = path
+        .extension()
+        .and_then(|extension| config.extensions.get(extension.to_string_lossy().as_ref()));
+
+    match extension_mode {
+        Some(TrackingMode::Skip) => FilePolicy::Skip(SkipReason::Excluded),
+        Some(mode) => FilePolicy::Track(*mode),
+        None if get_content_type(config, path, content) == ContentType::Binary => {
+            match config.binary {
+                TrackingMode::Skip => FilePolicy::Skip(SkipReason::Binary),
+                mode => FilePolicy::Track(mode),
+            }
+        }
+        None => FilePolicy::Track(TrackingMode::Diff),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_policy() {
+        let mut config = Config {
+            max_file_size: Some(4),
+            binary: TrackingMode::Skip,
+            ..Config::default()
+        };
+        config.extensions.insert("log".into(), TrackingMode::Skip);
+        config
+            .extensions
+            .insert("bin".into(), TrackingMode::Snapshot);
+
+        let policy =
+            |path: &str, content: &[u8]| get_file_policy(&config, Path::new(path), content);
+
+        assert_eq!(
+            policy("a.txt", b"text"),
+            FilePolicy::Track(TrackingMode::Diff)
+        );
+        assert_eq!(
+            policy("a.txt", b"too long"),
+            FilePolicy::Skip(SkipReason::TooLarge {
+                size: 8,
+                max_file_size: 4
+            })
+        );
+        assert_eq!(
+            policy("a.log", b"text"),
+            FilePolicy::Skip(SkipReason::Excluded)
+        );
+        assert_eq!(
+            policy("a.dat", &[0, 1]),
+            FilePolicy::Skip(SkipReason::Binary)
+        );
+        assert_eq!(
+            policy("a.bin", &[0, 1]),
+            FilePolicy::Track(TrackingMode::Snapshot)
+        );
+    }
+
+    #[test]
+    fn test_content_type() {
+        let mut config = Config::default();
+        config
+            .content_types
+            .insert("*.md".into(), ContentType::Text);
+
+        let content_type =
+            |path: &str, content: &[u8]| get_content_type(&config, Path::new(path), content);
+
+        assert_eq!(
+            content_type("a.txt", "ünïcode".as_bytes()),
+            ContentType::Text
+        );
+        assert_eq!(content_type("a.txt", &[0xff, 0xfe]), ContentType::Binary);
+        assert_eq!(content_type("a.txt", b"nul\0"), ContentType::Binary);
+        assert_eq!(content_type("dir/a.md", b"nul\0"), ContentType::Text);
+    }
+}
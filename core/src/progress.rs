@@ -0,0 +1,16 @@
+// How far a long-running action got, e.g. to show a progress bar while importing a large
+// directory. Actions report to whatever they are given, which may just ignore it.
+
+use std::path::Path;
+
+pub trait Progress {
+    // `done` of `total` files are handled, `working_path` being the latest one.
+    fn file_done(&mut self, working_path: &Path, done: usize, total: usize);
+}
+
+// For when nobody is watching.
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn file_done(&mut self, _working_path: &Path, _done: usize, _total: usize) {}
+}
@@ -0,0 +1,190 @@
+// How two repositories, e.g. one on each machine, exchange changes. The receiving side
+// advertises how far each of its histories goes, and the sending side answers with only the
+// changes it's missing, rather than whole history files.
+//
+// An exchange goes like this:
+// 1. Both sides send a `Hello` with the protocol versions they understand, and go on with
+//    the latest one both do.
+// 2. The receiving side sends an `Advertise`.
+// 3. The sending side answers with the missing `Changes`, or `Refused` if it can't tell
+//    which changes those are.
+//
+// Version 2 also advertises the IDs of all changes, so histories which diverged can still be
+// exchanged. The changes after the last one both sides have in common are sent, and the
+// receiving side records its own changes after those again, on top of the received ones.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::history::{FileChange, RepositoryChange};
+
+// Newest last. Support for older versions is only dropped with a new major version of ka.
+pub const PROTOCOL_VERSIONS: [u32; 2] = [1, 2];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Message {
+    Hello {
+        versions: Vec<u32>,
+    },
+    Advertise {
+        // The number of changes in the index and the ID of the latest one, which the sending
+        // side has to have as well.
+        change_count: usize,
+        latest_change_id: Option<String>,
+        // The IDs of all changes, oldest first. Only sent from version 2 on.
+        #[serde(default)]
+        change_ids: Vec<Option<String>>,
+        // The `change_index` of the latest change of every file history, by the path of the
+        // file relative to the repository.
+        files: BTreeMap<PathBuf, usize>,
+    },
+    Changes {
+        // The number of changes both sides have in common, the sent ones come after them.
+        // Unless the histories diverged, that's all the changes of the receiving side.
+        #[serde(default)]
+        base: Option<usize>,
+        // The affected files are relative to the repository, unlike in the index.
+        repository_changes: Vec<RepositoryChange>,
+        // With their content resolved, so they don't depend on objects or other histories.
+        file_changes: BTreeMap<PathBuf, Vec<FileChange>>,
+    },
+    Refused {
+        reason: String,
+    },
+}
+
+impl Message {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).context("Failed encoding message.")
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        serde_json::from_slice(buffer).context("Failed decoding message.")
+    }
+
+    // For errors about unexpected messages, which shouldn't include all of their content.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Message::Hello { .. } => "Hello",
+            Message::Advertise { .. } => "Advertise",
+            Message::Changes { .. } => "Changes",
+            Message::Refused { .. } => "Refused",
+        }
+    }
+}
+
+// Carries encoded messages to the other side and back, in order.
+pub trait Transport {
+    fn send(&mut self, message: Vec<u8>) -> Result<()>;
+    // Waits for the next message from the other side.
+    fn receive(&mut self) -> Result<Vec<u8>>;
+}
+
+// Connects two sides within the same process, e.g. on two threads in tests.
+pub struct MemoryTransport {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl MemoryTransport {
+    pub fn pair() -> (Self, Self) {
+        let (sender, other_receiver) = channel();
+        let (other_sender, receiver) = channel();
+        (
+            MemoryTransport { sender, receiver },
+            MemoryTransport {
+                sender: other_sender,
+                receiver: other_receiver,
+            },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send(&mut self, message: Vec<u8>) -> Result<()> {
+        self.sender
+            .send(message)
+            .ok()
+            .context("The other side hung up.")
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>> {
+        self.receiver.recv().ok().context("The other side hung up.")
+    }
+}
+
+pub fn send_message(transport: &mut impl Transport, message: &Message) -> Result<()> {
+    transport.send(message.encode()?)
+}
+
+pub fn receive_message(transport: &mut impl Transport) -> Result<Message> {
+    Message::decode(&transport.receive()?)
+}
+
+// Agrees on the protocol version with the other side, which does the same.
+pub fn negotiate_version(transport: &mut impl Transport) -> Result<u32> {
+    send_message(
+        transport,
+        &Message::Hello {
+            versions: PROTOCOL_VERSIONS.to_vec(),
+        },
+    )?;
+    match receive_message(transport)? {
+        Message::Hello { versions } => get_common_version(&versions).with_context(|| {
+            format!(
+                "The other side only understands the protocol versions {:?}, but this one only {:?}.",
+                versions, PROTOCOL_VERSIONS
+            )
+        }),
+        message => bail!(
+            "Expected a greeting from the other side, got '{}'.",
+            message.get_name()
+        ),
+    }
+}
+
+fn get_common_version(versions: &[u32]) -> Option<u32> {
+    versions
+        .iter()
+        .copied()
+        .filter(|version| PROTOCOL_VERSIONS.contains(version))
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{
+        get_common_version, negotiate_version, receive_message, send_message, MemoryTransport,
+        Message,
+    };
+
+    #[test]
+    fn negotiate_versions() {
+        assert_eq!(get_common_version(&[1, 3]), Some(1));
+        assert_eq!(get_common_version(&[0, 3]), None);
+
+        let (mut transport, mut other_transport) = MemoryTransport::pair();
+        let other_side = thread::spawn(move || negotiate_version(&mut other_transport));
+        assert_eq!(negotiate_version(&mut transport).unwrap(), 2);
+        assert_eq!(other_side.join().unwrap().unwrap(), 2);
+
+        let (mut transport, mut other_transport) = MemoryTransport::pair();
+        send_message(&mut other_transport, &Message::Hello { versions: vec![99] }).unwrap();
+        assert!(negotiate_version(&mut transport).is_err());
+        assert!(matches!(
+            receive_message(&mut other_transport).unwrap(),
+            Message::Hello { .. }
+        ));
+
+        drop(other_transport);
+        assert!(receive_message(&mut transport).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::convert::{TryFrom, TryInto};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+use crate::history::CorruptionError;
+
+// Histories are stored as a sequence of length-prefixed records following this marker,
+// so new changes can be appended without rewriting everything recorded before them.
+pub const MAGIC: &[u8] = b"KAREC\n";
+
+const LENGTH_SIZE: usize = 4;
+
+pub fn is_record_format(buffer: &[u8]) -> bool {
+    buffer.starts_with(MAGIC)
+}
+
+pub fn encode_record<T: Serialize>(record: &T) -> Result<Vec<u8>> {
+    let encoded = serde_json::to_vec(record).context("Failed encoding record.")?;
+    let length = u32::try_from(encoded.len()).context("The record is too large.")?;
+
+    let mut buffer = length.to_le_bytes().to_vec();
+    buffer.extend(encoded);
+    Ok(buffer)
+}
+
+pub fn decode_records(buffer: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut rest = buffer.strip_prefix(MAGIC).context(CorruptionError(
+        "The buffer isn't in the record format.".to_string(),
+    ))?;
+    let mut records = Vec::new();
+
+    while !rest.is_empty() {
+        let (record, after_record) = split_record(rest)?;
+        records.push(record);
+        rest = after_record;
+    }
+
+    Ok(records)
+}
+
+// Hands out the records of a buffer one at a time, so they can be decoded and dropped
+// again without holding all of them in memory.
+pub struct RecordReader {
+    buffer: Vec<u8>,
+    offset: usize,
+}
+
+impl RecordReader {
+    pub fn new(buffer: Vec<u8>) -> Result<Self> {
+        if !is_record_format(&buffer) {
+            bail!(CorruptionError(
+                "The buffer isn't in the record format.".to_string()
+            ));
+        }
+
+        Ok(Self {
+            buffer,
+            offset: MAGIC.len(),
+        })
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<&[u8]>> {
+        let rest = &self.buffer[self.offset..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+
+        let (record, _) = split_record(rest)?;
+        self.offset += LENGTH_SIZE + record.len();
+        Ok(Some(record))
+    }
+
+    // The length of the whole buffer, which is where new records would be appended.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+fn split_record(buffer: &[u8]) -> Result<(&[u8], &[u8])> {
+    if buffer.len() < LENGTH_SIZE {
+        bail!(CorruptionError("The last record is cut off.".to_string()));
+    }
+    let (length, after_length) = buffer.split_at(LENGTH_SIZE);
+    let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+
+    if after_length.len() < length {
+        bail!(CorruptionError("The last record is cut off.".to_string()));
+    }
+    Ok(after_length.split_at(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend(encode_record(&"first").unwrap());
+        buffer.extend(encode_record(&[1, 2, 3]).unwrap());
+
+        assert!(is_record_format(&buffer));
+        assert_eq!(
+            decode_records(&buffer).unwrap(),
+            vec![&b"\"first\""[..], &b"[1,2,3]"[..]]
+        );
+
+        assert!(decode_records(&buffer[..buffer.len() - 1]).is_err());
+        assert!(decode_records(b"{}").is_err());
+
+        let mut reader = RecordReader::new(buffer.clone()).unwrap();
+        assert_eq!(reader.next_record().unwrap(), Some(&b"\"first\""[..]));
+        assert_eq!(reader.next_record().unwrap(), Some(&b"[1,2,3]"[..]));
+        assert_eq!(reader.next_record().unwrap(), None);
+        assert_eq!(reader.len(), buffer.len());
+    }
+}
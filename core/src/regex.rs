@@ -0,0 +1,245 @@
+// A small regular expression matcher, enough for searching text line by line: literals, `.`,
+// classes like `[a-z]` or `[^,]`, the escapes `\d`, `\w` and `\s`, the quantifiers `*`, `+`
+// and `?`, and anchoring with `^` and `$`. There are no groups, alternatives or counted
+// repetitions, so patterns using them are rejected rather than taken literally, unless the
+// characters are escaped. Matching backtracks, which is fine for lines, but not for whole files.
+
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    One,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    nodes: Vec<(Atom, Repeat)>,
+    anchored_start: bool,
+    anchored_end: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut chars = pattern.chars().peekable();
+        let anchored_start = chars.next_if_eq(&'^').is_some();
+
+        let mut nodes: Vec<(Atom, Repeat)> = Vec::new();
+        let mut anchored_end = false;
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '$' if chars.peek().is_none() => {
+                    anchored_end = true;
+                    break;
+                }
+                '.' => Atom::Any,
+                '\\' => match chars.next() {
+                    Some(escaped) => parse_escape(escaped),
+                    None => bail!("The pattern '{}' ends with a backslash.", pattern),
+                },
+                '[' => parse_class(&mut chars, pattern)?,
+                '*' | '+' | '?' => {
+                    bail!("The '{}' in '{}' has nothing to repeat.", c, pattern)
+                }
+                '(' | ')' | '|' | '{' => bail!(
+                    "The '{}' in '{}' isn't supported, write '\\{}' to match it.",
+                    c,
+                    pattern,
+                    c
+                ),
+                c => Atom::Char(c),
+            };
+            let repeat = match chars.next_if(|c| matches!(c, '*' | '+' | '?')) {
+                Some('*') => Repeat::ZeroOrMore,
+                Some('+') => Repeat::OneOrMore,
+                Some('?') => Repeat::ZeroOrOne,
+                _ => Repeat::One,
+            };
+            nodes.push((atom, repeat));
+        }
+
+        Ok(Regex {
+            nodes,
+            anchored_start,
+            anchored_end,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    // The byte range of the leftmost match, which is as long as it can be from there.
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let get_offset = |index: usize| chars.get(index).map_or(text.len(), |(offset, _)| *offset);
+
+        let last_start = if self.anchored_start { 0 } else { chars.len() };
+        (0..=last_start).find_map(|start| {
+            self.match_here(&chars, 0, start)
+                .map(|end| (get_offset(start), get_offset(end)))
+        })
+    }
+
+    fn match_here(&self, chars: &[(usize, char)], node: usize, position: usize) -> Option<usize> {
+        let (atom, repeat) = match self.nodes.get(node) {
+            Some(node) => node,
+            None if self.anchored_end && position != chars.len() => return None,
+            None => return Some(position),
+        };
+        let matches_at = |position: usize| {
+            chars
+                .get(position)
+                .is_some_and(|(_, c)| atom_matches(atom, *c))
+        };
+
+        let (min, max) = match repeat {
+            Repeat::One => (1, 1),
+            Repeat::ZeroOrOne => (0, 1),
+            Repeat::ZeroOrMore => (0, usize::MAX),
+            Repeat::OneOrMore => (1, usize::MAX),
+        };
+        let mut count = 0;
+        while count < max && matches_at(position + count) {
+            count += 1;
+        }
+        if count < min {
+            return None;
+        }
+        // Greedy, so the longest repetition which lets the rest match wins.
+        (min..=count)
+            .rev()
+            .find_map(|count| self.match_here(chars, node + 1, position + count))
+    }
+}
+
+fn parse_escape(escaped: char) -> Atom {
+    let class = |ranges: &[(char, char)], negated: bool| Atom::Class {
+        ranges: ranges.to_vec(),
+        negated,
+    };
+    match escaped {
+        'd' => class(DIGITS, false),
+        'D' => class(DIGITS, true),
+        'w' => class(WORD, false),
+        'W' => class(WORD, true),
+        's' => class(SPACE, false),
+        'S' => class(SPACE, true),
+        't' => Atom::Char('\t'),
+        c => Atom::Char(c),
+    }
+}
+
+const DIGITS: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+// After the opening bracket. A `]` right at the start is part of the class.
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars>, pattern: &str) -> Result<Atom> {
+    let negated = chars.next_if_eq(&'^').is_some();
+    let mut ranges = Vec::new();
+    let mut is_first = true;
+    loop {
+        let c = match chars.next() {
+            Some(']') if !is_first => break,
+            Some(c) => c,
+            None => bail!("The class in '{}' isn't closed.", pattern),
+        };
+        is_first = false;
+
+        let start = match c {
+            '\\' => match chars.next().map(parse_escape) {
+                Some(Atom::Char(c)) => c,
+                Some(Atom::Class {
+                    ranges: escaped, ..
+                }) => {
+                    ranges.extend(escaped);
+                    continue;
+                }
+                _ => bail!("The class in '{}' isn't closed.", pattern),
+            },
+            c => c,
+        };
+        let mut lookahead = chars.clone();
+        let end = match (lookahead.next(), lookahead.next()) {
+            (Some('-'), Some(end)) if end != ']' => {
+                chars.next();
+                chars.next();
+                end
+            }
+            _ => start,
+        };
+        if end < start {
+            bail!("The range {}-{} in '{}' is reversed.", start, end, pattern);
+        }
+        ranges.push((start, end));
+    }
+    Ok(Atom::Class { ranges, negated })
+}
+
+fn atom_matches(atom: &Atom, c: char) -> bool {
+    match atom {
+        Atom::Char(expected) => c == *expected,
+        Atom::Any => true,
+        Atom::Class { ranges, negated } => {
+            ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&c))
+                != *negated
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    #[test]
+    fn find_matches() {
+        let find = |pattern: &str, text: &str| Regex::new(pattern).unwrap().find(text);
+
+        assert_eq!(find("needle", "haystack with needle"), Some((14, 20)));
+        assert_eq!(find("a.c", "abc"), Some((0, 3)));
+        assert_eq!(find("^b", "abc"), None);
+        assert_eq!(find("c$", "abc"), Some((2, 3)));
+        assert_eq!(find("b$", "abc"), None);
+        assert_eq!(find("x*", "abc"), Some((0, 0)));
+        assert_eq!(find("\\d+", "version 12.3"), Some((8, 10)));
+        assert_eq!(find("[a-c]+d", "xxabcabd"), Some((2, 8)));
+        assert_eq!(find("[^ ]+$", "last word"), Some((5, 9)));
+        assert_eq!(find("colou?r", "color"), Some((0, 5)));
+        assert_eq!(find("ä.", "xäy"), Some((1, 4)));
+        assert_eq!(find("[]x]", "a]"), Some((1, 2)));
+        assert_eq!(find("a\\.b", "axb a.b"), Some((4, 7)));
+
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("[ab").is_err());
+        assert!(Regex::new("a\\").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+    }
+
+    #[test]
+    fn reject_unsupported_syntax() {
+        for pattern in ["a|b", "(foo)+", "a)", "x{2}", "^(a|b)$"] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+
+        let find = |pattern: &str, text: &str| Regex::new(pattern).unwrap().find(text);
+        assert_eq!(find("a\\|b", "ab a|b"), Some((3, 6)));
+        assert_eq!(find("\\(x\\)", "f(x)"), Some((1, 4)));
+        assert_eq!(find("x\\{2}", "x{2}"), Some((0, 4)));
+        assert_eq!(find("[(|)]", "a|b"), Some((1, 2)));
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    vec::IntoIter,
+};
+
+use anyhow::Result;
+
+use crate::{
+    actions::{
+        self, ActionOptions, BackupSummary, DeletedFile, FileLogEntry, GrepMatch, LogBucket,
+        LogEntry, PullSummary, ShiftMode, Status, Summary, Timeline, UntrackedFiles, UpdateSummary,
+    },
+    clock::Clock,
+    config::Config,
+    diff::{DiffOptions, TextGranularity, TextSegment},
+    filesystem::Fs,
+    history::Annotations,
+    locks::Lock,
+    progress::Progress,
+    protocol::Transport,
+    templates::Template,
+};
+
+// The entry point for working with a repository from outside of this crate. Everything it
+// exposes is kept stable, while the modules below it may change between versions.
+pub struct Repository<'a, F: Fs> {
+    options: ActionOptions,
+    fs: &'a F,
+}
+
+impl<'a, F: Fs> Repository<'a, F> {
+    // Creates a repository at `path`, recording the files already there as its first change.
+    pub fn create(fs: &'a F, path: &Path, timestamp: u64) -> Result<(Self, UpdateSummary)> {
+        let options = ActionOptions::new(path.to_path_buf());
+        let summary = actions::create(options.clone(), fs, timestamp)?;
+        Ok((Repository { options, fs }, summary))
+    }
+
+    // Like `create`, but faster, for directories nothing else writes to while they're recorded.
+    pub fn create_baseline(
+        fs: &'a F,
+        path: &Path,
+        timestamp: u64,
+    ) -> Result<(Self, UpdateSummary)> {
+        let options = ActionOptions::new(path.to_path_buf());
+        let summary = actions::create_baseline(options.clone(), fs, timestamp)?;
+        Ok((Repository { options, fs }, summary))
+    }
+
+    // Like `create`, but starting with the config and ignore patterns of the template.
+    pub fn create_from_template(
+        fs: &'a F,
+        path: &Path,
+        timestamp: u64,
+        template: &Template,
+    ) -> Result<(Self, UpdateSummary)> {
+        let options = ActionOptions::new(path.to_path_buf());
+        let summary = actions::create_from_template(options.clone(), fs, timestamp, template)?;
+        Ok((Repository { options, fs }, summary))
+    }
+
+    // Like `create`, but records large directories in chunks and reports each file to
+    // `progress`. Calling it again after it was interrupted finishes the import.
+    pub fn import(
+        fs: &'a F,
+        path: &Path,
+        timestamp: u64,
+        progress: &mut dyn Progress,
+    ) -> Result<(Self, UpdateSummary)> {
+        let options = ActionOptions::new(path.to_path_buf());
+        let summary = actions::import(options.clone(), fs, timestamp, progress)?;
+        Ok((Repository { options, fs }, summary))
+    }
+
+    // Creates the repository at `path` from a backup made with `backup`, with the working files
+    // as they were when it was backed up. Returns the restored working files.
+    pub fn restore_from_backup(
+        fs: &'a F,
+        backup_path: &Path,
+        path: &Path,
+    ) -> Result<(Self, Vec<PathBuf>)> {
+        let restored_paths = actions::restore_from_backup(fs, backup_path, path)?;
+        let options = ActionOptions::new(path.to_path_buf());
+        Ok((Repository { options, fs }, restored_paths))
+    }
+
+    // Opens the repository `path` is in, which may be any path inside of it.
+    pub fn open(fs: &'a F, path: &Path) -> Result<Self> {
+        let options = ActionOptions::discover(fs, path)?;
+        Ok(Repository { options, fs })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.options.repository_path
+    }
+
+    pub fn options(&self) -> &ActionOptions {
+        &self.options
+    }
+
+    // Takes the current time from `clock` instead of the system clock.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Repository {
+            options: self.options.with_clock(clock),
+            ..self
+        }
+    }
+
+    // Locks files in the name of `lock_owner` instead of the identity in the config, which
+    // tells apart several tools of the same person.
+    pub fn with_lock_owner(self, lock_owner: &str) -> Self {
+        Repository {
+            options: self.options.with_lock_owner(lock_owner),
+            ..self
+        }
+    }
+
+    // The current time according to the clock, for the timestamps passed to the methods below.
+    pub fn now(&self) -> u64 {
+        self.options.now()
+    }
+
+    pub fn config(&self) -> Result<Config> {
+        Config::load_from(self.fs, &self.options)
+    }
+
+    // The diff options changes are recorded with.
+    pub fn diff_options(&self) -> Result<DiffOptions> {
+        Ok(self.config()?.diff)
+    }
+
+    // Finds the cursor of a change given either as its cursor or as a prefix of its ID.
+    pub fn resolve_change(&self, reference: &str) -> Result<usize> {
+        actions::resolve_change(self.options.clone(), self.fs, reference)
+    }
+
+    pub fn cursor(&self) -> Result<usize> {
+        Ok(self.status()?.cursor)
+    }
+
+    pub fn status(&self) -> Result<Status> {
+        actions::status(self.options.clone(), self.fs)
+    }
+
+    // The cursor, how many files are dirty and when the latest change was recorded, quickly.
+    pub fn summary(&self) -> Result<Summary> {
+        actions::summary(self.options.clone(), self.fs)
+    }
+
+    // The files which were deleted, but can still be recovered from their history.
+    pub fn deleted(&self) -> Result<Vec<DeletedFile>> {
+        actions::deleted(self.options.clone(), self.fs)
+    }
+
+    pub fn update(&self, timestamp: u64) -> Result<UpdateSummary> {
+        actions::update(self.options.clone(), self.fs, timestamp)
+    }
+
+    // Like `update`, but keeps `annotations` with the change, e.g. which app saved the files.
+    pub fn update_annotated(
+        &self,
+        timestamp: u64,
+        annotations: Annotations,
+    ) -> Result<UpdateSummary> {
+        actions::update_annotated(self.options.clone(), self.fs, timestamp, annotations)
+    }
+
+    // Tells others working on the repository that the file at `path` is being worked on, which
+    // `update` warns them about. Returns whether it wasn't locked yet.
+    pub fn lock_file(&self, path: &Path) -> Result<bool> {
+        actions::lock_file(self.options.clone(), self.fs, path)
+    }
+
+    // Releases a lock taken with `lock_file`. Returns whether the file was locked.
+    pub fn unlock_file(&self, path: &Path) -> Result<bool> {
+        actions::unlock_file(self.options.clone(), self.fs, path)
+    }
+
+    // The locked files by their working paths.
+    pub fn locked_files(&self) -> Result<BTreeMap<PathBuf, Lock>> {
+        actions::locked_files(self.options.clone(), self.fs)
+    }
+
+    // Returns the untracked files which were left in place.
+    pub fn shift(
+        &self,
+        cursor: usize,
+        mode: ShiftMode,
+        untracked_files: UntrackedFiles,
+    ) -> Result<Vec<PathBuf>> {
+        actions::shift(self.options.clone(), self.fs, cursor, mode, untracked_files)
+    }
+
+    // Puts back what the last shift changed, see `actions::undo_shift`.
+    pub fn undo_shift(&self, force: bool) -> Result<()> {
+        actions::undo_shift(self.options.clone(), self.fs, force)
+    }
+
+    // Sends the other side of `transport` the changes it's missing. Returns how many were sent.
+    pub fn push(&self, transport: &mut impl Transport) -> Result<usize> {
+        actions::push(self.options.clone(), self.fs, transport)
+    }
+
+    // Receives the changes this repository is missing, recording its own changes which the
+    // other side doesn't have again on top of them.
+    pub fn pull(&self, transport: &mut impl Transport) -> Result<PullSummary> {
+        actions::pull(self.options.clone(), self.fs, transport)
+    }
+
+    // Mirrors the repository's store into `target_path`, see `actions::backup`.
+    pub fn backup(&self, target_path: &Path, timestamp: u64) -> Result<BackupSummary> {
+        actions::backup(self.options.clone(), self.fs, target_path, timestamp)
+    }
+
+    // The content of `path`, relative to the repository, at `cursor`.
+    pub fn show(&self, path: &Path, cursor: usize) -> Result<Vec<u8>> {
+        actions::show(self.options.clone(), self.fs, path, cursor)
+    }
+
+    // The lines of the files at `cursor` which match the regular expression `pattern`.
+    pub fn grep(&self, pattern: &str, cursor: usize) -> Result<Vec<GrepMatch>> {
+        actions::grep(self.options.clone(), self.fs, pattern, cursor)
+    }
+
+    // The changes and the activity and sizes of every file over time, see `actions::timeline`.
+    pub fn timeline(&self) -> Result<Timeline> {
+        actions::export_timeline(self.options.clone(), self.fs)
+    }
+
+    // Compares `path` at `from` against `to`, or against the working file if `to` is unset.
+    pub fn diff(
+        &self,
+        path: &Path,
+        from: usize,
+        to: Option<usize>,
+        granularity: TextGranularity,
+    ) -> Result<Vec<TextSegment>> {
+        actions::diff(self.options.clone(), self.fs, path, from, to, granularity)
+    }
+
+    // All changes of the repository, oldest first.
+    pub fn changes(&self) -> Result<IntoIter<LogEntry>> {
+        Ok(actions::repository_log(self.options.clone(), self.fs)?.into_iter())
+    }
+
+    // The changes recorded at or after `since`, oldest first.
+    pub fn changes_since(&self, since: u64) -> Result<IntoIter<LogEntry>> {
+        Ok(actions::repository_log_since(self.options.clone(), self.fs, since)?.into_iter())
+    }
+
+    // The changes annotated with `key`, and with `value` for it if one is given, oldest first.
+    pub fn annotated_changes(&self, key: &str, value: Option<&str>) -> Result<IntoIter<LogEntry>> {
+        Ok(actions::annotated_log(self.options.clone(), self.fs, key, value)?.into_iter())
+    }
+
+    // The changes grouped into spans of `bucket_seconds`, oldest first.
+    pub fn change_buckets(&self, bucket_seconds: u64) -> Result<IntoIter<LogBucket>> {
+        Ok(actions::bucketed_log(self.options.clone(), self.fs, bucket_seconds)?.into_iter())
+    }
+
+    // The changes which affected any file below the directory `path`, oldest first.
+    pub fn directory_changes(&self, path: &Path) -> Result<IntoIter<LogEntry>> {
+        Ok(actions::directory_log(self.options.clone(), self.fs, path)?.into_iter())
+    }
+
+    // The changes which affected `path`, oldest first.
+    pub fn file_changes(&self, path: &Path) -> Result<IntoIter<FileLogEntry>> {
+        Ok(actions::file_log(self.options.clone(), self.fs, path)?.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use crate::{
+        actions::{FileLogKind, ShiftMode, UntrackedFiles},
+        clock::MockClock,
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::Repository;
+
+    #[test]
+    fn work_through_repository() {
+        let fs_mock = FsMock::new();
+        let now = 0xC0FFEE;
+
+        fs_mock.create_directory(Path::new("/notes")).unwrap();
+        write_file(&fs_mock, "/notes/todo", b"Water plants");
+        let (repository, _) = Repository::create(&fs_mock, Path::new("/notes"), now).unwrap();
+
+        write_file(&fs_mock, "/notes/todo", b"Water plants\nFeed cat");
+        repository.update(now + 1).unwrap();
+        assert_eq!(repository.cursor().unwrap(), 2);
+
+        let repository = Repository::open(&fs_mock, Path::new("/notes")).unwrap();
+        assert_eq!(repository.changes().unwrap().count(), 2);
+        let kinds: Vec<_> = repository
+            .file_changes(Path::new("todo"))
+            .unwrap()
+            .map(|entry| entry.kind)
+            .collect();
+        assert_eq!(kinds, vec![FileLogKind::Created, FileLogKind::Modified]);
+
+        repository
+            .shift(1, ShiftMode::Safe, UntrackedFiles::Keep)
+            .unwrap();
+        assert_eq!(
+            repository.show(Path::new("todo"), 2).unwrap(),
+            b"Water plants\nFeed cat"
+        );
+        assert_eq!(repository.cursor().unwrap(), 1);
+    }
+    #[test]
+    fn record_timestamps_from_clock() {
+        let fs_mock = FsMock::new();
+        let clock = Arc::new(MockClock::new(1000));
+
+        fs_mock.create_directory(Path::new("/notes")).unwrap();
+        write_file(&fs_mock, "/notes/todo", b"Water plants");
+        let (repository, _) = Repository::create(&fs_mock, Path::new("/notes"), 0).unwrap();
+        let repository = repository.with_clock(clock.clone());
+
+        clock.advance(90);
+        write_file(&fs_mock, "/notes/todo", b"Water plants\nFeed cat");
+        repository.update(repository.now()).unwrap();
+        clock.set(5000);
+        write_file(&fs_mock, "/notes/todo", b"Feed cat");
+        repository.update(repository.now()).unwrap();
+
+        let timestamps: Vec<_> = repository
+            .changes()
+            .unwrap()
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![0, 1090, 5000]);
+    }
+}
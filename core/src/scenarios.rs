@@ -0,0 +1,215 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    actions::{
+        create, recover, revert, shift, squash, update, ActionOptions, ShiftMode, UntrackedFiles,
+    },
+    files::Locations,
+    filesystem::{mock::FsMock, Fs, FsEntry, FsImpl},
+    history::RepositoryHistory,
+};
+
+// Scenarios are read from `tests/scenarios/*.json` and describe a sequence of edits to
+// the working directory and actions run on it, interleaved with the states we expect.
+#[derive(Deserialize)]
+struct Scenario {
+    steps: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    Write {
+        path: String,
+        content: String,
+    },
+    Delete {
+        path: String,
+    },
+    Create,
+    Update,
+    Shift {
+        cursor: usize,
+        #[serde(default)]
+        force: bool,
+        #[serde(default)]
+        clean: bool,
+    },
+    Revert {
+        change: usize,
+    },
+    Recover {
+        path: String,
+        cursor: Option<usize>,
+    },
+    Squash {
+        from: usize,
+        to: usize,
+    },
+    Expect {
+        cursor: Option<usize>,
+        files: Option<BTreeMap<String, String>>,
+    },
+    ExpectFailure(Box<Step>),
+}
+
+fn run_scenario(scenario: &Scenario, fs: &impl Fs, repository_path: &Path) -> Result<()> {
+    for (index, step) in scenario.steps.iter().enumerate() {
+        run_step(step, fs, repository_path, index as u64)
+            .with_context(|| format!("Step {} failed.", index + 1))?;
+    }
+    Ok(())
+}
+
+fn run_step(step: &Step, fs: &impl Fs, repository_path: &Path, timestamp: u64) -> Result<()> {
+    let options = || ActionOptions::new(repository_path.to_path_buf());
+
+    match step {
+        Step::Write { path, content } => {
+            let mut file = fs.create_file(&repository_path.join(path))?;
+            fs.write_to_file(&mut file, content.as_bytes().to_vec())?;
+        }
+        Step::Delete { path } => fs.delete_file(&repository_path.join(path))?,
+        Step::Create => {
+            create(options(), fs, timestamp)?;
+        }
+        Step::Update => {
+            update(options(), fs, timestamp)?;
+        }
+        Step::Shift {
+            cursor,
+            force,
+            clean,
+        } => {
+            let mode = if *force {
+                ShiftMode::Force
+            } else {
+                ShiftMode::Safe
+            };
+            let untracked_files = if *clean {
+                UntrackedFiles::Clean
+            } else {
+                UntrackedFiles::Keep
+            };
+            shift(options(), fs, *cursor, mode, untracked_files)?;
+        }
+        Step::Revert { change } => revert(options(), fs, *change, timestamp)?,
+        Step::Recover { path, cursor } => {
+            recover(options(), fs, Path::new(path), *cursor, timestamp)?
+        }
+        Step::Squash { from, to } => squash(options(), fs, *from, *to, None)?,
+        Step::Expect { cursor, files } => {
+            if let Some(cursor) = cursor {
+                let locations = Locations::from(&options());
+                let mut index_file =
+                    fs.open_readable_file(&locations.get_repository_index_path())?;
+                let history = RepositoryHistory::from_file(fs, &locations, &mut index_file)?;
+                if history.cursor != *cursor {
+                    bail!("Expected cursor {}, found {}.", cursor, history.cursor);
+                }
+            }
+
+            if let Some(files) = files {
+                let actual_files = read_working_files(fs, repository_path, repository_path)?;
+                if &actual_files != files {
+                    bail!(
+                        "Working files don't match.\nExpected: {:?}\nReceived: {:?}",
+                        files,
+                        actual_files
+                    );
+                }
+            }
+        }
+        Step::ExpectFailure(step) => {
+            if run_step(step, fs, repository_path, timestamp).is_ok() {
+                bail!("Expected the step to fail.");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn read_working_files(
+    fs: &impl Fs,
+    repository_path: &Path,
+    directory: &Path,
+) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+
+    for entry in fs.read_directory(directory)? {
+        let path = entry.path();
+        if path == repository_path.join(".ka") {
+            continue;
+        }
+
+        if entry.is_directory()? {
+            files.extend(read_working_files(fs, repository_path, &path)?);
+        } else {
+            let mut file = fs.open_readable_file(&path)?;
+            let content = String::from_utf8(fs.read_from_file(&mut file)?)?;
+            let relative_path = path.strip_prefix(repository_path)?;
+            files.insert(relative_path.to_string_lossy().into_owned(), content);
+        }
+    }
+
+    Ok(files)
+}
+
+fn load_scenarios() -> Vec<(String, Scenario)> {
+    let scenario_directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(&scenario_directory)
+        .expect("Failed reading scenario directory.")
+        .map(|entry| entry.expect("Failed reading scenario entry.").path())
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let content = fs::read(&path).expect("Failed reading scenario.");
+            let scenario = serde_json::from_slice(&content)
+                .unwrap_or_else(|e| panic!("Invalid scenario '{}': {}", path.display(), e));
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            (name, scenario)
+        })
+        .collect()
+}
+
+#[test]
+fn scenarios_on_mock() {
+    for (name, scenario) in load_scenarios() {
+        let fs_mock = FsMock::new();
+        if let Err(error) = run_scenario(&scenario, &fs_mock, Path::new(".")) {
+            panic!("Scenario '{}' failed: {:?}", name, error);
+        }
+    }
+}
+
+#[test]
+fn scenarios_on_disk() {
+    for (name, scenario) in load_scenarios() {
+        let repository_path =
+            std::env::temp_dir().join(format!("ka-scenario-{}-{}", std::process::id(), name));
+        if repository_path.exists() {
+            fs::remove_dir_all(&repository_path).unwrap();
+        }
+        fs::create_dir_all(&repository_path).unwrap();
+
+        let result = run_scenario(&scenario, &FsImpl {}, &repository_path);
+        fs::remove_dir_all(&repository_path).unwrap();
+
+        if let Err(error) = result {
+            panic!("Scenario '{}' failed: {:?}", name, error);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+// A minimal writer for SQLite database files, which histories can be exported as for querying
+// them with SQL. Only what a freshly written database needs is supported: tables of rows with
+// ascending rowids, stored in b-trees which are filled completely, with overflow pages for
+// values too large for a single page. There are no indexes and no free pages.
+
+use std::convert::TryFrom;
+
+use anyhow::{bail, Result};
+
+const PAGE_SIZE: usize = 4096;
+const HEADER_SIZE: usize = 100;
+const LEAF_HEADER_SIZE: usize = 8;
+const INTERIOR_HEADER_SIZE: usize = 12;
+const LEAF_TABLE_PAGE: u8 = 0x0D;
+const INTERIOR_TABLE_PAGE: u8 = 0x05;
+// The version of SQLite the file claims to be written by, 3.40.1.
+const SQLITE_VERSION_NUMBER: u32 = 3_040_001;
+
+// How much of a record a leaf cell holds itself before the rest spills onto overflow pages.
+const MAX_LOCAL_PAYLOAD: usize = PAGE_SIZE - 35;
+const MIN_LOCAL_PAYLOAD: usize = (PAGE_SIZE - 12) * 32 / 255 - 23;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+pub struct Table {
+    pub name: String,
+    // The statement the table is created with, whose columns have to match the rows. A column
+    // declared as `INTEGER PRIMARY KEY` is the rowid, and its value is `Null` in the row.
+    pub sql: String,
+    // By ascending rowid.
+    pub rows: Vec<(i64, Vec<Value>)>,
+}
+
+pub fn write_database(tables: &[Table]) -> Result<Vec<u8>> {
+    // The first page holds the header and the schema, so it is filled in last.
+    let mut pages = vec![Vec::new()];
+
+    let mut schema_rows = Vec::new();
+    for (rowid, table) in (1..).zip(tables) {
+        let root_page = write_table(&mut pages, table)?;
+        schema_rows.push((
+            rowid,
+            vec![
+                Value::Text("table".to_string()),
+                Value::Text(table.name.clone()),
+                Value::Text(table.name.clone()),
+                Value::Integer(root_page as i64),
+                Value::Text(table.sql.clone()),
+            ],
+        ));
+    }
+
+    let mut schema_cells = Vec::new();
+    for (rowid, values) in &schema_rows {
+        schema_cells.push(leaf_cell(&mut pages, *rowid, &encode_record(values))?);
+    }
+    if !fits_leaf(HEADER_SIZE, &schema_cells) {
+        bail!("The schema of the database doesn't fit its first page.");
+    }
+    pages[0] = leaf_page(HEADER_SIZE, &schema_cells);
+
+    let page_count = u32::try_from(pages.len())?;
+    pages[0][..HEADER_SIZE].copy_from_slice(&database_header(page_count));
+
+    Ok(pages.concat())
+}
+
+fn database_header(page_count: u32) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    // Legacy rather than WAL journaling, for both writing and reading.
+    header[18] = 1;
+    header[19] = 1;
+    // The fractions of the page payloads, which have to be these.
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    // The change counter.
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&page_count.to_be_bytes());
+    // The schema cookie.
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    // The schema format, 4 allows the serial types for 0 and 1.
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    // UTF-8 text.
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // Which change the page count is valid for.
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&SQLITE_VERSION_NUMBER.to_be_bytes());
+    header
+}
+
+// Writes the b-tree of the table, leaves first, and returns the number of its root page.
+fn write_table(pages: &mut Vec<Vec<u8>>, table: &Table) -> Result<u32> {
+    let mut leaves = Vec::new();
+    let mut cells = Vec::new();
+    let mut last_rowid = None;
+
+    for (rowid, values) in &table.rows {
+        if last_rowid.is_some_and(|last_rowid| *rowid <= last_rowid) {
+            bail!(
+                "The rows of the table '{}' aren't ordered by their rowids.",
+                table.name
+            );
+        }
+
+        let cell = leaf_cell(pages, *rowid, &encode_record(values))?;
+        cells.push(cell);
+        if !fits_leaf(0, &cells) {
+            let cell = cells.pop().expect("The cell was just pushed.");
+            leaves.push((push_page(pages, leaf_page(0, &cells))?, last_rowid));
+            cells = vec![cell];
+        }
+        last_rowid = Some(*rowid);
+    }
+    leaves.push((push_page(pages, leaf_page(0, &cells))?, last_rowid));
+
+    // Each level points to the pages of the one below, with the largest rowid of each but the
+    // last as the key, until a single page is left as the root.
+    let mut level = leaves;
+    while level.len() > 1 {
+        let mut parents = Vec::new();
+        let mut children: Vec<(u32, Option<i64>)> = Vec::new();
+        for child in level {
+            children.push(child);
+            if !fits_interior(&children) {
+                let child = children.pop().expect("The child was just pushed.");
+                let last_rowid = children.last().and_then(|(_, rowid)| *rowid);
+                parents.push((push_page(pages, interior_page(&children))?, last_rowid));
+                children = vec![child];
+            }
+        }
+        let last_rowid = children.last().and_then(|(_, rowid)| *rowid);
+        parents.push((push_page(pages, interior_page(&children))?, last_rowid));
+        level = parents;
+    }
+
+    Ok(level[0].0)
+}
+
+fn push_page(pages: &mut Vec<Vec<u8>>, page: Vec<u8>) -> Result<u32> {
+    pages.push(page);
+    Ok(u32::try_from(pages.len())?)
+}
+
+// The cell of a row in a leaf, whose record spills onto overflow pages if it's too large.
+fn leaf_cell(pages: &mut Vec<Vec<u8>>, rowid: i64, record: &[u8]) -> Result<Vec<u8>> {
+    let mut cell = encode_varint(record.len() as u64);
+    cell.extend(encode_varint(rowid as u64));
+
+    if record.len() <= MAX_LOCAL_PAYLOAD {
+        cell.extend(record);
+        return Ok(cell);
+    }
+
+    let spilled = MIN_LOCAL_PAYLOAD + (record.len() - MIN_LOCAL_PAYLOAD) % (PAGE_SIZE - 4);
+    let local_length = if spilled <= MAX_LOCAL_PAYLOAD {
+        spilled
+    } else {
+        MIN_LOCAL_PAYLOAD
+    };
+    cell.extend(&record[..local_length]);
+
+    // Each overflow page starts with the number of the next one, or 0 for the last.
+    let chunks = record[local_length..]
+        .chunks(PAGE_SIZE - 4)
+        .collect::<Vec<_>>();
+    let first_page = u32::try_from(pages.len() + 1)?;
+    for (number, chunk) in (first_page..).zip(&chunks) {
+        let next_page = if number - first_page + 1 < chunks.len() as u32 {
+            number + 1
+        } else {
+            0
+        };
+        let mut page = next_page.to_be_bytes().to_vec();
+        page.extend(*chunk);
+        page.resize(PAGE_SIZE, 0);
+        pages.push(page);
+    }
+    cell.extend(first_page.to_be_bytes());
+
+    Ok(cell)
+}
+
+fn fits_leaf(offset: usize, cells: &[Vec<u8>]) -> bool {
+    let size = cells.iter().map(|cell| cell.len() + 2).sum::<usize>();
+    offset + LEAF_HEADER_SIZE + size <= PAGE_SIZE
+}
+
+fn fits_interior(children: &[(u32, Option<i64>)]) -> bool {
+    // All children but the last get a cell, which is the page number and the key.
+    let size = children[..children.len() - 1]
+        .iter()
+        .map(|(_, rowid)| 4 + encode_varint(rowid.unwrap_or_default() as u64).len() + 2)
+        .sum::<usize>();
+    INTERIOR_HEADER_SIZE + size <= PAGE_SIZE
+}
+
+// Cells are written from the end of the page towards its start, after the pointers to them.
+fn leaf_page(offset: usize, cells: &[Vec<u8>]) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    let mut content_start = PAGE_SIZE;
+    let mut pointer = offset + LEAF_HEADER_SIZE;
+    for cell in cells {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        pointer += 2;
+    }
+
+    page[offset] = LEAF_TABLE_PAGE;
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[offset + 5..offset + 7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    page
+}
+
+fn interior_page(children: &[(u32, Option<i64>)]) -> Vec<u8> {
+    let (right_child, _) = children[children.len() - 1];
+    let cells = children[..children.len() - 1]
+        .iter()
+        .map(|(child, rowid)| {
+            let mut cell = child.to_be_bytes().to_vec();
+            cell.extend(encode_varint(rowid.unwrap_or_default() as u64));
+            cell
+        })
+        .collect::<Vec<_>>();
+
+    let mut page = vec![0; PAGE_SIZE];
+    let mut content_start = PAGE_SIZE;
+    let mut pointer = INTERIOR_HEADER_SIZE;
+    for cell in &cells {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        pointer += 2;
+    }
+
+    page[0] = INTERIOR_TABLE_PAGE;
+    page[3..5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    page[8..12].copy_from_slice(&right_child.to_be_bytes());
+    page
+}
+
+// A record is a header with the type of each value, followed by the values.
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body: Vec<u8> = Vec::new();
+    for value in values {
+        match value {
+            Value::Null => types.extend(encode_varint(0)),
+            Value::Integer(0) => types.extend(encode_varint(8)),
+            Value::Integer(1) => types.extend(encode_varint(9)),
+            Value::Integer(integer) => {
+                let (serial_type, length) = match integer {
+                    -0x80..=0x7F => (1, 1),
+                    -0x8000..=0x7FFF => (2, 2),
+                    -0x80_0000..=0x7F_FFFF => (3, 3),
+                    -0x8000_0000..=0x7FFF_FFFF => (4, 4),
+                    -0x8000_0000_0000..=0x7FFF_FFFF_FFFF => (5, 6),
+                    _ => (6, 8),
+                };
+                types.extend(encode_varint(serial_type));
+                body.extend(&integer.to_be_bytes()[8 - length..]);
+            }
+            Value::Text(text) => {
+                types.extend(encode_varint(text.len() as u64 * 2 + 13));
+                body.extend(text.as_bytes());
+            }
+        }
+    }
+
+    // The length of the header includes itself.
+    let mut header_length = types.len() + 1;
+    while encode_varint(header_length as u64).len() + types.len() != header_length {
+        header_length += 1;
+    }
+
+    let mut record = encode_varint(header_length as u64);
+    record.extend(types);
+    record.extend(body);
+    record
+}
+
+// Big-endian with 7 bits per byte, except for the ninth, which has all 8.
+pub fn encode_varint(value: u64) -> Vec<u8> {
+    if value >> 56 != 0 {
+        let mut bytes = (0..8)
+            .map(|index| (value >> (57 - 7 * index)) as u8 | 0x80)
+            .collect::<Vec<_>>();
+        bytes.push(value as u8);
+        return bytes;
+    }
+
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest != 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_record, encode_varint, Value};
+
+    #[test]
+    fn test_encode_varint() {
+        assert_eq!(encode_varint(0), vec![0]);
+        assert_eq!(encode_varint(0x7F), vec![0x7F]);
+        assert_eq!(encode_varint(0x80), vec![0x81, 0]);
+        assert_eq!(encode_varint(0x3FFF), vec![0xFF, 0x7F]);
+        assert_eq!(encode_varint(u64::MAX), vec![0xFF; 9]);
+    }
+
+    #[test]
+    fn test_encode_record() {
+        assert_eq!(
+            encode_record(&[
+                Value::Null,
+                Value::Integer(1),
+                Value::Integer(-2),
+                Value::Text("ka".to_string()),
+            ]),
+            vec![5, 0, 9, 1, 17, 0xFE, b'k', b'a']
+        );
+    }
+}
@@ -0,0 +1,433 @@
+// Changes to JSON files recorded as operations on their parsed tree, like setting the value at
+// `["dependencies", "serde"]`, instead of the bytes which changed. Config files mostly change
+// a value here and there, which is a few bytes as an operation, but often a lot more as a
+// diff of their bytes once a formatter moved things around. Which files are recorded like
+// this is up to `structured_diff` of the config.
+//
+// A structured change is only recorded if applying it to the content before it and
+// formatting the tree again reproduces the new content byte for byte, e.g. if the file was
+// written by a JSON serializer with one of the common indentations. Everything else is
+// diffed as bytes, as is YAML, which there's no parser for here.
+
+use std::{fmt, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{ser::PrettyFormatter, Number};
+
+use crate::{config::Config, ignore::match_glob};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StructuredChange {
+    // How the content is formatted after the change.
+    pub style: JsonStyle,
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JsonStyle {
+    // What nested values are indented with, everything is on one line without.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indent: Option<String>,
+    #[serde(default)]
+    pub trailing_newline: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    // Replaces the value at the path. A key the object doesn't have yet is added after all
+    // others, and an index just past the end of an array adds the value to it.
+    Set { path: Vec<PathSegment>, value: Node },
+    Remove { path: Vec<PathSegment> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Index(usize),
+    Key(String),
+}
+
+// A JSON value which keeps the keys of objects in the order they were written in, unlike
+// `serde_json::Value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Node>),
+    Object(Vec<(String, Node)>),
+}
+
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Node::Null => serializer.serialize_unit(),
+            Node::Bool(value) => serializer.serialize_bool(*value),
+            Node::Number(value) => value.serialize(serializer),
+            Node::String(value) => serializer.serialize_str(value),
+            Node::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Node::Object(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Node, E> {
+        Ok(Node::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Node, E> {
+        Ok(Node::Null)
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Node, E> {
+        Ok(Node::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Node, E> {
+        Ok(Node::Number(value.into()))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Node, E> {
+        Ok(Node::Number(value.into()))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Node, E> {
+        Number::from_f64(value)
+            .map(Node::Number)
+            .ok_or_else(|| E::custom("JSON numbers are finite"))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Node, E> {
+        Ok(Node::String(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Node, E> {
+        Ok(Node::String(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Node::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(Node::Object(entries))
+    }
+}
+
+impl JsonStyle {
+    // The style the content is formatted in, if it looks like it was written by a serializer.
+    fn detect(content: &[u8]) -> Option<Self> {
+        let trailing_newline = content.ends_with(b"\n");
+        let body = content.strip_suffix(b"\n").unwrap_or(content);
+        let indent = match body.iter().position(|&byte| byte == b'\n') {
+            Some(line_end) => {
+                let indent: Vec<u8> = body[line_end + 1..]
+                    .iter()
+                    .copied()
+                    .take_while(|&byte| byte == b' ' || byte == b'\t')
+                    .collect();
+                if indent.is_empty() {
+                    return None;
+                }
+                Some(String::from_utf8(indent).ok()?)
+            }
+            None => None,
+        };
+
+        Some(JsonStyle {
+            indent,
+            trailing_newline,
+        })
+    }
+
+    fn format(&self, node: &Node) -> Result<Vec<u8>> {
+        let mut content = match self.indent {
+            Some(ref indent) => {
+                let mut content = Vec::new();
+                let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+                let mut serializer =
+                    serde_json::Serializer::with_formatter(&mut content, formatter);
+                node.serialize(&mut serializer)?;
+                content
+            }
+            None => serde_json::to_vec(node)?,
+        };
+        if self.trailing_newline {
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+}
+
+pub fn is_structured(config: &Config, path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    config
+        .structured_diff
+        .iter()
+        .any(|pattern| match_glob(pattern.as_bytes(), name.as_bytes()))
+}
+
+// The operations which turn the old content into the new one, if both are JSON and the new
+// content is formatted in a way which can be reproduced.
+pub fn diff(old: &[u8], new: &[u8]) -> Option<StructuredChange> {
+    let old_node: Node = serde_json::from_slice(old).ok()?;
+    let new_node: Node = serde_json::from_slice(new).ok()?;
+    let style = JsonStyle::detect(new)?;
+
+    let mut operations = Vec::new();
+    diff_nodes(&mut Vec::new(), &old_node, &new_node, &mut operations);
+    let change = StructuredChange { style, operations };
+    match apply(old, &change) {
+        Ok(content) if content == new => Some(change),
+        _ => None,
+    }
+}
+
+// The content after the change, formatted in its style.
+pub fn apply(old: &[u8], change: &StructuredChange) -> Result<Vec<u8>> {
+    let mut node: Node =
+        serde_json::from_slice(old).context("The content before the change isn't JSON.")?;
+    for operation in change.operations.iter() {
+        apply_operation(&mut node, operation)?;
+    }
+    change
+        .style
+        .format(&node)
+        .context("Failed formatting the changed JSON.")
+}
+
+fn diff_nodes(
+    path: &mut Vec<PathSegment>,
+    old: &Node,
+    new: &Node,
+    operations: &mut Vec<Operation>,
+) {
+    if old == new {
+        return;
+    }
+
+    match (old, new) {
+        (Node::Object(old_entries), Node::Object(new_entries))
+            if keeps_key_order(old_entries, new_entries) =>
+        {
+            for (key, _) in old_entries {
+                if !new_entries.iter().any(|(new_key, _)| new_key == key) {
+                    path.push(PathSegment::Key(key.clone()));
+                    operations.push(Operation::Remove { path: path.clone() });
+                    path.pop();
+                }
+            }
+            for (key, new_value) in new_entries {
+                path.push(PathSegment::Key(key.clone()));
+                match old_entries.iter().find(|(old_key, _)| old_key == key) {
+                    Some((_, old_value)) => diff_nodes(path, old_value, new_value, operations),
+                    None => operations.push(Operation::Set {
+                        path: path.clone(),
+                        value: new_value.clone(),
+                    }),
+                }
+                path.pop();
+            }
+        }
+        (Node::Array(old_items), Node::Array(new_items)) => {
+            for (index, (old_item, new_item)) in old_items.iter().zip(new_items).enumerate() {
+                path.push(PathSegment::Index(index));
+                diff_nodes(path, old_item, new_item, operations);
+                path.pop();
+            }
+            for (index, new_item) in new_items.iter().enumerate().skip(old_items.len()) {
+                path.push(PathSegment::Index(index));
+                operations.push(Operation::Set {
+                    path: path.clone(),
+                    value: new_item.clone(),
+                });
+                path.pop();
+            }
+            // From the end, so the indices of the items still to be removed stay the same.
+            for index in (new_items.len()..old_items.len()).rev() {
+                path.push(PathSegment::Index(index));
+                operations.push(Operation::Remove { path: path.clone() });
+                path.pop();
+            }
+        }
+        _ => operations.push(Operation::Set {
+            path: path.clone(),
+            value: new.clone(),
+        }),
+    }
+}
+
+// Whether the keys both objects have are in the same order, with the new keys after them,
+// which is where setting them puts them.
+fn keeps_key_order(old_entries: &[(String, Node)], new_entries: &[(String, Node)]) -> bool {
+    let mut kept_keys = old_entries
+        .iter()
+        .filter(|(key, _)| new_entries.iter().any(|(new_key, _)| new_key == key));
+    let mut has_added = false;
+    for (key, _) in new_entries {
+        if old_entries.iter().any(|(old_key, _)| old_key == key) {
+            match kept_keys.next() {
+                Some((kept_key, _)) if kept_key == key && !has_added => {}
+                _ => return false,
+            }
+        } else {
+            has_added = true;
+        }
+    }
+    true
+}
+
+fn apply_operation(root: &mut Node, operation: &Operation) -> Result<()> {
+    let path = match operation {
+        Operation::Set { path, .. } | Operation::Remove { path } => path,
+    };
+    let (last, parent_path) = match path.split_last() {
+        Some(split) => split,
+        None => match operation {
+            Operation::Set { value, .. } => {
+                *root = value.clone();
+                return Ok(());
+            }
+            Operation::Remove { .. } => bail!("The whole document can't be removed."),
+        },
+    };
+
+    let mut parent = root;
+    for segment in parent_path {
+        parent = match (parent, segment) {
+            (Node::Object(entries), PathSegment::Key(key)) => {
+                match entries.iter_mut().find(|(entry_key, _)| entry_key == key) {
+                    Some((_, value)) => value,
+                    None => bail!("There is no '{}' to change.", key),
+                }
+            }
+            (Node::Array(items), PathSegment::Index(index)) => match items.get_mut(*index) {
+                Some(item) => item,
+                None => bail!("There is no item {} to change.", index),
+            },
+            _ => bail!("The path doesn't match the document."),
+        };
+    }
+
+    match (parent, last, operation) {
+        (Node::Object(entries), PathSegment::Key(key), Operation::Set { value, .. }) => {
+            match entries.iter_mut().find(|(entry_key, _)| entry_key == key) {
+                Some((_, entry_value)) => *entry_value = value.clone(),
+                None => entries.push((key.clone(), value.clone())),
+            }
+        }
+        (Node::Object(entries), PathSegment::Key(key), Operation::Remove { .. }) => {
+            match entries.iter().position(|(entry_key, _)| entry_key == key) {
+                Some(position) => {
+                    entries.remove(position);
+                }
+                None => bail!("There is no '{}' to remove.", key),
+            }
+        }
+        (Node::Array(items), &PathSegment::Index(index), Operation::Set { value, .. }) => {
+            if index < items.len() {
+                items[index] = value.clone();
+            } else if index == items.len() {
+                items.push(value.clone());
+            } else {
+                bail!("There is no item {} to change.", index);
+            }
+        }
+        (Node::Array(items), &PathSegment::Index(index), Operation::Remove { .. }) => {
+            if index >= items.len() {
+                bail!("There is no item {} to remove.", index);
+            }
+            items.remove(index);
+        }
+        _ => bail!("The path doesn't match the document."),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, diff, Operation, PathSegment};
+
+    #[test]
+    fn diff_json_trees() {
+        let old = b"{\n  \"name\": \"ka\",\n  \"features\": [\n    \"archive\"\n  ],\n  \"version\": 1\n}\n";
+        let new = b"{\n  \"name\": \"ka\",\n  \"features\": [\n    \"archive\",\n    \"testing\"\n  ],\n  \"version\": 2,\n  \"zebra\": null\n}\n";
+
+        let change = diff(old, new).unwrap();
+        assert_eq!(change.style.indent.as_deref(), Some("  "));
+        assert!(change.style.trailing_newline);
+        let paths: Vec<_> = change
+            .operations
+            .iter()
+            .map(|operation| match operation {
+                Operation::Set { path, .. } => path.clone(),
+                Operation::Remove { .. } => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                vec![PathSegment::Key("features".into()), PathSegment::Index(1)],
+                vec![PathSegment::Key("version".into())],
+                vec![PathSegment::Key("zebra".into())],
+            ]
+        );
+        assert_eq!(apply(old, &change).unwrap(), new);
+
+        // The keys keep their order, even where it isn't sorted.
+        let old = br#"{"b":1,"a":[1,2,3]}"#;
+        let new = br#"{"b":2,"a":[1]}"#;
+        let change = diff(old, new).unwrap();
+        assert_eq!(change.operations.len(), 3);
+        assert_eq!(apply(old, &change).unwrap(), new);
+
+        // Moving keys around sets the whole object again, formatting which can't be
+        // reproduced is left to byte diffs.
+        let change = diff(br#"{"a":1,"b":2}"#, br#"{"b":2,"a":1}"#).unwrap();
+        assert!(matches!(&change.operations[..], [Operation::Set { path, .. }] if path.is_empty()));
+        assert!(diff(br#"{"a":1}"#, br#"{ "a": 2 }"#).is_none());
+        assert!(diff(b"a: 1\n", b"a: 2\n").is_none());
+    }
+}
@@ -0,0 +1,507 @@
+// Diffs of tables like CSV files by their rows, matched by a key column set up in `tables` of
+// the config, instead of by their bytes. A byte diff of a large table easily hits its
+// deadline and falls back to replacing much more than changed, while matching rows by their
+// key is quick, and keeps an edited cell a change of that cell. `ka diff` shows the changes
+// the same way, row by row, or cell by cell with a finer granularity.
+//
+// Tables whose header changed, or which can't be parsed, are diffed as bytes.
+
+use std::{ops::Range, path::Path};
+
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, DiffOp};
+
+use crate::{
+    config::Config,
+    diff::{ContentChange, SegmentKind, TextGranularity, TextSegment},
+    ignore::match_glob,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TableConfig {
+    // The name of the column which tells rows apart, as it's written in the header.
+    pub key: String,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+// A record of the table, with its line ending. Cells are ranges of the whole content, as
+// they are written, e.g. with their quotes.
+struct Row {
+    range: Range<usize>,
+    cells: Vec<Range<usize>>,
+}
+
+struct Table<'a> {
+    content: &'a [u8],
+    header: Row,
+    rows: Vec<Row>,
+    key_index: usize,
+}
+
+impl<'a> Table<'a> {
+    fn parse(content: &'a [u8], table_config: &TableConfig) -> Option<Self> {
+        if !table_config.delimiter.is_ascii() {
+            return None;
+        }
+        let mut rows = parse_rows(content, table_config.delimiter as u8)?.into_iter();
+        let header = rows.next()?;
+        let key_index = header
+            .cells
+            .iter()
+            .position(|cell| unquote(&content[cell.clone()]) == table_config.key.as_bytes())?;
+
+        Some(Table {
+            content,
+            header,
+            rows: rows.collect(),
+            key_index,
+        })
+    }
+
+    fn get_bytes(&self, range: &Range<usize>) -> &'a [u8] {
+        &self.content[range.clone()]
+    }
+
+    fn get_keys(&self) -> Vec<&'a [u8]> {
+        self.rows
+            .iter()
+            .map(|row| match row.cells.get(self.key_index) {
+                Some(cell) => self.get_bytes(cell),
+                None => &[],
+            })
+            .collect()
+    }
+}
+
+pub fn get_table_config<'a>(config: &'a Config, path: &Path) -> Option<&'a TableConfig> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    config
+        .tables
+        .iter()
+        .find(|(pattern, _)| match_glob(pattern.as_bytes(), name.as_bytes()))
+        .map(|(_, table_config)| table_config)
+}
+
+// Rows which are in both tables are matched by their key, in the order they are in.
+fn align_rows<'a>(
+    config: &Config,
+    path: &Path,
+    old: &'a [u8],
+    new: &'a [u8],
+) -> Option<(Table<'a>, Table<'a>, Vec<DiffOp>)> {
+    let table_config = get_table_config(config, path)?;
+    let old_table = Table::parse(old, table_config)?;
+    let new_table = Table::parse(new, table_config)?;
+    if old_table.get_bytes(&old_table.header.range) != new_table.get_bytes(&new_table.header.range)
+    {
+        return None;
+    }
+
+    let row_ops = similar::capture_diff_slices(
+        Algorithm::Patience,
+        &old_table.get_keys(),
+        &new_table.get_keys(),
+    );
+    Some((old_table, new_table, row_ops))
+}
+
+// The changes which turn the old table into the new one, if the file is a table the config
+// knows of.
+pub fn diff_rows(
+    config: &Config,
+    path: &Path,
+    old: &[u8],
+    new: &[u8],
+) -> Option<Vec<ContentChange>> {
+    let (old_table, new_table, row_ops) = align_rows(config, path, old, new)?;
+
+    // Like in `ContentChange::diff`, positions refer to the content with the changes before
+    // them already applied.
+    let mut at = old_table.header.range.end;
+    let mut changes = Vec::new();
+    let delete_rows = |at: usize, rows: &[Row], changes: &mut Vec<ContentChange>| {
+        if let (Some(first), Some(last)) = (rows.first(), rows.last()) {
+            changes.push(ContentChange::Deleted {
+                at,
+                upto: at + last.range.end - first.range.start,
+                old_content: Some(old[first.range.start..last.range.end].to_vec()),
+            });
+        }
+    };
+    let insert_rows = |at: &mut usize, rows: &[Row], changes: &mut Vec<ContentChange>| {
+        if let (Some(first), Some(last)) = (rows.first(), rows.last()) {
+            changes.push(ContentChange::Inserted {
+                at: *at,
+                new_content: new[first.range.start..last.range.end].to_vec(),
+            });
+            *at += last.range.end - first.range.start;
+        }
+    };
+
+    for row_op in row_ops {
+        match row_op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => {
+                let old_rows = &old_table.rows[old_index..old_index + len];
+                let new_rows = &new_table.rows[new_index..new_index + len];
+                for (old_row, new_row) in old_rows.iter().zip(new_rows) {
+                    let old_row = old_table.get_bytes(&old_row.range);
+                    let new_row = new_table.get_bytes(&new_row.range);
+                    if old_row != new_row {
+                        changes.extend(
+                            ContentChange::diff(old_row, new_row)
+                                .into_iter()
+                                .map(|change| offset_change(change, at)),
+                        );
+                    }
+                    at += new_row.len();
+                }
+            }
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => delete_rows(
+                at,
+                &old_table.rows[old_index..old_index + old_len],
+                &mut changes,
+            ),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => insert_rows(
+                &mut at,
+                &new_table.rows[new_index..new_index + new_len],
+                &mut changes,
+            ),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                delete_rows(
+                    at,
+                    &old_table.rows[old_index..old_index + old_len],
+                    &mut changes,
+                );
+                insert_rows(
+                    &mut at,
+                    &new_table.rows[new_index..new_index + new_len],
+                    &mut changes,
+                );
+            }
+        }
+    }
+    Some(changes)
+}
+
+// Like `diff_text`, but row by row, if the file is a table the config knows of. Rows with the
+// same key are shown as changed, with the cells which changed marked unless the granularity
+// is by line.
+pub fn diff_table(
+    config: &Config,
+    path: &Path,
+    old: &[u8],
+    new: &[u8],
+    granularity: TextGranularity,
+) -> Option<Vec<TextSegment>> {
+    let (old_table, new_table, row_ops) = align_rows(config, path, old, new)?;
+
+    let mut segments = Vec::new();
+    push_segment(
+        &mut segments,
+        SegmentKind::Equal,
+        old_table.get_bytes(&old_table.header.range),
+    );
+    for row_op in row_ops {
+        let (old_index, old_len, new_index, new_len) = match row_op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => (old_index, len, new_index, len),
+            DiffOp::Delete {
+                old_index,
+                old_len,
+                new_index,
+            } => (old_index, old_len, new_index, 0),
+            DiffOp::Insert {
+                old_index,
+                new_index,
+                new_len,
+            } => (old_index, 0, new_index, new_len),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => (old_index, old_len, new_index, new_len),
+        };
+        let old_rows = &old_table.rows[old_index..old_index + old_len];
+        let new_rows = &new_table.rows[new_index..new_index + new_len];
+
+        if let DiffOp::Equal { .. } = row_op {
+            for (old_row, new_row) in old_rows.iter().zip(new_rows) {
+                push_row_segments(
+                    &mut segments,
+                    &old_table,
+                    old_row,
+                    &new_table,
+                    new_row,
+                    granularity,
+                );
+            }
+            continue;
+        }
+        for old_row in old_rows {
+            push_segment(
+                &mut segments,
+                SegmentKind::Deleted,
+                old_table.get_bytes(&old_row.range),
+            );
+        }
+        for new_row in new_rows {
+            push_segment(
+                &mut segments,
+                SegmentKind::Inserted,
+                new_table.get_bytes(&new_row.range),
+            );
+        }
+    }
+    Some(segments)
+}
+
+fn push_row_segments(
+    segments: &mut Vec<TextSegment>,
+    old_table: &Table,
+    old_row: &Row,
+    new_table: &Table,
+    new_row: &Row,
+    granularity: TextGranularity,
+) {
+    let old_bytes = old_table.get_bytes(&old_row.range);
+    let new_bytes = new_table.get_bytes(&new_row.range);
+    if old_bytes == new_bytes {
+        push_segment(segments, SegmentKind::Equal, old_bytes);
+        return;
+    }
+    if granularity == TextGranularity::Line || old_row.cells.len() != new_row.cells.len() {
+        push_segment(segments, SegmentKind::Deleted, old_bytes);
+        push_segment(segments, SegmentKind::Inserted, new_bytes);
+        return;
+    }
+
+    // What's between the cells is the same in both rows, except for the line ending.
+    let mut end = new_row.range.start;
+    for (old_cell, new_cell) in old_row.cells.iter().zip(new_row.cells.iter()) {
+        push_segment(
+            segments,
+            SegmentKind::Equal,
+            new_table.get_bytes(&(end..new_cell.start)),
+        );
+        let old_cell = old_table.get_bytes(old_cell);
+        let new_cell_bytes = new_table.get_bytes(new_cell);
+        if old_cell == new_cell_bytes {
+            push_segment(segments, SegmentKind::Equal, new_cell_bytes);
+        } else {
+            push_segment(segments, SegmentKind::Deleted, old_cell);
+            push_segment(segments, SegmentKind::Inserted, new_cell_bytes);
+        }
+        end = new_cell.end;
+    }
+    push_segment(
+        segments,
+        SegmentKind::Equal,
+        new_table.get_bytes(&(end..new_row.range.end)),
+    );
+}
+
+// Adjacent segments of the same kind are merged, like `diff_text` does.
+fn push_segment(segments: &mut Vec<TextSegment>, kind: SegmentKind, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    let text = String::from_utf8_lossy(bytes);
+    match segments.last_mut() {
+        Some(last) if last.kind == kind => last.text.push_str(&text),
+        _ => segments.push(TextSegment {
+            kind,
+            text: text.into_owned(),
+        }),
+    }
+}
+
+fn offset_change(change: ContentChange, offset: usize) -> ContentChange {
+    match change {
+        ContentChange::Inserted { at, new_content } => ContentChange::Inserted {
+            at: at + offset,
+            new_content,
+        },
+        ContentChange::Deleted {
+            at,
+            upto,
+            old_content,
+        } => ContentChange::Deleted {
+            at: at + offset,
+            upto: upto + offset,
+            old_content,
+        },
+        ContentChange::InsertedObject { at, object } => ContentChange::InsertedObject {
+            at: at + offset,
+            object,
+        },
+    }
+}
+
+// Records end with a line break outside of quotes, and quotes are escaped by doubling them.
+// Returns `None` if a quote is never closed.
+fn parse_rows(content: &[u8], delimiter: u8) -> Option<Vec<Row>> {
+    let mut rows = Vec::new();
+    let mut cells = Vec::new();
+    let mut row_start = 0;
+    let mut cell_start = 0;
+    let mut in_quotes = false;
+
+    let mut index = 0;
+    while index < content.len() {
+        let byte = content[index];
+        if in_quotes {
+            if byte == b'"' {
+                if content.get(index + 1) == Some(&b'"') {
+                    index += 1;
+                } else {
+                    in_quotes = false;
+                }
+            }
+        } else if byte == b'"' {
+            in_quotes = true;
+        } else if byte == delimiter {
+            cells.push(cell_start..index);
+            cell_start = index + 1;
+        } else if byte == b'\n' {
+            let cell_end = if index > cell_start && content[index - 1] == b'\r' {
+                index - 1
+            } else {
+                index
+            };
+            cells.push(cell_start..cell_end);
+            rows.push(Row {
+                range: row_start..index + 1,
+                cells: std::mem::take(&mut cells),
+            });
+            row_start = index + 1;
+            cell_start = index + 1;
+        }
+        index += 1;
+    }
+
+    if in_quotes {
+        return None;
+    }
+    if row_start < content.len() {
+        cells.push(cell_start..content.len());
+        rows.push(Row {
+            range: row_start..content.len(),
+            cells,
+        });
+    }
+    Some(rows)
+}
+
+fn unquote(cell: &[u8]) -> Vec<u8> {
+    match cell
+        .strip_prefix(b"\"")
+        .and_then(|cell| cell.strip_suffix(b"\""))
+    {
+        Some(quoted) => {
+            let mut unquoted = Vec::with_capacity(quoted.len());
+            let mut bytes = quoted.iter().peekable();
+            while let Some(&byte) = bytes.next() {
+                if byte == b'"' && bytes.peek() == Some(&&b'"') {
+                    bytes.next();
+                }
+                unquoted.push(byte);
+            }
+            unquoted
+        }
+        None => cell.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        config::Config,
+        diff::{SegmentKind, TextGranularity, TextSegment},
+    };
+
+    use super::{diff_rows, diff_table, TableConfig};
+
+    fn get_config() -> Config {
+        let mut config = Config::default();
+        config.tables.insert(
+            "*.csv".into(),
+            TableConfig {
+                key: "id".into(),
+                delimiter: ',',
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn diff_rows_by_key() {
+        let config = get_config();
+        let path = Path::new("./prices.csv");
+        let old = b"id,name,price\n1,tea,3\n2,\"cake, large\",5\n3,milk,1\n";
+        let new = b"id,name,price\n2,\"cake, large\",6\n1,tea,3\n4,\"quote \"\"\",2\n";
+
+        let changes = diff_rows(&config, path, old, new).unwrap();
+        let mut content = old.to_vec();
+        for change in changes.iter() {
+            change.apply(&mut content).unwrap();
+        }
+        assert_eq!(content, new);
+
+        // Files the config doesn't know of, or whose header changed, are left to byte diffs.
+        assert!(diff_rows(&config, Path::new("./prices.txt"), old, new).is_none());
+        assert!(diff_rows(&config, path, old, b"id,name\n1,tea\n").is_none());
+        assert!(diff_rows(&config, path, old, b"id,name\n\"1,tea\n").is_none());
+    }
+
+    #[test]
+    fn render_changed_cells() {
+        let config = get_config();
+        let path = Path::new("./prices.csv");
+        let old = b"id,name,price\n1,tea,3\n2,cake,5\n";
+        let new = b"id,name,price\n1,tea,4\n3,milk,1\n";
+        let segment = |kind, text: &str| TextSegment {
+            kind,
+            text: text.into(),
+        };
+
+        assert_eq!(
+            diff_table(&config, path, old, new, TextGranularity::Word).unwrap(),
+            [
+                segment(SegmentKind::Equal, "id,name,price\n1,tea,"),
+                segment(SegmentKind::Deleted, "3"),
+                segment(SegmentKind::Inserted, "4"),
+                segment(SegmentKind::Equal, "\n"),
+                segment(SegmentKind::Deleted, "2,cake,5\n"),
+                segment(SegmentKind::Inserted, "3,milk,1\n"),
+            ]
+        );
+        assert_eq!(
+            diff_table(&config, path, old, new, TextGranularity::Line).unwrap()[1],
+            segment(SegmentKind::Deleted, "1,tea,3\n")
+        );
+    }
+}
@@ -0,0 +1,155 @@
+// Starting points for new repositories, so they don't begin with bare defaults. A template is
+// the config of the repository along with patterns for its `.kaignore`. There are built-in
+// ones for common kinds of directories, and users can add their own, or replace the built-in
+// ones, as JSON files in `~/.config/ka/templates`, e.g. `notes.json`.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    files::Locations,
+    filesystem::Fs,
+    ignore::{get_user_config_path, IGNORE_FILE_NAME},
+    policy::{ContentType, TrackingMode},
+};
+
+pub const BUILT_IN_TEMPLATES: &[&str] = &["notes", "code", "photos"];
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Template {
+    pub config: Config,
+    // Added to the `.kaignore` of the repository.
+    pub ignore: Vec<String>,
+}
+
+impl Template {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        serde_json::from_slice::<Self>(buffer).context("Failed decoding template.")
+    }
+
+    pub fn built_in(name: &str) -> Option<Self> {
+        let template = match name {
+            // Notes are small and worth keeping forever, but editors save them all the time.
+            "notes" => Template {
+                config: Config {
+                    min_change_interval: Some("1m".to_string()),
+                    content_types: BTreeMap::from([
+                        ("*.md".to_string(), ContentType::Text),
+                        ("*.txt".to_string(), ContentType::Text),
+                    ]),
+                    ..Config::default()
+                },
+                ignore: to_patterns(&[".obsidian/workspace*", ".trash/", "*.tmp"]),
+            },
+            // Whatever is built can be built again, and older versions are in Git anyway.
+            "code" => Template {
+                config: Config {
+                    retention: Some("90d".to_string()),
+                    max_file_size: Some(10 * 1024 * 1024),
+                    ..Config::default()
+                },
+                ignore: to_patterns(&[
+                    ".git/",
+                    "target/",
+                    "build/",
+                    "dist/",
+                    "node_modules/",
+                    "__pycache__/",
+                    "*.pyc",
+                    "*.o",
+                ]),
+            },
+            // Photos don't diff well, and keeping every version of them takes lots of space.
+            "photos" => Template {
+                config: Config {
+                    retention: Some("30d".to_string()),
+                    binary: TrackingMode::Snapshot,
+                    perceptual_hashes: true,
+                    ..Config::default()
+                },
+                ignore: to_patterns(&[".thumbnails/", ".picasa.ini", "*.tmp"]),
+            },
+            _ => return None,
+        };
+        Some(template)
+    }
+
+    // The template of the user with the name if there is one, otherwise the built-in one.
+    pub fn load<FS: Fs>(fs: &FS, name: &str, templates_path: Option<&Path>) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            bail!("'{}' isn't the name of a template.", name);
+        }
+
+        let template_path = templates_path
+            .map(|templates_path| templates_path.join(format!("{}.json", name)))
+            .filter(|template_path| fs.path_exists(template_path));
+        if let Some(template_path) = template_path {
+            let mut template_file = fs.open_readable_file(&template_path)?;
+            let buffer = fs
+                .read_from_file(&mut template_file)
+                .with_context(|| format!("Failed reading '{}'.", template_path.display()))?;
+            return Self::decode(&buffer);
+        }
+
+        match Self::built_in(name) {
+            Some(template) => Ok(template),
+            None => bail!(
+                "There is no template '{}', the built-in ones are {}.",
+                name,
+                BUILT_IN_TEMPLATES.join(", ")
+            ),
+        }
+    }
+
+    // Writes the config of the template, and adds its patterns to the `.kaignore` of the
+    // repository, after those which are there already.
+    pub fn apply<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<()> {
+        self.config.write(fs, locations)?;
+
+        if self.ignore.is_empty() {
+            return Ok(());
+        }
+
+        let ignore_path = locations.repository_path.join(IGNORE_FILE_NAME);
+        let mut content = if fs.path_exists(&ignore_path) {
+            let mut ignore_file = fs.open_readable_file(&ignore_path)?;
+            let content = fs
+                .read_from_file(&mut ignore_file)
+                .with_context(|| format!("Failed reading '{}'.", ignore_path.display()))?;
+            String::from_utf8_lossy(&content).into_owned()
+        } else {
+            String::new()
+        };
+
+        let existing_patterns = content.lines().map(str::to_string).collect::<Vec<_>>();
+        for pattern in &self.ignore {
+            if existing_patterns.contains(pattern) {
+                continue;
+            }
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(pattern);
+            content.push('\n');
+        }
+
+        let mut ignore_file = fs.create_file(&ignore_path)?;
+        fs.write_to_file(&mut ignore_file, content.into_bytes())
+    }
+}
+
+// `~/.config/ka/templates`.
+pub fn get_templates_path() -> Option<PathBuf> {
+    Some(get_user_config_path()?.join("templates"))
+}
+
+fn to_patterns(patterns: &[&str]) -> Vec<String> {
+    patterns.iter().map(|pattern| pattern.to_string()).collect()
+}
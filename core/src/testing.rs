@@ -0,0 +1,336 @@
+// Randomized checks of the invariants everything else relies on: applying a diff turns the old
+// content into the new one, and the history reproduces every recorded state. Each check is
+// driven by a seed, so a failing case can be replayed exactly.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    actions::{create, shift, show, update, ActionOptions, ShiftMode, UntrackedFiles},
+    diff::{ContentChange, DiffAlgorithm, DiffOptions},
+    filesystem::{mock::FsMock, Fs},
+};
+
+// A small xorshift generator, which is plenty for test data and keeps runs reproducible.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero would only ever produce zeros.
+        Self(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // A number in `0..upto`, or 0 if `upto` is 0.
+    pub fn below(&mut self, upto: usize) -> usize {
+        match upto {
+            0 => 0,
+            upto => (self.next_u64() % upto as u64) as usize,
+        }
+    }
+
+    // Bytes from a small alphabet, so diffs find plenty to match up.
+    pub fn bytes(&mut self, length: usize) -> Vec<u8> {
+        (0..length).map(|_| b"ab\n"[self.below(3)]).collect()
+    }
+
+    pub fn bytes_below(&mut self, upto: usize) -> Vec<u8> {
+        let length = self.below(upto);
+        self.bytes(length)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Insert {
+        at: usize,
+        content: Vec<u8>,
+    },
+    Delete {
+        at: usize,
+        length: usize,
+    },
+    Replace {
+        at: usize,
+        length: usize,
+        content: Vec<u8>,
+    },
+}
+
+impl Edit {
+    // Positions past the end are clamped, so any edit applies to any content.
+    pub fn apply(&self, content: &mut Vec<u8>) {
+        let clamp = |at: usize, length: usize| {
+            let at = at.min(content.len());
+            (at, (at + length).min(content.len()))
+        };
+        match self {
+            Edit::Insert { at, content: new } => {
+                let (at, _) = clamp(*at, 0);
+                content.splice(at..at, new.iter().copied());
+            }
+            Edit::Delete { at, length } => {
+                let (at, upto) = clamp(*at, *length);
+                content.drain(at..upto);
+            }
+            Edit::Replace {
+                at,
+                length,
+                content: new,
+            } => {
+                let (at, upto) = clamp(*at, *length);
+                content.splice(at..upto, new.iter().copied());
+            }
+        }
+    }
+}
+
+pub fn random_edits(rng: &mut Rng, content_length: usize, count: usize) -> Vec<Edit> {
+    (0..count)
+        .map(|_| {
+            let at = rng.below(content_length + 1);
+            let length = rng.below(8);
+            match rng.below(3) {
+                0 => Edit::Insert {
+                    at,
+                    content: rng.bytes(length + 1),
+                },
+                1 => Edit::Delete { at, length },
+                _ => {
+                    let new_length = rng.below(8);
+                    Edit::Replace {
+                        at,
+                        length,
+                        content: rng.bytes(new_length),
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn apply_edits(content: &[u8], edits: &[Edit]) -> Vec<u8> {
+    let mut content = content.to_vec();
+    for edit in edits {
+        edit.apply(&mut content);
+    }
+    content
+}
+
+// Diffing `old` against `new` with every algorithm has to give changes which turn `old` into
+// `new`, and back again when undone.
+pub fn check_round_trip(old: &[u8], new: &[u8]) -> Result<()> {
+    for algorithm in [
+        DiffAlgorithm::Myers,
+        DiffAlgorithm::Patience,
+        DiffAlgorithm::Lcs,
+    ] {
+        let options = DiffOptions {
+            algorithm,
+            ..DiffOptions::default()
+        };
+        let changes = ContentChange::diff_with(old, new, &options).changes;
+
+        let mut buffer = old.to_vec();
+        for change in changes.iter() {
+            change.apply(&mut buffer)?;
+        }
+        if buffer != new {
+            bail!(
+                "Applying the {:?} diff of {:?} and {:?} gave {:?}.",
+                algorithm,
+                old,
+                new,
+                buffer
+            );
+        }
+
+        for change in changes.iter().rev() {
+            if !change.unapply(&mut buffer) {
+                bail!(
+                    "Undoing the {:?} diff of {:?} and {:?} failed.",
+                    algorithm,
+                    old,
+                    new
+                );
+            }
+        }
+        if buffer != old {
+            bail!(
+                "Undoing the {:?} diff of {:?} and {:?} gave {:?}.",
+                algorithm,
+                old,
+                new,
+                buffer
+            );
+        }
+    }
+    Ok(())
+}
+
+// Records random edits to a few files, shifting around in between, and checks that every
+// recorded state can be shown and shifted to again.
+pub fn check_history_replay(seed: u64, steps: usize) -> Result<()> {
+    let mut rng = Rng::new(seed);
+    let fs = FsMock::new();
+    let paths = ["./one", "./two", "./nested/three"];
+    let now = 0xC0FFEE;
+
+    // The expected content of every file after each change, `None` if it doesn't exist.
+    let mut states: Vec<Vec<Option<Vec<u8>>>> = Vec::new();
+    let mut contents: Vec<Option<Vec<u8>>> =
+        paths.iter().map(|_| Some(rng.bytes_below(32))).collect();
+    write_contents(&fs, &paths, &contents)?;
+    create(ActionOptions::from_path("."), &fs, now)?;
+    states.push(contents.clone());
+
+    for step in 1..=steps {
+        for content in contents.iter_mut() {
+            *content = match (content.take(), rng.below(8)) {
+                (Some(_), 0) => None,
+                (None, 0) => None,
+                (None, _) => Some(rng.bytes_below(32)),
+                (Some(old), _) => {
+                    let count = rng.below(4);
+                    let edits = random_edits(&mut rng, old.len(), count);
+                    Some(apply_edits(&old, &edits))
+                }
+            };
+        }
+        write_contents(&fs, &paths, &contents)?;
+        let summary = update(ActionOptions::from_path("."), &fs, now + step as u64)
+            .with_context(|| format!("Seed {}: update {} failed.", seed, step))?;
+        if !summary.skipped.is_empty() {
+            bail!("The update skipped {:?}.", summary.skipped);
+        }
+        if states.last() != Some(&contents) {
+            states.push(contents.clone());
+        }
+
+        // Shifting somewhere and back again has to leave the latest state in place.
+        let cursor = 1 + rng.below(states.len());
+        shift(
+            ActionOptions::from_path("."),
+            &fs,
+            cursor,
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .with_context(|| format!("Seed {}: shifting to {} failed.", seed, cursor))?;
+        check_contents(&fs, &paths, &states[cursor - 1], seed, cursor)?;
+        shift(
+            ActionOptions::from_path("."),
+            &fs,
+            states.len(),
+            ShiftMode::Safe,
+            UntrackedFiles::Keep,
+        )
+        .with_context(|| format!("Seed {}: shifting back to {} failed.", seed, states.len()))?;
+        check_contents(&fs, &paths, &contents, seed, states.len())?;
+    }
+
+    for (index, state) in states.iter().enumerate() {
+        for (path, content) in paths.iter().zip(state) {
+            let shown = show(
+                ActionOptions::from_path("."),
+                &fs,
+                Path::new(path),
+                index + 1,
+            );
+            match (content, shown) {
+                (Some(content), Ok(shown)) if *content == shown => {}
+                (None, Err(_)) => {}
+                (content, shown) => bail!(
+                    "Seed {}: '{}' at {} should be {:?}, but shows {:?}.",
+                    seed,
+                    path,
+                    index + 1,
+                    content,
+                    shown.ok()
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_contents(fs: &FsMock, paths: &[&str], contents: &[Option<Vec<u8>>]) -> Result<()> {
+    for (path, content) in paths.iter().zip(contents) {
+        let path = Path::new(path);
+        match content {
+            Some(content) => {
+                let mut file = fs.create_file(path)?;
+                fs.write_to_file(&mut file, content.clone())?;
+            }
+            None if fs.path_exists(path) => fs.delete_file(path)?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_contents(
+    fs: &FsMock,
+    paths: &[&str],
+    contents: &[Option<Vec<u8>>],
+    seed: u64,
+    cursor: usize,
+) -> Result<()> {
+    for (path, content) in paths.iter().zip(contents) {
+        let working_content = match fs.path_exists(Path::new(path)) {
+            true => {
+                let mut file = fs.open_readable_file(Path::new(path))?;
+                Some(
+                    fs.read_from_file(&mut file)
+                        .context("Failed reading file.")?,
+                )
+            }
+            false => None,
+        };
+        if working_content != *content {
+            bail!(
+                "Seed {}: after shifting to {}, '{}' is {:?} instead of {:?}.",
+                seed,
+                cursor,
+                path,
+                working_content,
+                content
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_edits, check_history_replay, check_round_trip, random_edits, Rng};
+
+    #[test]
+    fn diff_round_trips() {
+        for seed in 0..500 {
+            let mut rng = Rng::new(seed);
+            let old = rng.bytes_below(64);
+            let count = rng.below(6);
+            let edits = random_edits(&mut rng, old.len(), count);
+            let new = apply_edits(&old, &edits);
+            check_round_trip(&old, &new).unwrap();
+
+            let unrelated = rng.bytes_below(64);
+            check_round_trip(&old, &unrelated).unwrap();
+        }
+    }
+
+    #[test]
+    fn history_replays() {
+        for seed in 0..20 {
+            check_history_replay(seed, 12).unwrap();
+        }
+    }
+}
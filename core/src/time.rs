@@ -0,0 +1,50 @@
+// Calendar dates for the few formats which want them instead of Unix timestamps.
+
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// Converts days since 1970 to the civil date (year, month, day), following
+// http://howardhinnant.github.io/date_algorithms.html.
+pub fn to_civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+// Like `2021-03-04T05:06:08`, in UTC.
+pub fn to_iso_date_time(timestamp: u64) -> String {
+    let seconds = timestamp % SECONDS_PER_DAY;
+    let (year, month, day) = to_civil_date(timestamp / SECONDS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_civil_date, to_iso_date_time};
+
+    #[test]
+    fn convert_dates() {
+        assert_eq!(to_civil_date(0), (1970, 1, 1));
+        assert_eq!(to_civil_date(11016), (2000, 2, 29));
+        assert_eq!(to_iso_date_time(1614834368), "2021-03-04T05:06:08");
+    }
+}
@@ -0,0 +1,125 @@
+// Spans and events describing what the actions are doing, e.g. to find out why an update is
+// slow. They go to whichever subscriber was installed, or nowhere, in which case they cost
+// next to nothing.
+
+use std::{
+    cell::RefCell,
+    fmt::{self, Display},
+    sync::OnceLock,
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level.trim().to_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        f.pad(name)
+    }
+}
+
+pub type Fields<'a> = &'a [(&'static str, &'a dyn Display)];
+
+pub trait Subscriber: Send + Sync {
+    fn enabled(&self, level: Level) -> bool;
+    // `spans` are the names of the spans the event happened in, the outermost first.
+    fn event(&self, level: Level, spans: &[&'static str], message: &str, fields: Fields);
+}
+
+static SUBSCRIBER: OnceLock<&'static dyn Subscriber> = OnceLock::new();
+
+thread_local! {
+    static SPANS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+// Can only be done once per process.
+pub fn set_subscriber(subscriber: &'static dyn Subscriber) -> Result<()> {
+    if SUBSCRIBER.set(subscriber).is_err() {
+        bail!("A trace subscriber is already installed.");
+    }
+    Ok(())
+}
+
+pub fn is_enabled(level: Level) -> bool {
+    SUBSCRIBER
+        .get()
+        .is_some_and(|subscriber| subscriber.enabled(level))
+}
+
+pub fn event(level: Level, message: &str, fields: Fields) {
+    if let Some(subscriber) = SUBSCRIBER.get().filter(|s| s.enabled(level)) {
+        SPANS.with(|spans| subscriber.event(level, &spans.borrow(), message, fields));
+    }
+}
+
+// Everything until the span is dropped happens inside of it. Entering and leaving it are
+// events of their own, the latter with how long it took.
+pub fn span(level: Level, name: &'static str, fields: Fields) -> Span {
+    if !is_enabled(level) {
+        return Span { level, start: None };
+    }
+
+    SPANS.with(|spans| spans.borrow_mut().push(name));
+    event(level, "started", fields);
+    Span {
+        level,
+        start: Some(Instant::now()),
+    }
+}
+
+pub struct Span {
+    level: Level,
+    // Only set if the span was entered.
+    start: Option<Instant>,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let elapsed = format!("{:?}", start.elapsed());
+            event(self.level, "done", &[("elapsed", &elapsed)]);
+            SPANS.with(|spans| spans.borrow_mut().pop());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Level;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(Level::parse("debug"), Some(Level::Debug));
+        assert_eq!(Level::parse(" WARN "), Some(Level::Warn));
+        assert_eq!(Level::parse("loud"), None);
+        assert!(Level::Trace > Level::Info);
+    }
+}
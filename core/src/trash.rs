@@ -0,0 +1,218 @@
+// Where working files go which a shift removes, because they don't exist at the cursor it
+// shifts to. They can always be restored from their history, but a trash is easier to find
+// for anyone just exploring it.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collisions::get_suffixed_path,
+    files::{normalize_path, Locations},
+    filesystem::Fs,
+    trace::{self, Level},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RemovalPolicy {
+    #[default]
+    Delete,
+    // Move them to `.ka/trash`, keeping their path in the repository.
+    Trash,
+    // Move them to the trash of the desktop, as the freedesktop.org specification describes
+    // it. Where there is none, like on macOS and Windows, they go to `.ka/trash` instead.
+    SystemTrash,
+}
+
+pub fn remove_file(
+    fs: &impl Fs,
+    locations: &Locations,
+    policy: RemovalPolicy,
+    working_path: &Path,
+) -> Result<()> {
+    match policy {
+        RemovalPolicy::Delete => fs.delete_file(working_path),
+        RemovalPolicy::Trash => move_to_trash(fs, locations, working_path),
+        RemovalPolicy::SystemTrash => match get_system_trash_path() {
+            Some(trash_path) => move_to_system_trash(fs, &trash_path, working_path),
+            None => {
+                trace::event(
+                    Level::Warn,
+                    "There is no system trash, moved the file to the one of the repository.",
+                    &[("path", &working_path.display())],
+                );
+                move_to_trash(fs, locations, working_path)
+            }
+        },
+    }
+}
+
+// Files removed from the same path again get a suffix, like `notes (2).txt`.
+fn move_to_trash(fs: &impl Fs, locations: &Locations, working_path: &Path) -> Result<()> {
+    let relative_path = working_path.strip_prefix(&locations.repository_path)?;
+    let trash_path = locations.get_trash_path().join(relative_path);
+    let trash_path = if fs.path_exists(&trash_path) {
+        get_suffixed_path(&trash_path, |path| fs.path_exists(path))
+    } else {
+        trash_path
+    };
+    move_file(fs, working_path, &trash_path)
+}
+
+// The info file is written first, which reserves the name in the trash.
+fn move_to_system_trash(fs: &impl Fs, trash_path: &Path, working_path: &Path) -> Result<()> {
+    let files_path = trash_path.join("files");
+    let info_path = trash_path.join("info");
+    let file_name = working_path
+        .file_name()
+        .context("The removed file has no name.")?;
+    let get_info_path = |path: &Path| {
+        let mut info_name = path.file_name().unwrap_or_default().to_os_string();
+        info_name.push(".trashinfo");
+        info_path.join(info_name)
+    };
+    let is_taken = |path: &Path| fs.path_exists(path) || fs.path_exists(&get_info_path(path));
+
+    let mut trashed_path = files_path.join(file_name);
+    if is_taken(&trashed_path) {
+        trashed_path = get_suffixed_path(&trashed_path, is_taken);
+    }
+
+    let original_path = std::env::current_dir()
+        .map(|current_directory| normalize_path(&current_directory.join(working_path)))
+        .unwrap_or_else(|_| working_path.to_path_buf());
+    let mut info_file = fs.create_file(&get_info_path(&trashed_path))?;
+    fs.write_to_file(
+        &mut info_file,
+        encode_trash_info(&original_path, get_now()).into_bytes(),
+    )?;
+    move_file(fs, working_path, &trashed_path)
+}
+
+// The trash in the home directory of the user, if there is one at all.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn get_system_trash_path() -> Option<PathBuf> {
+    use std::env;
+
+    let data_path = match env::var_os("XDG_DATA_HOME") {
+        Some(data_path) if !data_path.is_empty() => PathBuf::from(data_path),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data_path.join("Trash"))
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn get_system_trash_path() -> Option<PathBuf> {
+    None
+}
+
+fn get_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// The specification asks for local time, but UTC is all we know here without a time zone
+// database, and it's only shown to tell removed files apart.
+fn encode_trash_info(original_path: &Path, timestamp: u64) -> String {
+    let mut encoded_path = String::new();
+    for byte in original_path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            encoded_path.push(byte as char);
+        } else {
+            encoded_path.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        encoded_path,
+        crate::time::to_iso_date_time(timestamp)
+    )
+}
+
+// There's no renaming through `Fs`, and the trash might be on another filesystem anyway.
+fn move_file(fs: &impl Fs, from: &Path, to: &Path) -> Result<()> {
+    let mut file = fs.open_readable_file(from)?;
+    let content = fs
+        .read_from_file(&mut file)
+        .with_context(|| format!("Failed reading '{}'.", from.display()))?;
+    let mut moved_file = fs.create_file(to)?;
+    fs.write_to_file(&mut moved_file, content)?;
+    trace::event(
+        Level::Debug,
+        "Moved the file to the trash.",
+        &[("path", &from.display()), ("trash", &to.display())],
+    );
+    fs.delete_file(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::ActionOptions,
+        files::Locations,
+        filesystem::{
+            mock::{read_file, write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{encode_trash_info, move_to_system_trash, remove_file, RemovalPolicy};
+
+    #[test]
+    fn move_files_to_trash() {
+        let fs_mock = FsMock::new();
+        let locations = Locations::from(&ActionOptions::from_path("."));
+
+        for content in [b"first", b"other"] {
+            write_file(&fs_mock, "./notes/todo.txt", content);
+            remove_file(
+                &fs_mock,
+                &locations,
+                RemovalPolicy::Trash,
+                Path::new("./notes/todo.txt"),
+            )
+            .unwrap();
+            assert!(!fs_mock.path_exists(Path::new("./notes/todo.txt")));
+        }
+
+        assert_eq!(read_file(&fs_mock, "./.ka/trash/notes/todo.txt"), b"first");
+        assert_eq!(
+            read_file(&fs_mock, "./.ka/trash/notes/todo (2).txt"),
+            b"other"
+        );
+    }
+
+    #[test]
+    fn move_files_to_system_trash() {
+        let fs_mock = FsMock::new();
+        let trash_path = Path::new("/home/ada/.local/share/Trash");
+
+        for _ in 0..2 {
+            write_file(&fs_mock, "/home/ada/notes/a b.txt", b"content");
+            move_to_system_trash(&fs_mock, trash_path, Path::new("/home/ada/notes/a b.txt"))
+                .unwrap();
+        }
+
+        let info = read_file(
+            &fs_mock,
+            "/home/ada/.local/share/Trash/info/a b (2).txt.trashinfo",
+        );
+        assert!(String::from_utf8(info)
+            .unwrap()
+            .starts_with("[Trash Info]\nPath=/home/ada/notes/a%20b.txt\n"));
+        assert_eq!(
+            read_file(&fs_mock, "/home/ada/.local/share/Trash/files/a b.txt"),
+            b"content"
+        );
+
+        assert_eq!(
+            encode_trash_info(Path::new("/tmp/x"), 1614834368),
+            "[Trash Info]\nPath=/tmp/x\nDeletionDate=2021-03-04T05:06:08\n"
+        );
+    }
+}
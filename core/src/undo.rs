@@ -0,0 +1,154 @@
+// What the working files and cursor were before the last shift, kept in `.ka/undo` so the
+// shift can be undone, even one which overwrote unrecorded changes. Only the files the shift
+// wrote or removed are kept, and only until the next shift.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    files::Locations,
+    filesystem::{Fs, FsEntry},
+    history::RepositoryHistory,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UndoSnapshot {
+    // Where the repository was before the shift.
+    pub cursor: usize,
+    pub file_cursors: BTreeMap<PathBuf, usize>,
+    // Where the shift moved it, and how many changes there were. Once either differs, the
+    // snapshot doesn't fit the repository anymore.
+    pub shifted_to: usize,
+    pub change_count: usize,
+    // The working paths the shift wrote or removed, with the number of the file below
+    // `.ka/undo` their content is kept in, or `None` if they didn't exist as a file.
+    files: Vec<(PathBuf, Option<usize>)>,
+}
+
+impl UndoSnapshot {
+    // Replaces the previous snapshot. Directories in `paths` are kept with all files in them.
+    pub fn take<FS: Fs>(
+        fs: &FS,
+        locations: &Locations,
+        repository_history: &RepositoryHistory,
+        shifted_to: usize,
+        paths: &[PathBuf],
+    ) -> Result<()> {
+        let undo_path = locations.get_undo_path();
+        if fs.path_exists(&undo_path) {
+            fs.delete_directory(&undo_path)?;
+        }
+
+        let mut file_paths = Vec::new();
+        for path in paths {
+            collect_files(fs, path, &mut file_paths)?;
+        }
+
+        let mut files = Vec::new();
+        for (path, exists) in file_paths {
+            if !exists {
+                files.push((path, None));
+                continue;
+            }
+            let mut file = fs.open_readable_file(&path)?;
+            let content = fs
+                .read_from_file(&mut file)
+                .with_context(|| format!("Failed keeping '{}' to undo.", path.display()))?;
+            let number = files.len();
+            let mut kept_file = fs.create_file(&undo_path.join(number.to_string()))?;
+            fs.write_to_file(&mut kept_file, content)?;
+            files.push((path, Some(number)));
+        }
+
+        // Written last, so an incomplete snapshot is never mistaken for one.
+        let snapshot = UndoSnapshot {
+            cursor: repository_history.cursor,
+            file_cursors: repository_history.file_cursors.clone(),
+            shifted_to,
+            change_count: repository_history.get_changes().len(),
+            files,
+        };
+        let encoded = serde_json::to_vec(&snapshot).context("Failed encoding undo snapshot.")?;
+        let mut snapshot_file = fs.create_file(&get_snapshot_path(locations))?;
+        fs.write_to_file(&mut snapshot_file, encoded)
+    }
+
+    pub fn load<FS: Fs>(fs: &FS, locations: &Locations) -> Result<Option<Self>> {
+        let snapshot_path = get_snapshot_path(locations);
+        if !fs.path_exists(&snapshot_path) {
+            return Ok(None);
+        }
+
+        let mut snapshot_file = fs.open_readable_file(&snapshot_path)?;
+        let buffer = fs
+            .read_from_file(&mut snapshot_file)
+            .context("Failed reading undo snapshot.")?;
+        serde_json::from_slice(&buffer)
+            .map(Some)
+            .context("Failed decoding undo snapshot.")
+    }
+
+    // Puts the working files back. Files which the shift created are removed first, as the
+    // ones put back might need their paths.
+    pub fn restore<FS: Fs>(&self, fs: &FS, locations: &Locations) -> Result<()> {
+        for (path, _) in self.files.iter().filter(|(_, number)| number.is_none()) {
+            if fs.path_exists(path) && fs.read_directory(path).is_err() {
+                fs.delete_file(path)?;
+            }
+        }
+
+        let undo_path = locations.get_undo_path();
+        for (path, number) in self.files.iter() {
+            let number = match number {
+                Some(number) => number,
+                None => continue,
+            };
+            let mut kept_file = fs.open_readable_file(&undo_path.join(number.to_string()))?;
+            let content = fs
+                .read_from_file(&mut kept_file)
+                .context("Failed reading undo snapshot.")?;
+            // Directories in the way were created by the shift, for files it restored.
+            if fs.read_directory(path).is_ok() {
+                fs.delete_directory(path)?;
+            }
+            let mut file = fs.create_file(path)?;
+            fs.write_to_file(&mut file, content)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete<FS: Fs>(fs: &FS, locations: &Locations) -> Result<()> {
+        fs.delete_directory(&locations.get_undo_path())
+    }
+}
+
+fn get_snapshot_path(locations: &Locations) -> PathBuf {
+    locations.get_undo_path().join("snapshot")
+}
+
+// The files at or below `path`, and whether they exist at all.
+fn collect_files<FS: Fs>(fs: &FS, path: &Path, files: &mut Vec<(PathBuf, bool)>) -> Result<()> {
+    let entries = match fs.read_directory(path) {
+        Ok(entries) => entries,
+        Err(_) => {
+            files.push((path.to_path_buf(), fs.path_exists(path)));
+            return Ok(());
+        }
+    };
+
+    // Whatever is put back here has to replace the directory.
+    files.push((path.to_path_buf(), false));
+    for entry in entries {
+        if entry.is_directory()? {
+            collect_files(fs, &entry.path(), files)?;
+        } else {
+            files.push((entry.path(), true));
+        }
+    }
+    Ok(())
+}
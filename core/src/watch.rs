@@ -0,0 +1,442 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{status, update, update_paths, ActionOptions, FileStatus, UpdateSummary},
+    cache::StatCache,
+    crypto,
+    files::Locations,
+    filesystem::Fs,
+    ignore::{match_glob, IgnoreRules},
+};
+
+// Which saves the watcher records, from the `watch` of the config and the flags of the watcher.
+// Saves of other files stay unrecorded until something else records them. For both kinds of
+// patterns, those starting with `!` exclude, and the others include. Whatever matches no
+// exclusion is included if it matches an inclusion, or if there are none.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct WatchFilters {
+    // Patterns like those of `.kaignore`, e.g. `*.md` or `!drafts/`.
+    pub paths: Vec<String>,
+    // Patterns for the names of the processes which have the saved file open, e.g. `!postgres`
+    // to leave out the files a database keeps writing to. Only Linux tells which processes
+    // those are, and only while they still have it open, see `includes_openers`.
+    pub processes: Vec<String>,
+}
+
+impl WatchFilters {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.processes.is_empty()
+    }
+
+    // Whether saves of the working file are recorded, going by its path.
+    pub fn includes_path(&self, locations: &Locations, working_path: &Path) -> Result<bool> {
+        if self.paths.is_empty() {
+            return Ok(true);
+        }
+
+        // Matching like ignore rules means excluding a directory excludes its files.
+        let mut inclusions = IgnoreRules::default();
+        let mut exclusions = IgnoreRules::default();
+        for pattern in &self.paths {
+            match pattern.strip_prefix('!') {
+                Some(pattern) => exclusions.add_patterns(pattern),
+                None => inclusions.add_patterns(pattern),
+            }
+        }
+
+        let relative_path = working_path.strip_prefix(&locations.repository_path)?;
+        let is_included = self.paths.iter().all(|pattern| pattern.starts_with('!'))
+            || inclusions.is_ignored(relative_path, false);
+        Ok(is_included && !exclusions.is_ignored(relative_path, false))
+    }
+
+    // Whether saves of a file which the processes have open are recorded. Files nobody has
+    // open are, as there's nothing to tell their saves apart by. That's the case for editors
+    // which close files right after saving them, for deleted files, and for all files where
+    // the processes can't be told.
+    pub fn includes_openers(&self, processes: &[String]) -> bool {
+        self.processes.is_empty()
+            || processes.is_empty()
+            || processes
+                .iter()
+                .any(|process| self.includes_process(process))
+    }
+
+    pub fn includes_process(&self, name: &str) -> bool {
+        let matches = |pattern: &str| match_glob(pattern.as_bytes(), name.as_bytes());
+        let (exclusions, inclusions): (Vec<_>, Vec<_>) = self
+            .processes
+            .iter()
+            .partition(|pattern| pattern.starts_with('!'));
+
+        (inclusions.is_empty() || inclusions.iter().any(|pattern| matches(pattern)))
+            && !exclusions.iter().any(|pattern| matches(&pattern[1..]))
+    }
+}
+
+// The names of the processes which have each of the working files open, from their file
+// descriptors in `/proc`, which is gone through once for all of them. Files nobody has open are
+// missing, and so are processes of other users, which can't be looked into.
+#[cfg(target_os = "linux")]
+fn get_opening_processes(working_paths: &[&Path]) -> HashMap<PathBuf, Vec<String>> {
+    use std::fs;
+
+    let mut openers = HashMap::new();
+    let canonical_paths = working_paths
+        .iter()
+        .filter_map(|working_path| Some((fs::canonicalize(working_path).ok()?, *working_path)))
+        .collect::<HashMap<_, _>>();
+    if canonical_paths.is_empty() {
+        return openers;
+    }
+    let processes = match fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(_) => return openers,
+    };
+
+    for process in processes.flatten() {
+        let process_path = process.path();
+        let descriptors = match fs::read_dir(process_path.join("fd")) {
+            Ok(descriptors) => descriptors,
+            Err(_) => continue,
+        };
+        let opened_paths = descriptors
+            .flatten()
+            .filter_map(|descriptor| canonical_paths.get(&fs::read_link(descriptor.path()).ok()?))
+            .collect::<HashSet<_>>();
+        if opened_paths.is_empty() {
+            continue;
+        }
+        let name = match fs::read_to_string(process_path.join("comm")) {
+            Ok(name) => name.trim_end().to_string(),
+            Err(_) => continue,
+        };
+        for working_path in opened_paths {
+            openers
+                .entry(working_path.to_path_buf())
+                .or_insert_with(Vec::new)
+                .push(name.clone());
+        }
+    }
+    openers
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_opening_processes(_working_paths: &[&Path]) -> HashMap<PathBuf, Vec<String>> {
+    HashMap::new()
+}
+
+// What a watcher has seen so far. It's stored in `.ka/watch-state` after every change,
+// so a restarted watcher continues the quiet period instead of starting over.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct WatchState {
+    // The working files with unrecorded changes, by a hash of their content. Deleted
+    // files have an empty hash.
+    pub pending: BTreeMap<PathBuf, String>,
+    // When the pending changes were last seen changing.
+    pub last_change: Option<u64>,
+    // Whether the pending files were found by this watcher, rather than loaded.
+    #[serde(skip)]
+    is_polled: bool,
+}
+
+impl WatchState {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).context("Failed encoding watch state.")
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        serde_json::from_slice::<Self>(buffer).context("Failed decoding watch state.")
+    }
+
+    pub fn load<FS: Fs>(fs: &FS, options: &ActionOptions) -> Result<Self> {
+        let state_path = Locations::from(options).get_watch_state_path();
+        if !fs.path_exists(&state_path) {
+            return Ok(Self::default());
+        }
+
+        let mut state_file = fs.open_readable_file(&state_path)?;
+        let buffer = fs
+            .read_from_file(&mut state_file)
+            .context("Failed reading watch state.")?;
+
+        // The state is only a hint, losing it just means waiting for another quiet period.
+        Ok(Self::decode(&buffer).unwrap_or_default())
+    }
+
+    pub fn write<FS: Fs>(&self, fs: &FS, options: &ActionOptions) -> Result<()> {
+        let state_path = Locations::from(options).get_watch_state_path();
+        let mut state_file = fs.create_file(&state_path)?;
+        fs.write_to_file(&mut state_file, self.encode()?)
+    }
+
+    // Looks at the working files once, and records them once nothing changed for
+    // `quiet_seconds`, so a burst of saves ends up as a single change. Only files the filters
+    // include are looked at and recorded.
+    pub fn poll<FS: Fs>(
+        &mut self,
+        fs: &FS,
+        options: &ActionOptions,
+        now: u64,
+        quiet_seconds: u64,
+        filters: &WatchFilters,
+    ) -> Result<Option<UpdateSummary>> {
+        // The stat cache kept by the previous poll's status tells whether any working file
+        // changed since, sparing a full status every poll. Which processes have the files open
+        // changes without them changing though.
+        let pending =
+            if self.is_polled && filters.processes.is_empty() && is_status_current(fs, options) {
+                self.pending.clone()
+            } else {
+                get_pending_files(fs, options, filters)?
+            };
+        self.is_polled = true;
+
+        if pending.is_empty() {
+            if !self.pending.is_empty() {
+                *self = Self::default();
+                self.write(fs, options)?;
+            }
+            return Ok(None);
+        }
+
+        if pending != self.pending {
+            self.pending = pending;
+            self.last_change = Some(now);
+            self.write(fs, options)?;
+            return Ok(None);
+        }
+
+        let last_change = self.last_change.unwrap_or(now);
+        if now.saturating_sub(last_change) < quiet_seconds {
+            return Ok(None);
+        }
+
+        let summary = if filters.is_empty() {
+            update(options.clone(), fs, now)?
+        } else {
+            let working_paths = self.pending.keys().cloned().collect::<HashSet<_>>();
+            update_paths(options.clone(), fs, now, &working_paths)?
+        };
+
+        // Files recorded too recently for their minimum change interval stay pending, and are
+        // tried again after another quiet period.
+        let pending_count = self.pending.len();
+        self.pending
+            .retain(|path, _| summary.deferred.contains(path));
+        self.last_change = Some(now).filter(|_| !self.pending.is_empty());
+        self.write(fs, options)?;
+        if self.pending.len() == pending_count {
+            return Ok(None);
+        }
+        Ok(Some(summary))
+    }
+}
+
+// Where a running watcher listens for commands.
+pub fn get_socket_path(options: &ActionOptions) -> PathBuf {
+    Locations::from(options).get_watch_socket_path()
+}
+
+fn is_status_current<FS: Fs>(fs: &FS, options: &ActionOptions) -> bool {
+    let locations = Locations::from(options);
+    let index = fs
+        .open_readable_file(&locations.get_repository_index_path())
+        .and_then(|mut index_file| fs.read_from_file(&mut index_file));
+    match index {
+        Ok(buffer) => StatCache::new(fs, &locations)
+            .get_dirty_count(buffer.len())
+            .is_some(),
+        Err(_) => false,
+    }
+}
+
+fn get_pending_files<FS: Fs>(
+    fs: &FS,
+    options: &ActionOptions,
+    filters: &WatchFilters,
+) -> Result<BTreeMap<PathBuf, String>> {
+    let locations = Locations::from(options);
+    let status = status(options.clone(), fs)?;
+
+    let mut changed_files = Vec::new();
+    for (path, file_status) in status.files {
+        if matches!(file_status, FileStatus::Skipped(_))
+            || !filters.includes_path(&locations, &path)?
+        {
+            continue;
+        }
+        changed_files.push((path, file_status));
+    }
+    let openers = if filters.processes.is_empty() {
+        HashMap::new()
+    } else {
+        let paths = changed_files
+            .iter()
+            .map(|(path, _)| path.as_path())
+            .collect::<Vec<_>>();
+        get_opening_processes(&paths)
+    };
+
+    let mut pending = BTreeMap::new();
+    for (path, file_status) in changed_files {
+        let processes = openers.get(&path).map_or(&[][..], Vec::as_slice);
+        if !filters.includes_openers(processes) {
+            continue;
+        }
+        let hash = match file_status {
+            FileStatus::Deleted => String::new(),
+            _ => {
+                let mut working_file = fs.open_readable_file(&path)?;
+                let content = fs.read_from_file(&mut working_file)?;
+                crypto::to_hex(&crypto::sha256(&content))
+            }
+        };
+        pending.insert(path, hash);
+    }
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        actions::{create, status, ActionOptions},
+        filesystem::{
+            mock::{write_file, FsMock},
+            Fs,
+        },
+    };
+
+    use super::{WatchFilters, WatchState};
+
+    #[test]
+    fn coalesce_saves_into_one_change() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let filters = WatchFilters::default();
+
+        write_file(&fs_mock, "./test", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+
+        let mut state = WatchState::load(&fs_mock, &options).unwrap();
+        write_file(&fs_mock, "./test", b"first second");
+        assert!(state
+            .poll(&fs_mock, &options, now + 1, 5, &filters)
+            .unwrap()
+            .is_none());
+        write_file(&fs_mock, "./test", b"first second third");
+        assert!(state
+            .poll(&fs_mock, &options, now + 3, 5, &filters)
+            .unwrap()
+            .is_none());
+
+        // A restarted watcher picks up where the previous one stopped.
+        let mut state = WatchState::load(&fs_mock, &options).unwrap();
+        assert_eq!(state.last_change, Some(now + 3));
+        assert!(state
+            .poll(&fs_mock, &options, now + 7, 5, &filters)
+            .unwrap()
+            .is_none());
+        let summary = state
+            .poll(&fs_mock, &options, now + 8, 5, &filters)
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.recorded, [Path::new("./test")]);
+
+        let status = status(options.clone(), &fs_mock).unwrap();
+        assert_eq!(status.cursor, 2);
+        assert!(status.files.is_empty());
+        assert_eq!(
+            WatchState::load(&fs_mock, &options).unwrap(),
+            WatchState::default()
+        );
+    }
+
+    #[test]
+    fn record_only_filtered_saves() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+        let options = ActionOptions::from_path(".");
+        let filters = WatchFilters {
+            paths: vec!["*.md".to_string(), "!drafts/".to_string()],
+            processes: vec![],
+        };
+
+        fs_mock.create_directory(Path::new("./drafts")).unwrap();
+        write_file(&fs_mock, "./notes.md", b"first");
+        write_file(&fs_mock, "./drafts/idea.md", b"first");
+        write_file(&fs_mock, "./build.log", b"first");
+        create(options.clone(), &fs_mock, now).unwrap();
+
+        write_file(&fs_mock, "./drafts/idea.md", b"second");
+        write_file(&fs_mock, "./build.log", b"second");
+        let mut state = WatchState::load(&fs_mock, &options).unwrap();
+        assert!(state
+            .poll(&fs_mock, &options, now + 1, 5, &filters)
+            .unwrap()
+            .is_none());
+        assert!(state.pending.is_empty());
+
+        write_file(&fs_mock, "./notes.md", b"second");
+        assert!(state
+            .poll(&fs_mock, &options, now + 2, 5, &filters)
+            .unwrap()
+            .is_none());
+        assert_eq!(state.pending.len(), 1);
+        assert!(state
+            .poll(&fs_mock, &options, now + 7, 5, &filters)
+            .unwrap()
+            .is_some());
+
+        // The excluded files are still waiting for something else to record them.
+        let status = status(options.clone(), &fs_mock).unwrap();
+        assert_eq!(status.cursor, 2);
+        assert_eq!(status.files.len(), 2);
+        assert!(status
+            .files
+            .iter()
+            .all(|(path, _)| path != Path::new("./notes.md")));
+
+        let filters = WatchFilters {
+            paths: vec![],
+            processes: vec!["!postgres*".to_string()],
+        };
+        assert!(filters.includes_process("vim"));
+        assert!(!filters.includes_process("postgres"));
+        let filters = WatchFilters {
+            paths: vec![],
+            processes: vec!["code".to_string(), "vim".to_string()],
+        };
+        assert!(filters.includes_process("vim"));
+        assert!(!filters.includes_process("postgres"));
+    }
+
+    #[test]
+    fn include_files_nobody_has_open() {
+        let filters = WatchFilters {
+            paths: vec![],
+            processes: vec!["vim".to_string()],
+        };
+        assert!(filters.includes_openers(&[]));
+        assert!(filters.includes_openers(&["vim".to_string(), "postgres".to_string()]));
+        assert!(!filters.includes_openers(&["postgres".to_string()]));
+
+        let filters = WatchFilters {
+            paths: vec![],
+            processes: vec!["!postgres".to_string()],
+        };
+        assert!(filters.includes_openers(&[]));
+        assert!(!filters.includes_openers(&["postgres".to_string()]));
+        assert!(WatchFilters::default().includes_openers(&["postgres".to_string()]));
+    }
+}
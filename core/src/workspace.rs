@@ -0,0 +1,150 @@
+use std::{
+    path::{Path, PathBuf},
+    thread,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{apply_retention, status, update, ActionOptions, Status, UpdateSummary},
+    filesystem::Fs,
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Workspace {
+    // Relative roots are resolved against the directory of the workspace file.
+    pub roots: Vec<PathBuf>,
+}
+
+impl Workspace {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self).context("Failed encoding workspace.")
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        serde_json::from_slice::<Self>(buffer).context("Failed decoding workspace.")
+    }
+
+    pub fn load<FS: Fs>(fs: &FS, workspace_path: &Path) -> Result<Self> {
+        let mut workspace_file = fs.open_readable_file(workspace_path)?;
+        let buffer = fs
+            .read_from_file(&mut workspace_file)
+            .context("Failed reading workspace.")?;
+
+        let mut workspace = Self::decode(&buffer)?;
+
+        let base_path = workspace_path.parent().unwrap_or_else(|| Path::new(""));
+        for root in workspace.roots.iter_mut() {
+            if root.is_relative() {
+                *root = base_path.join(&root);
+            }
+        }
+
+        Ok(workspace)
+    }
+
+    pub fn write<FS: Fs>(&self, fs: &FS, workspace_path: &Path) -> Result<()> {
+        let mut workspace_file = fs.create_file(workspace_path)?;
+        fs.write_to_file(&mut workspace_file, self.encode()?)
+    }
+
+    pub fn update<FS: Fs + Sync>(
+        &self,
+        fs: &FS,
+        timestamp: u64,
+    ) -> Vec<(PathBuf, Result<UpdateSummary>)> {
+        self.for_each_repository(|options| {
+            let summary = update(options.clone(), fs, timestamp)?;
+            apply_retention(options, fs, timestamp)?;
+            Ok(summary)
+        })
+    }
+
+    pub fn status<FS: Fs + Sync>(&self, fs: &FS) -> Vec<(PathBuf, Result<Status>)> {
+        self.for_each_repository(|options| status(options, fs))
+    }
+
+    // Repositories are independent of each other, so they can all be handled at once.
+    // A failure in one repository doesn't stop the others.
+    fn for_each_repository<T, F>(&self, action: F) -> Vec<(PathBuf, Result<T>)>
+    where
+        T: Send,
+        F: Fn(ActionOptions) -> Result<T> + Sync,
+    {
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .roots
+                .iter()
+                .map(|root| {
+                    let action = &action;
+                    scope.spawn(move || {
+                        action(ActionOptions::new(root.clone()))
+                            .with_context(|| format!("Failed in repository '{}'.", root.display()))
+                    })
+                })
+                .collect();
+
+            self.roots
+                .iter()
+                .cloned()
+                .zip(handles.into_iter().map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("Repository action panicked.")))
+                }))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{
+        actions::{create, ActionOptions, FileStatus},
+        filesystem::mock::{write_file, FsMock},
+    };
+
+    use super::Workspace;
+
+    #[test]
+    fn update_and_status_all_repositories() {
+        let now = 0xC0FFEE;
+        let fs_mock = FsMock::new();
+
+        write_file(&fs_mock, "./notes/todo", b"one");
+        write_file(&fs_mock, "./papers/draft", b"one");
+        create(ActionOptions::from_path("./notes"), &fs_mock, now).unwrap();
+        create(ActionOptions::from_path("./papers"), &fs_mock, now).unwrap();
+
+        Workspace {
+            roots: vec!["notes".into(), "papers".into(), "missing".into()],
+        }
+        .write(&fs_mock, Path::new("./workspace"))
+        .unwrap();
+        let workspace = Workspace::load(&fs_mock, Path::new("./workspace")).unwrap();
+        assert_eq!(workspace.roots[0], PathBuf::from("./notes"));
+
+        write_file(&fs_mock, "./notes/todo", b"one two");
+
+        let statuses = workspace.status(&fs_mock);
+        assert_eq!(
+            statuses[0].1.as_ref().unwrap().files,
+            vec![(PathBuf::from("./notes/todo"), FileStatus::Modified)]
+        );
+        assert!(statuses[1].1.as_ref().unwrap().files.is_empty());
+        assert!(statuses[2].1.is_err());
+
+        let results = workspace.update(&fs_mock, now + 1);
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_ok());
+        assert!(results[2].1.is_err());
+
+        let statuses = workspace.status(&fs_mock);
+        assert_eq!(statuses[0].1.as_ref().unwrap().cursor, 2);
+        assert_eq!(statuses[1].1.as_ref().unwrap().cursor, 1);
+    }
+}
@@ -0,0 +1,69 @@
+#ifndef KA_H
+#define KA_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ * Functions returning int return 0 on success and -1 on failure, functions returning a
+ * pointer return NULL on failure. `ka_last_error` describes the last failure on the
+ * calling thread.
+ *
+ * All string arguments must be NUL-terminated UTF-8 and valid for the duration of the call.
+ * A repository must not be used from two threads at the same time.
+ */
+
+typedef struct KaRepository KaRepository;
+
+/* Opens the repository at `path`, free it with `ka_close`. */
+KaRepository *ka_open(const char *path);
+
+/* `repository` may be NULL, and must not be used afterwards. */
+void ka_close(KaRepository *repository);
+
+/* Records the changes to the working files, with `timestamp` in seconds since the epoch. */
+int ka_update(const KaRepository *repository, uint64_t timestamp);
+
+/* Restores the working files to `cursor`. Without `force`, fails if there are unrecorded changes. */
+int ka_shift(const KaRepository *repository, size_t cursor, bool force);
+
+/*
+ * Gets the content of the file at `path`, relative to the repository, at `cursor`. On
+ * success `*content` and `*length` are set, free the content with `ka_free_content`.
+ */
+int ka_get_content(const KaRepository *repository,
+                   const char *path,
+                   size_t cursor,
+                   uint8_t **content,
+                   size_t *length);
+
+/* `content` and `length` must be exactly as returned by `ka_get_content`. */
+void ka_free_content(uint8_t *content, size_t length);
+
+/*
+ * Gets the status as JSON, `{"cursor": 2, "files": [{"path": "...", "status": "modified"}]}`.
+ * The status is one of "added", "modified", "deleted" or "skipped". Free it with
+ * `ka_free_string`.
+ */
+char *ka_status(const KaRepository *repository);
+
+/*
+ * Gets the changes which turn `text`, like the buffer of an editor, into the content of the
+ * file at `path` at `cursor`, as JSON, `[{"at": 0, "upto": 2}, {"at": 0, "text": "..."}]`.
+ * Changes with "upto" delete up to that position, the others insert "text". Positions
+ * count characters, and the changes apply in order. Fails if either is not valid UTF-8.
+ * Free it with `ka_free_string`.
+ */
+char *ka_get_text_changes(const KaRepository *repository,
+                          const char *path,
+                          size_t cursor,
+                          const char *text);
+
+/* `string` must have been returned by ka, or be NULL. */
+void ka_free_string(char *string);
+
+/* Valid until the next call on the same thread, NULL if nothing failed yet. */
+const char *ka_last_error(void);
+
+#endif /* KA_H */
@@ -0,0 +1,321 @@
+// A C interface to ka, so editor plugins can embed it instead of running the CLI. All
+// functions report failure through their return value, and `ka_last_error` describes the
+// last failure on the calling thread. The safety requirements of every function are
+// documented in `include/ka.h`.
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    path::Path,
+    ptr,
+};
+
+use anyhow::{bail, Context, Result};
+use ka::{
+    actions::{self, ActionOptions, FileStatus, ShiftMode, UntrackedFiles},
+    diff::TextChange,
+    filesystem::FsImpl,
+};
+use serde_json::json;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+pub struct KaRepository {
+    options: ActionOptions,
+    fs: FsImpl,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ka_open(path: *const c_char) -> *mut KaRepository {
+    let repository = to_str(path).and_then(|path| {
+        if !Path::new(path).join(".ka").is_dir() {
+            bail!("'{}' is not a ka repository.", path);
+        }
+        Ok(KaRepository {
+            options: ActionOptions::from_path(path),
+            fs: FsImpl {},
+        })
+    });
+
+    match repository {
+        Ok(repository) => Box::into_raw(Box::new(repository)),
+        Err(error) => {
+            set_last_error(error);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ka_close(repository: *mut KaRepository) {
+    if !repository.is_null() {
+        drop(Box::from_raw(repository));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ka_update(repository: *const KaRepository, timestamp: u64) -> c_int {
+    to_status(get_repository(repository).and_then(|repository| {
+        actions::update(repository.options.clone(), &repository.fs, timestamp)?;
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ka_shift(
+    repository: *const KaRepository,
+    cursor: usize,
+    force: bool,
+) -> c_int {
+    let mode = if force {
+        ShiftMode::Force
+    } else {
+        ShiftMode::Safe
+    };
+
+    to_status(get_repository(repository).and_then(|repository| {
+        actions::shift(
+            repository.options.clone(),
+            &repository.fs,
+            cursor,
+            mode,
+            UntrackedFiles::Keep,
+        )?;
+        Ok(())
+    }))
+}
+
+// The content is owned by the caller afterwards, and has to be freed with `ka_free_content`.
+#[no_mangle]
+pub unsafe extern "C" fn ka_get_content(
+    repository: *const KaRepository,
+    path: *const c_char,
+    cursor: usize,
+    content: *mut *mut u8,
+    length: *mut usize,
+) -> c_int {
+    to_status((|| {
+        if content.is_null() || length.is_null() {
+            bail!("The content and length pointers must not be NULL.");
+        }
+
+        let repository = get_repository(repository)?;
+        let path = to_str(path)?;
+        let buffer = actions::show(
+            repository.options.clone(),
+            &repository.fs,
+            Path::new(path),
+            cursor,
+        )?;
+
+        let buffer = buffer.into_boxed_slice();
+        *length = buffer.len();
+        *content = Box::into_raw(buffer) as *mut u8;
+        Ok(())
+    })())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ka_free_content(content: *mut u8, length: usize) {
+    if !content.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            content, length,
+        )));
+    }
+}
+
+// Returns the status as JSON, `{"cursor": 2, "files": [{"path": "...", "status": "modified"}]}`,
+// or NULL on failure. The string has to be freed with `ka_free_string`.
+#[no_mangle]
+pub unsafe extern "C" fn ka_status(repository: *const KaRepository) -> *mut c_char {
+    let status = get_repository(repository).and_then(|repository| {
+        let status = actions::status(repository.options.clone(), &repository.fs)?;
+
+        let files = status
+            .files
+            .iter()
+            .map(|(path, file_status)| {
+                let file_status = match file_status {
+                    FileStatus::Added => "added",
+                    FileStatus::Modified => "modified",
+                    FileStatus::Deleted => "deleted",
+                    FileStatus::Skipped(_) => "skipped",
+                };
+                json!({ "path": path, "status": file_status })
+            })
+            .collect::<Vec<_>>();
+
+        let encoded = json!({ "cursor": status.cursor, "files": files }).to_string();
+        CString::new(encoded).context("Failed encoding status.")
+    });
+
+    match status {
+        Ok(status) => status.into_raw(),
+        Err(error) => {
+            set_last_error(error);
+            ptr::null_mut()
+        }
+    }
+}
+
+// Returns the changes which turn `text`, like the buffer of an editor, into the content of the
+// file at `cursor` as JSON, `[{"at": 0, "upto": 2}, {"at": 0, "text": "..."}]`, or NULL on
+// failure. Positions count characters. The string has to be freed with `ka_free_string`.
+#[no_mangle]
+pub unsafe extern "C" fn ka_get_text_changes(
+    repository: *const KaRepository,
+    path: *const c_char,
+    cursor: usize,
+    text: *const c_char,
+) -> *mut c_char {
+    let changes = (|| {
+        let repository = get_repository(repository)?;
+        let path = to_str(path)?;
+        if text.is_null() {
+            bail!("The text must not be NULL.");
+        }
+        let text = CStr::from_ptr(text)
+            .to_str()
+            .context("The text is not valid UTF-8.")?;
+
+        let content = actions::show(
+            repository.options.clone(),
+            &repository.fs,
+            Path::new(path),
+            cursor,
+        )?;
+        let content = String::from_utf8(content)
+            .with_context(|| format!("The content of '{}' is not valid UTF-8.", path))?;
+
+        let changes = TextChange::diff(text, &content)
+            .into_iter()
+            .map(|change| match change {
+                TextChange::Inserted { at, text } => json!({ "at": at, "text": text }),
+                TextChange::Deleted { at, upto } => json!({ "at": at, "upto": upto }),
+            })
+            .collect::<Vec<_>>();
+        CString::new(json!(changes).to_string()).context("Failed encoding text changes.")
+    })();
+
+    match changes {
+        Ok(changes) => changes.into_raw(),
+        Err(error) => {
+            set_last_error(error);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ka_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+// The message stays valid until the next call on the same thread. NULL if nothing failed yet.
+#[no_mangle]
+pub extern "C" fn ka_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+unsafe fn get_repository<'a>(repository: *const KaRepository) -> Result<&'a KaRepository> {
+    match repository.as_ref() {
+        Some(repository) => Ok(repository),
+        None => bail!("The repository must not be NULL."),
+    }
+}
+
+unsafe fn to_str<'a>(string: *const c_char) -> Result<&'a str> {
+    if string.is_null() {
+        bail!("The path must not be NULL.");
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .context("The path is not valid UTF-8.")
+}
+
+fn to_status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            set_last_error(error);
+            -1
+        }
+    }
+}
+
+fn set_last_error(error: anyhow::Error) {
+    // Interior NUL bytes can't be represented, so they are dropped from the message.
+    let message = format!("{:#}", error).replace('\0', "");
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        ffi::{CStr, CString},
+        fs, ptr, slice,
+    };
+
+    use ka::{actions, filesystem::FsImpl};
+
+    use super::*;
+
+    #[test]
+    fn update_and_read_through_c_interface() {
+        let directory = env::temp_dir().join(format!("ka-ffi-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("notes"), b"first").unwrap();
+        let options = actions::ActionOptions::new(directory.clone());
+        actions::create(options, &FsImpl {}, 1).unwrap();
+
+        let path = CString::new(directory.to_str().unwrap()).unwrap();
+        unsafe {
+            let repository = ka_open(path.as_ptr());
+            assert!(!repository.is_null());
+
+            fs::write(directory.join("notes"), b"first second").unwrap();
+            let status = ka_status(repository);
+            let encoded = CStr::from_ptr(status).to_str().unwrap().to_owned();
+            ka_free_string(status);
+            assert!(encoded.contains(r#""status":"modified""#));
+
+            assert_eq!(ka_update(repository, 2), 0);
+
+            let file = CString::new("notes").unwrap();
+            let mut content = ptr::null_mut();
+            let mut length = 0;
+            assert_eq!(
+                ka_get_content(repository, file.as_ptr(), 1, &mut content, &mut length),
+                0
+            );
+            assert_eq!(slice::from_raw_parts(content, length), b"first");
+            ka_free_content(content, length);
+
+            let text = CString::new("first sécond").unwrap();
+            let changes = ka_get_text_changes(repository, file.as_ptr(), 2, text.as_ptr());
+            let encoded = CStr::from_ptr(changes).to_str().unwrap().to_owned();
+            ka_free_string(changes);
+            assert_eq!(encoded, r#"[{"at":7,"upto":8},{"at":7,"text":"e"}]"#);
+
+            assert_eq!(ka_shift(repository, 5, false), -1);
+            assert!(!ka_last_error().is_null());
+
+            ka_close(repository);
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+#![no_main]
+
+use ka::diff::ContentChange;
+use libfuzzer_sys::fuzz_target;
+
+// The first byte splits the input into the content and the JSON of the changes, so both
+// are under the fuzzer's control.
+fuzz_target!(|data: &[u8]| {
+    let (split, rest) = match data.split_first() {
+        Some((split, rest)) => (*split as usize, rest),
+        None => return,
+    };
+    let (content, changes) = rest.split_at(split.min(rest.len()));
+    let changes: Vec<ContentChange> = match serde_json::from_slice(changes) {
+        Ok(changes) => changes,
+        Err(_) => return,
+    };
+
+    let mut buffer = content.to_vec();
+    for change in changes.iter() {
+        if change.apply(&mut buffer).is_err() {
+            return;
+        }
+    }
+    for change in changes.iter().rev() {
+        let _ = change.unapply(&mut buffer);
+    }
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use ka::history::FileHistory;
+use libfuzzer_sys::fuzz_target;
+
+// Any history which decodes has to replay to every cursor without panicking, whether the
+// content comes out right or not.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(history) = FileHistory::decode(data) {
+        let last_cursor = history
+            .get_changes()
+            .iter()
+            .map(|change| change.change_index)
+            .max()
+            .unwrap_or(0);
+        for cursor in 0..=last_cursor.min(64) {
+            let _ = history.does_file_exist(cursor);
+            if let Ok(content) = history.get_content(cursor) {
+                let _ = history.get_content_backwards(content, cursor, 0);
+            }
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use ka::history::RepositoryHistory;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(history) = RepositoryHistory::decode(data) {
+        let _ = history.encode();
+    }
+});
@@ -1,6 +0,0 @@
-pub mod actions;
-pub mod filesystem;
-
-mod diff;
-mod files;
-mod history;
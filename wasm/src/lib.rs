@@ -0,0 +1,99 @@
+// JavaScript bindings to ka, so browser based note apps can keep histories of their notes. The
+// repository lives in memory, and `export` and `import` move it to and from wherever the host
+// stores it. Failures are thrown as errors with the message of the failure.
+//
+// Timestamps are seconds since the Unix epoch. JavaScript numbers hold them exactly, so they
+// are taken as numbers instead of as `BigInt`s.
+
+use ka::memory::MemoryRepository;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Repository {
+    inner: MemoryRepository,
+}
+
+#[wasm_bindgen]
+impl Repository {
+    pub fn create(timestamp: f64) -> Result<Repository, JsError> {
+        let inner = MemoryRepository::create(to_timestamp(timestamp)).map_err(to_error)?;
+        Ok(Repository { inner })
+    }
+
+    // Restores a repository from the output of `export`.
+    pub fn import(buffer: &[u8]) -> Result<Repository, JsError> {
+        let inner = MemoryRepository::import(buffer).map_err(to_error)?;
+        Ok(Repository { inner })
+    }
+
+    pub fn export(&self) -> Result<Vec<u8>, JsError> {
+        self.inner.export().map_err(to_error)
+    }
+
+    #[wasm_bindgen(js_name = writeFile)]
+    pub fn write_file(&self, path: &str, content: &[u8]) -> Result<(), JsError> {
+        self.inner.write_file(path, content).map_err(to_error)
+    }
+
+    #[wasm_bindgen(js_name = readFile)]
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, JsError> {
+        self.inner.read_file(path).map_err(to_error)
+    }
+
+    #[wasm_bindgen(js_name = deleteFile)]
+    pub fn delete_file(&self, path: &str) -> Result<(), JsError> {
+        self.inner.delete_file(path).map_err(to_error)
+    }
+
+    // Returns the paths of the files the change recorded, none if nothing changed.
+    pub fn update(&self, timestamp: f64) -> Result<Vec<String>, JsError> {
+        let summary = self
+            .inner
+            .update(to_timestamp(timestamp))
+            .map_err(to_error)?;
+        Ok(summary
+            .recorded
+            .iter()
+            .map(|path| {
+                let path = path.strip_prefix(".").unwrap_or(path);
+                path.to_string_lossy().into_owned()
+            })
+            .collect())
+    }
+
+    pub fn shift(&self, cursor: usize, force: bool) -> Result<(), JsError> {
+        self.inner.shift(cursor, force).map_err(to_error)
+    }
+
+    pub fn show(&self, path: &str, cursor: usize) -> Result<Vec<u8>, JsError> {
+        self.inner.show(path, cursor).map_err(to_error)
+    }
+}
+
+fn to_timestamp(timestamp: f64) -> u64 {
+    timestamp as u64
+}
+
+fn to_error(error: anyhow::Error) -> JsError {
+    JsError::new(&format!("{:#}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repository;
+
+    // Only the paths which succeed can run outside of `wasm32`, as errors are JavaScript values.
+    #[test]
+    fn record_and_export_through_bindings() {
+        let now = 0xC0FFEE as f64;
+
+        let repository = Repository::create(now).unwrap();
+        repository.write_file("notes/today", b"first").unwrap();
+        let recorded = repository.update(now + 1.0).unwrap();
+        assert_eq!(recorded, vec!["notes/today".to_string()]);
+
+        let exported = repository.export().unwrap();
+        let repository = Repository::import(&exported).unwrap();
+        assert_eq!(repository.show("notes/today", 1).unwrap(), b"first");
+    }
+}